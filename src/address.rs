@@ -0,0 +1,145 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Rendering of the same script pubkey as the addresses of all bitcoin networks,
+//! for example, to check the configuration of the anchoring nodes.
+//!
+//! Note that the testnet and signet addresses share the same encoding.
+
+use bitcoin::{
+    blockdata::script::Script,
+    network::constants::Network,
+    util::address::{Address, Payload},
+    PublicKey,
+};
+
+use std::fmt;
+
+use crate::{multisig::RedeemScript, p2wpk, p2wsh};
+
+/// All bitcoin networks in the rendering order.
+pub const NETWORKS: [Network; 4] = [
+    Network::Bitcoin,
+    Network::Testnet,
+    Network::Regtest,
+    Network::Signet,
+];
+
+/// The addresses of the same script pubkey in all bitcoin networks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NetworkAddresses {
+    payload: Payload,
+}
+
+impl NetworkAddresses {
+    /// Creates the addresses of the given script pubkey, or returns `None` if the script
+    /// pubkey has no address form.
+    pub fn from_script_pubkey(script_pubkey: &Script) -> Option<NetworkAddresses> {
+        Payload::from_script(script_pubkey).map(|payload| NetworkAddresses { payload })
+    }
+
+    /// Creates the `P2WSH` addresses of the given redeem script.
+    pub fn from_redeem_script(redeem_script: &RedeemScript) -> NetworkAddresses {
+        NetworkAddresses::from_script_pubkey(&p2wsh::script_pubkey(redeem_script))
+            .expect("`P2WSH` script pubkey is a correct witness program")
+    }
+
+    /// Creates the `P2WPK` addresses of the given public key.
+    pub fn from_public_key(public_key: &PublicKey) -> NetworkAddresses {
+        NetworkAddresses::from_script_pubkey(&p2wpk::script_pubkey(public_key))
+            .expect("`P2WPK` script pubkey is a correct witness program")
+    }
+
+    /// Returns the address in the given network.
+    pub fn address(&self, network: Network) -> Address {
+        Address {
+            payload: self.payload.clone(),
+            network,
+        }
+    }
+
+    /// Returns the addresses in all networks in the order of [`NETWORKS`][networks].
+    ///
+    /// [networks]: constant.NETWORKS.html
+    pub fn iter(&self) -> impl Iterator<Item = Address> + '_ {
+        NETWORKS.iter().map(move |&network| self.address(network))
+    }
+
+    /// Returns the script pubkey of the addresses.
+    pub fn script_pubkey(&self) -> Script {
+        self.payload.script_pubkey()
+    }
+
+    /// Checks whether the given address is one of the rendered ones, regardless
+    /// of its network.
+    pub fn contains(&self, address: &Address) -> bool {
+        address.payload == self.payload
+    }
+}
+
+/// Writes the addresses one per line, prefixed by the network name.
+impl fmt::Display for NetworkAddresses {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (index, address) in self.iter().enumerate() {
+            if index > 0 {
+                f.write_str("\n")?;
+            }
+            write!(f, "{}: {}", address.network, address)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{NetworkAddresses, NETWORKS};
+    use crate::{multisig::RedeemScriptBuilder, p2wsh, test_data::deterministic_keypair};
+
+    use bitcoin::{network::constants::Network, Address};
+
+    use std::str::FromStr;
+
+    #[test]
+    fn test_network_addresses() {
+        let public_keys = (0..3)
+            .map(|_| deterministic_keypair(Network::Bitcoin).0)
+            .collect::<Vec<_>>();
+        let redeem_script = RedeemScriptBuilder::with_public_keys(public_keys)
+            .quorum(2)
+            .to_script()
+            .unwrap();
+
+        let addresses = NetworkAddresses::from_redeem_script(&redeem_script);
+        for (address, &network) in addresses.iter().zip(&NETWORKS) {
+            assert_eq!(address, p2wsh::address(&redeem_script, network));
+            assert!(addresses.contains(&Address::from_str(&address.to_string()).unwrap()));
+        }
+        assert_eq!(
+            addresses.script_pubkey(),
+            p2wsh::script_pubkey(&redeem_script)
+        );
+
+        let rendered = addresses.to_string();
+        let lines = rendered.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("bitcoin: bc1q"));
+        assert!(lines[2].starts_with("regtest: bcrt1q"));
+        assert!(lines[3].starts_with("signet: tb1q"));
+
+        let public_key = deterministic_keypair(Network::Bitcoin).0;
+        let other = NetworkAddresses::from_public_key(&public_key);
+        assert!(!other.contains(&addresses.address(Network::Bitcoin)));
+        assert!(NetworkAddresses::from_script_pubkey(&Default::default()).is_none());
+    }
+}
@@ -0,0 +1,284 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The [`BIP-37`][bip-37] bloom filter, which is sent to the filtered peers, so they relay
+//! only the transactions relevant to the watched scripts and outpoints.
+//!
+//! [bip-37]: https://github.com/bitcoin/bips/blob/master/bip-0037.mediawiki
+
+use bitcoin::{
+    blockdata::{
+        script::{Instruction, Script},
+        transaction::OutPoint,
+    },
+    consensus,
+};
+
+use std::f64::consts::LN_2;
+
+use crate::scan::OutputScanner;
+
+/// The maximum size of the filter in bytes.
+pub const MAX_BLOOM_FILTER_SIZE: usize = 36_000;
+/// The maximum number of the hash functions.
+pub const MAX_HASH_FUNCS: u32 = 50;
+
+/// The multiplier of the hash function index, which is used to derive the hash seeds.
+const HASH_SEED_MULTIPLIER: u32 = 0xFBA4_C795;
+
+/// The way the filtered peer updates the filter when it finds the matching output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum BloomFlags {
+    /// The filter is not updated.
+    None = 0,
+    /// The outpoints of all matching outputs are added to the filter.
+    All = 1,
+    /// The outpoints of the matching pay-to-pubkey and bare multisig outputs are added
+    /// to the filter.
+    PubkeyOnly = 2,
+}
+
+/// The `BIP-37` bloom filter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BloomFilter {
+    data: Vec<u8>,
+    hash_funcs: u32,
+    tweak: u32,
+    flags: BloomFlags,
+}
+
+impl BloomFilter {
+    /// Creates an empty filter for the given number of elements with the given
+    /// false positive rate, for example `0.0001`. The filter size is limited by
+    /// the protocol, so the actual false positive rate may be higher for the large
+    /// number of elements.
+    ///
+    /// The tweak randomizes the hash functions, so the different filters for the same
+    /// elements do not match the same set of foreign transactions.
+    pub fn new(elements: usize, fp_rate: f64, tweak: u32, flags: BloomFlags) -> BloomFilter {
+        let elements = elements.max(1) as f64;
+        let bits = -1.0 / (LN_2 * LN_2) * elements * fp_rate.ln();
+        // The float to integer casts saturate, so the extreme rates are clamped.
+        let size = ((bits.min((MAX_BLOOM_FILTER_SIZE * 8) as f64) as usize) / 8).max(1);
+        let hash_funcs = ((size * 8) as f64 / elements * LN_2) as u32;
+        BloomFilter {
+            data: vec![0; size],
+            hash_funcs: hash_funcs.clamp(1, MAX_HASH_FUNCS),
+            tweak,
+            flags,
+        }
+    }
+
+    /// Creates a filter for the scripts watched by the given scanner and the given
+    /// outpoints, for example, the outpoints of the [`UtxoSet`][utxo-set].
+    ///
+    /// [utxo-set]: ../utxo/struct.UtxoSet.html
+    pub fn from_watched<I>(
+        scanner: &OutputScanner,
+        outpoints: I,
+        fp_rate: f64,
+        tweak: u32,
+    ) -> BloomFilter
+    where
+        I: IntoIterator<Item = OutPoint>,
+    {
+        let outpoints = outpoints.into_iter().collect::<Vec<_>>();
+        let script_pubkeys = scanner.script_pubkeys().collect::<Vec<_>>();
+        let elements = script_pubkeys.len() + outpoints.len();
+        let mut filter = BloomFilter::new(elements, fp_rate, tweak, BloomFlags::None);
+        for script_pubkey in script_pubkeys {
+            filter.insert_script_pubkey(script_pubkey);
+        }
+        for outpoint in &outpoints {
+            filter.insert_outpoint(outpoint);
+        }
+        filter
+    }
+
+    /// Returns the filter bits.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Returns the number of the hash functions.
+    pub fn hash_funcs(&self) -> u32 {
+        self.hash_funcs
+    }
+
+    /// Returns the tweak of the hash functions.
+    pub fn tweak(&self) -> u32 {
+        self.tweak
+    }
+
+    /// Returns the update flags.
+    pub fn flags(&self) -> BloomFlags {
+        self.flags
+    }
+
+    /// Adds the given data to the filter.
+    pub fn insert(&mut self, data: &[u8]) {
+        for index in 0..self.hash_funcs {
+            let bit = self.bit_index(index, data);
+            self.data[bit >> 3] |= 1 << (bit & 7);
+        }
+    }
+
+    /// Checks if the given data matches the filter.
+    pub fn contains(&self, data: &[u8]) -> bool {
+        (0..self.hash_funcs).all(|index| {
+            let bit = self.bit_index(index, data);
+            self.data[bit >> 3] & (1 << (bit & 7)) != 0
+        })
+    }
+
+    /// Adds the data pushes of the given script pubkey, for example the witness program,
+    /// so the peer matches the outputs which pay to this script.
+    pub fn insert_script_pubkey(&mut self, script_pubkey: &Script) {
+        for instruction in script_pubkey.instructions() {
+            if let Ok(Instruction::PushBytes(data)) = instruction {
+                if !data.is_empty() {
+                    self.insert(data);
+                }
+            }
+        }
+    }
+
+    /// Adds the given outpoint, so the peer matches the inputs which spend it.
+    pub fn insert_outpoint(&mut self, outpoint: &OutPoint) {
+        self.insert(&consensus::serialize(outpoint));
+    }
+
+    /// Serializes the filter into the payload of the `filterload` message.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = consensus::serialize(&self.data);
+        bytes.extend_from_slice(&self.hash_funcs.to_le_bytes());
+        bytes.extend_from_slice(&self.tweak.to_le_bytes());
+        bytes.push(self.flags as u8);
+        bytes
+    }
+
+    fn bit_index(&self, index: u32, data: &[u8]) -> usize {
+        let seed = index
+            .wrapping_mul(HASH_SEED_MULTIPLIER)
+            .wrapping_add(self.tweak);
+        murmur_hash3(seed, data) as usize % (self.data.len() * 8)
+    }
+}
+
+/// The 32-bit `MurmurHash3` hash function.
+fn murmur_hash3(seed: u32, data: &[u8]) -> u32 {
+    const C1: u32 = 0xcc9e_2d51;
+    const C2: u32 = 0x1b87_3593;
+
+    let mut hash = seed;
+    let mut chunks = data.chunks_exact(4);
+    for chunk in &mut chunks {
+        let mut k = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        k = k.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+        hash = (hash ^ k).rotate_left(13);
+        hash = hash.wrapping_mul(5).wrapping_add(0xe654_6b64);
+    }
+
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        let mut k = 0_u32;
+        for (i, byte) in tail.iter().enumerate() {
+            k ^= u32::from(*byte) << (8 * i);
+        }
+        k = k.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+        hash ^= k;
+    }
+
+    hash ^= data.len() as u32;
+    hash ^= hash >> 16;
+    hash = hash.wrapping_mul(0x85eb_ca6b);
+    hash ^= hash >> 13;
+    hash = hash.wrapping_mul(0xc2b2_ae35);
+    hash ^ (hash >> 16)
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{blockdata::transaction::OutPoint, network::constants::Network};
+
+    use super::{murmur_hash3, BloomFilter, BloomFlags};
+    use crate::{p2wpk, scan::OutputScanner, test_data::keypair_from_wif};
+
+    #[test]
+    fn test_murmur_hash3() {
+        // Reference values of the 32-bit `MurmurHash3` for various seeds and tail lengths.
+        let vectors: &[(u32, u32, &str)] = &[
+            (0x0000_0000, 0x0000_0000, ""),
+            (0x6a39_6f08, 0xFBA4_C795, ""),
+            (0x81f1_6f39, 0xffff_ffff, ""),
+            (0x514e_28b7, 0x0000_0000, "00"),
+            (0xea3f_0b17, 0xFBA4_C795, "00"),
+            (0xfd6c_f10d, 0x0000_0000, "ff"),
+            (0x16c6_b7ab, 0x0000_0000, "0011"),
+            (0x8eb5_1c3d, 0x0000_0000, "001122"),
+            (0xb447_1bf8, 0x0000_0000, "00112233"),
+            (0xe230_1fa8, 0x0000_0000, "0011223344"),
+            (0xb074_502c, 0x0000_0000, "00112233445566"),
+        ];
+        for (expected, seed, data) in vectors {
+            assert_eq!(murmur_hash3(*seed, &hex::decode(data).unwrap()), *expected);
+        }
+    }
+
+    #[test]
+    fn test_bloom_filter_reference() {
+        // Test vectors from Bitcoin Core.
+        for (tweak, expected) in &[
+            (0, "03614e9b050000000000000001"),
+            (2_147_483_649, "03ce4299050000000100008001"),
+        ] {
+            let mut filter = BloomFilter::new(3, 0.01, *tweak, BloomFlags::All);
+            let element = hex::decode("99108ad8ed9bb6274d3980bab5a85c048f0950c8").unwrap();
+            filter.insert(&element);
+            assert!(filter.contains(&element));
+            let foreign = hex::decode("19108ad8ed9bb6274d3980bab5a85c048f0950c8").unwrap();
+            assert!(!filter.contains(&foreign));
+            filter.insert(&hex::decode("b5a2c786d9ef4658287ced5914b37a1b4aa32eee").unwrap());
+            filter.insert(&hex::decode("b9300670b4c5366e95b2699e8b18bc75e5f729c5").unwrap());
+            assert_eq!(hex::encode(filter.to_bytes()), *expected);
+        }
+    }
+
+    #[test]
+    fn test_bloom_filter_from_watched() {
+        let (public_key, _) =
+            keypair_from_wif("cPHmynxvqfr7sXsJcohiGzoPGBShggxL6VWUdW14skohFZ1LQoeV");
+        let mut scanner = OutputScanner::new();
+        scanner.public_key(&public_key);
+        let outpoint = OutPoint::new(Default::default(), 1);
+
+        let filter = BloomFilter::from_watched(&scanner, vec![outpoint], 0.0001, 5);
+        assert_eq!(filter.flags(), BloomFlags::None);
+        let script_pubkey = p2wpk::script_pubkey(&public_key);
+        // The witness program is matched.
+        assert!(filter.contains(&script_pubkey.as_bytes()[2..]));
+        assert!(filter.contains(&bitcoin::consensus::serialize(&outpoint)));
+        assert!(
+            !filter.contains(&bitcoin::consensus::serialize(&OutPoint::new(
+                Default::default(),
+                2
+            )))
+        );
+        let (other_key, _) =
+            keypair_from_wif("cTtSTL1stvg2tmK349WTmQDfHLMLqkkxwuo8ZJeQov9zEhtYtb4u");
+        let other_script = p2wpk::address(&other_key, Network::Testnet).script_pubkey();
+        assert!(!filter.contains(&other_script.as_bytes()[2..]));
+    }
+}
@@ -0,0 +1,834 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Helpers for creating unsigned transactions which spend outputs locked by a redeem script.
+
+use crate::{
+    multisig::RedeemScript,
+    p2wsh,
+    policy::{self, MAX_STANDARD_TX_WEIGHT, TRUC_MAX_VSIZE, TRUC_VERSION},
+    uri, Amount, Error, FeeRate, LockTime,
+};
+use bitcoin::{
+    blockdata::{
+        script::Script,
+        transaction::{OutPoint, Transaction, TxIn, TxOut},
+    },
+    network::constants::Network,
+    util::address::Address,
+};
+
+use std::collections::{HashSet, VecDeque};
+
+/// The maximum size of the DER encoded signature with the sighash type byte.
+const MAX_SIGNATURE_SIZE: usize = 73;
+/// The size of the compressed public key.
+const PUBLIC_KEY_SIZE: usize = 33;
+/// The size of the `BIP-340` signature with the default sighash type.
+const SCHNORR_SIGNATURE_SIZE: usize = 64;
+
+/// The builder of transactions which spend outputs locked by the given redeem script.
+///
+/// The fee is computed from the size of the fully signed transaction, and the remaining
+/// balance is sent to the change script, which is the `P2WSH` script pubkey of the redeem
+/// script by default.
+#[derive(Debug)]
+pub struct TransactionBuilder {
+    redeem_script: RedeemScript,
+    inputs: Vec<(OutPoint, TxOut)>,
+    outputs: Vec<TxOut>,
+    change_script: Option<Script>,
+    fee_rate: FeeRate,
+    version: i32,
+    lock_time: LockTime,
+    max_weight: usize,
+}
+
+impl TransactionBuilder {
+    /// Creates builder for the given redeem script.
+    pub fn new(redeem_script: RedeemScript) -> TransactionBuilder {
+        TransactionBuilder {
+            redeem_script,
+            inputs: Vec::default(),
+            outputs: Vec::default(),
+            change_script: None,
+            fee_rate: FeeRate::ZERO,
+            version: 2,
+            lock_time: LockTime::ZERO,
+            max_weight: MAX_STANDARD_TX_WEIGHT,
+        }
+    }
+
+    /// Adds a new unspent output to be spent.
+    pub fn input(&mut self, outpoint: OutPoint, output: TxOut) -> &mut TransactionBuilder {
+        self.inputs.push((outpoint, output));
+        self
+    }
+
+    /// Adds the given unspent outputs to be spent.
+    pub fn inputs<I: IntoIterator<Item = (OutPoint, TxOut)>>(
+        &mut self,
+        inputs: I,
+    ) -> &mut TransactionBuilder {
+        self.inputs.extend(inputs);
+        self
+    }
+
+    /// Adds a new transaction output.
+    pub fn output(&mut self, output: TxOut) -> &mut TransactionBuilder {
+        self.outputs.push(output);
+        self
+    }
+
+    /// Adds the payments to the given recipients, which addresses must belong to the given
+    /// network. Each recipient must pay to its own script pubkey, which is not paid by
+    /// the already added outputs. If any recipient is incorrect, none of them are added.
+    pub fn recipients<I>(
+        &mut self,
+        recipients: I,
+        network: Network,
+    ) -> Result<&mut TransactionBuilder, Error>
+    where
+        I: IntoIterator<Item = (Address, Amount)>,
+    {
+        let mut script_pubkeys = self
+            .outputs
+            .iter()
+            .map(|output| output.script_pubkey.clone())
+            .collect::<HashSet<_>>();
+        let mut outputs = Vec::new();
+        for (index, (address, amount)) in recipients.into_iter().enumerate() {
+            ensure!(
+                uri::is_same_network(&address, network),
+                Error::RecipientNetwork {
+                    index,
+                    expected: network,
+                    actual: address.network,
+                }
+            );
+            let script_pubkey = address.script_pubkey();
+            ensure!(
+                script_pubkeys.insert(script_pubkey.clone()),
+                Error::DuplicateRecipient(index)
+            );
+            outputs.push(TxOut {
+                value: amount.as_sat(),
+                script_pubkey,
+            });
+        }
+        self.outputs.extend(outputs);
+        Ok(self)
+    }
+
+    /// Sets the script pubkey which receives the remaining balance.
+    pub fn change_script(&mut self, script_pubkey: Script) -> &mut TransactionBuilder {
+        self.change_script = Some(script_pubkey);
+        self
+    }
+
+    /// Sets the fee rate.
+    pub fn fee_rate(&mut self, fee_rate: FeeRate) -> &mut TransactionBuilder {
+        self.fee_rate = fee_rate;
+        self
+    }
+
+    /// Sets the transaction version, which is 2 by default.
+    ///
+    /// The version 3 transactions are the [`TRUC`][truc] ones, so the weight of
+    /// the transactions created by the [`to_transactions`][to-transactions] method
+    /// is limited by the `TRUC` size limit as well.
+    ///
+    /// [truc]: ../policy/constant.TRUC_VERSION.html
+    /// [to-transactions]: #method.to_transactions
+    pub fn version(&mut self, version: i32) -> &mut TransactionBuilder {
+        self.version = version;
+        self
+    }
+
+    /// Sets the transaction lock time.
+    ///
+    /// The inputs get the [sequence][input-sequence], which enables the lock time.
+    ///
+    /// [input-sequence]: ../enum.LockTime.html#method.input_sequence
+    pub fn lock_time(&mut self, lock_time: LockTime) -> &mut TransactionBuilder {
+        self.lock_time = lock_time;
+        self
+    }
+
+    /// Sets the maximum weight of the signed transactions created by the
+    /// [`to_transactions`][to-transactions] method. The standard weight limit is used
+    /// by default.
+    ///
+    /// [to-transactions]: #method.to_transactions
+    pub fn max_weight(&mut self, max_weight: usize) -> &mut TransactionBuilder {
+        self.max_weight = max_weight;
+        self
+    }
+
+    /// Finalizes the transaction building.
+    pub fn to_transaction(&self) -> Result<Transaction, Error> {
+        ensure!(!self.inputs.is_empty(), Error::NoInputs);
+
+        let available = sum_values(self.inputs.iter().map(|(_, output)| output))?;
+        let spent = sum_values(self.outputs.iter())?;
+        let change_script = self
+            .change_script
+            .clone()
+            .unwrap_or_else(|| p2wsh::script_pubkey(&self.redeem_script));
+        // Tries to create transaction with the change output.
+        let mut transaction = self.unsigned_transaction();
+        transaction.output.push(TxOut {
+            value: 0,
+            script_pubkey: change_script.clone(),
+        });
+        let required = self.fee(&transaction).saturating_add(spent);
+        if let Some(change) = available.checked_sub(required) {
+            if change >= policy::dust_threshold(&change_script, policy::DUST_RELAY_FEE_RATE) {
+                transaction.output.last_mut().unwrap().value = change;
+                return Ok(transaction);
+            }
+        }
+        // Otherwise the remaining balance is left to miners.
+        transaction.output.pop();
+        let required = self.fee(&transaction).saturating_add(spent);
+        ensure!(
+            !transaction.output.is_empty() && available >= required,
+            Error::InsufficientFunds {
+                available,
+                required,
+            }
+        );
+        Ok(transaction)
+    }
+
+    /// Finalizes the building of the batch payment, which outputs may not fit into a single
+    /// transaction of the [maximum weight][max-weight].
+    ///
+    /// The outputs are split across the transactions in the given order. Each transaction
+    /// spends the next unspent outputs required to pay for its outputs and the fee, and sends
+    /// the remaining balance to the change script. The unspent outputs which are not required
+    /// are left unspent. The transactions do not depend on each other, so they can be signed
+    /// and broadcast in any order.
+    ///
+    /// [max-weight]: #method.max_weight
+    pub fn to_transactions(&self) -> Result<Vec<Transaction>, Error> {
+        if self.outputs.is_empty() {
+            return self.to_transaction().map(|transaction| vec![transaction]);
+        }
+
+        let max_weight = if self.version == TRUC_VERSION {
+            self.max_weight.min(TRUC_MAX_VSIZE * 4)
+        } else {
+            self.max_weight
+        };
+        let mut inputs = self.inputs.iter().cloned().collect::<VecDeque<_>>();
+        let mut outputs = self.outputs.iter().cloned().collect::<VecDeque<_>>();
+        let mut transactions = Vec::new();
+        while !outputs.is_empty() {
+            let mut batch = self.batch_builder();
+            let mut transaction = None;
+            while let Some(output) = outputs.front() {
+                batch.outputs.push(output.clone());
+                let inputs_count = batch.inputs.len();
+                // Adds the unspent outputs until the batch is funded.
+                let candidate = loop {
+                    match batch.to_transaction() {
+                        Err(Error::NoInputs) | Err(Error::InsufficientFunds { .. })
+                            if !inputs.is_empty() =>
+                        {
+                            batch.inputs.extend(inputs.pop_front());
+                        }
+                        result => break result?,
+                    }
+                };
+                if batch.signed_weight(&candidate) > max_weight {
+                    // The batch is full, so the unspent outputs added for the output
+                    // are returned to the next batch.
+                    for input in batch.inputs.drain(inputs_count..).rev() {
+                        inputs.push_front(input);
+                    }
+                    ensure!(
+                        transaction.is_some(),
+                        Error::TransactionTooLarge(max_weight)
+                    );
+                    break;
+                }
+                outputs.pop_front();
+                transaction = Some(candidate);
+            }
+            transactions.extend(transaction);
+        }
+        Ok(transactions)
+    }
+
+    fn batch_builder(&self) -> TransactionBuilder {
+        TransactionBuilder {
+            redeem_script: self.redeem_script.clone(),
+            inputs: Vec::default(),
+            outputs: Vec::default(),
+            change_script: self.change_script.clone(),
+            fee_rate: self.fee_rate,
+            version: self.version,
+            lock_time: self.lock_time,
+            max_weight: self.max_weight,
+        }
+    }
+
+    fn unsigned_transaction(&self) -> Transaction {
+        Transaction {
+            version: self.version,
+            lock_time: self.lock_time.to_consensus_u32(),
+            input: self
+                .inputs
+                .iter()
+                .map(|(outpoint, _)| TxIn {
+                    previous_output: *outpoint,
+                    script_sig: Script::default(),
+                    sequence: self.lock_time.input_sequence().0,
+                    witness: Vec::default(),
+                })
+                .collect(),
+            output: self.outputs.clone(),
+        }
+    }
+
+    /// Computes the fee of the given unsigned transaction as if all of its inputs
+    /// were signed by the required number of participants.
+    fn fee(&self, transaction: &Transaction) -> u64 {
+        self.fee_rate
+            .fee_wu(self.signed_weight(transaction) as u64)
+            .unwrap_or(u64::MAX)
+    }
+
+    /// Computes the weight of the given unsigned transaction as if all of its inputs
+    /// were signed by the required number of participants.
+    fn signed_weight(&self, transaction: &Transaction) -> usize {
+        let witness = DummyInput::P2wsh(self.redeem_script.clone()).witness();
+        let mut transaction = transaction.clone();
+        for input in &mut transaction.input {
+            input.witness = witness.clone();
+        }
+        transaction.get_weight()
+    }
+}
+
+/// The spending conditions of the input, which define the size of its placeholder witness.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DummyInput {
+    /// The `P2WPK` input with the signature and the compressed public key.
+    P2wpk,
+    /// The `P2WSH` multisig input with the quorum of signatures.
+    P2wsh(RedeemScript),
+    /// The taproot key path input with the signature of the default sighash type.
+    P2trKeyPath,
+    /// The input with the witness items of the given sizes, for example,
+    /// the one which spends the script template.
+    Witness(Vec<usize>),
+}
+
+impl DummyInput {
+    /// Returns the placeholder witness, which items have the maximum size of the real ones.
+    pub fn witness(&self) -> Vec<Vec<u8>> {
+        match self {
+            DummyInput::P2wpk => vec![vec![0; MAX_SIGNATURE_SIZE], vec![0; PUBLIC_KEY_SIZE]],
+            DummyInput::P2wsh(redeem_script) => {
+                let mut witness = vec![Vec::default()];
+                witness.extend((0..redeem_script.quorum()).map(|_| vec![0; MAX_SIGNATURE_SIZE]));
+                witness.push(redeem_script.script.to_bytes());
+                witness
+            }
+            DummyInput::P2trKeyPath => vec![vec![0; SCHNORR_SIGNATURE_SIZE]],
+            DummyInput::Witness(sizes) => sizes.iter().map(|&size| vec![0; size]).collect(),
+        }
+    }
+}
+
+/// Fills the witnesses of all transaction inputs with the placeholders of the given
+/// spending conditions, which are given in the input order.
+///
+/// The weight of the resulting transaction is the upper bound of the weight of the signed one,
+/// which may be only a few bytes less because of the shorter signatures. So it is the most
+/// accurate way to compute the fee for the transaction with the mixed input types.
+pub fn fill_dummy_witnesses(
+    transaction: &mut Transaction,
+    inputs: &[DummyInput],
+) -> Result<(), Error> {
+    ensure!(
+        transaction.input.len() == inputs.len(),
+        Error::OutputsCount {
+            inputs: transaction.input.len(),
+            outputs: inputs.len(),
+        }
+    );
+    for (input, dummy) in transaction.input.iter_mut().zip(inputs) {
+        input.witness = dummy.witness();
+    }
+    Ok(())
+}
+
+/// Returns the virtual size of the given transaction after signing of all its inputs
+/// with the given spending conditions. [Read more...][fill-dummy-witnesses]
+///
+/// [fill-dummy-witnesses]: fn.fill_dummy_witnesses.html
+pub fn signed_vsize(transaction: &Transaction, inputs: &[DummyInput]) -> Result<u64, Error> {
+    let mut transaction = transaction.clone();
+    fill_dummy_witnesses(&mut transaction, inputs)?;
+    Ok((transaction.get_weight() as u64 + 3) / 4)
+}
+
+pub(crate) fn sum_values<'a, I: Iterator<Item = &'a TxOut>>(mut outputs: I) -> Result<u64, Error> {
+    outputs.try_fold(0_u64, |sum, output| {
+        sum.checked_add(output.value).ok_or(Error::ValueOverflow)
+    })
+}
+
+/// Creates a transaction which moves the entire balance of the given unspent outputs
+/// locked by the `from` redeem script to the address of the `to` redeem script.
+///
+/// This is useful when the set of participants changes, thus the funds should be transferred
+/// to the new multisig address. The fee is deducted from the transferred balance.
+pub fn migration_transaction<I>(
+    from: &RedeemScript,
+    to: &RedeemScript,
+    unspent_outputs: I,
+    fee_rate: FeeRate,
+) -> Result<Transaction, Error>
+where
+    I: IntoIterator<Item = (OutPoint, TxOut)>,
+{
+    sweep_transaction(from, unspent_outputs, p2wsh::script_pubkey(to), fee_rate)
+}
+
+/// Creates a transaction which drains all the given unspent outputs locked by the redeem script
+/// to the given destination, for example, to the cold storage address.
+///
+/// Unlike the regular spending, the fee rate may be arbitrarily high, so in case of emergency
+/// the transaction can outbid any conflicting transaction. The fee is deducted from the swept
+/// balance.
+pub fn sweep_transaction<I>(
+    redeem_script: &RedeemScript,
+    unspent_outputs: I,
+    destination: Script,
+    fee_rate: FeeRate,
+) -> Result<Transaction, Error>
+where
+    I: IntoIterator<Item = (OutPoint, TxOut)>,
+{
+    TransactionBuilder::new(redeem_script.clone())
+        .inputs(unspent_outputs)
+        .change_script(destination)
+        .fee_rate(fee_rate)
+        .to_transaction()
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{
+        blockdata::{
+            script::Script,
+            transaction::{OutPoint, Transaction, TxIn, TxOut},
+        },
+        network::constants::Network,
+        util::address::Address,
+    };
+
+    use std::collections::HashSet;
+
+    use crate::{
+        builder::{
+            fill_dummy_witnesses, migration_transaction, signed_vsize, sweep_transaction,
+            DummyInput, TransactionBuilder,
+        },
+        multisig::RedeemScriptBuilder,
+        p2wpk, p2wsh,
+        policy::{TRUC_MAX_VSIZE, TRUC_VERSION},
+        test_data::deterministic_keypair,
+        Amount, Error, FeeRate, LockTime, Sequence, TxInRef,
+    };
+
+    #[test]
+    fn test_migration_transaction() {
+        let keypairs = (0..5)
+            .map(|_| deterministic_keypair(Network::Testnet))
+            .collect::<Vec<_>>();
+        let old_script = RedeemScriptBuilder::with_public_keys(keypairs[0..4].iter().map(|x| x.0))
+            .quorum(3)
+            .to_script()
+            .unwrap();
+        let new_script = RedeemScriptBuilder::with_public_keys(keypairs[1..5].iter().map(|x| x.0))
+            .quorum(3)
+            .to_script()
+            .unwrap();
+
+        let unspent_outputs = (0..3)
+            .map(|vout| {
+                let output = TxOut {
+                    value: 100_000,
+                    script_pubkey: p2wsh::script_pubkey(&old_script),
+                };
+                (OutPoint::new(Default::default(), vout), output)
+            })
+            .collect::<Vec<_>>();
+        let fee_rate = FeeRate::from_sat_per_vb(10).unwrap();
+        let mut transaction =
+            migration_transaction(&old_script, &new_script, unspent_outputs.clone(), fee_rate)
+                .unwrap();
+        assert_eq!(transaction.input.len(), 3);
+        assert_eq!(transaction.output.len(), 1);
+        assert_eq!(
+            transaction.output[0].script_pubkey,
+            p2wsh::script_pubkey(&new_script)
+        );
+        // Signs transaction and checks that the fee is enough.
+        let mut signer = p2wsh::InputSigner::new(old_script);
+        for (index, (_, output)) in unspent_outputs.iter().enumerate() {
+            let signatures = keypairs[0..3]
+                .iter()
+                .map(|keypair| {
+                    let txin = TxInRef::new(&transaction, index);
+                    signer.sign_input(txin, output, &keypair.1.key).unwrap()
+                })
+                .collect::<Vec<_>>();
+            signer
+                .spend_input(&mut transaction, index, output, signatures)
+                .unwrap();
+        }
+        let fee = 300_000 - transaction.output[0].value;
+        let vsize = (transaction.get_weight() as u64 + 3) / 4;
+        assert!(fee >= fee_rate.fee_vb(vsize).unwrap());
+        assert!(fee < fee_rate.fee_vb(vsize + 3 * 3).unwrap());
+    }
+
+    #[test]
+    fn test_sweep_transaction() {
+        let keypairs = (0..3)
+            .map(|_| deterministic_keypair(Network::Testnet))
+            .collect::<Vec<_>>();
+        let script = RedeemScriptBuilder::with_public_keys(keypairs.iter().map(|x| x.0))
+            .quorum(2)
+            .to_script()
+            .unwrap();
+        let (cold_key, _) = deterministic_keypair(Network::Testnet);
+        let cold_script = p2wpk::script_pubkey(&cold_key);
+
+        let unspent_outputs = (0..4)
+            .map(|vout| {
+                let output = TxOut {
+                    value: 50_000,
+                    script_pubkey: p2wsh::script_pubkey(&script),
+                };
+                (OutPoint::new(Default::default(), vout), output)
+            })
+            .collect::<Vec<_>>();
+        // Aggressive fee rate still leaves some funds.
+        let fee_rate = FeeRate::from_sat_per_vb(250).unwrap();
+        let transaction = sweep_transaction(
+            &script,
+            unspent_outputs.clone(),
+            cold_script.clone(),
+            fee_rate,
+        )
+        .unwrap();
+        assert_eq!(transaction.input.len(), 4);
+        assert_eq!(transaction.output.len(), 1);
+        assert_eq!(transaction.output[0].script_pubkey, cold_script);
+        assert!(transaction.output[0].value < 200_000 - 250 * 100);
+        // Fee rate that consumes the entire balance.
+        let fee_rate = FeeRate::from_sat_per_vb(1_000).unwrap();
+        assert!(sweep_transaction(&script, unspent_outputs, cold_script, fee_rate).is_err());
+    }
+
+    #[test]
+    fn test_transaction_builder_insufficient_funds() {
+        let (pk, _) = deterministic_keypair(Network::Testnet);
+        let script = RedeemScriptBuilder::with_public_keys(vec![pk])
+            .to_script()
+            .unwrap();
+        let output = TxOut {
+            value: 1_000,
+            script_pubkey: p2wsh::script_pubkey(&script),
+        };
+
+        assert_eq!(
+            TransactionBuilder::new(script.clone()).to_transaction(),
+            Err(Error::NoInputs)
+        );
+        let error = TransactionBuilder::new(script)
+            .input(OutPoint::default(), output)
+            .fee_rate(FeeRate::from_sat_per_vb(100).unwrap())
+            .to_transaction()
+            .unwrap_err();
+        match error {
+            Error::InsufficientFunds { available, .. } => {
+                assert_eq!(available, 1_000)
+            }
+            other => panic!("Unexpected error: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_transaction_builder_lock_time() {
+        let (pk, _) = deterministic_keypair(Network::Testnet);
+        let script = RedeemScriptBuilder::with_public_keys(vec![pk])
+            .to_script()
+            .unwrap();
+        let output = TxOut {
+            value: 100_000,
+            script_pubkey: p2wsh::script_pubkey(&script),
+        };
+        let mut builder = TransactionBuilder::new(script);
+        builder
+            .inputs((0..2).map(|vout| (OutPoint::new(Default::default(), vout), output.clone())));
+
+        // The inputs are final without the lock time.
+        let transaction = builder.to_transaction().unwrap();
+        assert_eq!(transaction.lock_time, 0);
+        assert!(transaction
+            .input
+            .iter()
+            .all(|input| Sequence(input.sequence).is_final()));
+        // The lock time is enforced only if some input is not final.
+        let lock_time = LockTime::from_height(700_000).unwrap();
+        let transaction = builder.lock_time(lock_time).to_transaction().unwrap();
+        assert_eq!(transaction.lock_time, 700_000);
+        for input in &transaction.input {
+            assert_eq!(Sequence(input.sequence), Sequence::ENABLE_LOCK_TIME_NO_RBF);
+        }
+    }
+
+    #[test]
+    fn test_signed_vsize() {
+        let keypairs = (0..3)
+            .map(|_| deterministic_keypair(Network::Testnet))
+            .collect::<Vec<_>>();
+        let redeem_script = RedeemScriptBuilder::with_public_keys(keypairs.iter().map(|x| x.0))
+            .quorum(2)
+            .to_script()
+            .unwrap();
+        let (public_key, private_key) = deterministic_keypair(Network::Testnet);
+
+        let prev_outputs = [
+            TxOut {
+                value: 50_000,
+                script_pubkey: p2wsh::script_pubkey(&redeem_script),
+            },
+            TxOut {
+                value: 20_000,
+                script_pubkey: p2wpk::script_pubkey(&public_key),
+            },
+        ];
+        let mut transaction = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: (0..2)
+                .map(|vout| TxIn {
+                    previous_output: OutPoint::new(Default::default(), vout),
+                    script_sig: Script::default(),
+                    sequence: 0xFFFF_FFFF,
+                    witness: Vec::default(),
+                })
+                .collect(),
+            output: vec![TxOut {
+                value: 60_000,
+                script_pubkey: p2wpk::script_pubkey(&public_key),
+            }],
+        };
+        let inputs = vec![DummyInput::P2wsh(redeem_script.clone()), DummyInput::P2wpk];
+        let vsize = signed_vsize(&transaction, &inputs).unwrap();
+        assert_eq!(
+            signed_vsize(&transaction, &inputs[..1]),
+            Err(Error::OutputsCount {
+                inputs: 2,
+                outputs: 1
+            })
+        );
+
+        let mut dummy = transaction.clone();
+        fill_dummy_witnesses(&mut dummy, &inputs).unwrap();
+        assert_eq!(dummy.input[0].witness.len(), 4);
+        assert_eq!(dummy.input[1].witness.len(), 2);
+
+        // Signs the transaction and compares the real size with the estimated one.
+        let mut signer = p2wsh::InputSigner::new(redeem_script);
+        let signatures = keypairs[0..2]
+            .iter()
+            .map(|keypair| {
+                let txin = TxInRef::new(&transaction, 0);
+                signer
+                    .sign_input(txin, &prev_outputs[0], &keypair.1.key)
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        signer
+            .spend_input(&mut transaction, 0, &prev_outputs[0], signatures)
+            .unwrap();
+        let mut signer = p2wpk::InputSigner::new(public_key, Network::Testnet);
+        let signature = signer
+            .sign_input(
+                TxInRef::new(&transaction, 1),
+                &prev_outputs[1],
+                &private_key.key,
+            )
+            .unwrap();
+        signer.spend_input(&mut transaction.input[1], signature);
+
+        let real_vsize = (transaction.get_weight() as u64 + 3) / 4;
+        assert!(real_vsize <= vsize);
+        // Each of the three signatures may be a couple of bytes shorter.
+        assert!(vsize <= real_vsize + 2);
+    }
+
+    #[test]
+    fn test_transaction_builder_recipients() {
+        let keypairs = (0..3)
+            .map(|_| deterministic_keypair(Network::Testnet))
+            .collect::<Vec<_>>();
+        let script = RedeemScriptBuilder::with_public_keys(keypairs.iter().map(|x| x.0))
+            .quorum(2)
+            .to_script()
+            .unwrap();
+        let unspent_outputs = (0..10)
+            .map(|vout| {
+                let output = TxOut {
+                    value: 100_000,
+                    script_pubkey: p2wsh::script_pubkey(&script),
+                };
+                (OutPoint::new(Default::default(), vout), output)
+            })
+            .collect::<Vec<_>>();
+        let recipients = (0..30)
+            .map(|_| {
+                let public_key = deterministic_keypair(Network::Testnet).0;
+                (
+                    p2wpk::address(&public_key, Network::Testnet),
+                    Amount::from_sat(10_000),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let mut builder = TransactionBuilder::new(script);
+        builder
+            .inputs(unspent_outputs.clone())
+            .fee_rate(FeeRate::from_sat_per_vb(10).unwrap())
+            .max_weight(4_000);
+        // Incorrect recipients are rejected as a whole.
+        let mut duplicate = recipients[..2].to_vec();
+        duplicate.push(recipients[0].clone());
+        assert_eq!(
+            builder.recipients(duplicate, Network::Testnet).unwrap_err(),
+            Error::DuplicateRecipient(2)
+        );
+        assert_eq!(
+            builder
+                .recipients(recipients.clone(), Network::Bitcoin)
+                .unwrap_err(),
+            Error::RecipientNetwork {
+                index: 0,
+                expected: Network::Bitcoin,
+                actual: Network::Testnet,
+            }
+        );
+        builder
+            .recipients(recipients.clone(), Network::Testnet)
+            .unwrap();
+        assert_eq!(
+            builder
+                .recipients(recipients[5..6].to_vec(), Network::Testnet)
+                .unwrap_err(),
+            Error::DuplicateRecipient(0)
+        );
+
+        let transactions = builder.to_transactions().unwrap();
+        assert!(transactions.len() > 1);
+        let mut paid = Vec::new();
+        let mut spent = HashSet::new();
+        for transaction in &transactions {
+            let vsize = signed_vsize(
+                transaction,
+                &vec![DummyInput::P2wsh(builder.redeem_script.clone()); transaction.input.len()],
+            )
+            .unwrap();
+            assert!(vsize <= 1_000);
+            for input in &transaction.input {
+                assert!(spent.insert(input.previous_output));
+            }
+            let available = transaction.input.len() as u64 * 100_000;
+            let total = transaction.output.iter().map(|x| x.value).sum::<u64>();
+            assert!(total < available);
+            paid.extend(
+                transaction
+                    .output
+                    .iter()
+                    .filter(|output| output.value == 10_000)
+                    .map(|output| output.script_pubkey.clone()),
+            );
+        }
+        let expected = recipients
+            .iter()
+            .map(|(address, _)| address.script_pubkey())
+            .collect::<Vec<_>>();
+        assert_eq!(paid, expected);
+
+        // The output which does not fit into the weight limit alone.
+        builder.max_weight(400);
+        assert_eq!(
+            builder.to_transactions(),
+            Err(Error::TransactionTooLarge(400))
+        );
+    }
+
+    #[test]
+    fn test_transaction_builder_truc() {
+        let (public_key, _) = deterministic_keypair(Network::Testnet);
+        let script = RedeemScriptBuilder::with_public_keys(vec![public_key])
+            .to_script()
+            .unwrap();
+        let unspent_outputs = (0..10)
+            .map(|vout| {
+                let output = TxOut {
+                    value: 1_000_000,
+                    script_pubkey: p2wsh::script_pubkey(&script),
+                };
+                (OutPoint::new(Default::default(), vout), output)
+            })
+            .collect::<Vec<_>>();
+        let recipients = (0..250_u32)
+            .map(|index| {
+                let script = Script::from(index.to_le_bytes().to_vec());
+                (
+                    Address::p2wsh(&script, Network::Testnet),
+                    Amount::from_sat(10_000),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let mut builder = TransactionBuilder::new(script.clone());
+        builder
+            .inputs(unspent_outputs)
+            .recipients(recipients, Network::Testnet)
+            .unwrap()
+            .fee_rate(FeeRate::from_sat_per_vb(1).unwrap());
+        assert_eq!(builder.to_transactions().unwrap().len(), 1);
+
+        // The batch is split by the TRUC size limit.
+        let transactions = builder.version(TRUC_VERSION).to_transactions().unwrap();
+        assert_eq!(transactions.len(), 2);
+        for transaction in &transactions {
+            assert_eq!(transaction.version, TRUC_VERSION);
+            let inputs = vec![DummyInput::P2wsh(script.clone()); transaction.input.len()];
+            let vsize = signed_vsize(transaction, &inputs).unwrap();
+            assert!(vsize <= TRUC_MAX_VSIZE as u64);
+        }
+    }
+}
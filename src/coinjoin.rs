@@ -0,0 +1,447 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Helpers to build the multi-party CoinJoin transactions.
+//!
+//! The coordinator registers the inputs and outputs of all participants and builds
+//! the transaction template, which inputs and outputs are sorted according to the
+//! [`BIP-69`][bip-69], so the order does not reveal the participants. After the template
+//! is agreed, each participant signs only its own inputs with the `SIGHASH_ALL` type
+//! and sends the witnesses to the coordinator, which checks them and blames
+//! the participants with incorrect signatures.
+//!
+//! Only the `P2WPKH` inputs are supported.
+//!
+//! [bip-69]: https://github.com/bitcoin/bips/blob/master/bip-0069.mediawiki
+
+use bitcoin::{
+    blockdata::{
+        script::Script,
+        transaction::{OutPoint, SigHashType, Transaction, TxIn, TxOut},
+    },
+    network::constants::Network,
+};
+use thiserror::Error;
+
+use crate::{builder::sum_values, p2wpk, Error, LockTime, TxInRef};
+
+/// Possible errors of the CoinJoin transaction building.
+#[derive(Debug, Clone, Error, PartialEq)]
+pub enum CoinJoinError {
+    /// There are no registered participants.
+    #[error("There are no registered participants.")]
+    NoParticipants,
+    /// The input is registered more than once.
+    #[error("The input {0} is registered more than once.")]
+    DuplicateInput(OutPoint),
+    /// The input with the given index does not belong to the participant.
+    #[error("The input {input} does not belong to the participant {participant}.")]
+    NotOwner {
+        /// The participant index.
+        participant: usize,
+        /// The input index.
+        input: usize,
+    },
+    /// The participant has submitted an incorrect witness for its input.
+    #[error("The participant {participant} has submitted an incorrect witness for input {input}.")]
+    Blame {
+        /// The participant index.
+        participant: usize,
+        /// The input index.
+        input: usize,
+    },
+    /// Some participants have not signed their inputs.
+    #[error("The participants {0:?} have not signed their inputs.")]
+    MissingSignatures(Vec<usize>),
+}
+
+/// The inputs and outputs of a single participant.
+#[derive(Debug, Clone, PartialEq)]
+struct Registration {
+    inputs: Vec<(OutPoint, TxOut)>,
+    outputs: Vec<TxOut>,
+}
+
+/// The CoinJoin transaction builder.
+#[derive(Debug, Default)]
+pub struct CoinJoinBuilder {
+    participants: Vec<Registration>,
+    lock_time: LockTime,
+}
+
+impl CoinJoinBuilder {
+    /// Creates builder.
+    pub fn new() -> CoinJoinBuilder {
+        CoinJoinBuilder::default()
+    }
+
+    /// Registers the next participant with the given spent outputs and the new outputs.
+    /// The participants are identified by their registration order, starting from zero.
+    ///
+    /// The participant pays its share of the fee, so the value of its outputs must not
+    /// exceed the value of its inputs.
+    pub fn participant<I, O>(&mut self, inputs: I, outputs: O) -> &mut CoinJoinBuilder
+    where
+        I: IntoIterator<Item = (OutPoint, TxOut)>,
+        O: IntoIterator<Item = TxOut>,
+    {
+        self.participants.push(Registration {
+            inputs: inputs.into_iter().collect(),
+            outputs: outputs.into_iter().collect(),
+        });
+        self
+    }
+
+    /// Sets the transaction lock time.
+    ///
+    /// The inputs get the [sequence][input-sequence], which enables the lock time.
+    ///
+    /// [input-sequence]: ../enum.LockTime.html#method.input_sequence
+    pub fn lock_time(&mut self, lock_time: LockTime) -> &mut CoinJoinBuilder {
+        self.lock_time = lock_time;
+        self
+    }
+
+    /// Builds the transaction template, which should be signed by the participants.
+    pub fn to_session(&self) -> Result<CoinJoinSession, Error> {
+        ensure!(!self.participants.is_empty(), CoinJoinError::NoParticipants);
+
+        let mut inputs = Vec::new();
+        let mut outputs = Vec::new();
+        for (participant, registration) in self.participants.iter().enumerate() {
+            let available = sum_values(registration.inputs.iter().map(|(_, output)| output))?;
+            let required = sum_values(registration.outputs.iter())?;
+            ensure!(
+                available >= required,
+                Error::InsufficientFunds {
+                    available,
+                    required
+                }
+            );
+
+            for (outpoint, spent_output) in &registration.inputs {
+                ensure!(
+                    inputs.iter().all(|(o, _, _)| o != outpoint),
+                    CoinJoinError::DuplicateInput(*outpoint)
+                );
+                ensure!(
+                    spent_output.script_pubkey.is_v0_p2wpkh(),
+                    Error::UnsupportedScript
+                );
+                inputs.push((*outpoint, spent_output.clone(), participant));
+            }
+            outputs.extend(registration.outputs.iter().cloned());
+        }
+
+        inputs.sort_by_key(|(outpoint, _, _)| {
+            let mut txid = outpoint.txid.to_vec();
+            txid.reverse();
+            (txid, outpoint.vout)
+        });
+        outputs.sort_by(|a, b| {
+            (a.value, a.script_pubkey.as_bytes()).cmp(&(b.value, b.script_pubkey.as_bytes()))
+        });
+
+        let transaction = Transaction {
+            version: 2,
+            lock_time: self.lock_time.to_consensus_u32(),
+            input: inputs
+                .iter()
+                .map(|(outpoint, _, _)| TxIn {
+                    previous_output: *outpoint,
+                    script_sig: Script::default(),
+                    sequence: self.lock_time.input_sequence().0,
+                    witness: Vec::default(),
+                })
+                .collect(),
+            output: outputs,
+        };
+        Ok(CoinJoinSession {
+            transaction,
+            spent_outputs: inputs.iter().map(|(_, output, _)| output.clone()).collect(),
+            owners: inputs.iter().map(|(_, _, owner)| *owner).collect(),
+            participants: self.participants.len(),
+        })
+    }
+}
+
+/// The agreed CoinJoin transaction template, which collects the witnesses of the participants.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoinJoinSession {
+    transaction: Transaction,
+    spent_outputs: Vec<TxOut>,
+    owners: Vec<usize>,
+    participants: usize,
+}
+
+impl CoinJoinSession {
+    /// Returns the transaction template. The participants compute the signature hashes
+    /// of their inputs for this transaction.
+    pub fn transaction(&self) -> &Transaction {
+        &self.transaction
+    }
+
+    /// Returns the outputs spent by the transaction inputs in the input order.
+    pub fn spent_outputs(&self) -> &[TxOut] {
+        &self.spent_outputs
+    }
+
+    /// Returns the indices of the inputs, which belong to the given participant.
+    pub fn inputs_of(&self, participant: usize) -> Vec<usize> {
+        self.owners
+            .iter()
+            .enumerate()
+            .filter(|(_, owner)| **owner == participant)
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    /// Checks the witness of the input submitted by the given participant and puts it into
+    /// the transaction.
+    ///
+    /// The witness must contain a `SIGHASH_ALL` signature of the transaction template,
+    /// which matches the spent output, otherwise the participant is blamed.
+    pub fn add_witness(
+        &mut self,
+        participant: usize,
+        input: usize,
+        witness: Vec<Vec<u8>>,
+    ) -> Result<(), Error> {
+        ensure!(
+            self.owners.get(input) == Some(&participant),
+            CoinJoinError::NotOwner { participant, input }
+        );
+        let blame = CoinJoinError::Blame { participant, input };
+
+        let parsed = p2wpk::parse_witness(&witness).map_err(|_| blame.clone())?;
+        let spent_output = &self.spent_outputs[input];
+        ensure!(
+            p2wpk::script_pubkey(&parsed.public_key) == spent_output.script_pubkey
+                && parsed.signature.try_sighash_type() == Ok(SigHashType::All),
+            blame.clone()
+        );
+        p2wpk::InputSigner::new(parsed.public_key, Network::Bitcoin)
+            .verify_input(
+                TxInRef::new(&self.transaction, input),
+                spent_output.value,
+                &parsed.public_key,
+                &parsed.signature,
+            )
+            .map_err(|_| blame)?;
+
+        self.transaction.input[input].witness = witness;
+        Ok(())
+    }
+
+    /// Returns the fully signed transaction, or the list of participants, which have
+    /// not signed their inputs yet.
+    pub fn to_transaction(&self) -> Result<Transaction, Error> {
+        let mut missing = (0..self.participants)
+            .filter(|participant| {
+                self.inputs_of(*participant)
+                    .into_iter()
+                    .any(|input| self.transaction.input[input].witness.is_empty())
+            })
+            .collect::<Vec<_>>();
+        missing.dedup();
+        ensure!(
+            missing.is_empty(),
+            CoinJoinError::MissingSignatures(missing)
+        );
+        Ok(self.transaction.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{
+        blockdata::transaction::{OutPoint, TxOut},
+        network::constants::Network,
+        PrivateKey, PublicKey,
+    };
+
+    use super::{CoinJoinBuilder, CoinJoinError};
+    use crate::{
+        p2wpk,
+        test_data::{fake_funding_tx, keypair_from_wif},
+        Error, LockTime, Sequence, TxInRef,
+    };
+
+    fn participant(wif: &str, value: u64) -> (PublicKey, PrivateKey, (OutPoint, TxOut), TxOut) {
+        let (public_key, private_key) = keypair_from_wif(wif);
+        let script_pubkey = p2wpk::script_pubkey(&public_key);
+        let funding = fake_funding_tx(&script_pubkey, value);
+        let input = (OutPoint::new(funding.txid(), 0), funding.output[0].clone());
+        let output = TxOut {
+            value: 10_000,
+            script_pubkey,
+        };
+        (public_key, private_key, input, output)
+    }
+
+    #[test]
+    fn test_coinjoin_flow() {
+        let participants = [
+            participant(
+                "cPHmynxvqfr7sXsJcohiGzoPGBShggxL6VWUdW14skohFZ1LQoeV",
+                20_000,
+            ),
+            participant(
+                "cTtSTL1stvg2tmK349WTmQDfHLMLqkkxwuo8ZJeQov9zEhtYtb4u",
+                30_000,
+            ),
+        ];
+        let mut builder = CoinJoinBuilder::new();
+        for (_, _, input, output) in &participants {
+            builder.participant(vec![input.clone()], vec![output.clone()]);
+        }
+        let mut session = builder.to_session().unwrap();
+        assert_eq!(session.transaction().input.len(), 2);
+        assert_eq!(
+            session.to_transaction(),
+            Err(Error::from(CoinJoinError::MissingSignatures(vec![0, 1])))
+        );
+
+        let sign = |session: &super::CoinJoinSession, participant: usize, wrong_key: bool| {
+            let (public_key, private_key, _, _) = &participants[participant];
+            let input = session.inputs_of(participant)[0];
+            let mut signer = p2wpk::InputSigner::new(*public_key, Network::Testnet);
+            let key = if wrong_key {
+                &participants[1 - participant].1
+            } else {
+                private_key
+            };
+            let signature = signer
+                .sign_input(
+                    TxInRef::new(session.transaction(), input),
+                    session.spent_outputs()[input].value,
+                    &key.key,
+                )
+                .unwrap();
+            (input, vec![signature.into(), public_key.to_bytes()])
+        };
+
+        // The participant with the incorrect signature is blamed.
+        let (input, witness) = sign(&session, 0, true);
+        assert_eq!(
+            session.add_witness(0, input, witness),
+            Err(Error::from(CoinJoinError::Blame {
+                participant: 0,
+                input
+            }))
+        );
+        let (input, witness) = sign(&session, 0, false);
+        assert_eq!(
+            session.add_witness(1, input, witness.clone()),
+            Err(Error::from(CoinJoinError::NotOwner {
+                participant: 1,
+                input
+            }))
+        );
+        session.add_witness(0, input, witness).unwrap();
+        assert_eq!(
+            session.to_transaction(),
+            Err(Error::from(CoinJoinError::MissingSignatures(vec![1])))
+        );
+
+        let (input, witness) = sign(&session, 1, false);
+        session.add_witness(1, input, witness).unwrap();
+        let transaction = session.to_transaction().unwrap();
+        assert!(transaction
+            .input
+            .iter()
+            .all(|input| !input.witness.is_empty()));
+    }
+
+    #[test]
+    fn test_coinjoin_lock_time() {
+        let participants = [
+            participant(
+                "cPHmynxvqfr7sXsJcohiGzoPGBShggxL6VWUdW14skohFZ1LQoeV",
+                20_000,
+            ),
+            participant(
+                "cTtSTL1stvg2tmK349WTmQDfHLMLqkkxwuo8ZJeQov9zEhtYtb4u",
+                30_000,
+            ),
+        ];
+        let mut builder = CoinJoinBuilder::new();
+        for (_, _, input, output) in &participants {
+            builder.participant(vec![input.clone()], vec![output.clone()]);
+        }
+
+        // The inputs are final without the lock time.
+        let transaction = builder.to_session().unwrap().transaction().clone();
+        assert!(transaction
+            .input
+            .iter()
+            .all(|input| Sequence(input.sequence).is_final()));
+        // The lock time is enforced only if some input is not final.
+        let lock_time = LockTime::from_time(1_700_000_000).unwrap();
+        let session = builder.lock_time(lock_time).to_session().unwrap();
+        let transaction = session.transaction();
+        assert_eq!(transaction.lock_time, 1_700_000_000);
+        for input in &transaction.input {
+            assert_eq!(Sequence(input.sequence), Sequence::ENABLE_LOCK_TIME_NO_RBF);
+        }
+    }
+
+    #[test]
+    fn test_coinjoin_registration_errors() {
+        let (_, _, input, output) = participant(
+            "cPHmynxvqfr7sXsJcohiGzoPGBShggxL6VWUdW14skohFZ1LQoeV",
+            20_000,
+        );
+        assert_eq!(
+            CoinJoinBuilder::new()
+                .participant(vec![input.clone()], vec![output.clone()])
+                .participant(vec![input.clone()], vec![output.clone()])
+                .to_session(),
+            Err(Error::from(CoinJoinError::DuplicateInput(input.0)))
+        );
+        assert_eq!(
+            CoinJoinBuilder::new()
+                .participant(
+                    vec![input.clone()],
+                    vec![output.clone(), output.clone(), output.clone()]
+                )
+                .to_session(),
+            Err(Error::InsufficientFunds {
+                available: 20_000,
+                required: 30_000
+            })
+        );
+        // The values given by the participant overflow.
+        let mut huge_output = output.clone();
+        huge_output.value = u64::MAX;
+        assert_eq!(
+            CoinJoinBuilder::new()
+                .participant(vec![input.clone()], vec![output.clone(), huge_output])
+                .to_session(),
+            Err(Error::ValueOverflow)
+        );
+        let mut huge_input = input.clone();
+        huge_input.1.value = u64::MAX;
+        assert_eq!(
+            CoinJoinBuilder::new()
+                .participant(vec![input, huge_input], vec![output])
+                .to_session(),
+            Err(Error::ValueOverflow)
+        );
+        assert_eq!(
+            CoinJoinBuilder::new().to_session(),
+            Err(Error::from(CoinJoinError::NoParticipants))
+        );
+    }
+}
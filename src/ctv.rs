@@ -0,0 +1,369 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Experimental support of `OP_CHECKTEMPLATEVERIFY` as proposed by [`BIP-119`][bip-119].
+//! The opcode restricts the spending transaction to the one committed by the template
+//! hash, so the output is locked by the script:
+//!
+//! ```text
+//! <template_hash> OP_CHECKTEMPLATEVERIFY
+//! ```
+//!
+//! The opcode is not activated on the mainnet, so the module is only suitable for
+//! prototyping on the signets which enforce it.
+//!
+//! [bip-119]: https://github.com/bitcoin/bips/blob/master/bip-0119.mediawiki
+
+use bitcoin::{
+    blockdata::{
+        opcodes::{self, all::OP_NOP4},
+        script::{Builder, Script},
+        transaction::{OutPoint, Transaction, TxIn, TxOut},
+    },
+    consensus::encode::Encodable,
+    network::constants::Network,
+    util::address::Address,
+};
+use bitcoin_hashes::{sha256, Hash, HashEngine};
+
+use crate::{Amount, Error, Sequence};
+
+/// The `OP_CHECKTEMPLATEVERIFY` opcode, which redefines `OP_NOP4`.
+pub const OP_CHECKTEMPLATEVERIFY: opcodes::All = OP_NOP4;
+
+/// Computes the default template hash of the transaction for the input with the given index,
+/// that is the hash of the transaction version, lock time, input scripts, sequences and
+/// outputs. The spent outpoints are not committed, so the template can be computed before
+/// the output it spends is created.
+pub fn template_hash(transaction: &Transaction, index: usize) -> Result<sha256::Hash, Error> {
+    ensure!(index < transaction.input.len(), Error::NoSuchInput(index));
+
+    // Writing to the hash engine never fails.
+    let mut engine = sha256::Hash::engine();
+    transaction.version.consensus_encode(&mut engine).unwrap();
+    transaction.lock_time.consensus_encode(&mut engine).unwrap();
+    if transaction
+        .input
+        .iter()
+        .any(|input| !input.script_sig.is_empty())
+    {
+        let mut script_sigs = sha256::Hash::engine();
+        for input in &transaction.input {
+            input.script_sig.consensus_encode(&mut script_sigs).unwrap();
+        }
+        engine.input(&sha256::Hash::from_engine(script_sigs)[..]);
+    }
+    (transaction.input.len() as u32)
+        .consensus_encode(&mut engine)
+        .unwrap();
+    let mut sequences = sha256::Hash::engine();
+    for input in &transaction.input {
+        input.sequence.consensus_encode(&mut sequences).unwrap();
+    }
+    engine.input(&sha256::Hash::from_engine(sequences)[..]);
+    (transaction.output.len() as u32)
+        .consensus_encode(&mut engine)
+        .unwrap();
+    let mut outputs = sha256::Hash::engine();
+    for output in &transaction.output {
+        output.consensus_encode(&mut outputs).unwrap();
+    }
+    engine.input(&sha256::Hash::from_engine(outputs)[..]);
+    (index as u32).consensus_encode(&mut engine).unwrap();
+    Ok(sha256::Hash::from_engine(engine))
+}
+
+/// The output, which can be spent only by the transaction with the given template hash.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CtvTemplate {
+    /// The template hash of the spending transaction.
+    pub hash: sha256::Hash,
+}
+
+impl CtvTemplate {
+    /// Creates the template of the given transaction, which spends the output by the input
+    /// with the given index.
+    pub fn from_transaction(transaction: &Transaction, index: usize) -> Result<Self, Error> {
+        template_hash(transaction, index).map(|hash| CtvTemplate { hash })
+    }
+
+    /// Returns the witness script of the output.
+    pub fn to_script(&self) -> Script {
+        Builder::new()
+            .push_slice(&self.hash[..])
+            .push_opcode(OP_CHECKTEMPLATEVERIFY)
+            .into_script()
+    }
+
+    /// Returns the `P2WSH` script pubkey of the output.
+    pub fn script_pubkey(&self) -> Script {
+        self.to_script().to_v0_p2wsh()
+    }
+
+    /// Returns the `P2WSH` address of the output for the given network.
+    pub fn address(&self, network: Network) -> Address {
+        Address::p2wsh(&self.to_script(), network)
+    }
+
+    /// Puts the witness into the input with the given index, which has no signatures
+    /// and contains only the witness script. The transaction must match the template.
+    pub fn spend_input(&self, transaction: &mut Transaction, index: usize) -> Result<(), Error> {
+        ensure!(
+            template_hash(transaction, index)? == self.hash,
+            Error::TemplateMismatch
+        );
+        transaction.input[index].witness = vec![self.to_script().to_bytes()];
+        Ok(())
+    }
+}
+
+/// The congestion control tree, which commits to many payments by a single output.
+///
+/// The payments are split between the leaf transactions, which are spent by the upper levels
+/// of the tree up to the root output. Each transaction has at most `radix` outputs
+/// and pays the same fee. Once the root output is confirmed, the payments are guaranteed,
+/// and the tree can be expanded later, when the fees are lower.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CongestionTree {
+    root: TxOut,
+    radix: usize,
+    // The transactions without the spent outpoints level by level, starting from the root.
+    levels: Vec<Vec<(Transaction, CtvTemplate)>>,
+}
+
+impl CongestionTree {
+    /// Creates the tree of transactions which pay to the given outputs. Each transaction
+    /// of the tree pays the given fee. The radix must be at least two.
+    pub fn new(outputs: Vec<TxOut>, radix: usize, fee: Amount) -> Result<CongestionTree, Error> {
+        ensure!(radix >= 2, Error::InvalidRadix(radix));
+        ensure!(!outputs.is_empty(), Error::NoOutputs);
+
+        let mut levels = Vec::new();
+        let mut outputs = outputs;
+        loop {
+            let level = outputs
+                .chunks(radix)
+                .map(|chunk| {
+                    let transaction = Transaction {
+                        version: 2,
+                        lock_time: 0,
+                        input: vec![TxIn {
+                            previous_output: OutPoint::default(),
+                            script_sig: Script::default(),
+                            sequence: Sequence::MAX.0,
+                            witness: Vec::default(),
+                        }],
+                        output: chunk.to_vec(),
+                    };
+                    let template = CtvTemplate::from_transaction(&transaction, 0)?;
+                    Ok((transaction, template))
+                })
+                .collect::<Result<Vec<_>, Error>>()?;
+            outputs = level
+                .iter()
+                .map(|(transaction, template)| {
+                    let value = transaction
+                        .output
+                        .iter()
+                        .try_fold(fee.as_sat(), |sum, output| sum.checked_add(output.value))
+                        .ok_or(Error::ValueOverflow)?;
+                    Ok(TxOut {
+                        value,
+                        script_pubkey: template.script_pubkey(),
+                    })
+                })
+                .collect::<Result<Vec<_>, Error>>()?;
+            levels.push(level);
+            if outputs.len() == 1 {
+                break;
+            }
+        }
+        levels.reverse();
+
+        Ok(CongestionTree {
+            root: outputs.remove(0),
+            radix,
+            levels,
+        })
+    }
+
+    /// Returns the root output, which commits to the whole tree.
+    pub fn root_output(&self) -> &TxOut {
+        &self.root
+    }
+
+    /// Returns the number of the tree levels.
+    pub fn depth(&self) -> usize {
+        self.levels.len()
+    }
+
+    /// Returns all transactions of the tree, which expand the given root outpoint, with
+    /// the witnesses. Each transaction follows the one whose output it spends.
+    pub fn transactions(&self, root_outpoint: OutPoint) -> Vec<Transaction> {
+        let mut transactions = Vec::new();
+        let mut parents = Vec::new();
+        for level in &self.levels {
+            let txids = level
+                .iter()
+                .enumerate()
+                .map(|(position, (transaction, template))| {
+                    let mut transaction = transaction.clone();
+                    transaction.input[0].previous_output = if parents.is_empty() {
+                        root_outpoint
+                    } else {
+                        OutPoint::new(
+                            parents[position / self.radix],
+                            (position % self.radix) as u32,
+                        )
+                    };
+                    template
+                        .spend_input(&mut transaction, 0)
+                        .expect("The spent outpoint is not committed by the template");
+                    let txid = transaction.txid();
+                    transactions.push(transaction);
+                    txid
+                })
+                .collect::<Vec<_>>();
+            parents = txids;
+        }
+        transactions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{
+        blockdata::{
+            script::Script,
+            transaction::{OutPoint, Transaction, TxIn, TxOut},
+        },
+        network::constants::Network,
+    };
+    use bitcoin_hashes::hex::ToHex;
+
+    use super::{template_hash, CongestionTree, CtvTemplate};
+    use crate::{p2wpk, test_data::deterministic_keypair, Amount, Error};
+
+    #[test]
+    fn test_ctv_template() {
+        let public_key = deterministic_keypair(Network::Testnet).0;
+        let mut transaction = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::default(),
+                script_sig: Script::default(),
+                sequence: 0xFFFF_FFFF,
+                witness: Vec::default(),
+            }],
+            output: vec![TxOut {
+                value: 10_000,
+                script_pubkey: p2wpk::script_pubkey(&public_key),
+            }],
+        };
+        let template = CtvTemplate::from_transaction(&transaction, 0).unwrap();
+        assert_eq!(
+            template.to_script().as_bytes()[1..33].to_hex(),
+            template.hash.to_hex()
+        );
+        assert_eq!(template.to_script().as_bytes()[33], 0xb3);
+
+        // The spent outpoint and the witness are not committed.
+        transaction.input[0].previous_output.vout = 1;
+        transaction.input[0].witness = vec![vec![1]];
+        template.spend_input(&mut transaction, 0).unwrap();
+        assert_eq!(
+            transaction.input[0].witness,
+            vec![template.to_script().to_bytes()]
+        );
+        // But the input index and the sequences are.
+        let mut two_inputs = transaction.clone();
+        two_inputs.input.push(transaction.input[0].clone());
+        assert_ne!(
+            template_hash(&two_inputs, 0).unwrap(),
+            template_hash(&two_inputs, 1).unwrap()
+        );
+        transaction.input[0].sequence = 0;
+        assert_eq!(
+            template.spend_input(&mut transaction, 0),
+            Err(Error::TemplateMismatch)
+        );
+        assert_eq!(
+            template.spend_input(&mut transaction, 1),
+            Err(Error::NoSuchInput(1))
+        );
+    }
+
+    #[test]
+    fn test_congestion_tree() {
+        let public_key = deterministic_keypair(Network::Testnet).0;
+        let payments = (1..=5)
+            .map(|value| TxOut {
+                value: value * 1_000,
+                script_pubkey: p2wpk::script_pubkey(&public_key),
+            })
+            .collect::<Vec<_>>();
+        let tree = CongestionTree::new(payments.clone(), 2, Amount::from_sat(100)).unwrap();
+        assert_eq!(tree.depth(), 3);
+        // The root output pays for 5 payments and 6 transactions.
+        assert_eq!(tree.root_output().value, 15_600);
+
+        let root_outpoint = OutPoint::new(Default::default(), 1);
+        let transactions = tree.transactions(root_outpoint);
+        assert_eq!(transactions.len(), 6);
+        assert_eq!(transactions[0].input[0].previous_output, root_outpoint);
+        let spent_output = |outpoint: &OutPoint| {
+            if *outpoint == root_outpoint {
+                return tree.root_output().clone();
+            }
+            let parent = transactions
+                .iter()
+                .find(|transaction| transaction.txid() == outpoint.txid)
+                .unwrap();
+            parent.output[outpoint.vout as usize].clone()
+        };
+        for transaction in &transactions {
+            let spent_output = spent_output(&transaction.input[0].previous_output);
+            let template = CtvTemplate::from_transaction(transaction, 0).unwrap();
+            assert_eq!(spent_output.script_pubkey, template.script_pubkey());
+            let value = transaction
+                .output
+                .iter()
+                .map(|output| output.value)
+                .sum::<u64>();
+            assert_eq!(spent_output.value, value + 100);
+        }
+        let leaves = transactions
+            .iter()
+            .flat_map(|transaction| transaction.output.iter())
+            .filter(|output| output.script_pubkey == payments[0].script_pubkey)
+            .cloned()
+            .collect::<Vec<_>>();
+        assert_eq!(leaves.len(), payments.len());
+
+        assert_eq!(
+            CongestionTree::new(Vec::new(), 2, Amount::from_sat(100)),
+            Err(Error::NoOutputs)
+        );
+        for radix in 0..2 {
+            assert_eq!(
+                CongestionTree::new(payments.clone(), radix, Amount::from_sat(100)),
+                Err(Error::InvalidRadix(radix))
+            );
+        }
+        let single = CongestionTree::new(payments[..1].to_vec(), 2, Amount::from_sat(100)).unwrap();
+        assert_eq!(single.depth(), 1);
+        assert_eq!(single.root_output().value, 1_100);
+    }
+}
@@ -0,0 +1,644 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Helpers for the Discreet Log Contracts (`DLC`).
+//!
+//! Both parties lock their collateral in the 2-of-2 multisig funding output. For each
+//! possible outcome of the event, there is the contract execution transaction (`CET`),
+//! which spends the funding output and pays the parties according to the outcome.
+//!
+//! The oracle announces its nonce for the event in advance, so the point of its future
+//! [`BIP-340`][bip-340] signature of each outcome is known. Before the funding, each party
+//! gives the other one the [adaptor signatures][adaptor] of all `CET`s, which are encrypted
+//! with the corresponding attestation points. Once the oracle attests the outcome, its
+//! signature decrypts the adaptor signature of the matching `CET` only, so either party
+//! can complete and broadcast it.
+//!
+//! [bip-340]: https://github.com/bitcoin/bips/blob/master/bip-0340.mediawiki
+//! [adaptor]: ../struct.AdaptorSignature.html
+
+use bitcoin::{
+    blockdata::{
+        script::Script,
+        transaction::{OutPoint, SigHashType, Transaction, TxIn, TxOut},
+    },
+    PublicKey,
+};
+use bitcoin_hashes::{sha256, Hash, HashEngine};
+use secp256k1::{schnorrsig, Message, SecretKey};
+
+use crate::{
+    ecc::{mul_point, scalar_from_hash, sum_points, tagged_engine},
+    multisig::{RedeemScript, RedeemScriptBuilder},
+    p2wsh, policy, sign,
+    tapscript::XOnlyPublicKey,
+    AdaptorSignature, Amount, Error, InputSignature, LockTime, Sequence, TxInRef,
+};
+
+/// The oracle, which attests the outcome of the single event.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Oracle {
+    /// The public key of the oracle.
+    pub public_key: XOnlyPublicKey,
+    /// The nonce, which the oracle has announced for the event.
+    pub nonce: XOnlyPublicKey,
+}
+
+impl Oracle {
+    /// Returns the point of the oracle signature of the given outcome, that is the public
+    /// key corresponding to the attestation of the outcome.
+    pub fn attestation_point(&self, outcome: &[u8]) -> Result<secp256k1::PublicKey, Error> {
+        let public_key = even_point(&self.public_key)?;
+        let challenge = self.challenge(outcome)?;
+        Ok(sum_points(&[
+            even_point(&self.nonce)?,
+            mul_point(public_key, &challenge)?,
+        ])?)
+    }
+
+    /// Verifies the oracle signature of the given outcome and returns the attestation
+    /// secret, which decrypts the adaptor signatures for this outcome.
+    pub fn verify_attestation(
+        &self,
+        outcome: &[u8],
+        attestation: &schnorrsig::Signature,
+    ) -> Result<SecretKey, Error> {
+        ensure!(
+            attestation[..32] == self.nonce.serialize(),
+            Error::InvalidAttestation
+        );
+        sign::shared_context()
+            .schnorrsig_verify(
+                attestation,
+                &outcome_message(outcome),
+                &self.public_key.into(),
+            )
+            .map_err(|_| Error::InvalidAttestation)?;
+        Ok(SecretKey::from_slice(&attestation[32..])?)
+    }
+
+    fn challenge(&self, outcome: &[u8]) -> Result<SecretKey, Error> {
+        let mut engine = tagged_engine("BIP0340/challenge");
+        engine.input(&self.nonce.serialize());
+        engine.input(&self.public_key.serialize());
+        engine.input(&outcome_message(outcome)[..]);
+        Ok(scalar_from_hash(sha256::Hash::from_engine(engine))?)
+    }
+}
+
+/// Returns the 2-of-2 multisig script of the funding output. The public keys are sorted,
+/// so both parties get the same script.
+pub fn funding_script(first: PublicKey, second: PublicKey) -> RedeemScript {
+    let mut public_keys = [first, second];
+    public_keys.sort_by_key(|key| key.key.serialize());
+    RedeemScriptBuilder::with_public_keys(public_keys.iter().copied())
+        .to_script()
+        .expect("The 2-of-2 multisig of the compressed keys is correct")
+}
+
+/// Creates the unsigned funding transaction, which spends the given outpoints of both parties
+/// and pays the given value to the funding output followed by the given change outputs.
+/// Each party signs its own inputs.
+pub fn funding_transaction<I>(
+    outpoints: I,
+    funding_script: &RedeemScript,
+    value: Amount,
+    change: Vec<TxOut>,
+) -> Transaction
+where
+    I: IntoIterator<Item = OutPoint>,
+{
+    let funding = TxOut {
+        value: value.as_sat(),
+        script_pubkey: p2wsh::script_pubkey(funding_script),
+    };
+    Transaction {
+        version: 2,
+        lock_time: 0,
+        input: outpoints
+            .into_iter()
+            .map(|previous_output| TxIn {
+                previous_output,
+                script_sig: Script::default(),
+                sequence: Sequence::ENABLE_LOCK_TIME_NO_RBF.0,
+                witness: Vec::default(),
+            })
+            .collect(),
+        output: Some(funding).into_iter().chain(change).collect(),
+    }
+}
+
+/// The funding output of the contract.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FundingOutput {
+    /// The 2-of-2 multisig script, which locks the output.
+    pub redeem_script: RedeemScript,
+    /// The outpoint of the funding output.
+    pub outpoint: OutPoint,
+    /// The value of the funding output.
+    pub value: Amount,
+}
+
+impl FundingOutput {
+    /// Finds the output locked by the given funding script in the funding transaction.
+    pub fn from_transaction(
+        transaction: &Transaction,
+        redeem_script: RedeemScript,
+    ) -> Result<FundingOutput, Error> {
+        let (vout, output) = transaction
+            .output
+            .iter()
+            .enumerate()
+            .find(|(_, output)| redeem_script.matches_output(output))
+            .ok_or(Error::ScriptMismatch)?;
+        Ok(FundingOutput {
+            outpoint: OutPoint::new(transaction.txid(), vout as u32),
+            value: Amount::from_sat(output.value),
+            redeem_script,
+        })
+    }
+}
+
+/// The contract execution transaction for the single outcome of the event.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContractExecution {
+    funding: FundingOutput,
+    transaction: Transaction,
+    oracle: Oracle,
+    outcome: Vec<u8>,
+    attestation_point: secp256k1::PublicKey,
+}
+
+impl ContractExecution {
+    /// Creates the unsigned `CET`, which spends the funding output and pays the given payouts
+    /// if the oracle attests the given outcome. The dust payouts are dropped, and the rest
+    /// of the funding value is the fee.
+    ///
+    /// The lock time should be the expected time of the attestation, so the refund
+    /// transaction, if any, can be broadcast only after all `CET`s.
+    pub fn new(
+        funding: FundingOutput,
+        payouts: Vec<TxOut>,
+        lock_time: LockTime,
+        oracle: Oracle,
+        outcome: &[u8],
+    ) -> Result<ContractExecution, Error> {
+        let output = payouts
+            .into_iter()
+            .filter(|payout| !policy::is_dust(payout, policy::DUST_RELAY_FEE_RATE))
+            .collect::<Vec<_>>();
+        ensure!(!output.is_empty(), Error::NoOutputs);
+        let required = output.iter().try_fold(0_u64, |sum, payout| {
+            sum.checked_add(payout.value).ok_or(Error::ValueOverflow)
+        })?;
+        ensure!(
+            required <= funding.value.as_sat(),
+            Error::InsufficientFunds {
+                available: funding.value.as_sat(),
+                required,
+            }
+        );
+
+        let transaction = Transaction {
+            version: 2,
+            lock_time: lock_time.to_consensus_u32(),
+            input: vec![TxIn {
+                previous_output: funding.outpoint,
+                script_sig: Script::default(),
+                sequence: Sequence::ENABLE_LOCK_TIME_NO_RBF.0,
+                witness: Vec::default(),
+            }],
+            output,
+        };
+        Ok(ContractExecution {
+            attestation_point: oracle.attestation_point(outcome)?,
+            funding,
+            transaction,
+            oracle,
+            outcome: outcome.to_vec(),
+        })
+    }
+
+    /// Returns a reference to the unsigned `CET`.
+    pub fn transaction(&self) -> &Transaction {
+        &self.transaction
+    }
+
+    /// Returns the outcome of the event, for which the `CET` is valid.
+    pub fn outcome(&self) -> &[u8] {
+        &self.outcome
+    }
+
+    /// Returns the attestation point of the outcome, which encrypts the adaptor signatures.
+    pub fn attestation_point(&self) -> &secp256k1::PublicKey {
+        &self.attestation_point
+    }
+
+    /// Creates the adaptor signature of the `CET` for the counterparty, which is encrypted
+    /// with the attestation point of the outcome.
+    pub fn encrypt_signature(&self, secret_key: &SecretKey) -> Result<AdaptorSignature, Error> {
+        let public_key = secp256k1::PublicKey::from_secret_key(&sign::shared_context(), secret_key);
+        ensure!(
+            self.funding
+                .redeem_script
+                .public_keys()
+                .any(|key| key.key == public_key),
+            Error::KeyNotInScript
+        );
+        AdaptorSignature::encrypt(&self.sighash()?, secret_key, &self.attestation_point)
+    }
+
+    /// Checks the adaptor signature of the `CET` received from the counterparty with
+    /// the given public key.
+    pub fn verify_signature(
+        &self,
+        public_key: &PublicKey,
+        signature: &AdaptorSignature,
+    ) -> Result<(), Error> {
+        ensure!(
+            self.funding.redeem_script.key_index(public_key).is_some(),
+            Error::UnknownPublicKey
+        );
+        signature.verify(&self.sighash()?, &public_key.key, &self.attestation_point)
+    }
+
+    /// Completes the `CET` after the oracle has attested the outcome. The adaptor signature
+    /// of the counterparty with the given public key is decrypted by the attestation,
+    /// and the own signature is created with the given secret key.
+    pub fn finalize(
+        &self,
+        secret_key: &SecretKey,
+        counterparty: &PublicKey,
+        signature: &AdaptorSignature,
+        attestation: &schnorrsig::Signature,
+    ) -> Result<Transaction, Error> {
+        let decryption_key = self.oracle.verify_attestation(&self.outcome, attestation)?;
+        let counterparty_signature =
+            InputSignature::from_signature(&signature.decrypt(&decryption_key)?, SigHashType::All);
+        let counterparty_index = self
+            .funding
+            .redeem_script
+            .key_index(counterparty)
+            .ok_or(Error::UnknownPublicKey)?;
+
+        let mut signer = p2wsh::InputSigner::new(self.funding.redeem_script.clone());
+        let txin = TxInRef::new(&self.transaction, 0);
+        signer.verify_input(
+            txin,
+            self.funding.value,
+            counterparty,
+            &counterparty_signature,
+        )?;
+        let own_signature = signer.sign_input(txin, self.funding.value, secret_key)?;
+
+        // Signatures must be ordered in the same way as public keys in the redeem script.
+        let signatures = if counterparty_index == 0 {
+            [counterparty_signature, own_signature]
+        } else {
+            [own_signature, counterparty_signature]
+        };
+        let mut transaction = self.transaction.clone();
+        signer.spend_input(&mut transaction, 0, self.funding.value, signatures)?;
+        Ok(transaction)
+    }
+
+    fn sighash(&self) -> Result<Message, Error> {
+        let sighash = sign::signature_hash(
+            TxInRef::new(&self.transaction, 0),
+            &self.funding.redeem_script.script,
+            self.funding.value,
+        );
+        Ok(Message::from_slice(&sighash[..])?)
+    }
+}
+
+/// The message signed by the oracle for the given outcome.
+fn outcome_message(outcome: &[u8]) -> Message {
+    Message::from_slice(&sha256::Hash::hash(outcome)[..]).expect("The hash has 32 bytes")
+}
+
+/// Returns the point with the given `x` coordinate and the even `y` coordinate.
+fn even_point(key: &XOnlyPublicKey) -> Result<secp256k1::PublicKey, Error> {
+    let mut bytes = [0x02; 33];
+    bytes[1..].copy_from_slice(&key.serialize());
+    Ok(secp256k1::PublicKey::from_slice(&bytes)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{
+        blockdata::transaction::{OutPoint, Transaction, TxOut},
+        network::constants::Network,
+        PrivateKey, PublicKey,
+    };
+    use bitcoin_hashes::{sha256, Hash, HashEngine};
+    use secp256k1::{schnorrsig, SecretKey};
+
+    use super::{
+        funding_script, funding_transaction, outcome_message, ContractExecution, FundingOutput,
+        Oracle,
+    };
+    use crate::{
+        ecc::{has_odd_y, scalar_from_hash, tagged_engine},
+        p2wpk, sign,
+        tapscript::XOnlyPublicKey,
+        test_data::deterministic_keypair,
+        verify::verify_transaction,
+        AdaptorError, Amount, Error, LockTime,
+    };
+
+    /// Creates the `BIP-340` signature with the given nonce, as the oracle does.
+    fn attest(secret_key: &SecretKey, nonce: &SecretKey, outcome: &[u8]) -> schnorrsig::Signature {
+        let context = sign::shared_context();
+        let even = |mut scalar: SecretKey| {
+            if has_odd_y(&secp256k1::PublicKey::from_secret_key(&context, &scalar)) {
+                scalar.negate_assign();
+            }
+            scalar
+        };
+        let (mut x, mut k) = (even(*secret_key), even(*nonce));
+        let point = |scalar: &SecretKey| {
+            secp256k1::PublicKey::from_secret_key(&context, scalar).serialize()
+        };
+
+        let mut engine = tagged_engine("BIP0340/challenge");
+        engine.input(&point(&k)[1..]);
+        engine.input(&point(&x)[1..]);
+        engine.input(&outcome_message(outcome)[..]);
+        let challenge = scalar_from_hash(sha256::Hash::from_engine(engine)).unwrap();
+        x.mul_assign(&challenge[..]).unwrap();
+        let r = point(&k);
+        k.add_assign(&x[..]).unwrap();
+
+        let mut bytes = [0; 64];
+        bytes[..32].copy_from_slice(&r[1..]);
+        bytes[32..].copy_from_slice(&k[..]);
+        schnorrsig::Signature::from_slice(&bytes).unwrap()
+    }
+
+    struct Fixture {
+        oracle: Oracle,
+        oracle_key: SecretKey,
+        oracle_nonce: SecretKey,
+        alice: (PublicKey, PrivateKey),
+        bob: (PublicKey, PrivateKey),
+        funding_tx: Transaction,
+        funding: FundingOutput,
+        cets: Vec<ContractExecution>,
+    }
+
+    fn x_only(scalar: &SecretKey) -> XOnlyPublicKey {
+        secp256k1::PublicKey::from_secret_key(&sign::shared_context(), scalar).into()
+    }
+
+    fn payout(public_key: PublicKey, value: u64) -> TxOut {
+        TxOut {
+            value,
+            script_pubkey: p2wpk::script_pubkey(&public_key),
+        }
+    }
+
+    /// Creates the contract, in which Alice wins if the oracle attests "rain",
+    /// and Bob wins if the oracle attests "sun".
+    fn fixture() -> Fixture {
+        let (oracle_key, oracle_nonce) = (
+            SecretKey::from_slice(&[1; 32]).unwrap(),
+            SecretKey::from_slice(&[2; 32]).unwrap(),
+        );
+        let oracle = Oracle {
+            public_key: x_only(&oracle_key),
+            nonce: x_only(&oracle_nonce),
+        };
+
+        let alice = deterministic_keypair(Network::Testnet);
+        let bob = deterministic_keypair(Network::Testnet);
+        let redeem_script = funding_script(alice.0, bob.0);
+        let funding_tx = funding_transaction(
+            vec![OutPoint::default()],
+            &redeem_script,
+            Amount::from_sat(100_000),
+            Vec::new(),
+        );
+        let funding = FundingOutput::from_transaction(&funding_tx, redeem_script).unwrap();
+
+        let lock_time = LockTime::from_height(700_000).unwrap();
+        let cets = [
+            (b"rain".as_ref(), 99_000, 100),
+            (b"sun".as_ref(), 0, 99_000),
+        ]
+        .iter()
+        .map(|&(outcome, alice_payout, bob_payout)| {
+            let payouts = vec![payout(alice.0, alice_payout), payout(bob.0, bob_payout)];
+            ContractExecution::new(funding.clone(), payouts, lock_time, oracle, outcome).unwrap()
+        })
+        .collect();
+        Fixture {
+            oracle,
+            oracle_key,
+            oracle_nonce,
+            alice,
+            bob,
+            funding_tx,
+            funding,
+            cets,
+        }
+    }
+
+    #[test]
+    fn test_oracle_attestation() {
+        let Fixture {
+            oracle,
+            oracle_key,
+            oracle_nonce,
+            ..
+        } = fixture();
+        let attestation = attest(&oracle_key, &oracle_nonce, b"rain");
+        let attestation_key = oracle.verify_attestation(b"rain", &attestation).unwrap();
+        assert_eq!(
+            secp256k1::PublicKey::from_secret_key(&sign::shared_context(), &attestation_key),
+            oracle.attestation_point(b"rain").unwrap()
+        );
+        assert_ne!(
+            oracle.attestation_point(b"rain").unwrap(),
+            oracle.attestation_point(b"sun").unwrap()
+        );
+
+        // The attestation of another outcome.
+        assert_eq!(
+            oracle.verify_attestation(b"sun", &attestation),
+            Err(Error::InvalidAttestation)
+        );
+        // The attestation with the nonce, which was not announced.
+        let other_nonce = SecretKey::from_slice(&[3; 32]).unwrap();
+        let attestation = attest(&oracle_key, &other_nonce, b"rain");
+        assert_eq!(
+            oracle.verify_attestation(b"rain", &attestation),
+            Err(Error::InvalidAttestation)
+        );
+        // The attestation with the announced nonce made by another key.
+        let other_key = SecretKey::from_slice(&[4; 32]).unwrap();
+        let attestation = attest(&other_key, &oracle_nonce, b"rain");
+        assert_eq!(
+            oracle.verify_attestation(b"rain", &attestation),
+            Err(Error::InvalidAttestation)
+        );
+    }
+
+    #[test]
+    fn test_funding_output() {
+        let Fixture {
+            alice,
+            bob,
+            funding_tx,
+            funding,
+            ..
+        } = fixture();
+        assert_eq!(funding.redeem_script, funding_script(bob.0, alice.0));
+        assert_eq!(funding.outpoint, OutPoint::new(funding_tx.txid(), 0));
+        assert_eq!(funding.value, Amount::from_sat(100_000));
+
+        let other_script = funding_script(alice.0, deterministic_keypair(Network::Testnet).0);
+        assert_eq!(
+            FundingOutput::from_transaction(&funding_tx, other_script),
+            Err(Error::ScriptMismatch)
+        );
+    }
+
+    #[test]
+    fn test_contract_execution_payouts() {
+        let Fixture {
+            oracle,
+            alice,
+            bob,
+            funding,
+            cets,
+            ..
+        } = fixture();
+        // The dust payouts are dropped.
+        assert_eq!(cets[0].transaction().output, vec![payout(alice.0, 99_000)]);
+        assert_eq!(cets[1].transaction().output, vec![payout(bob.0, 99_000)]);
+        assert_eq!(cets[0].outcome(), b"rain");
+        assert_eq!(
+            *cets[0].attestation_point(),
+            oracle.attestation_point(b"rain").unwrap()
+        );
+
+        let lock_time = LockTime::from_height(700_000).unwrap();
+        assert_eq!(
+            ContractExecution::new(
+                funding.clone(),
+                vec![payout(alice.0, 100)],
+                lock_time,
+                oracle,
+                b"rain"
+            ),
+            Err(Error::NoOutputs)
+        );
+        assert_eq!(
+            ContractExecution::new(
+                funding,
+                vec![payout(alice.0, 100_001)],
+                lock_time,
+                oracle,
+                b"rain"
+            ),
+            Err(Error::InsufficientFunds {
+                available: 100_000,
+                required: 100_001,
+            })
+        );
+    }
+
+    #[test]
+    fn test_adaptor_signatures() {
+        let Fixture {
+            alice, bob, cets, ..
+        } = fixture();
+        let adaptors = cets
+            .iter()
+            .map(|cet| cet.encrypt_signature(&bob.1.key).unwrap())
+            .collect::<Vec<_>>();
+        for (cet, adaptor) in cets.iter().zip(&adaptors) {
+            cet.verify_signature(&bob.0, adaptor).unwrap();
+            // The adaptor signature is made by Bob, not Alice.
+            assert_eq!(
+                cet.verify_signature(&alice.0, adaptor),
+                Err(AdaptorError::InvalidSignature.into())
+            );
+        }
+
+        // The key does not belong to the contract.
+        let (stranger, stranger_sk) = deterministic_keypair(Network::Testnet);
+        assert_eq!(
+            cets[0].encrypt_signature(&stranger_sk.key),
+            Err(Error::KeyNotInScript)
+        );
+        assert_eq!(
+            cets[0].verify_signature(&stranger, &adaptors[0]),
+            Err(Error::UnknownPublicKey)
+        );
+        // The adaptor signature is encrypted with the attestation point of another outcome.
+        assert_eq!(
+            cets[0].verify_signature(&bob.0, &adaptors[1]),
+            Err(AdaptorError::InvalidProof.into())
+        );
+    }
+
+    #[test]
+    fn test_contract_execution() {
+        let Fixture {
+            oracle_key,
+            oracle_nonce,
+            alice,
+            bob,
+            funding_tx,
+            cets,
+            ..
+        } = fixture();
+        // Bob gives Alice the adaptor signatures for all outcomes.
+        let adaptors = cets
+            .iter()
+            .map(|cet| cet.encrypt_signature(&bob.1.key).unwrap())
+            .collect::<Vec<_>>();
+
+        // The oracle attests "rain", so only the first CET can be completed.
+        let attestation = attest(&oracle_key, &oracle_nonce, b"rain");
+        let signed = cets[0]
+            .finalize(&alice.1.key, &bob.0, &adaptors[0], &attestation)
+            .unwrap();
+        verify_transaction(&signed, &funding_tx.output[..1]).unwrap();
+        assert_eq!(
+            cets[1].finalize(&alice.1.key, &bob.0, &adaptors[1], &attestation),
+            Err(Error::InvalidAttestation)
+        );
+        // The attestation does not decrypt the adaptor signature of another outcome.
+        assert_eq!(
+            cets[0].finalize(&alice.1.key, &bob.0, &adaptors[1], &attestation),
+            Err(secp256k1::Error::IncorrectSignature.into())
+        );
+        // The counterparty key does not match the adaptor signature.
+        assert_eq!(
+            cets[0].finalize(&bob.1.key, &alice.0, &adaptors[0], &attestation),
+            Err(secp256k1::Error::IncorrectSignature.into())
+        );
+
+        // Bob completes the second CET, once the oracle attests "sun".
+        let attestation = attest(&oracle_key, &oracle_nonce, b"sun");
+        let alice_adaptor = cets[1].encrypt_signature(&alice.1.key).unwrap();
+        let signed = cets[1]
+            .finalize(&bob.1.key, &alice.0, &alice_adaptor, &attestation)
+            .unwrap();
+        verify_transaction(&signed, &funding_tx.output[..1]).unwrap();
+    }
+}
@@ -0,0 +1,175 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Scalar and point arithmetic helpers on top of the secp256k1 tweak operations.
+
+use bitcoin_hashes::{sha256, Hash, HashEngine};
+use secp256k1::{
+    constants::CURVE_ORDER,
+    ffi::{self, types::c_void, CPtr},
+    Message, PublicKey, Secp256k1, SecretKey, Signature, Signing,
+};
+
+use crate::sign;
+
+/// Creates a hash engine for the BIP-340 tagged hash with the given tag.
+pub(crate) fn tagged_engine(tag: &str) -> sha256::HashEngine {
+    let tag_hash = sha256::Hash::hash(tag.as_bytes());
+    let mut engine = sha256::Hash::engine();
+    engine.input(&tag_hash[..]);
+    engine.input(&tag_hash[..]);
+    engine
+}
+
+/// Interprets the big-endian bytes as a scalar modulo the curve order.
+pub(crate) fn scalar_from_bytes(bytes: [u8; 32]) -> Result<SecretKey, secp256k1::Error> {
+    SecretKey::from_slice(&reduce_scalar(bytes))
+}
+
+/// Reduces the big-endian bytes modulo the curve order, the result may be zero.
+fn reduce_scalar(mut bytes: [u8; 32]) -> [u8; 32] {
+    if bytes >= CURVE_ORDER {
+        let mut borrow = 0;
+        for (byte, order_byte) in bytes.iter_mut().zip(&CURVE_ORDER).rev() {
+            let diff = i16::from(*byte) - i16::from(*order_byte) - borrow;
+            borrow = if diff < 0 { 1 } else { 0 };
+            *byte = (diff + (borrow << 8)) as u8;
+        }
+    }
+    bytes
+}
+
+/// Reduces the hash modulo the curve order.
+pub(crate) fn scalar_from_hash(hash: sha256::Hash) -> Result<SecretKey, secp256k1::Error> {
+    scalar_from_bytes(hash.into_inner())
+}
+
+pub(crate) fn scalar_one() -> SecretKey {
+    let mut bytes = [0; 32];
+    bytes[31] = 1;
+    SecretKey::from_slice(&bytes).unwrap()
+}
+
+/// Computes the multiplicative inverse of the scalar as `a^(n - 2)`.
+pub(crate) fn invert_scalar(scalar: &SecretKey) -> SecretKey {
+    let mut exponent = CURVE_ORDER;
+    exponent[31] -= 2;
+
+    let mut result = scalar_one();
+    for byte in exponent.iter() {
+        for bit in (0..8).rev() {
+            let square = result;
+            result.mul_assign(&square[..]).unwrap();
+            if byte >> bit & 1 == 1 {
+                result.mul_assign(&scalar[..]).unwrap();
+            }
+        }
+    }
+    result
+}
+
+pub(crate) fn mul_point(
+    mut point: PublicKey,
+    scalar: &SecretKey,
+) -> Result<PublicKey, secp256k1::Error> {
+    point.mul_assign(sign::verification_context(), &scalar[..])?;
+    Ok(point)
+}
+
+/// Adds up the points; fails if the sum is the point at infinity.
+pub(crate) fn sum_points(points: &[PublicKey]) -> Result<PublicKey, secp256k1::Error> {
+    let points = points.iter().collect::<Vec<_>>();
+    PublicKey::combine_keys(&points)
+}
+
+pub(crate) fn has_odd_y(point: &PublicKey) -> bool {
+    point.serialize()[0] == 0x03
+}
+
+/// Signs the message with the `RFC 6979` nonce, which is derived by the
+/// `secp256k1_nonce_function_rfc6979` function of `libsecp256k1` with the given extra data.
+#[allow(unsafe_code)]
+pub(crate) fn sign_with_extra_data<C: Signing>(
+    context: &Secp256k1<C>,
+    message: &Message,
+    secret_key: &SecretKey,
+    extra_data: &[u8; 32],
+) -> Result<Signature, secp256k1::Error> {
+    // Safety: the pointers refer to the valid context, message, secret key and 32 bytes
+    // of the nonce data, which live until the function returns.
+    let signature = unsafe {
+        let mut signature = ffi::Signature::new();
+        let result = ffi::secp256k1_ecdsa_sign(
+            *context.ctx(),
+            &mut signature,
+            message.as_c_ptr(),
+            secret_key.as_c_ptr(),
+            ffi::secp256k1_nonce_function_rfc6979,
+            extra_data.as_ptr() as *const c_void,
+        );
+        if result != 1 {
+            return Err(secp256k1::Error::InvalidSecretKey);
+        }
+        signature
+    };
+    Ok(Signature::from(signature))
+}
+
+#[cfg(test)]
+mod tests {
+    use secp256k1::{Message, SecretKey};
+
+    use super::sign_with_extra_data;
+    use crate::sign;
+
+    #[test]
+    fn test_sign_with_extra_data_known_answers() {
+        // The signatures are produced by `secp256k1_ecdsa_sign` of `libsecp256k1` with
+        // the `RFC 6979` nonce function and the given nonce data.
+        let cases = [
+            (
+                [0x11; 32],
+                [0x22; 32],
+                [0x33; 32],
+                "21b4edf9671dda10effa3fc902e33f45149f16f7a6d0c1dfb5b9bd34b8e3a857\
+                 3e9c68558314375794699c45ec265dd45c81537e9e45692d6531941fccea72a2",
+            ),
+            (
+                [0x01; 32],
+                [0xab; 32],
+                [0x00; 32],
+                "f8274fc518d1ff3de0adee85f712f4bb91dc5181bcd49a7ae6b0f93adf9eeb46\
+                 16fd226fa693931be7c0b034418e4403af733d31163d17fcd2d42302ddd6ccf2",
+            ),
+            (
+                [0x7f; 32],
+                [0xfe; 32],
+                [0xff; 32],
+                "353069841f60c7503a990fe53f62458b16c01a44b16d915a198d2e5be26a3c93\
+                 74d2e3b2a21947390b5341a1f01905289d3c9a175b3013555041b870867d31f5",
+            ),
+        ];
+
+        let context = sign::shared_context();
+        for (secret_key, message, extra_data, expected) in &cases {
+            let secret_key = SecretKey::from_slice(secret_key).unwrap();
+            let message = Message::from_slice(message).unwrap();
+            let signature =
+                sign_with_extra_data(&context, &message, &secret_key, extra_data).unwrap();
+            assert_eq!(hex::encode(&signature.serialize_compact()[..]), *expected);
+            // The zero extra data is mixed into the nonce too.
+            assert_ne!(signature, context.sign(&message, &secret_key));
+        }
+    }
+}
@@ -0,0 +1,344 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A minimal client of the [Electrum protocol][protocol] for the deployments which
+//! do not run a full node.
+//!
+//! The client is able to fetch the unspent outputs which pay to the given script and
+//! to broadcast signed transactions. It is available with the `electrum` feature.
+//!
+//! [protocol]: https://electrumx.readthedocs.io/en/latest/protocol.html
+
+use bitcoin::{
+    blockdata::{
+        script::Script,
+        transaction::{OutPoint, Transaction, TxOut},
+    },
+    consensus,
+    hash_types::Txid,
+};
+use bitcoin_hashes::{hex::FromHex, sha256, Hash};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use thiserror::Error;
+
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+};
+
+use crate::{multisig::RedeemScript, p2wsh, utxo::Utxo, BtcTransaction};
+
+/// Possible errors of the Electrum client.
+#[derive(Debug, Error)]
+pub enum ElectrumError {
+    /// Unable to communicate with the server.
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// The server response is not a correct JSON-RPC message.
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    /// The server returned an error.
+    #[error("The server returned error {code}: {message}")]
+    Server {
+        /// The error code.
+        code: i64,
+        /// The error message.
+        message: String,
+    },
+    /// The server response has an unexpected content.
+    #[error("Unexpected server response: {0}")]
+    Response(String),
+}
+
+#[derive(Debug, Deserialize)]
+struct Response {
+    id: u64,
+    #[serde(default)]
+    result: Value,
+    #[serde(default)]
+    error: Option<ServerError>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ServerError {
+    code: i64,
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct UnspentEntry {
+    tx_hash: String,
+    tx_pos: u32,
+    height: i64,
+    value: u64,
+}
+
+/// The Electrum protocol client over the given duplex stream.
+#[derive(Debug)]
+pub struct ElectrumClient<S: Read + Write = TcpStream> {
+    stream: BufReader<S>,
+    next_id: u64,
+}
+
+impl ElectrumClient<TcpStream> {
+    /// Connects to the Electrum server at the given address via plain TCP.
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> Result<ElectrumClient, ElectrumError> {
+        let stream = TcpStream::connect(addr)?;
+        Ok(ElectrumClient::new(stream))
+    }
+}
+
+impl<S: Read + Write> ElectrumClient<S> {
+    /// Creates a client which communicates with the server over the given stream.
+    pub fn new(stream: S) -> ElectrumClient<S> {
+        ElectrumClient {
+            stream: BufReader::new(stream),
+            next_id: 0,
+        }
+    }
+
+    /// Returns the unspent outputs which pay to the given script pubkey.
+    pub fn list_unspent(
+        &mut self,
+        script_pubkey: &Script,
+    ) -> Result<Vec<(OutPoint, Utxo)>, ElectrumError> {
+        let result = self.call(
+            "blockchain.scripthash.listunspent",
+            json!([script_hash(script_pubkey)]),
+        )?;
+        let entries: Vec<UnspentEntry> = serde_json::from_value(result)?;
+        entries
+            .into_iter()
+            .map(|entry| {
+                let txid = Txid::from_hex(&entry.tx_hash)
+                    .map_err(|_| ElectrumError::Response(entry.tx_hash.clone()))?;
+                let utxo = Utxo {
+                    output: TxOut {
+                        value: entry.value,
+                        script_pubkey: script_pubkey.clone(),
+                    },
+                    // Electrum uses zero and negative heights for the mempool transactions.
+                    height: if entry.height > 0 {
+                        Some(entry.height as u32)
+                    } else {
+                        None
+                    },
+                };
+                Ok((OutPoint::new(txid, entry.tx_pos), utxo))
+            })
+            .collect()
+    }
+
+    /// Returns the unspent outputs which are locked by the given multisig redeem script.
+    pub fn list_multisig_unspent(
+        &mut self,
+        redeem_script: &RedeemScript,
+    ) -> Result<Vec<(OutPoint, Utxo)>, ElectrumError> {
+        self.list_unspent(&p2wsh::script_pubkey(redeem_script))
+    }
+
+    /// Returns the transaction with the given identifier.
+    pub fn transaction(&mut self, txid: &Txid) -> Result<Transaction, ElectrumError> {
+        let result = self.call("blockchain.transaction.get", json!([txid.to_string()]))?;
+        let hex = result
+            .as_str()
+            .ok_or_else(|| ElectrumError::Response(result.to_string()))?;
+        hex.parse::<BtcTransaction>()
+            .map(Transaction::from)
+            .map_err(|_| ElectrumError::Response(hex.to_owned()))
+    }
+
+    /// Broadcasts the given transaction and returns its identifier.
+    pub fn broadcast(&mut self, transaction: &Transaction) -> Result<Txid, ElectrumError> {
+        let hex = hex::encode(consensus::serialize(transaction));
+        let result = self.call("blockchain.transaction.broadcast", json!([hex]))?;
+        result
+            .as_str()
+            .and_then(|txid| Txid::from_hex(txid).ok())
+            .ok_or_else(|| ElectrumError::Response(result.to_string()))
+    }
+
+    /// Returns the underlying stream.
+    pub fn into_inner(self) -> S {
+        self.stream.into_inner()
+    }
+
+    fn call(&mut self, method: &str, params: Value) -> Result<Value, ElectrumError> {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+            "params": params,
+        });
+        let stream = self.stream.get_mut();
+        serde_json::to_writer(&mut *stream, &request)?;
+        stream.write_all(b"\n")?;
+        stream.flush()?;
+
+        let mut line = String::new();
+        if self.stream.read_line(&mut line)? == 0 {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        let response: Response = serde_json::from_str(&line)?;
+        if let Some(error) = response.error {
+            return Err(ElectrumError::Server {
+                code: error.code,
+                message: error.message,
+            });
+        }
+        ensure!(
+            response.id == id,
+            ElectrumError::Response(format!("unexpected response id {}", response.id))
+        );
+        Ok(response.result)
+    }
+}
+
+/// Returns the Electrum script hash of the given script pubkey, that is the reversed
+/// `SHA256` digest in hex.
+pub fn script_hash(script_pubkey: &Script) -> String {
+    let mut hash = sha256::Hash::hash(script_pubkey.as_bytes()).into_inner();
+    hash.reverse();
+    hex::encode(hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::blockdata::transaction::OutPoint;
+
+    use std::io::{self, Cursor, Read, Write};
+
+    use crate::{
+        electrum::{script_hash, ElectrumClient, ElectrumError},
+        multisig::RedeemScript,
+        p2wsh,
+        test_data::btc_tx_from_hex,
+    };
+
+    /// A stream which replays the predefined server responses and records the requests.
+    struct MockStream {
+        responses: Cursor<Vec<u8>>,
+        requests: Vec<u8>,
+    }
+
+    impl MockStream {
+        fn new(responses: &[&str]) -> MockStream {
+            MockStream {
+                responses: Cursor::new((responses.join("\n") + "\n").into_bytes()),
+                requests: Vec::new(),
+            }
+        }
+    }
+
+    impl Read for MockStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.responses.read(buf)
+        }
+    }
+
+    impl Write for MockStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.requests.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_electrum_script_hash() {
+        // The example from the protocol documentation.
+        let script_pubkey = hex::decode("76a91462e907b15cbf27d5425399ebf6f0fb50ebb88f1888ac")
+            .unwrap()
+            .into();
+        assert_eq!(
+            script_hash(&script_pubkey),
+            "8b01df4e368ea28f8dc0423bcf7a4923e3a12d307c875e47a0cfbf90b5c39161"
+        );
+    }
+
+    #[test]
+    fn test_electrum_list_unspent() {
+        let redeem_script: RedeemScript = "5221027db7837e51888e94c094703030d162c682c8dba312210f\
+                                           44ff440fbd5e5c24732102bdd272891c9e4dfc3962b1fdffd5a59\
+                                           732019816f9db4833634dbdaf01a401a52103280883dc31ccaee3\
+                                           4218819aaa245480c35a33acd91283586ff6d1284ed681e52103e\
+                                           2bc790a6e32bf5a766919ff55b1f9e9914e13aed84f502c0e4171\
+                                           976e19deb054ae"
+            .parse()
+            .unwrap();
+        let txid = "4ec6ccef10e4f4a1a2a5ffbcf35e06ed8f3d1b06d1c8c5b1dfcb1b0a5e2fd9d7";
+        let stream = MockStream::new(&[&format!(
+            "{{\"jsonrpc\":\"2.0\",\"id\":0,\"result\":[\
+             {{\"tx_hash\":\"{}\",\"tx_pos\":1,\"height\":437146,\"value\":45318048}},\
+             {{\"tx_hash\":\"{}\",\"tx_pos\":0,\"height\":0,\"value\":1000}}]}}",
+            txid, txid
+        )]);
+        let mut client = ElectrumClient::new(stream);
+
+        let utxos = client.list_multisig_unspent(&redeem_script).unwrap();
+        assert_eq!(utxos.len(), 2);
+        assert_eq!(utxos[0].0, OutPoint::new(txid.parse().unwrap(), 1));
+        assert_eq!(utxos[0].1.height, Some(437_146));
+        assert_eq!(utxos[0].1.output.value, 45_318_048);
+        assert_eq!(
+            utxos[0].1.output.script_pubkey,
+            p2wsh::script_pubkey(&redeem_script)
+        );
+        assert_eq!(utxos[1].1.height, None);
+
+        let request = String::from_utf8(client.into_inner().requests).unwrap();
+        assert!(request.contains("blockchain.scripthash.listunspent"));
+        assert!(request.contains(&script_hash(&p2wsh::script_pubkey(&redeem_script))));
+        assert!(request.ends_with('\n'));
+    }
+
+    #[test]
+    fn test_electrum_broadcast() {
+        let transaction = btc_tx_from_hex(
+            "02000000000101beccab33bc72bfc81b63fdec8a4a9a4719e4418bdb7b20e47b02074dc42f2d800000000\
+             017160014f3b1b3819c1290cd5d675c1319dc7d9d98d571bcfeffffff02dceffa0200000000160014368c\
+             6b7c38f0ff0839bf78d77544da96cb685bf28096980000000000160014284175e336fa10865fb4d1351c9e\
+             18e730f5d6f90247304402207c893c85d75e2230dde04f5a1e2c83c4f0b7d93213372746eb2227b068260d\
+             840220705484b6ec70a8fc0d1f80c3a98079602595351b7a9bca7caddb9a6adb0a3440012103150514f05f\
+             3e3f40c7b404b16f8a09c2c71bad3ba8da5dd1e411a7069cc080a004b91300",
+        );
+        let stream = MockStream::new(&[
+            &format!(
+                "{{\"jsonrpc\":\"2.0\",\"id\":0,\"result\":\"{}\"}}",
+                transaction.txid()
+            ),
+            "{\"jsonrpc\":\"2.0\",\"id\":1,\"error\":{\"code\":1,\"message\":\"missing inputs\"}}",
+        ]);
+        let mut client = ElectrumClient::new(stream);
+
+        assert_eq!(client.broadcast(&transaction).unwrap(), transaction.txid());
+        match client.broadcast(&transaction) {
+            Err(ElectrumError::Server { code, message }) => {
+                assert_eq!(code, 1);
+                assert_eq!(message, "missing inputs");
+            }
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        assert!(matches!(
+            client.broadcast(&transaction),
+            Err(ElectrumError::Io(_))
+        ));
+    }
+}
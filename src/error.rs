@@ -0,0 +1,266 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The error type returned by all fallible operations of this crate.
+
+use bitcoin::{blockdata::transaction::OutPoint, network::constants::Network};
+use thiserror::Error;
+
+use crate::{
+    coinjoin::CoinJoinError, locktime::Sequence, multisig::RedeemScriptError, musig2::MusigError,
+    payjoin::PayjoinError, payload::PayloadError, silent_payments::SilentPaymentError,
+    uri::UriError, AdaptorError, SignatureError, WitnessError,
+};
+
+/// Possible errors of the crate operations.
+///
+/// The detailed errors of the redeem scripts, signatures and witnesses are kept in the separate
+/// enums, and the errors related to the specific transaction input are wrapped into the
+/// [`Input`][input] variant, so the whole chain can be inspected via the `source` method.
+///
+/// [input]: #variant.Input
+#[derive(Debug, Clone, Error, PartialEq)]
+pub enum Error {
+    /// The error is related to the transaction input with the given index.
+    #[error("Input {index} is incorrect.")]
+    Input {
+        /// The input index.
+        index: usize,
+        /// The underlying error.
+        #[source]
+        source: Box<Error>,
+    },
+    /// The redeem script is incorrect.
+    #[error(transparent)]
+    RedeemScript(#[from] RedeemScriptError),
+    /// The adaptor signature operation failed.
+    #[error(transparent)]
+    Adaptor(#[from] AdaptorError),
+    /// The CoinJoin transaction construction failed.
+    #[error(transparent)]
+    CoinJoin(#[from] CoinJoinError),
+    /// The MuSig2 protocol failed.
+    #[error(transparent)]
+    Musig(#[from] MusigError),
+    /// The input signature is incorrect.
+    #[error(transparent)]
+    Signature(#[from] SignatureError),
+    /// The payjoin transaction is incorrect.
+    #[error(transparent)]
+    Payjoin(#[from] PayjoinError),
+    /// The `OP_RETURN` payload is incorrect.
+    #[error(transparent)]
+    Payload(#[from] PayloadError),
+    /// The silent payment address or derivation is incorrect.
+    #[error(transparent)]
+    SilentPayment(#[from] SilentPaymentError),
+    /// The payment URI is incorrect.
+    #[error(transparent)]
+    Uri(#[from] UriError),
+    /// The input witness is incorrect.
+    #[error(transparent)]
+    Witness(#[from] WitnessError),
+    /// The transaction has no input with the given index.
+    #[error("The transaction has no input with index {0}.")]
+    NoSuchInput(usize),
+    /// The transaction has no output with the given index.
+    #[error("The transaction has no output with index {0}.")]
+    NoSuchOutput(usize),
+    /// The output spent by the input is unknown.
+    #[error("The output {0} spent by the input is unknown.")]
+    UnknownOutput(OutPoint),
+    /// The script pubkey of the spent output does not match the signer.
+    #[error("The script pubkey of the spent output does not match the signer.")]
+    ScriptMismatch,
+    /// The script pubkey of the spent output is not supported.
+    #[error("The script pubkey of the spent output is not supported.")]
+    UnsupportedScript,
+    /// The input spending a native segwit output has a non-empty script sig.
+    #[error("The script sig of the input spending a native segwit output must be empty.")]
+    NonEmptyScriptSig,
+    /// The leaf script is not a part of the taproot script tree.
+    #[error("The leaf script is not a part of the taproot script tree.")]
+    UnknownTapLeaf,
+    /// The taproot annex is incorrect.
+    #[error("The taproot annex must follow other witness elements and start with 0x50 byte.")]
+    InvalidAnnex,
+    /// The signature hash type is not allowed.
+    #[error("The signature hash type {0:#04x} is not allowed.")]
+    InvalidSigHashType(u8),
+    /// The private key belongs to the other network.
+    #[error("The private key belongs to the {actual} network instead of {expected}.")]
+    NetworkMismatch {
+        /// The expected network.
+        expected: Network,
+        /// The network of the private key.
+        actual: Network,
+    },
+    /// The signature hash does not match the transaction input.
+    #[error("The signature hash does not match the transaction input.")]
+    SighashMismatch,
+    /// The transaction does not match the `OP_CHECKTEMPLATEVERIFY` template of the spent
+    /// output.
+    #[error("The transaction does not match the template of the spent output.")]
+    TemplateMismatch,
+    /// The radix of the congestion tree is less than two.
+    #[error("The radix {0} of the congestion tree is less than two.")]
+    InvalidRadix(usize),
+    /// The external signer has failed to produce the signature.
+    #[error("The external signer has failed: {0}")]
+    ExternalSigner(String),
+    /// The signing key does not belong to the redeem script participants.
+    #[error("The signing key does not belong to the redeem script participants.")]
+    KeyNotInScript,
+    /// The public key is not a participant of the redeem script.
+    #[error("The public key is not a participant of the redeem script.")]
+    UnknownPublicKey,
+    /// The number of signatures is not equal to the quorum.
+    #[error("Expected {quorum} signatures, but {actual} were given.")]
+    SignaturesCount {
+        /// The number of signatures required by the redeem script.
+        quorum: usize,
+        /// The number of the given signatures.
+        actual: usize,
+    },
+    /// The total weight of the signers does not reach the threshold.
+    #[error("The total weight of the signers is {actual}, but {threshold} is required.")]
+    InsufficientWeight {
+        /// The total weight required by the script.
+        threshold: u16,
+        /// The total weight of the given signers.
+        actual: u32,
+    },
+    /// The signature with the given index is incorrect or does not match the order
+    /// of public keys in the redeem script.
+    #[error("Signature {0} is incorrect or does not match the order of public keys.")]
+    InvalidSignature(usize),
+    /// The signature with the given index is the same as one of the previous signatures.
+    #[error("Signature {0} is a duplicate of one of the previous signatures.")]
+    DuplicateSignature(usize),
+    /// The signature with the given index is made by the same public key as one of
+    /// the previous signatures.
+    #[error("Signature {0} is made by the same public key as one of the previous signatures.")]
+    DuplicatePublicKey(usize),
+    /// The number of spent outputs does not match the number of inputs.
+    #[error("The transaction has {inputs} inputs, but {outputs} spent outputs were given.")]
+    OutputsCount {
+        /// The number of transaction inputs.
+        inputs: usize,
+        /// The number of the given spent outputs.
+        outputs: usize,
+    },
+    /// The number of input descriptions does not match the number of inputs.
+    #[error(
+        "The transaction has {transaction} inputs, but {inputs} input descriptions were given."
+    )]
+    InputsCount {
+        /// The number of transaction inputs.
+        transaction: usize,
+        /// The number of the given input descriptions.
+        inputs: usize,
+    },
+    /// The transaction has no output for the payment with the given index.
+    #[error("The transaction has no output for payment {0}.")]
+    MissingPayment(usize),
+    /// The output with the given index is neither a payment nor a change to the approved script.
+    #[error("Output {0} is neither a payment nor a change to the approved script.")]
+    UnapprovedChange(usize),
+    /// The output with the given index pays to the script, which has no address.
+    #[error("Output {0} pays to the script without an address.")]
+    NoOutputAddress(usize),
+    /// The address of the recipient with the given index belongs to the other network.
+    #[error(
+        "The address of recipient {index} belongs to the {actual} network instead of {expected}."
+    )]
+    RecipientNetwork {
+        /// The index of the recipient.
+        index: usize,
+        /// The expected network.
+        expected: Network,
+        /// The network of the address.
+        actual: Network,
+    },
+    /// The recipient with the given index pays to the same script pubkey as one of
+    /// the previous outputs.
+    #[error("Recipient {0} pays to the same script pubkey as one of the previous outputs.")]
+    DuplicateRecipient(usize),
+    /// The transaction with a single output does not fit into the given weight limit.
+    #[error("The transaction does not fit into the weight limit of {0}.")]
+    TransactionTooLarge(usize),
+    /// There are no outputs to spend.
+    #[error("There are no outputs to spend.")]
+    NoInputs,
+    /// There are no outputs to pay to.
+    #[error("There are no outputs to pay to.")]
+    NoOutputs,
+    /// The balance of spent outputs is not enough to pay for the outputs and the fee.
+    #[error("Insufficient funds: {available} satoshis available, {required} required.")]
+    InsufficientFunds {
+        /// The balance of spent outputs.
+        available: u64,
+        /// The value required to pay for the outputs and the fee.
+        required: u64,
+    },
+    /// The total value of outputs does not fit into 64 bits.
+    #[error("The total value of outputs is too large.")]
+    ValueOverflow,
+    /// The preimage does not match the payment hash of the contract.
+    #[error("The preimage does not match the payment hash.")]
+    PreimageMismatch,
+    /// The oracle attestation does not match the announced nonce or the outcome.
+    #[error("The oracle attestation does not match the announced nonce or outcome.")]
+    InvalidAttestation,
+    /// The unvault transaction is signed before the clawback one is fully signed.
+    #[error("The clawback transaction must be fully signed before the unvault one.")]
+    ClawbackNotSigned,
+    /// The transaction lock time is a block height, while a timestamp is required,
+    /// or vice versa.
+    #[error("The transaction lock time conflicts with the required one.")]
+    LockTimeConflict,
+    /// The value is not a correct lock time of the required kind.
+    #[error("The value {0} is not a correct lock time of the required kind.")]
+    InvalidLockTime(u32),
+    /// The input sequence or the transaction version does not satisfy the relative lock time
+    /// required by `OP_CHECKSEQUENCEVERIFY`.
+    #[error("The input does not satisfy the relative lock time of {0} required by the script.")]
+    RelativeLockTime(Sequence),
+    /// The message signature is not correctly encoded.
+    #[error("Incorrect message signature encoding: {0}")]
+    MessageSignatureEncoding(String),
+    /// The message signature is not made by the owner of the address.
+    #[error("The message signature does not match the address.")]
+    MessageSignatureMismatch,
+    /// The data is not a correct consensus encoding.
+    #[error("Incorrect consensus encoding: {0}")]
+    ConsensusEncoding(String),
+    /// The script assembly contains an unknown token.
+    #[error("Unknown script assembly token `{0}`.")]
+    AsmToken(String),
+    /// The string is not a correct hex.
+    #[error(transparent)]
+    Hex(#[from] hex::FromHexError),
+    /// An error in the secp256k1 engine.
+    #[error(transparent)]
+    Secp256k1(#[from] secp256k1::Error),
+}
+
+impl Error {
+    /// Wraps the error into the context of the transaction input with the given index.
+    pub fn input(index: usize, source: impl Into<Error>) -> Error {
+        Error::Input {
+            index,
+            source: Box::new(source.into()),
+        }
+    }
+}
@@ -0,0 +1,130 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Implementations of the Exonum storage traits, which are available with the `exonum`
+//! feature.
+//!
+//! The values are stored in their canonical binary form: the script bytes for the redeem
+//! script, the DER signature with the sighash type byte for the input signature and
+//! the consensus encoding for the transaction.
+
+use bitcoin::blockdata::script::Script;
+use exonum_crypto::Hash;
+use exonum_merkledb::{BinaryValue, ObjectHash};
+
+use std::borrow::Cow;
+
+use crate::{multisig::RedeemScript, BtcTransaction, InputSignature};
+
+impl BinaryValue for RedeemScript {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.script.to_bytes()
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        self.script.into_bytes()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> anyhow::Result<Self> {
+        let script = Script::from(bytes.into_owned());
+        RedeemScript::from_script(script).map_err(From::from)
+    }
+}
+
+impl BinaryValue for InputSignature {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.as_ref().to_vec()
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        self.into()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> anyhow::Result<Self> {
+        InputSignature::from_bytes(bytes.into_owned()).map_err(From::from)
+    }
+}
+
+impl BinaryValue for BtcTransaction {
+    fn to_bytes(&self) -> Vec<u8> {
+        BtcTransaction::to_bytes(self)
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> anyhow::Result<Self> {
+        BtcTransaction::from_bytes(bytes.as_ref()).map_err(From::from)
+    }
+}
+
+macro_rules! impl_object_hash {
+    ($($type:ty),*) => {
+        $(
+            impl ObjectHash for $type {
+                fn object_hash(&self) -> Hash {
+                    exonum_crypto::hash(&BinaryValue::to_bytes(self))
+                }
+            }
+        )*
+    };
+}
+
+impl_object_hash! { RedeemScript, InputSignature, BtcTransaction }
+
+#[cfg(test)]
+mod tests {
+    use exonum_merkledb::{BinaryValue, ObjectHash};
+
+    use std::{borrow::Cow, fmt::Debug};
+
+    use crate::{multisig::RedeemScript, BtcTransaction, InputSignature};
+
+    fn assert_roundtrip<T: BinaryValue + ObjectHash + PartialEq + Debug + Clone>(value: T) {
+        let bytes = value.to_bytes();
+        assert_eq!(value.clone().into_bytes(), bytes);
+        assert_eq!(value.object_hash(), exonum_crypto::hash(&bytes));
+        assert_eq!(T::from_bytes(Cow::Owned(bytes)).unwrap(), value);
+    }
+
+    #[test]
+    fn test_binary_value_roundtrip() {
+        let redeem_script: RedeemScript = "5221027db7837e51888e94c094703030d162c682c8dba312210f\
+                                           44ff440fbd5e5c24732102bdd272891c9e4dfc3962b1fdffd5a59\
+                                           732019816f9db4833634dbdaf01a401a52103280883dc31ccaee3\
+                                           4218819aaa245480c35a33acd91283586ff6d1284ed681e52103e\
+                                           2bc790a6e32bf5a766919ff55b1f9e9914e13aed84f502c0e4171\
+                                           976e19deb054ae"
+            .parse()
+            .unwrap();
+        let signature: InputSignature = "304402207c893c85d75e2230dde04f5a1e2c83c4f0b7d93213372\
+                                         746eb2227b068260d840220705484b6ec70a8fc0d1f80c3a98079\
+                                         602595351b7a9bca7caddb9a6adb0a344001"
+            .parse()
+            .unwrap();
+        let transaction: BtcTransaction =
+            "02000000000101beccab33bc72bfc81b63fdec8a4a9a4719e4418bdb7b20e47b02074dc42f2d800000000\
+             017160014f3b1b3819c1290cd5d675c1319dc7d9d98d571bcfeffffff02dceffa0200000000160014368c\
+             6b7c38f0ff0839bf78d77544da96cb685bf28096980000000000160014284175e336fa10865fb4d1351c9e\
+             18e730f5d6f90247304402207c893c85d75e2230dde04f5a1e2c83c4f0b7d93213372746eb2227b068260d\
+             840220705484b6ec70a8fc0d1f80c3a98079602595351b7a9bca7caddb9a6adb0a3440012103150514f05f\
+             3e3f40c7b404b16f8a09c2c71bad3ba8da5dd1e411a7069cc080a004b91300"
+                .parse()
+                .unwrap();
+
+        assert_roundtrip(redeem_script);
+        assert_roundtrip(signature);
+        assert_roundtrip(transaction);
+
+        assert!(<RedeemScript as BinaryValue>::from_bytes(Cow::Borrowed(&[0x51])).is_err());
+        assert!(<InputSignature as BinaryValue>::from_bytes(Cow::Borrowed(&[])).is_err());
+    }
+}
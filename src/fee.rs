@@ -0,0 +1,130 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The fee rate with the same units and rounding rules as in Bitcoin Core.
+
+use serde::{Deserialize, Serialize};
+
+use std::fmt;
+
+/// The fee rate, which is kept in satoshis per 1000 virtual bytes, like Bitcoin Core does.
+///
+/// The fee for the given size is rounded up, so the fee computed by this type is never
+/// below the one required by Bitcoin Core for the same rate.
+#[derive(
+    Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+pub struct FeeRate(u64);
+
+impl FeeRate {
+    /// The zero fee rate.
+    pub const ZERO: FeeRate = FeeRate(0);
+
+    /// Creates the fee rate from satoshis per 1000 virtual bytes.
+    pub const fn from_sat_per_kvb(sat_per_kvb: u64) -> FeeRate {
+        FeeRate(sat_per_kvb)
+    }
+
+    /// Creates the fee rate from satoshis per virtual byte.
+    /// Returns `None` on overflow.
+    pub fn from_sat_per_vb(sat_per_vb: u64) -> Option<FeeRate> {
+        sat_per_vb.checked_mul(1_000).map(FeeRate)
+    }
+
+    /// Creates the fee rate from satoshis per 1000 weight units.
+    /// Returns `None` on overflow.
+    pub fn from_sat_per_kwu(sat_per_kwu: u64) -> Option<FeeRate> {
+        sat_per_kwu.checked_mul(4).map(FeeRate)
+    }
+
+    /// Returns the fee rate in satoshis per 1000 virtual bytes.
+    pub fn to_sat_per_kvb(self) -> u64 {
+        self.0
+    }
+
+    /// Returns the fee rate in satoshis per virtual byte, rounded down.
+    pub fn to_sat_per_vb_floor(self) -> u64 {
+        self.0 / 1_000
+    }
+
+    /// Returns the fee rate in satoshis per virtual byte, rounded up.
+    pub fn to_sat_per_vb_ceil(self) -> u64 {
+        self.0 / 1_000 + u64::from(self.0 % 1_000 != 0)
+    }
+
+    /// Returns the fee rate in satoshis per 1000 weight units, rounded down.
+    pub fn to_sat_per_kwu(self) -> u64 {
+        self.0 / 4
+    }
+
+    /// Computes the fee for the given virtual size. Returns `None` on overflow.
+    pub fn fee_vb(self, vsize: u64) -> Option<u64> {
+        self.0
+            .checked_mul(vsize)
+            .and_then(|fee_per_kvb| fee_per_kvb.checked_add(999))
+            .map(|fee_per_kvb| fee_per_kvb / 1_000)
+    }
+
+    /// Computes the fee for the given weight. Returns `None` on overflow.
+    ///
+    /// The weight is rounded up to the virtual bytes first, as Bitcoin Core does.
+    pub fn fee_wu(self, weight: u64) -> Option<u64> {
+        self.fee_vb(weight / 4 + u64::from(weight % 4 != 0))
+    }
+}
+
+impl fmt::Display for FeeRate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{:03} sat/vB", self.0 / 1_000, self.0 % 1_000)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FeeRate;
+
+    #[test]
+    fn test_fee_rate_units() {
+        let fee_rate = FeeRate::from_sat_per_vb(5).unwrap();
+        assert_eq!(fee_rate.to_sat_per_kvb(), 5_000);
+        assert_eq!(fee_rate.to_sat_per_kwu(), 1_250);
+        assert_eq!(FeeRate::from_sat_per_kwu(1_250), Some(fee_rate));
+        assert_eq!(fee_rate.to_string(), "5.000 sat/vB");
+
+        let fee_rate = FeeRate::from_sat_per_kvb(1_001);
+        assert_eq!(fee_rate.to_sat_per_vb_floor(), 1);
+        assert_eq!(fee_rate.to_sat_per_vb_ceil(), 2);
+        assert_eq!(fee_rate.to_string(), "1.001 sat/vB");
+
+        assert_eq!(FeeRate::from_sat_per_vb(u64::MAX), None);
+        assert_eq!(FeeRate::from_sat_per_kwu(u64::MAX), None);
+    }
+
+    #[test]
+    fn test_fee_rate_rounding() {
+        let fee_rate = FeeRate::from_sat_per_kvb(1_001);
+        // The fee is rounded up.
+        assert_eq!(fee_rate.fee_vb(0), Some(0));
+        assert_eq!(fee_rate.fee_vb(1), Some(2));
+        assert_eq!(fee_rate.fee_vb(1_000), Some(1_001));
+        // The weight is rounded up to the virtual bytes first.
+        assert_eq!(fee_rate.fee_wu(4_000), Some(1_001));
+        assert_eq!(fee_rate.fee_wu(3_997), Some(1_001));
+        assert_eq!(fee_rate.fee_wu(4_001), Some(1_003));
+
+        assert_eq!(FeeRate::ZERO.fee_vb(1_000), Some(0));
+        assert_eq!(FeeRate::from_sat_per_kvb(u64::MAX).fee_vb(2), None);
+    }
+}
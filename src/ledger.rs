@@ -0,0 +1,611 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The signer backed by the Ledger hardware wallet with the Bitcoin application.
+//!
+//! The device does not sign the arbitrary signature hashes, instead it hashes the whole
+//! transaction itself and shows the outputs to the user, so the signing uses the segwit
+//! flow of the application protocol:
+//!
+//! 1. All inputs with the spent values are sent to the device.
+//! 2. The outputs are sent to the device and confirmed by the user.
+//! 3. Each input is sent again with its script code and signed.
+//!
+//! The device is accessed via the [`LedgerTransport`][transport] trait, which is implemented
+//! for the HID transport of the `ledger-transport-hid` crate and can be implemented with
+//! the TCP connection to the emulator.
+//!
+//! The [`LedgerSigner`][signer] implements the [`ExternalSigner`][external-signer] for
+//! the inputs of the transaction shown to it, so it can be used with the input signers.
+//!
+//! [transport]: trait.LedgerTransport.html
+//! [signer]: struct.LedgerSigner.html
+//! [external-signer]: ../trait.ExternalSigner.html
+
+use bitcoin::{
+    blockdata::{
+        script::Script,
+        transaction::{SigHashType, Transaction},
+    },
+    consensus::{self, encode::VarInt},
+    network::constants::Network,
+    util::{address::Address, bip32::DerivationPath},
+    PublicKey,
+};
+use ledger_apdu::APDUCommand;
+use ledger_transport_hid::TransportNativeHID;
+
+use std::{cell::RefCell, convert::TryFrom};
+
+use crate::{multisig::RedeemScript, sign, Amount, Error, ExternalSigner, InputSignature, TxInRef};
+
+/// The class of the Bitcoin application commands.
+const CLA: u8 = 0xE0;
+/// Returns the public key for the given derivation path.
+const INS_GET_WALLET_PUBLIC_KEY: u8 = 0x40;
+/// Hashes the transaction version and inputs.
+const INS_HASH_INPUT_START: u8 = 0x44;
+/// Hashes the transaction outputs.
+const INS_HASH_INPUT_FINALIZE_FULL: u8 = 0x4A;
+/// Signs the hashed transaction.
+const INS_HASH_SIGN: u8 = 0x48;
+/// The status word of the successful command.
+const SW_OK: u16 = 0x9000;
+/// The maximum size of the outputs chunk.
+const OUTPUTS_CHUNK_SIZE: usize = 50;
+/// The maximum size of the script chunk, which leaves room for the input sequence.
+const SCRIPT_CHUNK_SIZE: usize = 251;
+
+/// The transport, which sends the application commands to the device.
+pub trait LedgerTransport {
+    /// Sends the given command to the device and returns the response data followed
+    /// by the two-byte status word.
+    fn exchange(&self, command: &[u8]) -> Result<Vec<u8>, Error>;
+}
+
+impl LedgerTransport for TransportNativeHID {
+    fn exchange(&self, command: &[u8]) -> Result<Vec<u8>, Error> {
+        // The command header consists of the class, the instruction, two parameters
+        // and the data length.
+        ensure!(
+            command.len() >= 5,
+            Error::ExternalSigner("The command has no header.".to_owned())
+        );
+        let command = APDUCommand {
+            cla: command[0],
+            ins: command[1],
+            p1: command[2],
+            p2: command[3],
+            data: &command[5..],
+        };
+        let answer = TransportNativeHID::exchange(self, &command)
+            .map_err(|error| Error::ExternalSigner(error.to_string()))?;
+        let mut response = answer.data().to_vec();
+        response.extend_from_slice(&answer.retcode().to_be_bytes());
+        Ok(response)
+    }
+}
+
+/// The transaction input to be signed by the device.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LedgerInput {
+    /// The value of the spent output.
+    pub value: Amount,
+    /// The script code of the input, which is used in the signature hash.
+    pub script_code: Script,
+    /// The derivation path of the signing key.
+    pub path: DerivationPath,
+}
+
+impl LedgerInput {
+    /// Creates the input, which spends the `P2WPK` output of the given public key.
+    pub fn p2wpk(public_key: &PublicKey, value: Amount, path: DerivationPath) -> LedgerInput {
+        // The network does not affect the script.
+        let script_code = Address::p2pkh(public_key, Network::Bitcoin).script_pubkey();
+        LedgerInput {
+            value,
+            script_code,
+            path,
+        }
+    }
+
+    /// Creates the input, which spends the `P2WSH` output of the given redeem script.
+    pub fn p2wsh(redeem_script: &RedeemScript, value: Amount, path: DerivationPath) -> LedgerInput {
+        LedgerInput {
+            value,
+            script_code: redeem_script.script.clone(),
+            path,
+        }
+    }
+}
+
+/// The signer, which uses the key of the Ledger device with the given derivation path.
+///
+/// The signer implements the [`ExternalSigner`][external-signer] for the inputs of the
+/// transaction given to the [`set_transaction`][set-transaction] method. The device signs
+/// all inputs at the first signature hash request, and the returned signatures are reused
+/// for the other inputs. Other signature hashes are rejected with the
+/// [`Error::SighashMismatch`][sighash-mismatch].
+///
+/// [external-signer]: ../trait.ExternalSigner.html
+/// [set-transaction]: #method.set_transaction
+/// [sighash-mismatch]: ../enum.Error.html#variant.SighashMismatch
+#[derive(Debug)]
+pub struct LedgerSigner<T> {
+    transport: T,
+    public_key: PublicKey,
+    transaction: Option<(Transaction, Vec<LedgerInput>)>,
+    signatures: RefCell<Vec<InputSignature>>,
+}
+
+impl<T: LedgerTransport> LedgerSigner<T> {
+    /// Creates a signer, which uses the given transport and the key with the given
+    /// derivation path.
+    pub fn new(transport: T, path: &DerivationPath) -> Result<LedgerSigner<T>, Error> {
+        let public_key = device_public_key(&transport, path)?;
+        Ok(LedgerSigner {
+            transport,
+            public_key,
+            transaction: None,
+            signatures: RefCell::default(),
+        })
+    }
+
+    /// Returns the compressed public key for the given derivation path.
+    pub fn derive_public_key(&self, path: &DerivationPath) -> Result<PublicKey, Error> {
+        device_public_key(&self.transport, path)
+    }
+
+    /// Sets the transaction, which inputs are signed via the
+    /// [`ExternalSigner`][external-signer] interface. The inputs are given in the transaction
+    /// input order.
+    ///
+    /// [external-signer]: ../trait.ExternalSigner.html
+    pub fn set_transaction(
+        &mut self,
+        transaction: Transaction,
+        inputs: Vec<LedgerInput>,
+    ) -> Result<&mut Self, Error> {
+        check_inputs(&transaction, &inputs)?;
+        self.transaction = Some((transaction, inputs));
+        self.signatures.borrow_mut().clear();
+        Ok(self)
+    }
+
+    /// Signs all inputs of the given transaction, the inputs are given in the transaction
+    /// input order. The device asks the user to confirm the outputs.
+    ///
+    /// The returned signatures are verified against the public keys of the given paths.
+    pub fn sign_inputs(
+        &self,
+        transaction: &Transaction,
+        inputs: &[LedgerInput],
+    ) -> Result<Vec<InputSignature>, Error> {
+        check_inputs(transaction, inputs)?;
+        self.hash_inputs(transaction, inputs, None)?;
+        let outputs = consensus::serialize(&transaction.output);
+        let chunks = outputs.chunks(OUTPUTS_CHUNK_SIZE).collect::<Vec<_>>();
+        for (index, chunk) in chunks.iter().enumerate() {
+            let p1 = if index + 1 == chunks.len() {
+                0x80
+            } else {
+                0x00
+            };
+            self.exchange(INS_HASH_INPUT_FINALIZE_FULL, p1, 0x00, chunk)?;
+        }
+
+        inputs
+            .iter()
+            .enumerate()
+            .map(|(index, input)| {
+                self.sign_input(transaction, index, input)
+                    .map_err(|error| Error::input(index, error))
+            })
+            .collect()
+    }
+
+    fn sign_input(
+        &self,
+        transaction: &Transaction,
+        index: usize,
+        input: &LedgerInput,
+    ) -> Result<InputSignature, Error> {
+        self.hash_inputs(transaction, std::slice::from_ref(input), Some(index))?;
+
+        let mut data = encode_path(&input.path);
+        // The empty user validation code.
+        data.push(0x00);
+        data.extend_from_slice(&transaction.lock_time.to_be_bytes());
+        data.push(SigHashType::All as u8);
+        let mut signature = self.exchange(INS_HASH_SIGN, 0x00, 0x00, &data)?;
+        // The newer versions of the application put the parity of `R` into the first byte.
+        *signature.first_mut().ok_or_else(malformed_response)? = 0x30;
+        let signature = InputSignature::from_bytes(signature)?;
+
+        let public_key = self.derive_public_key(&input.path)?;
+        sign::verify_input_signature(
+            sign::verification_context(),
+            TxInRef::new(transaction, index),
+            &input.script_code,
+            input.value.as_sat(),
+            &public_key,
+            &signature,
+        )?;
+        Ok(signature)
+    }
+
+    /// Sends the transaction version and the given inputs. If the index is `None`,
+    /// the new transaction is started, otherwise only the input with the given index
+    /// is sent with its script code.
+    fn hash_inputs(
+        &self,
+        transaction: &Transaction,
+        inputs: &[LedgerInput],
+        index: Option<usize>,
+    ) -> Result<(), Error> {
+        let p2 = if index.is_none() { 0x02 } else { 0x80 };
+        let mut data = transaction.version.to_le_bytes().to_vec();
+        data.extend(consensus::serialize(&VarInt(inputs.len() as u64)));
+        self.exchange(INS_HASH_INPUT_START, 0x00, p2, &data)?;
+
+        for (position, input) in inputs.iter().enumerate() {
+            let input_index = index.unwrap_or(position);
+            let txin = &transaction.input[input_index];
+            let script: &[u8] = if index.is_some() || position == 0 {
+                input.script_code.as_bytes()
+            } else {
+                &[]
+            };
+            let sequence = txin.sequence.to_le_bytes();
+
+            // The segwit input with the spent value.
+            let mut data = vec![0x02];
+            data.extend(consensus::serialize(&txin.previous_output));
+            data.extend_from_slice(&input.value.as_sat().to_le_bytes());
+            data.extend(consensus::serialize(&VarInt(script.len() as u64)));
+            if script.is_empty() {
+                data.extend_from_slice(&sequence);
+            }
+            self.exchange(INS_HASH_INPUT_START, 0x80, 0x00, &data)?;
+
+            let chunks = script.chunks(SCRIPT_CHUNK_SIZE).collect::<Vec<_>>();
+            for (chunk_index, chunk) in chunks.iter().enumerate() {
+                let mut data = chunk.to_vec();
+                if chunk_index + 1 == chunks.len() {
+                    data.extend_from_slice(&sequence);
+                }
+                self.exchange(INS_HASH_INPUT_START, 0x80, 0x00, &data)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn exchange(&self, ins: u8, p1: u8, p2: u8, data: &[u8]) -> Result<Vec<u8>, Error> {
+        exchange(&self.transport, ins, p1, p2, data)
+    }
+}
+
+impl<T: LedgerTransport> ExternalSigner for LedgerSigner<T> {
+    fn public_key(&self) -> PublicKey {
+        self.public_key
+    }
+
+    fn sign_sighash(&self, sighash: &[u8; 32]) -> Result<Vec<u8>, Error> {
+        let (transaction, inputs) = self.transaction.as_ref().ok_or(Error::SighashMismatch)?;
+        let index = inputs
+            .iter()
+            .enumerate()
+            .position(|(index, input)| {
+                let txin = TxInRef::new(transaction, index);
+                let hash = sign::signature_hash(txin, &input.script_code, input.value);
+                hash[..] == sighash[..]
+            })
+            .ok_or(Error::SighashMismatch)?;
+
+        let mut signatures = self.signatures.borrow_mut();
+        // The transaction has at least one input, so the empty list means that the device
+        // has not signed the transaction yet.
+        if signatures.is_empty() {
+            *signatures = self.sign_inputs(transaction, inputs)?;
+        }
+        Ok(signatures[index].content().to_vec())
+    }
+}
+
+fn check_inputs(transaction: &Transaction, inputs: &[LedgerInput]) -> Result<(), Error> {
+    ensure!(
+        transaction.input.len() == inputs.len(),
+        Error::InputsCount {
+            transaction: transaction.input.len(),
+            inputs: inputs.len(),
+        }
+    );
+    ensure!(!inputs.is_empty(), Error::NoInputs);
+    Ok(())
+}
+
+fn device_public_key<T: LedgerTransport>(
+    transport: &T,
+    path: &DerivationPath,
+) -> Result<PublicKey, Error> {
+    let response = exchange(
+        transport,
+        INS_GET_WALLET_PUBLIC_KEY,
+        0x00,
+        0x00,
+        &encode_path(path),
+    )?;
+    let key_len = usize::from(*response.first().ok_or_else(malformed_response)?);
+    let key = response.get(1..=key_len).ok_or_else(malformed_response)?;
+    let key = secp256k1::PublicKey::from_slice(key)?;
+    Ok(PublicKey {
+        compressed: true,
+        key,
+    })
+}
+
+fn exchange<T: LedgerTransport>(
+    transport: &T,
+    ins: u8,
+    p1: u8,
+    p2: u8,
+    data: &[u8],
+) -> Result<Vec<u8>, Error> {
+    let len = u8::try_from(data.len()).map_err(|_| {
+        Error::ExternalSigner(format!(
+            "The command data of {} bytes does not fit into the APDU.",
+            data.len()
+        ))
+    })?;
+    let mut command = vec![CLA, ins, p1, p2, len];
+    command.extend_from_slice(data);
+    let mut response = transport.exchange(&command)?;
+    ensure!(response.len() >= 2, malformed_response());
+    let status = response.split_off(response.len() - 2);
+    let status = u16::from_be_bytes([status[0], status[1]]);
+    ensure!(
+        status == SW_OK,
+        Error::ExternalSigner(format!("The device returned status {:#06x}.", status))
+    );
+    Ok(response)
+}
+
+fn encode_path(path: &DerivationPath) -> Vec<u8> {
+    let path = path.as_ref();
+    let mut bytes = Vec::with_capacity(1 + path.len() * 4);
+    bytes.push(path.len() as u8);
+    for child in path {
+        bytes.extend_from_slice(&u32::from(*child).to_be_bytes());
+    }
+    bytes
+}
+
+fn malformed_response() -> Error {
+    Error::ExternalSigner("The device returned the malformed response.".to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{
+        blockdata::{
+            script::Script,
+            transaction::{OutPoint, Transaction, TxIn, TxOut},
+        },
+        network::constants::Network,
+        util::bip32::{ChildNumber, DerivationPath},
+    };
+    use bitcoin_hashes::Hash;
+
+    use std::{cell::RefCell, collections::VecDeque, str::FromStr};
+
+    use super::{LedgerInput, LedgerSigner, LedgerTransport};
+    use crate::{
+        multisig::RedeemScriptBuilder, p2wpk, p2wsh, sign, test_data::keypair_from_wif, Amount,
+        Error, ExternalSigner, InputSignature, TxInRef,
+    };
+
+    /// The transport, which records the commands and replies with the prepared responses.
+    #[derive(Debug, Default)]
+    struct TestTransport {
+        commands: RefCell<Vec<Vec<u8>>>,
+        responses: RefCell<VecDeque<Vec<u8>>>,
+    }
+
+    impl TestTransport {
+        fn reply(&self, mut data: Vec<u8>) {
+            data.extend_from_slice(&[0x90, 0x00]);
+            self.responses.borrow_mut().push_back(data);
+        }
+    }
+
+    impl LedgerTransport for &TestTransport {
+        fn exchange(&self, command: &[u8]) -> Result<Vec<u8>, Error> {
+            self.commands.borrow_mut().push(command.to_vec());
+            Ok(self
+                .responses
+                .borrow_mut()
+                .pop_front()
+                .unwrap_or_else(|| vec![0x90, 0x00]))
+        }
+    }
+
+    /// Returns the transaction with the `P2WSH` and `P2WPK` inputs, their descriptions,
+    /// the signatures made by the device key and the device response with this key.
+    fn sign_fixture() -> (Transaction, Vec<LedgerInput>, Vec<InputSignature>, Vec<u8>) {
+        let keypairs = vec![
+            "cPHmynxvqfr7sXsJcohiGzoPGBShggxL6VWUdW14skohFZ1LQoeV",
+            "cTtSTL1stvg2tmK349WTmQDfHLMLqkkxwuo8ZJeQov9zEhtYtb4u",
+        ]
+        .into_iter()
+        .map(keypair_from_wif)
+        .collect::<Vec<_>>();
+        let redeem_script = RedeemScriptBuilder::with_public_keys(keypairs.iter().map(|x| x.0))
+            .quorum(1)
+            .to_script()
+            .unwrap();
+        let transaction = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: (0..2)
+                .map(|vout| TxIn {
+                    previous_output: OutPoint::new(Default::default(), vout),
+                    script_sig: Script::default(),
+                    sequence: 0xFFFF_FFFD,
+                    witness: Vec::default(),
+                })
+                .collect(),
+            output: vec![TxOut {
+                value: 10_000,
+                script_pubkey: p2wpk::script_pubkey(&keypairs[0].0),
+            }],
+        };
+        let path = DerivationPath::from_str("m/84'/1'/0'/0/0").unwrap();
+        let inputs = vec![
+            LedgerInput::p2wsh(&redeem_script, Amount::from_sat(20_000), path.clone()),
+            LedgerInput::p2wpk(&keypairs[0].0, Amount::from_sat(30_000), path),
+        ];
+
+        let mut p2wsh_signer = p2wsh::InputSigner::new(redeem_script);
+        let mut p2wpk_signer = p2wpk::InputSigner::new(keypairs[0].0, Network::Testnet);
+        let signatures = vec![
+            p2wsh_signer
+                .sign_input(TxInRef::new(&transaction, 0), 20_000, &keypairs[0].1.key)
+                .unwrap(),
+            p2wpk_signer
+                .sign_input(TxInRef::new(&transaction, 1), 30_000, &keypairs[0].1.key)
+                .unwrap(),
+        ];
+        let mut public_key_response = vec![65];
+        public_key_response.extend_from_slice(&keypairs[0].0.key.serialize_uncompressed());
+        public_key_response.push(0);
+        public_key_response.extend_from_slice(&[0; 32]);
+        (transaction, inputs, signatures, public_key_response)
+    }
+
+    /// Prepares the device responses to the signing of the fixture transaction.
+    fn reply_signatures(
+        transport: &TestTransport,
+        signatures: &[InputSignature],
+        public_key_response: &[u8],
+    ) {
+        // The transaction start, two inputs with the first script and the outputs.
+        for _ in 0..5 {
+            transport.reply(Vec::default());
+        }
+        for signature in signatures {
+            // The input start, the input, its script and the signature.
+            for _ in 0..3 {
+                transport.reply(Vec::default());
+            }
+            let mut device_signature = signature.as_ref().to_vec();
+            device_signature[0] = 0x31;
+            transport.reply(device_signature);
+            transport.reply(public_key_response.to_vec());
+        }
+    }
+
+    #[test]
+    fn test_ledger_sign_inputs() {
+        let (transaction, inputs, expected, public_key_response) = sign_fixture();
+        let transport = TestTransport::default();
+        transport.reply(public_key_response.clone());
+        reply_signatures(&transport, &expected, &public_key_response);
+
+        let path = DerivationPath::from_str("m/84'/1'/0'/0/0").unwrap();
+        let signer = LedgerSigner::new(&transport, &path).unwrap();
+        assert_eq!(
+            signer.sign_inputs(&transaction, &inputs[..1]).unwrap_err(),
+            Error::InputsCount {
+                transaction: 2,
+                inputs: 1,
+            }
+        );
+        let signatures = signer.sign_inputs(&transaction, &inputs).unwrap();
+        assert_eq!(signatures, expected);
+
+        let commands = transport.commands.borrow();
+        assert_eq!(commands.len(), 16);
+        // The transaction start with the version and the number of inputs.
+        assert_eq!(commands[1], vec![0xE0, 0x44, 0x00, 0x02, 5, 2, 0, 0, 0, 2]);
+        // The outputs.
+        assert_eq!(&commands[5][..4], &[0xE0, 0x4A, 0x80, 0x00]);
+        // The signature request with the path, the lock time and the sighash type.
+        let sign_command = &commands[9];
+        assert_eq!(&sign_command[..5], &[0xE0, 0x48, 0x00, 0x00, 27]);
+        assert_eq!(sign_command[5], 5);
+        assert_eq!(&sign_command[26..], &[0, 0, 0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn test_ledger_external_signer() {
+        let (transaction, inputs, expected, public_key_response) = sign_fixture();
+        let transport = TestTransport::default();
+        transport.reply(public_key_response.clone());
+        reply_signatures(&transport, &expected, &public_key_response);
+
+        let path = DerivationPath::from_str("m/84'/1'/0'/0/0").unwrap();
+        let mut signer = LedgerSigner::new(&transport, &path).unwrap();
+        let public_key = ExternalSigner::public_key(&signer);
+        let txin = TxInRef::new(&transaction, 1);
+        // The signer does not sign the inputs of the unknown transaction.
+        assert_eq!(
+            signer.sign_sighash(&[0; 32]).unwrap_err(),
+            Error::SighashMismatch
+        );
+        let script_code = inputs[0].script_code.clone();
+        signer.set_transaction(transaction.clone(), inputs).unwrap();
+        assert_eq!(
+            signer.sign_sighash(&[0; 32]).unwrap_err(),
+            Error::SighashMismatch
+        );
+        assert_eq!(transport.commands.borrow().len(), 1);
+
+        let signature = p2wpk::InputSigner::new(public_key, Network::Testnet)
+            .sign_input_external(txin, 30_000, &signer)
+            .unwrap();
+        assert_eq!(signature, expected[1]);
+        // The device is asked once for all inputs.
+        assert_eq!(transport.commands.borrow().len(), 16);
+        let sighash = sign::signature_hash(TxInRef::new(&transaction, 0), &script_code, 20_000);
+        assert_eq!(
+            signer.sign_sighash(&sighash.into_inner()).unwrap(),
+            expected[0].content()
+        );
+        assert_eq!(transport.commands.borrow().len(), 16);
+    }
+
+    #[test]
+    fn test_ledger_status_error() {
+        let transport = TestTransport::default();
+        transport.responses.borrow_mut().push_back(vec![0x69, 0x85]);
+        let path = DerivationPath::from_str("m/84'/0'/0'").unwrap();
+        assert_eq!(
+            LedgerSigner::new(&transport, &path).unwrap_err(),
+            Error::ExternalSigner("The device returned status 0x6985.".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_ledger_command_too_long() {
+        let transport = TestTransport::default();
+        let path = (0..64).map(ChildNumber::from).collect::<Vec<_>>().into();
+        assert_eq!(
+            LedgerSigner::new(&transport, &path).unwrap_err(),
+            Error::ExternalSigner(
+                "The command data of 257 bytes does not fit into the APDU.".to_owned()
+            )
+        );
+        assert!(transport.commands.borrow().is_empty());
+    }
+}
@@ -20,12 +20,15 @@
 //! - [Create][redeem-script] a redeem script and a corresponding multisig address (3 of 4).
 //! - [Sign][p2wpk] the `P2WPK` inputs.
 //! - [Sign][p2wsh] the `P2WSH` inputs.
+//! - [Create][builder] unsigned transactions which spend the multisig outputs.
 //!
 //! # Examples
 //!
 //! ## Create a redeem script and a corresponding multisig address (3 of 4).
 //!
 //! ```
+//! # #[cfg(feature = "random")]
+//! # fn main() {
 //! use bitcoin::network::constants::Network;
 //! use btc_transaction_utils::multisig::RedeemScriptBuilder;
 //! use btc_transaction_utils::test_data::secp_gen_keypair;
@@ -44,11 +47,16 @@
 //! // Create a corresponding testnet address for the given redeem script.
 //! let address = p2wsh::address(&script, Network::Testnet);
 //! println!("{}", address.to_string());
+//! # }
+//! # #[cfg(not(feature = "random"))]
+//! # fn main() {}
 //! ```
 //!
 //! ## Sign P2WPK input
 //!
 //! ```
+//! # #[cfg(feature = "random")]
+//! # fn main() {
 //! use bitcoin::{
 //!     blockdata::opcodes::all::OP_RETURN,
 //!     blockdata::script::{Builder, Script},
@@ -101,18 +109,25 @@
 //!         },
 //!     ],
 //! };
-//! // Create a signature for the given input.
+//! // Create a signature for the given input. The example key pair does not own the output,
+//! // so only its value is passed, otherwise the signer would reject the output script.
+//! let prev_value = prev_tx.output[1].value;
 //! let mut signer = p2wpk::InputSigner::new(keypair.0, Network::Testnet);
 //! let signature = signer
-//!     .sign_input(TxInRef::new(&transaction, 0), &prev_tx, &keypair.1.key)
+//!     .sign_input(TxInRef::new(&transaction, 0), prev_value, &keypair.1.key)
 //!     .unwrap();
 //! // Finalize the transaction.
 //! signer.spend_input(&mut transaction.input[0], signature);
+//! # }
+//! # #[cfg(not(feature = "random"))]
+//! # fn main() {}
 //! ```
 //!
 //! ## Sign P2WSH input
 //!
 //! ```
+//! # #[cfg(feature = "random")]
+//! # fn main() {
 //! use bitcoin::{
 //!     blockdata::opcodes::all::OP_RETURN,
 //!     blockdata::script::{Builder, Script},
@@ -176,22 +191,30 @@
 //!         },
 //!     ],
 //! };
-//! // Create signatures for the given input.
+//! // Create signatures for the given input. The example redeem script does not lock
+//! // the output, so only its value is passed, otherwise the signer would reject the output script.
+//! let prev_value = prev_tx.output[1].value;
 //! let mut signer = p2wsh::InputSigner::new(redeem_script.clone());
 //! let signatures = keypairs[0..quorum]
 //!     .iter()
 //!     .map(|keypair| {
 //!         let txin = TxInRef::new(&transaction, 0);
-//!         signer.sign_input(txin, &prev_tx, &keypair.1.key).unwrap()
+//!         signer.sign_input(txin, prev_value, &keypair.1.key).unwrap()
 //!     })
 //!     .collect::<Vec<_>>();
 //! // Finalize the transaction.
-//! signer.spend_input(&mut transaction.input[0], signatures);
+//! signer
+//!     .spend_input(&mut transaction, 0, prev_value, signatures)
+//!     .unwrap();
+//! # }
+//! # #[cfg(not(feature = "random"))]
+//! # fn main() {}
 //! ```
 //!
 //! [redeem-script]: #create-a-redeem-script-and-a-corresponding-multisig-address-3-of-4
 //! [p2wpk]: #sign-p2wpk-input
 //! [p2wsh]: #sign-p2wsh-input
+//! [builder]: builder/index.html
 
 #![deny(
     missing_debug_implementations,
@@ -202,17 +225,71 @@
 
 #[macro_use]
 mod macros;
+mod ecc;
+mod error;
+#[cfg(feature = "exonum")]
+mod exonum;
 mod sign;
+mod transaction;
+
+use bitcoin::blockdata::{
+    script::Script,
+    transaction::{OutPoint, Transaction, TxIn, TxOut},
+};
 
-use bitcoin::blockdata::transaction::{Transaction, TxIn, TxOut};
+use std::collections::{BTreeMap, HashMap};
 
+pub mod address;
+pub mod bloom;
+pub mod builder;
+pub mod coinjoin;
+#[cfg(feature = "experimental")]
+pub mod ctv;
+pub mod dlc;
+#[cfg(feature = "electrum")]
+pub mod electrum;
+pub mod fee;
+#[cfg(feature = "ledger")]
+pub mod ledger;
+pub mod locktime;
+pub mod message;
 pub mod multisig;
+pub mod musig2;
 pub mod p2wpk;
 pub mod p2wsh;
+pub mod payjoin;
+pub mod payload;
+#[cfg(feature = "pkcs11")]
+pub mod pkcs11;
+pub mod policy;
+pub mod scan;
+pub mod script;
+pub mod session;
+pub mod silent_payments;
+pub mod tapscript;
+pub mod templates;
 pub mod test_data;
+pub mod tracker;
+#[cfg(feature = "trezor")]
+pub mod trezor;
+pub mod uri;
+pub mod utxo;
+pub mod vault;
+pub mod verify;
+#[cfg(feature = "yubihsm")]
+pub mod yubihsm;
 
+pub use bitcoin::util::amount::{Amount, Denomination};
 pub(crate) use bitcoin_hashes::{hash160::Hash as Hash160, sha256d::Hash as Sha256dHash, Hash};
-pub use sign::{InputSignature, InputSignatureRef};
+pub use error::Error;
+pub use fee::FeeRate;
+pub use locktime::{LockTime, Sequence};
+pub use sign::{
+    sign_raw_sighash, signature_hashes, AdaptorError, AdaptorSignature, ExternalSigner,
+    ExtraEntropy, InputReport, InputSignature, InputSignatureRef, SignatureError, SigningReport,
+    WitnessError,
+};
+pub use transaction::{decode_tx, BtcTransaction};
 
 /// A borrowed reference to a transaction input.
 #[derive(Debug, Copy, Clone)]
@@ -223,9 +300,22 @@ pub struct TxInRef<'a> {
 
 impl<'a> TxInRef<'a> {
     /// Constructs a reference to the input with the given index of the given transaction.
+    ///
+    /// # Panics
+    ///
+    /// - If the transaction has no input with the given index.
     pub fn new(transaction: &'a Transaction, index: usize) -> TxInRef<'a> {
-        assert!(transaction.input.len() > index);
-        TxInRef { transaction, index }
+        TxInRef::checked_new(transaction, index).expect("Input index is out of range")
+    }
+
+    /// Constructs a reference to the input with the given index of the given transaction,
+    /// or returns `None` if the transaction has no such input.
+    pub fn checked_new(transaction: &'a Transaction, index: usize) -> Option<TxInRef<'a>> {
+        if index < transaction.input.len() {
+            Some(TxInRef { transaction, index })
+        } else {
+            None
+        }
     }
 
     /// Returns a reference to the borrowed transaction.
@@ -242,6 +332,11 @@ impl<'a> TxInRef<'a> {
     pub fn index(&self) -> usize {
         self.index
     }
+
+    /// Returns the output spent by the input, if the given provider knows about it.
+    pub fn prev_output<'b, P: TxOutProvider + ?Sized>(&self, provider: &'b P) -> Option<&'b TxOut> {
+        provider.tx_out(&self.input().previous_output)
+    }
 }
 
 impl<'a> AsRef<TxIn> for TxInRef<'a> {
@@ -273,6 +368,18 @@ impl<'a> UnspentTxOutValue<'a> {
             UnspentTxOutValue::PrevOut(out) => out.value,
         }
     }
+
+    /// Returns the script pubkey of the spent output, if it is known.
+    pub fn script_pubkey(self, txin: TxInRef) -> Option<&'a Script> {
+        match self {
+            UnspentTxOutValue::Balance(_) => None,
+            UnspentTxOutValue::PrevTx(prev_tx) => prev_tx
+                .output
+                .get(txin.input().previous_output.vout as usize)
+                .map(|out| &out.script_pubkey),
+            UnspentTxOutValue::PrevOut(out) => Some(&out.script_pubkey),
+        }
+    }
 }
 
 impl<'a> From<u64> for UnspentTxOutValue<'a> {
@@ -281,8 +388,14 @@ impl<'a> From<u64> for UnspentTxOutValue<'a> {
     }
 }
 
+impl<'a> From<Amount> for UnspentTxOutValue<'a> {
+    fn from(balance: Amount) -> UnspentTxOutValue<'a> {
+        UnspentTxOutValue::Balance(balance.as_sat())
+    }
+}
+
 impl<'a> From<&'a Transaction> for UnspentTxOutValue<'a> {
-    fn from(tx_ref: &'a Transaction) -> UnspentTxOutValue {
+    fn from(tx_ref: &'a Transaction) -> UnspentTxOutValue<'a> {
         UnspentTxOutValue::PrevTx(tx_ref)
     }
 }
@@ -292,3 +405,40 @@ impl<'a> From<&'a TxOut> for UnspentTxOutValue<'a> {
         UnspentTxOutValue::PrevOut(tx_out)
     }
 }
+
+/// A source of the previous transaction outputs, which is used to resolve
+/// the outputs spent by the signed inputs automatically.
+pub trait TxOutProvider {
+    /// Returns the transaction output with the given outpoint, if it is known.
+    fn tx_out(&self, outpoint: &OutPoint) -> Option<&TxOut>;
+}
+
+impl TxOutProvider for Transaction {
+    fn tx_out(&self, outpoint: &OutPoint) -> Option<&TxOut> {
+        if outpoint.txid == self.txid() {
+            self.output.get(outpoint.vout as usize)
+        } else {
+            None
+        }
+    }
+}
+
+impl TxOutProvider for [Transaction] {
+    fn tx_out(&self, outpoint: &OutPoint) -> Option<&TxOut> {
+        self.iter()
+            .find(|transaction| transaction.txid() == outpoint.txid)
+            .and_then(|transaction| transaction.output.get(outpoint.vout as usize))
+    }
+}
+
+impl TxOutProvider for HashMap<OutPoint, TxOut> {
+    fn tx_out(&self, outpoint: &OutPoint) -> Option<&TxOut> {
+        self.get(outpoint)
+    }
+}
+
+impl TxOutProvider for BTreeMap<OutPoint, TxOut> {
+    fn tx_out(&self, outpoint: &OutPoint) -> Option<&TxOut> {
+        self.get(outpoint)
+    }
+}
@@ -0,0 +1,537 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The typed transaction lock times.
+//!
+//! The raw `nLockTime` value is interpreted either as a block height or as a unix timestamp
+//! depending on the [`LOCKTIME_THRESHOLD`][threshold], and the lock times of different
+//! kinds are incomparable.
+//!
+//! The input [`Sequence`][sequence] encodes the [`BIP-68`][bip-68] relative lock time,
+//! which is checked by `OP_CHECKSEQUENCEVERIFY` according to [`BIP-112`][bip-112].
+//!
+//! [threshold]: constant.LOCKTIME_THRESHOLD.html
+//! [sequence]: struct.Sequence.html
+//! [bip-68]: https://github.com/bitcoin/bips/blob/master/bip-0068.mediawiki
+//! [bip-112]: https://github.com/bitcoin/bips/blob/master/bip-0112.mediawiki
+
+use bitcoin::blockdata::{
+    opcodes::all::{OP_CSV, OP_PUSHNUM_1, OP_PUSHNUM_16},
+    script::{Instruction, Script},
+    transaction::Transaction,
+};
+
+use std::{cmp::Ordering, convert::TryFrom, fmt};
+
+use crate::Error;
+
+/// The maximum size of the `OP_CHECKSEQUENCEVERIFY` operand.
+const CSV_OPERAND_SIZE: usize = 5;
+
+/// The lock time values below this threshold are interpreted as block heights,
+/// and the other ones as unix timestamps.
+pub const LOCKTIME_THRESHOLD: u32 = 500_000_000;
+
+/// The absolute lock time of the transaction, which is also used by
+/// `OP_CHECKLOCKTIMEVERIFY`.
+///
+/// Use the [`from_height`][from-height] and [`from_time`][from-time] constructors to check
+/// that the value matches its kind.
+///
+/// [from-height]: #method.from_height
+/// [from-time]: #method.from_time
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LockTime {
+    /// The block height.
+    Height(u32),
+    /// The unix timestamp, which is compared with the median time past of the previous blocks.
+    Time(u32),
+}
+
+impl LockTime {
+    /// The lock time which does not restrict the transaction.
+    pub const ZERO: LockTime = LockTime::Height(0);
+
+    /// Creates the lock time from the block height, which must be below
+    /// the [`LOCKTIME_THRESHOLD`][threshold].
+    ///
+    /// [threshold]: constant.LOCKTIME_THRESHOLD.html
+    pub fn from_height(height: u32) -> Result<LockTime, Error> {
+        ensure!(height < LOCKTIME_THRESHOLD, Error::InvalidLockTime(height));
+        Ok(LockTime::Height(height))
+    }
+
+    /// Creates the lock time from the unix timestamp, which must be not less than
+    /// the [`LOCKTIME_THRESHOLD`][threshold].
+    ///
+    /// [threshold]: constant.LOCKTIME_THRESHOLD.html
+    pub fn from_time(timestamp: u32) -> Result<LockTime, Error> {
+        ensure!(
+            timestamp >= LOCKTIME_THRESHOLD,
+            Error::InvalidLockTime(timestamp)
+        );
+        Ok(LockTime::Time(timestamp))
+    }
+
+    /// Interprets the raw `nLockTime` value according to the consensus rules.
+    pub fn from_consensus(value: u32) -> LockTime {
+        if value < LOCKTIME_THRESHOLD {
+            LockTime::Height(value)
+        } else {
+            LockTime::Time(value)
+        }
+    }
+
+    /// Returns the raw `nLockTime` value.
+    pub fn to_consensus_u32(self) -> u32 {
+        match self {
+            LockTime::Height(value) | LockTime::Time(value) => value,
+        }
+    }
+
+    /// Checks whether both lock times are block heights or both are timestamps.
+    pub fn is_same_unit(self, other: LockTime) -> bool {
+        matches!(
+            (self, other),
+            (LockTime::Height(_), LockTime::Height(_)) | (LockTime::Time(_), LockTime::Time(_))
+        )
+    }
+
+    /// Checks whether the transaction with this lock time can be included into the block
+    /// with the given height, which previous blocks have the given median time past.
+    ///
+    /// Note that the lock time is ignored if all inputs of the transaction have
+    /// the final sequence.
+    pub fn is_satisfied_by(self, height: u32, median_time_past: u32) -> bool {
+        match self {
+            LockTime::Height(0) => true,
+            LockTime::Height(value) => value < height,
+            LockTime::Time(value) => value < median_time_past,
+        }
+    }
+
+    /// Returns the sequence of the transaction inputs, which enables this lock time.
+    ///
+    /// The lock time is ignored if all inputs of the transaction are final, so the non-zero
+    /// lock time requires the [`ENABLE_LOCK_TIME_NO_RBF`][sequence] sequence, and the inputs
+    /// stay final otherwise.
+    ///
+    /// [sequence]: struct.Sequence.html#associatedconstant.ENABLE_LOCK_TIME_NO_RBF
+    pub fn input_sequence(self) -> Sequence {
+        if self.to_consensus_u32() == 0 {
+            Sequence::MAX
+        } else {
+            Sequence::ENABLE_LOCK_TIME_NO_RBF
+        }
+    }
+
+    /// Checks whether the transaction with the given lock time can spend the output locked
+    /// by `OP_CHECKLOCKTIMEVERIFY` with this lock time, that is the lock times are of
+    /// the same kind, and the transaction one is not less than this one.
+    pub fn is_implied_by(self, other: LockTime) -> bool {
+        self.is_same_unit(other) && self.to_consensus_u32() <= other.to_consensus_u32()
+    }
+}
+
+impl Default for LockTime {
+    fn default() -> LockTime {
+        LockTime::ZERO
+    }
+}
+
+/// The lock times of different kinds are incomparable.
+impl PartialOrd for LockTime {
+    fn partial_cmp(&self, other: &LockTime) -> Option<Ordering> {
+        if self.is_same_unit(*other) {
+            Some(self.to_consensus_u32().cmp(&other.to_consensus_u32()))
+        } else {
+            None
+        }
+    }
+}
+
+impl From<u32> for LockTime {
+    fn from(value: u32) -> LockTime {
+        LockTime::from_consensus(value)
+    }
+}
+
+impl From<LockTime> for u32 {
+    fn from(lock_time: LockTime) -> u32 {
+        lock_time.to_consensus_u32()
+    }
+}
+
+impl fmt::Display for LockTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LockTime::Height(height) => write!(f, "height {}", height),
+            LockTime::Time(timestamp) => write!(f, "time {}", timestamp),
+        }
+    }
+}
+
+/// The sequence number of the transaction input.
+///
+/// Unless the [disable flag][disable-flag] is set, the sequence of the input of
+/// the transaction of version 2 or higher encodes the relative lock time in blocks
+/// or in 512-second intervals, as defined by [`BIP-68`][bip-68].
+///
+/// [disable-flag]: #associatedconstant.LOCK_TIME_DISABLE_FLAG
+/// [bip-68]: https://github.com/bitcoin/bips/blob/master/bip-0068.mediawiki
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Sequence(pub u32);
+
+impl Sequence {
+    /// The final sequence, which also disables the absolute lock time of the transaction.
+    pub const MAX: Sequence = Sequence(0xFFFF_FFFF);
+    /// The sequence which enables the absolute lock time, but does not signal
+    /// the replaceability.
+    pub const ENABLE_LOCK_TIME_NO_RBF: Sequence = Sequence(0xFFFF_FFFE);
+    /// The maximum sequence, which signals the replaceability.
+    pub const ENABLE_RBF_NO_LOCK_TIME: Sequence = Sequence(0xFFFF_FFFD);
+    /// The flag, which disables the relative lock time.
+    pub const LOCK_TIME_DISABLE_FLAG: u32 = 1 << 31;
+    /// The flag, which means that the relative lock time is in 512-second intervals
+    /// instead of blocks.
+    pub const LOCK_TIME_TYPE_FLAG: u32 = 1 << 22;
+    /// The mask of the relative lock time value.
+    pub const LOCK_TIME_MASK: u32 = 0xFFFF;
+    /// The granularity of the time based relative lock time in seconds.
+    pub const SECONDS_PER_INTERVAL: u32 = 512;
+
+    /// Creates the sequence with the relative lock time in blocks.
+    pub fn from_height(blocks: u16) -> Sequence {
+        Sequence(u32::from(blocks))
+    }
+
+    /// Creates the sequence with the relative lock time in 512-second intervals.
+    pub fn from_512_second_intervals(intervals: u16) -> Sequence {
+        Sequence(Sequence::LOCK_TIME_TYPE_FLAG | u32::from(intervals))
+    }
+
+    /// Creates the sequence with the relative lock time of at least the given number
+    /// of seconds, which is rounded up to the 512-second intervals. Returns an error
+    /// if the lock time does not fit into the sequence.
+    pub fn from_seconds_ceil(seconds: u32) -> Result<Sequence, Error> {
+        let interval = u64::from(Sequence::SECONDS_PER_INTERVAL);
+        let intervals = (u64::from(seconds) + interval - 1) / interval;
+        let intervals = u16::try_from(intervals).map_err(|_| Error::InvalidLockTime(seconds))?;
+        Ok(Sequence::from_512_second_intervals(intervals))
+    }
+
+    /// Checks whether the sequence is final, so the input does not enable
+    /// the absolute lock time of the transaction.
+    pub fn is_final(self) -> bool {
+        self == Sequence::MAX
+    }
+
+    /// Checks whether the sequence signals the replaceability of the transaction
+    /// according to `BIP-125`.
+    pub fn is_rbf(self) -> bool {
+        self < Sequence::ENABLE_LOCK_TIME_NO_RBF
+    }
+
+    /// Returns the relative lock time encoded in the sequence, or `None` if it is disabled.
+    ///
+    /// Note that the relative lock time is ignored by the consensus rules for
+    /// the transactions of version 1.
+    pub fn to_relative_lock_time(self) -> Option<RelativeLockTime> {
+        if self.0 & Sequence::LOCK_TIME_DISABLE_FLAG != 0 {
+            return None;
+        }
+        let value = (self.0 & Sequence::LOCK_TIME_MASK) as u16;
+        Some(if self.0 & Sequence::LOCK_TIME_TYPE_FLAG != 0 {
+            RelativeLockTime::Intervals(value)
+        } else {
+            RelativeLockTime::Blocks(value)
+        })
+    }
+}
+
+impl From<RelativeLockTime> for Sequence {
+    fn from(lock_time: RelativeLockTime) -> Sequence {
+        match lock_time {
+            RelativeLockTime::Blocks(blocks) => Sequence::from_height(blocks),
+            RelativeLockTime::Intervals(intervals) => {
+                Sequence::from_512_second_intervals(intervals)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Sequence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.to_relative_lock_time() {
+            Some(lock_time) => fmt::Display::fmt(&lock_time, f),
+            None => write!(f, "{:#010x}", self.0),
+        }
+    }
+}
+
+/// The relative lock time of the input, which is counted from the confirmation
+/// of the spent output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RelativeLockTime {
+    /// The number of blocks.
+    Blocks(u16),
+    /// The number of 512-second intervals of the median time past.
+    Intervals(u16),
+}
+
+impl RelativeLockTime {
+    /// Checks whether the input with the given relative lock time can spend the output
+    /// locked by `OP_CHECKSEQUENCEVERIFY` with this relative lock time, that is the lock
+    /// times are of the same kind, and the input one is not less than this one.
+    pub fn is_implied_by(self, other: RelativeLockTime) -> bool {
+        match (self, other) {
+            (RelativeLockTime::Blocks(required), RelativeLockTime::Blocks(actual))
+            | (RelativeLockTime::Intervals(required), RelativeLockTime::Intervals(actual)) => {
+                required <= actual
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for RelativeLockTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RelativeLockTime::Blocks(blocks) => write!(f, "{} blocks", blocks),
+            RelativeLockTime::Intervals(intervals) => write!(
+                f,
+                "{} seconds",
+                u32::from(*intervals) * Sequence::SECONDS_PER_INTERVAL
+            ),
+        }
+    }
+}
+
+/// Checks that the input with the given index satisfies all `OP_CHECKSEQUENCEVERIFY`
+/// checks of the given witness script, as defined by [`BIP-112`][bip-112]. The script
+/// operands are expected to be pushed right before the opcodes, as in the scripts built
+/// by the crate.
+///
+/// [bip-112]: https://github.com/bitcoin/bips/blob/master/bip-0112.mediawiki
+pub fn check_sequence(
+    transaction: &Transaction,
+    index: usize,
+    witness_script: &Script,
+) -> Result<(), Error> {
+    let input = transaction
+        .input
+        .get(index)
+        .ok_or(Error::NoSuchInput(index))?;
+    let sequence = Sequence(input.sequence);
+
+    let mut operand = None;
+    for instruction in witness_script.instructions() {
+        let instruction = instruction.map_err(|_| Error::UnsupportedScript)?;
+        if instruction == Instruction::Op(OP_CSV) {
+            let operand = operand
+                .filter(|&operand| operand >= 0)
+                .ok_or(Error::UnsupportedScript)?;
+            // The operand with the disable flag makes the opcode a no-op.
+            if operand & i64::from(Sequence::LOCK_TIME_DISABLE_FLAG) != 0 {
+                continue;
+            }
+            let required = Sequence(operand as u32);
+            let is_satisfied = transaction.version >= 2
+                && match (
+                    required.to_relative_lock_time(),
+                    sequence.to_relative_lock_time(),
+                ) {
+                    (Some(required), Some(actual)) => required.is_implied_by(actual),
+                    _ => false,
+                };
+            ensure!(is_satisfied, Error::RelativeLockTime(required));
+        }
+        operand = read_number(&instruction);
+    }
+    Ok(())
+}
+
+/// Reads the number pushed by the given instruction, if any.
+fn read_number(instruction: &Instruction) -> Option<i64> {
+    match *instruction {
+        Instruction::PushBytes(bytes) if bytes.len() <= CSV_OPERAND_SIZE => {
+            let (&last, _) = bytes.split_last().unwrap_or((&0, &[]));
+            let mut value = bytes
+                .iter()
+                .rev()
+                .fold(0_i64, |value, &byte| (value << 8) | i64::from(byte));
+            // The most significant bit of the last byte is the sign.
+            if last & 0x80 != 0 {
+                value &= !(0x80_i64 << (8 * (bytes.len() - 1)));
+                value = -value;
+            }
+            Some(value)
+        }
+        Instruction::Op(op) => {
+            let code = op.into_u8();
+            if (OP_PUSHNUM_1.into_u8()..=OP_PUSHNUM_16.into_u8()).contains(&code) {
+                Some(i64::from(code - OP_PUSHNUM_1.into_u8() + 1))
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{
+        blockdata::transaction::{OutPoint, Transaction, TxIn, TxOut},
+        network::constants::Network,
+        Script,
+    };
+
+    use super::{check_sequence, LockTime, RelativeLockTime, Sequence, LOCKTIME_THRESHOLD};
+    use crate::{
+        multisig::RedeemScriptBuilder, templates::recovery::MultisigWithRecovery,
+        test_data::deterministic_keypair, Error,
+    };
+
+    #[test]
+    fn test_lock_time() {
+        let height = LockTime::from_height(650_000).unwrap();
+        let time = LockTime::from_time(1_600_000_000).unwrap();
+        assert_eq!(LockTime::from(650_000), height);
+        assert_eq!(LockTime::from_consensus(1_600_000_000), time);
+        assert_eq!(
+            LockTime::from_consensus(LOCKTIME_THRESHOLD),
+            LockTime::Time(LOCKTIME_THRESHOLD)
+        );
+        assert_eq!(u32::from(time), 1_600_000_000);
+        assert_eq!(
+            LockTime::from_height(LOCKTIME_THRESHOLD),
+            Err(Error::InvalidLockTime(LOCKTIME_THRESHOLD))
+        );
+        assert_eq!(
+            LockTime::from_time(650_000),
+            Err(Error::InvalidLockTime(650_000))
+        );
+        assert_eq!(height.to_string(), "height 650000");
+        assert_eq!(time.to_string(), "time 1600000000");
+
+        // The transaction can be included into the block after the lock time.
+        assert!(!height.is_satisfied_by(650_000, 2_000_000_000));
+        assert!(height.is_satisfied_by(650_001, 0));
+        assert!(!time.is_satisfied_by(u32::MAX, 1_600_000_000));
+        assert!(time.is_satisfied_by(0, 1_600_000_001));
+        assert!(LockTime::ZERO.is_satisfied_by(0, 0));
+
+        assert!(height.is_implied_by(LockTime::Height(650_001)));
+        assert!(!height.is_implied_by(LockTime::Height(649_999)));
+        assert!(!height.is_implied_by(time));
+        assert!(height < LockTime::Height(650_001));
+        assert_eq!(height.partial_cmp(&time), None);
+
+        // The non-zero lock time is enforced only with the non-final sequence.
+        assert_eq!(LockTime::ZERO.input_sequence(), Sequence::MAX);
+        assert_eq!(height.input_sequence(), Sequence::ENABLE_LOCK_TIME_NO_RBF);
+        assert_eq!(time.input_sequence(), Sequence::ENABLE_LOCK_TIME_NO_RBF);
+        assert!(!time.input_sequence().is_final());
+    }
+
+    #[test]
+    fn test_sequence() {
+        let blocks = Sequence::from_height(144);
+        assert_eq!(blocks, Sequence(144));
+        assert_eq!(
+            blocks.to_relative_lock_time(),
+            Some(RelativeLockTime::Blocks(144))
+        );
+        let intervals = Sequence::from_seconds_ceil(3_600).unwrap();
+        assert_eq!(intervals, Sequence(0x0040_0008));
+        assert_eq!(
+            intervals.to_relative_lock_time(),
+            Some(RelativeLockTime::Intervals(8))
+        );
+        assert_eq!(Sequence::from(RelativeLockTime::Intervals(8)), intervals);
+        assert_eq!(
+            Sequence::from_seconds_ceil(512 * 0x1_0000),
+            Err(Error::InvalidLockTime(512 * 0x1_0000))
+        );
+        assert_eq!(Sequence::MAX.to_relative_lock_time(), None);
+        assert!(Sequence::MAX.is_final());
+        assert!(!Sequence::ENABLE_LOCK_TIME_NO_RBF.is_rbf());
+        assert!(Sequence::ENABLE_RBF_NO_LOCK_TIME.is_rbf());
+        assert_eq!(blocks.to_string(), "144 blocks");
+        assert_eq!(intervals.to_string(), "4096 seconds");
+        assert_eq!(Sequence::MAX.to_string(), "0xffffffff");
+
+        assert!(RelativeLockTime::Blocks(10).is_implied_by(RelativeLockTime::Blocks(10)));
+        assert!(!RelativeLockTime::Blocks(10).is_implied_by(RelativeLockTime::Blocks(9)));
+        assert!(!RelativeLockTime::Blocks(10).is_implied_by(RelativeLockTime::Intervals(10)));
+    }
+
+    #[test]
+    fn test_check_sequence() {
+        let public_keys = (0..3)
+            .map(|_| deterministic_keypair(Network::Testnet).0)
+            .collect::<Vec<_>>();
+        let multisig = RedeemScriptBuilder::with_public_keys(public_keys.clone())
+            .quorum(2)
+            .to_script()
+            .unwrap();
+        let mut transaction = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::default(),
+                script_sig: Script::default(),
+                sequence: 144,
+                witness: Vec::default(),
+            }],
+            output: vec![TxOut::default()],
+        };
+
+        // The large delay is pushed as data, and the small one as the opcode.
+        for &delay in &[144, 16] {
+            let template = MultisigWithRecovery {
+                multisig: multisig.clone(),
+                recovery_key: public_keys[0],
+                delay,
+            };
+            let script = template.to_script();
+            check_sequence(&transaction, 0, &script).unwrap();
+            assert_eq!(
+                check_sequence(&transaction, 1, &script),
+                Err(Error::NoSuchInput(1))
+            );
+        }
+
+        let template = MultisigWithRecovery {
+            multisig,
+            recovery_key: public_keys[0],
+            delay: 145,
+        };
+        let script = template.to_script();
+        let error = Err(Error::RelativeLockTime(Sequence::from_height(145)));
+        assert_eq!(check_sequence(&transaction, 0, &script), error);
+        transaction.input[0].sequence = 145;
+        check_sequence(&transaction, 0, &script).unwrap();
+        // The relative lock time of another kind.
+        transaction.input[0].sequence = Sequence::from_512_second_intervals(145).0;
+        assert_eq!(check_sequence(&transaction, 0, &script), error);
+        transaction.input[0].sequence = Sequence::MAX.0;
+        assert_eq!(check_sequence(&transaction, 0, &script), error);
+        // The relative lock times are not enforced for the version 1 transactions.
+        transaction.version = 1;
+        transaction.input[0].sequence = 145;
+        assert_eq!(check_sequence(&transaction, 0, &script), error);
+    }
+}
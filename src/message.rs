@@ -0,0 +1,118 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Helper functions to sign and verify messages in the format of the `signmessage`
+//! and `verifymessage` commands of Bitcoin Core.
+//!
+//! The signature is a recoverable signature of the message with the magic prefix encoded
+//! in base64, it proves the ownership of the `P2PKH` address.
+//!
+//! # Examples
+//!
+//! ```
+//! use bitcoin::{network::constants::Network, util::address::Address};
+//! use btc_transaction_utils::{message, test_data::keypair_from_wif};
+//!
+//! let (public_key, private_key) =
+//!     keypair_from_wif("cPHmynxvqfr7sXsJcohiGzoPGBShggxL6VWUdW14skohFZ1LQoeV");
+//! let address = Address::p2pkh(&public_key, Network::Testnet);
+//!
+//! let signature = message::sign_message("Hello, Exonum!", &private_key).to_string();
+//! message::verify_message(&address, "Hello, Exonum!", &signature).unwrap();
+//! ```
+
+use bitcoin::{
+    util::{
+        address::Address,
+        misc::{signed_msg_hash, MessageSignature},
+    },
+    PrivateKey,
+};
+use secp256k1::Message;
+
+use crate::{sign, Error};
+
+/// Signs the message with the given private key.
+///
+/// The `Display` implementation of the returned signature gives the base64 string.
+pub fn sign_message(message: &str, private_key: &PrivateKey) -> MessageSignature {
+    let hash = signed_msg_hash(message);
+    let message = Message::from_slice(&hash[..]).unwrap();
+    let signature = sign::shared_context().sign_recoverable(&message, &private_key.key);
+    MessageSignature::new(signature, private_key.compressed)
+}
+
+/// Checks that the base64 encoded signature of the message is made by the owner
+/// of the given `P2PKH` address.
+pub fn verify_message(address: &Address, message: &str, signature: &str) -> Result<(), Error> {
+    let signature = signature
+        .parse::<MessageSignature>()
+        .map_err(|e| Error::MessageSignatureEncoding(e.to_string()))?;
+    let is_valid = signature
+        .is_signed_by_address(
+            sign::verification_context(),
+            address,
+            signed_msg_hash(message),
+        )
+        .unwrap_or(false);
+    ensure!(is_valid, Error::MessageSignatureMismatch);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{network::constants::Network, util::address::Address};
+
+    use super::{sign_message, verify_message};
+    use crate::{test_data::keypair_from_wif, Error};
+
+    #[test]
+    fn test_sign_verify_message() {
+        let (public_key, private_key) =
+            keypair_from_wif("cPHmynxvqfr7sXsJcohiGzoPGBShggxL6VWUdW14skohFZ1LQoeV");
+        let address = Address::p2pkh(&public_key, Network::Testnet);
+
+        let signature = sign_message("Hello, Exonum!", &private_key).to_string();
+        verify_message(&address, "Hello, Exonum!", &signature).unwrap();
+        assert_eq!(
+            verify_message(&address, "Hello, Bitcoin!", &signature),
+            Err(Error::MessageSignatureMismatch)
+        );
+        // Only `P2PKH` addresses are supported.
+        let address = Address::p2wpkh(&public_key, Network::Testnet).unwrap();
+        assert_eq!(
+            verify_message(&address, "Hello, Exonum!", &signature),
+            Err(Error::MessageSignatureMismatch)
+        );
+        assert!(matches!(
+            verify_message(&address, "Hello, Exonum!", "not a base64"),
+            Err(Error::MessageSignatureEncoding(_))
+        ));
+    }
+
+    #[test]
+    fn test_sign_message_uncompressed_key() {
+        let (mut public_key, mut private_key) =
+            keypair_from_wif("cPHmynxvqfr7sXsJcohiGzoPGBShggxL6VWUdW14skohFZ1LQoeV");
+        // The header byte encodes the recovery id and whether the key is compressed.
+        assert!(sign_message("Hello, Exonum!", &private_key).serialize()[0] >= 31);
+
+        public_key.compressed = false;
+        private_key.compressed = false;
+        let address = Address::p2pkh(&public_key, Network::Testnet);
+        let signature = sign_message("Hello, Exonum!", &private_key);
+        assert!(signature.serialize()[0] < 31);
+        verify_message(&address, "Hello, Exonum!", &signature.to_string()).unwrap();
+    }
+}
@@ -20,47 +20,183 @@
 
 use bitcoin::{
     blockdata::{
-        opcodes::{all::OP_CHECKMULTISIG, Class},
-        script::{read_uint, Builder, Instruction, Script},
+        opcodes::{self, all::OP_CHECKMULTISIG, Class},
+        script::{self, read_uint, Builder, Instruction, Script},
+        transaction::TxOut,
     },
-    util::psbt::serialize::Serialize,
+    network::constants::Network,
+    util::{address::Address, psbt::serialize::Serialize},
     PublicKey,
 };
 use hex;
 use thiserror::Error;
 
-use std::{fmt, str::FromStr};
+use std::{convert::TryFrom, fmt, str::FromStr};
+
+use crate::{p2wsh, Error};
+
+/// The maximum number of public keys allowed by the `OP_CHECKMULTISIG` opcode.
+pub const MAX_PUBLIC_KEYS: usize = 20;
+/// The maximum size of the standard `P2WSH` witness script in bytes.
+pub const MAX_SCRIPT_SIZE: usize = 3_600;
+/// The maximum size of numbers in scripts.
+const MAX_NUMBER_SIZE: usize = 4;
 
 /// A standard redeem script.
-#[derive(Debug, PartialEq, Clone)]
-pub struct RedeemScript(pub(crate) Script);
+///
+/// The public keys are parsed once when the script is created, so the signers can look
+/// them up without parsing the script on each input.
+#[derive(PartialEq, Clone)]
+pub struct RedeemScript {
+    pub(crate) script: Script,
+    keys: Vec<ScriptKey>,
+    quorum: usize,
+}
+
+/// The public key of the redeem script participant.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScriptKey {
+    /// The public key.
+    pub public_key: PublicKey,
+    /// The compressed public key as it is pushed in the script.
+    pub bytes: [u8; 33],
+    /// The offset of the public key push in the script.
+    pub offset: usize,
+}
 
 impl RedeemScript {
     /// Tries to parse a raw script as a standard redeem script and returns error
     /// if the script doesn't satisfy `BIP-16` standard.
-    pub fn from_script(script: Script) -> Result<RedeemScript, RedeemScriptError> {
-        RedeemScriptContent::parse(&script)?;
-        Ok(RedeemScript(script))
+    pub fn from_script(script: Script) -> Result<RedeemScript, Error> {
+        let (content, offsets) = RedeemScriptContent::parse_with_offsets(&script, true)?;
+        let keys = content
+            .public_keys
+            .iter()
+            .zip(offsets)
+            .map(|(public_key, offset)| ScriptKey {
+                public_key: *public_key,
+                bytes: public_key.key.serialize(),
+                offset,
+            })
+            .collect();
+        Ok(RedeemScript {
+            script,
+            keys,
+            quorum: content.quorum,
+        })
     }
 
     /// Returns the redeem script content.
     pub fn content(&self) -> RedeemScriptContent {
-        RedeemScriptContent::parse(&self.0).unwrap()
+        RedeemScriptContent {
+            public_keys: self.public_keys().collect(),
+            quorum: self.quorum,
+        }
+    }
+
+    /// Returns the public keys of the participants in the script order.
+    pub fn keys(&self) -> &[ScriptKey] {
+        &self.keys
+    }
+
+    /// Returns an iterator over the public keys of the participants in the script order.
+    pub fn public_keys(&self) -> impl Iterator<Item = PublicKey> + '_ {
+        self.keys.iter().map(|key| key.public_key)
+    }
+
+    /// Returns the number of signatures required to spend the input.
+    pub fn quorum(&self) -> usize {
+        self.quorum
+    }
+
+    /// Returns the index of the given public key in the script, or `None` if the key
+    /// is not a participant of the script.
+    pub fn key_index(&self, public_key: &PublicKey) -> Option<usize> {
+        let bytes = public_key.key.serialize();
+        self.keys
+            .iter()
+            .position(|key| public_key.compressed && key.bytes == bytes)
+    }
+
+    /// Returns the `P2WSH` script pubkey of the outputs locked by this redeem script.
+    pub fn script_pubkey(&self) -> Script {
+        self.script.to_v0_p2wsh()
+    }
+
+    /// Checks whether the given output is locked by this redeem script.
+    pub fn matches_output(&self, output: &TxOut) -> bool {
+        output.script_pubkey == self.script_pubkey()
+    }
+
+    /// Replaces the public key of a single participant and builds the new redeem script
+    /// with the same quorum. Returns the new script, its `P2WSH` address for the given
+    /// network and the positions of the participants in the new script.
+    pub fn rotate_key(
+        &self,
+        old_key: &PublicKey,
+        new_key: PublicKey,
+        order: KeyOrder,
+        network: Network,
+    ) -> Result<KeyRotation, Error> {
+        let mut content = self.content();
+        let old_index = content
+            .public_keys
+            .iter()
+            .position(|key| key == old_key)
+            .ok_or(Error::UnknownPublicKey)?;
+        ensure!(
+            !content.public_keys.contains(&new_key),
+            RedeemScriptError::DuplicatePublicKey
+        );
+        content.public_keys[old_index] = new_key;
+
+        let mut positions = (0..content.public_keys.len()).collect::<Vec<_>>();
+        if order == KeyOrder::Sorted {
+            let mut keys = content
+                .public_keys
+                .iter()
+                .copied()
+                .enumerate()
+                .collect::<Vec<_>>();
+            keys.sort_by_key(|(_, key)| key.key.serialize());
+            for (new_index, (index, _)) in keys.iter().enumerate() {
+                positions[*index] = new_index;
+            }
+            content.public_keys = keys.into_iter().map(|(_, key)| key).collect();
+        }
+
+        let redeem_script = content.to_script()?;
+        let address = p2wsh::address(&redeem_script, network);
+        Ok(KeyRotation {
+            redeem_script,
+            address,
+            old_key: *old_key,
+            new_key,
+            old_index,
+            new_index: positions[old_index],
+            positions,
+        })
+    }
+}
+
+impl fmt::Debug for RedeemScript {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("RedeemScript").field(&self.script).finish()
     }
 }
 
 impl fmt::Display for RedeemScript {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        fmt::LowerHex::fmt(&self.0, f)
+        fmt::LowerHex::fmt(&self.script, f)
     }
 }
 
 impl FromStr for RedeemScript {
-    type Err = anyhow::Error;
+    type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let script = Script::from(hex::decode(s)?);
-        RedeemScript::from_script(script).map_err(Into::into)
+        RedeemScript::from_script(script)
     }
 }
 
@@ -72,13 +208,13 @@ impl From<&'static str> for RedeemScript {
 
 impl From<RedeemScript> for Script {
     fn from(s: RedeemScript) -> Script {
-        s.0
+        s.script
     }
 }
 
 impl AsRef<Script> for RedeemScript {
     fn as_ref(&self) -> &Script {
-        &self.0
+        &self.script
     }
 }
 
@@ -101,7 +237,7 @@ impl<'de> ::serde::Deserialize<'de> for RedeemScript {
 }
 
 /// Redeem script content.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct RedeemScriptContent {
     /// The public keys of the participants of this redeem script.
     pub public_keys: Vec<PublicKey>,
@@ -113,65 +249,214 @@ pub struct RedeemScriptContent {
 impl RedeemScriptContent {
     /// Tries to fetch redeem script content from the given raw script and returns error
     /// if the script doesn't satisfy `BIP-16` standard.
-    pub fn parse(script: &Script) -> Result<RedeemScriptContent, RedeemScriptError> {
-        fn read_usize(instruction: Instruction) -> Option<usize> {
-            match instruction {
-                Instruction::Op(op) => {
-                    if let Class::PushNum(num) = op.classify() {
-                        Some(num as usize)
-                    } else {
-                        None
-                    }
-                }
-                Instruction::PushBytes(data) => {
-                    let num = read_uint(data, data.len()).ok()?;
-                    Some(num as usize)
+    ///
+    /// The public keys must be compressed and the numbers must be minimally encoded,
+    /// as required for the witness scripts.
+    pub fn parse(script: &Script) -> Result<RedeemScriptContent, Error> {
+        RedeemScriptContent::parse_with_mode(script, true)
+    }
+
+    /// Tries to fetch redeem script content from the given raw script in the same way
+    /// as the [`parse`][parse] method does, but tolerates uncompressed public keys
+    /// and non-minimal encodings of numbers and pushes.
+    ///
+    /// It can be useful to inspect the scripts produced by older wallets, but such scripts
+    /// are not standard, so they cannot be used to create the [`RedeemScript`][redeem-script].
+    ///
+    /// [parse]: #method.parse
+    /// [redeem-script]: struct.RedeemScript.html
+    pub fn parse_lenient(script: &Script) -> Result<RedeemScriptContent, Error> {
+        RedeemScriptContent::parse_with_mode(script, false)
+    }
+
+    /// Builds the canonical redeem script with the given quorum and public keys.
+    /// It is the inverse of the [`parse`][parse] method.
+    ///
+    /// [parse]: #method.parse
+    pub fn to_script(&self) -> Result<RedeemScript, Error> {
+        let total_count = self.public_keys.len();
+        // Check preconditions
+        ensure!(self.quorum > 0, RedeemScriptError::NoQuorum);
+        ensure!(total_count != 0, RedeemScriptError::NotEnoughPublicKeys);
+        ensure!(
+            total_count >= self.quorum,
+            RedeemScriptError::IncorrectQuorum
+        );
+        ensure!(
+            total_count <= MAX_PUBLIC_KEYS,
+            RedeemScriptError::TooManyPublicKeys
+        );
+        ensure!(
+            self.public_keys.iter().all(|key| key.compressed),
+            RedeemScriptError::UncompressedPublicKey
+        );
+        // Construct simple redeem script in form like <1 <pubkey1> <pubkey2> 2 CHECKMULTISIG>
+        // See https://github.com/bitcoin/bips/blob/master/bip-0141.mediawiki#p2wsh
+        let mut builder = Builder::default().push_int(self.quorum as i64);
+        let compressed_keys = self.public_keys.iter().map(Serialize::serialize);
+        for key in compressed_keys {
+            builder = builder.push_slice(key.as_ref());
+        }
+        let inner = builder
+            .push_int(total_count as i64)
+            .push_opcode(OP_CHECKMULTISIG)
+            .into_script();
+        ensure!(
+            inner.len() <= MAX_SCRIPT_SIZE,
+            RedeemScriptError::ScriptTooLarge
+        );
+        RedeemScript::from_script(inner)
+    }
+
+    fn parse_with_mode(script: &Script, strict: bool) -> Result<RedeemScriptContent, Error> {
+        RedeemScriptContent::parse_with_offsets(script, strict).map(|(content, _)| content)
+    }
+
+    /// Parses the script and returns its content with the offsets of the public keys.
+    fn parse_with_offsets(
+        script: &Script,
+        strict: bool,
+    ) -> Result<(RedeemScriptContent, Vec<usize>), Error> {
+        // Numbers are encoded as `OP_PUSHNUM` for values up to 16 and as a single byte push
+        // for greater ones, but older wallets can use longer pushes for them.
+        let read_usize = |instruction: InstructionResult| match instruction {
+            Ok(Instruction::Op(op)) => {
+                if let Class::PushNum(num) = op.classify() {
+                    usize::try_from(num).ok()
+                } else {
+                    None
                 }
+            }
+            Ok(Instruction::PushBytes(data)) if strict => match *data {
+                [num] if num > 16 => Some(usize::from(num)),
                 _ => None,
+            },
+            Ok(Instruction::PushBytes(data)) if data.len() <= MAX_NUMBER_SIZE => {
+                read_uint(data, data.len()).ok()
             }
+            _ => None,
         };
 
-        let mut instructions = script.iter(true).peekable();
+        let end = script.len();
+        let instructions = if strict {
+            script.instructions_minimal()
+        } else {
+            script.instructions()
+        };
+        let mut instructions = instructions.zip(instruction_offsets(script)).peekable();
         // Parses quorum.
-        let quorum = instructions
-            .next()
-            .and_then(read_usize)
-            .ok_or_else(|| RedeemScriptError::NoQuorum)?;
+        let next = instructions.next();
+        let quorum = next
+            .clone()
+            .and_then(|(instruction, _)| read_usize(instruction))
+            .ok_or_else(|| unexpected_token(next, end, ExpectedToken::Quorum))?;
+        let mut offsets = Vec::new();
         let public_keys = {
             // Parses public keys.
             let mut public_keys = Vec::new();
-            while let Some(Instruction::PushBytes(slice)) = instructions.peek().cloned() {
-                // HACK: `public_keys_len` can be pushed as `OP_PUSHNUM` or as `OP_PUSHBYTES`
-                // but its length cannot be greater than 1.
-                if slice.len() == 1 {
+            while let Some((Ok(Instruction::PushBytes(slice)), offset)) =
+                instructions.peek().cloned()
+            {
+                // `public_keys_len` can be pushed as `OP_PUSHNUM` or as `OP_PUSHBYTES`,
+                // but its length is much smaller than the length of public keys.
+                if slice.len() <= MAX_NUMBER_SIZE {
                     break;
                 }
                 // Extracts public key from slice.
-                let pub_key =
-                    PublicKey::from_slice(slice).map_err(|_| RedeemScriptError::NotStandard)?;
+                let expected = if strict {
+                    ExpectedToken::CompressedPublicKey
+                } else {
+                    ExpectedToken::PublicKey
+                };
+                let pub_key = PublicKey::from_slice(slice)
+                    .ok()
+                    .filter(|pub_key| pub_key.compressed || !strict)
+                    .ok_or(RedeemScriptError::UnexpectedToken {
+                        offset,
+                        expected,
+                        found: ScriptToken::Push(slice.len()),
+                    })?;
                 public_keys.push(pub_key);
+                offsets.push(offset);
                 instructions.next();
             }
             // Checks tail.
-            let public_keys_len = instructions
-                .next()
-                .and_then(read_usize)
-                .ok_or_else(|| RedeemScriptError::NotStandard)?;
+            let next = instructions.next();
+            let public_keys_len = next
+                .clone()
+                .and_then(|(instruction, _)| read_usize(instruction))
+                .ok_or_else(|| unexpected_token(next, end, ExpectedToken::PublicKeysCount))?;
             ensure!(
                 public_keys.len() == public_keys_len,
                 RedeemScriptError::NotEnoughPublicKeys
             );
             ensure!(
-                Some(Instruction::Op(OP_CHECKMULTISIG)) == instructions.next(),
-                RedeemScriptError::NotStandard
+                public_keys_len <= MAX_PUBLIC_KEYS,
+                RedeemScriptError::TooManyPublicKeys
+            );
+            let next = instructions.next();
+            ensure!(
+                matches!(next, Some((Ok(Instruction::Op(OP_CHECKMULTISIG)), _))),
+                unexpected_token(next, end, ExpectedToken::CheckMultisig)
             );
+            if let Some(next) = instructions.next() {
+                return Err(unexpected_token(Some(next), end, ExpectedToken::End).into());
+            }
             public_keys
         };
         // Returns parsed script.
-        Ok(RedeemScriptContent {
+        let content = RedeemScriptContent {
             quorum,
             public_keys,
-        })
+        };
+        Ok((content, offsets))
+    }
+}
+
+/// An instruction yielded by the script iterator.
+type InstructionResult<'a> = Result<Instruction<'a>, script::Error>;
+
+/// Returns offsets of the script instructions in the same order as they are yielded
+/// by the script iterator.
+fn instruction_offsets(script: &Script) -> Vec<usize> {
+    let bytes = script.as_bytes();
+    let mut offsets = Vec::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        offsets.push(offset);
+        let opcode = bytes[offset];
+        let (prefix_len, data_len) = match opcode {
+            0x00..=0x4b => (1, usize::from(opcode)),
+            // `OP_PUSHDATA1`, `OP_PUSHDATA2` and `OP_PUSHDATA4` with the 1, 2 and 4 bytes
+            // length prefixes respectively.
+            0x4c..=0x4e => {
+                let size = 1 << (opcode - 0x4c);
+                match bytes.get(offset + 1..offset + 1 + size) {
+                    Some(prefix) => (1 + size, read_uint(prefix, size).unwrap()),
+                    None => break,
+                }
+            }
+            _ => (1, 0),
+        };
+        offset += prefix_len + data_len;
+    }
+    offsets
+}
+
+fn unexpected_token(
+    next: Option<(InstructionResult, usize)>,
+    end: usize,
+    expected: ExpectedToken,
+) -> RedeemScriptError {
+    let (found, offset) = match next {
+        Some((Ok(instruction), offset)) => (ScriptToken::from(instruction), offset),
+        Some((Err(_), offset)) => (ScriptToken::Invalid, offset),
+        None => (ScriptToken::End, end),
+    };
+    RedeemScriptError::UnexpectedToken {
+        offset,
+        expected,
+        found,
     }
 }
 
@@ -209,6 +494,17 @@ impl RedeemScriptBuilder {
         })
     }
 
+    /// Creates builder seeded with the quorum and public keys of the given redeem script,
+    /// so it can be modified and finalized again.
+    pub fn from_script(script: &RedeemScript) -> RedeemScriptBuilder {
+        RedeemScriptBuilder::from_content(script.content())
+    }
+
+    /// Creates builder seeded with the given redeem script content.
+    pub fn from_content(content: RedeemScriptContent) -> RedeemScriptBuilder {
+        RedeemScriptBuilder(content)
+    }
+
     /// Adds a new bitcoin public key.
     pub fn public_key<K: Into<PublicKey>>(&mut self, pub_key: K) -> &mut RedeemScriptBuilder {
         self.0.public_keys.push(pub_key.into());
@@ -222,27 +518,8 @@ impl RedeemScriptBuilder {
     }
 
     /// Finalizes the redeem script building.
-    pub fn to_script(&self) -> Result<RedeemScript, RedeemScriptError> {
-        let total_count = self.0.public_keys.len();
-        // Check preconditions
-        ensure!(self.0.quorum > 0, RedeemScriptError::NoQuorum);
-        ensure!(total_count != 0, RedeemScriptError::NotEnoughPublicKeys);
-        ensure!(
-            total_count >= self.0.quorum,
-            RedeemScriptError::IncorrectQuorum
-        );
-        // Construct simple redeem script in form like <1 <pubkey1> <pubkey2> 2 CHECKMULTISIG>
-        // See https://github.com/bitcoin/bips/blob/master/bip-0141.mediawiki#p2wsh
-        let mut builder = Builder::default().push_int(self.0.quorum as i64);
-        let compressed_keys = self.0.public_keys.iter().map(Serialize::serialize);
-        for key in compressed_keys {
-            builder = builder.push_slice(key.as_ref());
-        }
-        let inner = builder
-            .push_int(total_count as i64)
-            .push_opcode(OP_CHECKMULTISIG)
-            .into_script();
-        Ok(RedeemScript(inner))
+    pub fn to_script(&self) -> Result<RedeemScript, Error> {
+        self.0.to_script()
     }
 }
 
@@ -252,6 +529,37 @@ impl Default for RedeemScriptBuilder {
     }
 }
 
+/// The order of the public keys in the redeem script after the key rotation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyOrder {
+    /// The new key takes the position of the replaced one.
+    Preserve,
+    /// The public keys are sorted lexicographically according to [`BIP-67`][bip-67].
+    ///
+    /// [bip-67]: https://github.com/bitcoin/bips/blob/master/bip-0067.mediawiki
+    Sorted,
+}
+
+/// The result of the participant key rotation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyRotation {
+    /// The new redeem script.
+    pub redeem_script: RedeemScript,
+    /// The `P2WSH` address of the new redeem script.
+    pub address: Address,
+    /// The replaced public key.
+    pub old_key: PublicKey,
+    /// The new public key.
+    pub new_key: PublicKey,
+    /// The position of the replaced key in the old redeem script.
+    pub old_index: usize,
+    /// The position of the new key in the new redeem script.
+    pub new_index: usize,
+    /// The positions of the participants in the new redeem script, indexed by their
+    /// positions in the old one. They differ only if the keys are sorted.
+    pub positions: Vec<usize>,
+}
+
 /// Possible errors related to the redeem script.
 #[derive(Debug, Copy, Clone, Error, PartialEq)]
 pub enum RedeemScriptError {
@@ -264,25 +572,124 @@ pub enum RedeemScriptError {
     /// Not enough public keys. At least one public key must be specified.
     #[error("Not enough public keys. At least one public key must be specified.")]
     NotEnoughPublicKeys,
-    /// Given script is not the standard redeem script.
-    #[error("Given script is not the standard redeem script.")]
-    NotStandard,
+    /// The redeem script contains an unexpected token at the given byte offset.
+    #[error("Expected {expected} at offset {offset}, found {found}.")]
+    UnexpectedToken {
+        /// The byte offset of the token in the script.
+        offset: usize,
+        /// The token expected by the parser.
+        expected: ExpectedToken,
+        /// The token found in the script.
+        found: ScriptToken,
+    },
+    /// Too many public keys. At most `MAX_PUBLIC_KEYS` public keys can be specified.
+    #[error("Too many public keys. At most 20 public keys can be specified.")]
+    TooManyPublicKeys,
+    /// Public keys in the witness scripts must be compressed.
+    #[error("Public keys in the witness scripts must be compressed.")]
+    UncompressedPublicKey,
+    /// The public key is already a participant of the redeem script.
+    #[error("The public key is already a participant of the redeem script.")]
+    DuplicatePublicKey,
+    /// The participant weights must be positive.
+    #[error("The participant weights must be positive.")]
+    ZeroWeight,
+    /// The redeem script exceeds the standard witness script size limit.
+    #[error("The redeem script exceeds the standard witness script size limit.")]
+    ScriptTooLarge,
+}
+
+/// A token expected by the redeem script parser.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ExpectedToken {
+    /// The number of signatures required to spend the output.
+    Quorum,
+    /// A public key.
+    PublicKey,
+    /// A compressed public key.
+    CompressedPublicKey,
+    /// The total number of public keys.
+    PublicKeysCount,
+    /// The `OP_CHECKMULTISIG` opcode.
+    CheckMultisig,
+    /// The end of the script.
+    End,
+}
+
+impl fmt::Display for ExpectedToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ExpectedToken::Quorum => "quorum",
+            ExpectedToken::PublicKey => "public key",
+            ExpectedToken::CompressedPublicKey => "compressed public key",
+            ExpectedToken::PublicKeysCount => "number of public keys",
+            ExpectedToken::CheckMultisig => "OP_CHECKMULTISIG",
+            ExpectedToken::End => "end of script",
+        })
+    }
+}
+
+/// A token found in the script by the redeem script parser.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ScriptToken {
+    /// An opcode which does not push data.
+    Op(opcodes::All),
+    /// A data push of the given length in bytes.
+    Push(usize),
+    /// An incorrectly or non-minimally encoded instruction.
+    Invalid,
+    /// The end of the script.
+    End,
+}
+
+impl<'a> From<Instruction<'a>> for ScriptToken {
+    fn from(instruction: Instruction<'a>) -> ScriptToken {
+        match instruction {
+            Instruction::Op(op) => ScriptToken::Op(op),
+            Instruction::PushBytes(data) => ScriptToken::Push(data.len()),
+        }
+    }
+}
+
+impl fmt::Display for ScriptToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScriptToken::Op(op) => write!(f, "{:?}", op),
+            ScriptToken::Push(len) => write!(f, "push of {} bytes", len),
+            ScriptToken::Invalid => f.write_str("invalid instruction"),
+            ScriptToken::End => f.write_str("end of script"),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use bitcoin::{
+        blockdata::{
+            opcodes::all::{OP_CHECKMULTISIG, OP_CHECKSIG},
+            script::Builder,
+            transaction::TxOut,
+        },
+        network::constants::Network,
+    };
+
     use std::str::FromStr;
 
     use crate::{
-        multisig::{RedeemScript, RedeemScriptBuilder, RedeemScriptError},
-        test_data::keypair_from_wif,
+        multisig::{
+            ExpectedToken, KeyOrder, RedeemScript, RedeemScriptBuilder, RedeemScriptContent,
+            RedeemScriptError, ScriptToken, MAX_PUBLIC_KEYS,
+        },
+        p2wsh,
+        test_data::{deterministic_keypair, keypair_from_wif},
+        Error,
     };
 
     #[test]
     fn test_redeem_script_builder_no_quorum() {
         assert_eq!(
             RedeemScriptBuilder::with_quorum(0).to_script(),
-            Err(RedeemScriptError::NoQuorum)
+            Err(Error::RedeemScript(RedeemScriptError::NoQuorum))
         );
     }
 
@@ -290,7 +697,7 @@ mod tests {
     fn test_redeem_script_builder_not_enough_keys() {
         assert_eq!(
             RedeemScriptBuilder::with_quorum(3).to_script(),
-            Err(RedeemScriptError::NotEnoughPublicKeys)
+            Err(Error::RedeemScript(RedeemScriptError::NotEnoughPublicKeys))
         );
     }
 
@@ -308,13 +715,108 @@ mod tests {
             RedeemScriptBuilder::with_public_keys(keys)
                 .quorum(3)
                 .to_script(),
-            Err(RedeemScriptError::IncorrectQuorum)
+            Err(Error::RedeemScript(RedeemScriptError::IncorrectQuorum))
+        );
+    }
+
+    #[test]
+    fn test_redeem_script_builder_too_many_keys() {
+        let keys = (0..=MAX_PUBLIC_KEYS).map(|_| deterministic_keypair(Network::Testnet).0);
+        assert_eq!(
+            RedeemScriptBuilder::with_public_keys(keys)
+                .quorum(2)
+                .to_script(),
+            Err(Error::RedeemScript(RedeemScriptError::TooManyPublicKeys))
+        );
+    }
+
+    #[test]
+    fn test_redeem_script_builder_uncompressed_key() {
+        let (mut public_key, _) = deterministic_keypair(Network::Testnet);
+        public_key.compressed = false;
+        assert_eq!(
+            RedeemScriptBuilder::with_public_keys(vec![public_key]).to_script(),
+            Err(Error::RedeemScript(
+                RedeemScriptError::UncompressedPublicKey
+            ))
+        );
+    }
+
+    #[test]
+    fn test_redeem_script_parse_lenient() {
+        let (mut uncompressed_key, _) = deterministic_keypair(Network::Testnet);
+        uncompressed_key.compressed = false;
+        let (compressed_key, _) = deterministic_keypair(Network::Testnet);
+        // The quorum is pushed as bytes instead of `OP_PUSHNUM`, which is a non-minimal push.
+        let script = Builder::new()
+            .push_slice(&[1])
+            .push_key(&uncompressed_key)
+            .push_key(&compressed_key)
+            .push_int(2)
+            .push_opcode(OP_CHECKMULTISIG)
+            .into_script();
+
+        let error = Error::RedeemScript(RedeemScriptError::UnexpectedToken {
+            offset: 0,
+            expected: ExpectedToken::Quorum,
+            found: ScriptToken::Invalid,
+        });
+        assert_eq!(RedeemScriptContent::parse(&script), Err(error.clone()));
+        assert_eq!(RedeemScript::from_script(script.clone()), Err(error));
+        assert_eq!(
+            RedeemScriptContent::parse_lenient(&script),
+            Ok(RedeemScriptContent {
+                quorum: 1,
+                public_keys: vec![uncompressed_key, compressed_key],
+            })
+        );
+    }
+
+    #[test]
+    fn test_redeem_script_parse_diagnostics() {
+        let (public_key, _) = deterministic_keypair(Network::Testnet);
+        let script = Builder::new()
+            .push_int(1)
+            .push_key(&public_key)
+            .push_int(1)
+            .push_opcode(OP_CHECKSIG)
+            .into_script();
+        let error = RedeemScriptContent::parse(&script).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Expected OP_CHECKMULTISIG at offset 36, found OP_CHECKSIG."
+        );
+
+        let script = Builder::new()
+            .push_int(1)
+            .push_slice(&[0x05; 33])
+            .into_script();
+        assert_eq!(
+            RedeemScriptContent::parse(&script),
+            Err(Error::RedeemScript(RedeemScriptError::UnexpectedToken {
+                offset: 1,
+                expected: ExpectedToken::CompressedPublicKey,
+                found: ScriptToken::Push(33),
+            }))
+        );
+
+        let script = Builder::new()
+            .push_int(1)
+            .push_key(&public_key)
+            .into_script();
+        assert_eq!(
+            RedeemScriptContent::parse(&script),
+            Err(Error::RedeemScript(RedeemScriptError::UnexpectedToken {
+                offset: 35,
+                expected: ExpectedToken::PublicKeysCount,
+                found: ScriptToken::End,
+            }))
         );
     }
 
     #[test]
     fn test_redeem_script_from_hex_standard_short() {
-        RedeemScript::from(
+        let _script = RedeemScript::from(
             "5321027db7837e51888e94c094703030d162c682c8dba312210f44ff440fbd5e5c24732102bdd272891c9\
              e4dfc3962b1fdffd5a59732019816f9db4833634dbdaf01a401a52103280883dc31ccaee34218819aaa24\
              5480c35a33acd91283586ff6d1284ed681e52103e2bc790a6e32bf5a766919ff55b1f9e9914e13aed84f5\
@@ -324,7 +826,7 @@ mod tests {
 
     #[test]
     fn test_redeem_script_from_hex_standard_long() {
-        RedeemScript::from(
+        let _script = RedeemScript::from(
             "5c21031cf96b4fef362af7d86ee6c7159fa89485730dac8e3090163dd0c282dbc84f2221028839757bba9\
              bdf46ae553c124479e5c3ded609495f3e93e88ab23c0f559e8be521035c70ffb21d1b454ec650e511e76f6\
              bd3fe76f49c471522ee187abac8d0131a18210234acd7dee22bc23688beed0c7e42c0930cfe024204b7298\
@@ -375,4 +877,197 @@ mod tests {
         let script2 = RedeemScript::from_str(&string).unwrap();
         assert_eq!(script, script2);
     }
+
+    #[test]
+    fn test_redeem_script_content_to_script() {
+        let script = RedeemScript::from(
+            "5321027db7837e51888e94c094703030d162c682c8dba312210f44ff440fbd5e5c24732102bdd272891c9\
+             e4dfc3962b1fdffd5a59732019816f9db4833634dbdaf01a401a52103280883dc31ccaee34218819aaa24\
+             5480c35a33acd91283586ff6d1284ed681e52103e2bc790a6e32bf5a766919ff55b1f9e9914e13aed84f5\
+             02c0e4171976e19deb054ae",
+        );
+        let mut content = script.content();
+        assert_eq!(content.to_script().unwrap(), script);
+
+        // Replaces one of the keys.
+        let (public_key, _) =
+            keypair_from_wif("cMs8EwSJwfQ5DrVqYcDgjKV52k3DrGZhK1MDNrabY16WxPjvACgG");
+        content.public_keys[1] = public_key;
+        let edited = content.to_script().unwrap();
+        assert_ne!(edited, script);
+        assert_eq!(edited.content(), content);
+
+        content.quorum = 5;
+        assert_eq!(
+            content.to_script(),
+            Err(Error::RedeemScript(RedeemScriptError::IncorrectQuorum))
+        );
+    }
+
+    #[test]
+    fn test_redeem_script_builder_from_script() {
+        let public_keys = vec![
+            "cMs8EwSJwfQ5DrVqYcDgjKV52k3DrGZhK1MDNrabY16WxPjvACgG",
+            "cVwwcsdqRGV1cV1HLX1y7ccg2iu7aSHvSVRW3sPZpgZGr6Wzg9VR",
+            "cNqiotwcBrkLsFMC5wwehvSQ6CcjXu74U4mEeZn6vx3ZLYH2k3QY",
+        ]
+        .into_iter()
+        .map(|wif| keypair_from_wif(wif).0)
+        .collect::<Vec<_>>();
+        let script = RedeemScriptBuilder::with_public_keys(public_keys[..2].to_vec())
+            .quorum(1)
+            .to_script()
+            .unwrap();
+        assert_eq!(
+            RedeemScriptBuilder::from_script(&script).to_script(),
+            Ok(script.clone())
+        );
+
+        let extended = RedeemScriptBuilder::from_script(&script)
+            .public_key(public_keys[2])
+            .quorum(2)
+            .to_script()
+            .unwrap();
+        assert_eq!(
+            extended.content(),
+            RedeemScriptContent {
+                public_keys: public_keys.clone(),
+                quorum: 2,
+            }
+        );
+
+        let mut content = script.content();
+        content.public_keys[0] = public_keys[2];
+        let swapped = RedeemScriptBuilder::from_content(content)
+            .to_script()
+            .unwrap();
+        assert_eq!(
+            swapped.content().public_keys,
+            vec![public_keys[2], public_keys[1]]
+        );
+    }
+
+    #[test]
+    fn test_redeem_script_keys() {
+        let public_keys = (0..MAX_PUBLIC_KEYS)
+            .map(|_| deterministic_keypair(Network::Testnet).0)
+            .collect::<Vec<_>>();
+        for &quorum in &[1, 17] {
+            let redeem_script = RedeemScriptBuilder::with_public_keys(public_keys.clone())
+                .quorum(quorum)
+                .to_script()
+                .unwrap();
+            assert_eq!(redeem_script.quorum(), quorum);
+            assert_eq!(redeem_script.public_keys().collect::<Vec<_>>(), public_keys);
+
+            let bytes = redeem_script.script.as_bytes();
+            // The quorum greater than 16 is pushed as a single byte.
+            let first_offset = if quorum > 16 { 2 } else { 1 };
+            for (index, key) in redeem_script.keys().iter().enumerate() {
+                assert_eq!(key.public_key, public_keys[index]);
+                assert_eq!(key.offset, first_offset + index * 34);
+                assert_eq!(bytes[key.offset], 33);
+                assert_eq!(&bytes[key.offset + 1..key.offset + 34], &key.bytes[..]);
+                assert_eq!(redeem_script.key_index(&key.public_key), Some(index));
+            }
+
+            // The parsed script caches the same keys.
+            let parsed = RedeemScript::from_script(redeem_script.script.clone()).unwrap();
+            assert_eq!(parsed, redeem_script);
+            assert_eq!(
+                format!("{:?}", parsed),
+                format!("RedeemScript({:?})", redeem_script.script)
+            );
+        }
+
+        let other_key = deterministic_keypair(Network::Testnet).0;
+        let redeem_script = RedeemScriptBuilder::with_public_keys(public_keys)
+            .to_script()
+            .unwrap();
+        assert_eq!(redeem_script.key_index(&other_key), None);
+    }
+
+    #[test]
+    fn test_redeem_script_matches_output() {
+        let public_keys = (0..3)
+            .map(|_| deterministic_keypair(Network::Testnet).0)
+            .collect::<Vec<_>>();
+        let redeem_script = RedeemScriptBuilder::with_public_keys(public_keys.clone())
+            .quorum(2)
+            .to_script()
+            .unwrap();
+        let other_script = RedeemScriptBuilder::with_public_keys(public_keys)
+            .quorum(3)
+            .to_script()
+            .unwrap();
+
+        let script_pubkey = redeem_script.script_pubkey();
+        assert!(script_pubkey.is_v0_p2wsh());
+        assert_eq!(
+            script_pubkey,
+            p2wsh::address(&redeem_script, Network::Testnet).script_pubkey()
+        );
+
+        let output = TxOut {
+            value: 1_000,
+            script_pubkey,
+        };
+        assert!(redeem_script.matches_output(&output));
+        assert!(!other_script.matches_output(&output));
+    }
+
+    #[test]
+    fn test_redeem_script_rotate_key() {
+        let mut public_keys = (0..4)
+            .map(|_| deterministic_keypair(Network::Testnet).0)
+            .collect::<Vec<_>>();
+        public_keys.sort_by_key(|key| key.key.serialize());
+        let (old_keys, new_key) = (&public_keys[1..], public_keys[0]);
+        let script = RedeemScriptBuilder::with_public_keys(old_keys.to_vec())
+            .quorum(2)
+            .to_script()
+            .unwrap();
+
+        let rotation = script
+            .rotate_key(&old_keys[2], new_key, KeyOrder::Preserve, Network::Testnet)
+            .unwrap();
+        assert_eq!(
+            rotation.redeem_script.content(),
+            RedeemScriptContent {
+                public_keys: vec![old_keys[0], old_keys[1], new_key],
+                quorum: 2,
+            }
+        );
+        assert_eq!(
+            rotation.address,
+            p2wsh::address(&rotation.redeem_script, Network::Testnet)
+        );
+        assert_eq!((rotation.old_index, rotation.new_index), (2, 2));
+        assert_eq!(rotation.positions, vec![0, 1, 2]);
+
+        // The new key is the smallest one, so it goes first.
+        let rotation = script
+            .rotate_key(&old_keys[2], new_key, KeyOrder::Sorted, Network::Testnet)
+            .unwrap();
+        assert_eq!(
+            rotation.redeem_script.content().public_keys,
+            public_keys[..3].to_vec()
+        );
+        assert_eq!((rotation.old_index, rotation.new_index), (2, 0));
+        assert_eq!(rotation.positions, vec![1, 2, 0]);
+
+        assert_eq!(
+            script.rotate_key(&new_key, old_keys[0], KeyOrder::Preserve, Network::Testnet),
+            Err(Error::UnknownPublicKey)
+        );
+        assert_eq!(
+            script.rotate_key(
+                &old_keys[0],
+                old_keys[1],
+                KeyOrder::Preserve,
+                Network::Testnet
+            ),
+            Err(Error::from(RedeemScriptError::DuplicatePublicKey))
+        );
+    }
 }
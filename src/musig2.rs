@@ -0,0 +1,867 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! An implementation of the [MuSig2] multi-signature scheme, which allows the n-of-n set
+//! of validators to produce a single Schnorr signature for the aggregated public key.
+//!
+//! The signing process consists of the following steps:
+//!
+//! 1. All participants create the [`KeyAggContext`] from the same ordered list of public keys.
+//! 2. Each participant generates a fresh [`SecretNonce`] and sends the corresponding
+//!    [`PublicNonce`] to the others.
+//! 3. Each participant aggregates the received nonces into the [`AggregateNonce`], starts
+//!    the [`Session`] for the message and creates its [`PartialSignature`].
+//! 4. Any party aggregates the partial signatures into the final BIP-340 signature.
+//!
+//! When the nonces are exchanged over the consensus messages, the participants should first
+//! send the [`NonceCommit`] messages and reveal their nonces only after all commitments are
+//! received, so no participant can choose its nonce depending on the others. The
+//! [`NonceExchange`] tracks both rounds and checks the revealed nonces against the commitments.
+//!
+//! Key tweaking (used for the taproot outputs with a script path) is not supported.
+//!
+//! [MuSig2]: https://github.com/bitcoin/bips/blob/master/bip-0327.mediawiki
+//! [`KeyAggContext`]: struct.KeyAggContext.html
+//! [`SecretNonce`]: struct.SecretNonce.html
+//! [`PublicNonce`]: struct.PublicNonce.html
+//! [`AggregateNonce`]: struct.AggregateNonce.html
+//! [`Session`]: struct.Session.html
+//! [`PartialSignature`]: struct.PartialSignature.html
+//! [`NonceCommit`]: struct.NonceCommit.html
+//! [`NonceExchange`]: struct.NonceExchange.html
+
+use bitcoin::PublicKey;
+use bitcoin_hashes::{sha256, Hash, HashEngine};
+#[cfg(feature = "random")]
+use rand::Rng;
+use secp256k1::{schnorrsig, Message, SecretKey};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use std::{fmt, str::FromStr};
+
+use crate::{
+    ecc::{self, has_odd_y, mul_point, scalar_from_hash, scalar_one, tagged_engine},
+    sign, Error,
+};
+
+/// Possible errors of the MuSig2 protocol.
+#[derive(Debug, Copy, Clone, Error, PartialEq)]
+pub enum MusigError {
+    /// The set of public keys is empty.
+    #[error("The set of public keys is empty.")]
+    NoKeys,
+    /// The public key does not belong to the aggregated key set.
+    #[error("The public key does not belong to the aggregated key set.")]
+    UnknownKey,
+    /// The sum of the public keys or nonces is the point at infinity.
+    #[error("The sum of the public keys or nonces is the point at infinity.")]
+    PointAtInfinity,
+    /// The encoding of the nonce or partial signature is incorrect.
+    #[error("Incorrect encoding of the nonce or partial signature.")]
+    Encoding,
+    /// The number of partial signatures is not equal to the number of participants.
+    #[error("Expected {expected} partial signatures, but {actual} were given.")]
+    SignaturesCount {
+        /// The number of participants.
+        expected: usize,
+        /// The number of the given partial signatures.
+        actual: usize,
+    },
+    /// The partial signature is incorrect.
+    #[error("The partial signature is incorrect.")]
+    InvalidPartialSignature,
+    /// The participant has already sent a different nonce commitment.
+    #[error("The participant has already sent a different nonce commitment.")]
+    ConflictingCommitment,
+    /// The nonce is revealed before the commitments of all participants are received.
+    #[error("The nonce is revealed before all nonce commitments are received.")]
+    MissingCommitments,
+    /// Not all participants have revealed their nonces.
+    #[error("Not all participants have revealed their nonces.")]
+    MissingNonces,
+    /// The revealed nonce does not match the commitment.
+    #[error("The revealed nonce does not match the commitment.")]
+    CommitmentMismatch,
+}
+
+/// The aggregated public key of the ordered set of participants.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyAggContext {
+    keys: Vec<PublicKey>,
+    coefficients: Vec<SecretKey>,
+    aggregate: secp256k1::PublicKey,
+}
+
+impl KeyAggContext {
+    /// Aggregates the given public keys. The order of keys matters: the same set of keys
+    /// in a different order gives a different aggregated key.
+    pub fn new(keys: &[PublicKey]) -> Result<KeyAggContext, Error> {
+        ensure!(!keys.is_empty(), MusigError::NoKeys);
+
+        let mut engine = tagged_engine("KeyAgg list");
+        for key in keys {
+            engine.input(&key.key.serialize());
+        }
+        let list_hash = sha256::Hash::from_engine(engine);
+        // The coefficient of the second distinct key is fixed to one as an optimization.
+        let second_key = keys.iter().find(|key| key.key != keys[0].key);
+
+        let coefficients = keys
+            .iter()
+            .map(|key| {
+                if Some(key) == second_key {
+                    Ok(scalar_one())
+                } else {
+                    let mut engine = tagged_engine("KeyAgg coefficient");
+                    engine.input(&list_hash[..]);
+                    engine.input(&key.key.serialize());
+                    scalar_from_hash(sha256::Hash::from_engine(engine))
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let terms = keys
+            .iter()
+            .zip(&coefficients)
+            .map(|(key, coefficient)| mul_point(key.key, coefficient))
+            .collect::<Result<Vec<_>, _>>()?;
+        let aggregate = sum_points(&terms)?;
+
+        Ok(KeyAggContext {
+            keys: keys.to_vec(),
+            coefficients,
+            aggregate,
+        })
+    }
+
+    /// Returns the ordered set of the aggregated public keys.
+    pub fn public_keys(&self) -> &[PublicKey] {
+        &self.keys
+    }
+
+    /// Returns the aggregated x-only public key, which verifies the final signature.
+    pub fn aggregated_key(&self) -> schnorrsig::PublicKey {
+        x_only(&self.aggregate)
+    }
+
+    fn coefficient(&self, key: &secp256k1::PublicKey) -> Option<&SecretKey> {
+        self.keys
+            .iter()
+            .position(|k| k.key == *key)
+            .map(|index| &self.coefficients[index])
+    }
+}
+
+/// The secret part of the signer nonce.
+///
+/// The secret nonce must never be reused for several signatures, otherwise the secret key
+/// of the signer can be recovered, thus it is consumed by the [`Session::sign`] method.
+///
+/// [`Session::sign`]: struct.Session.html#method.sign
+#[derive(Debug)]
+pub struct SecretNonce {
+    k1: SecretKey,
+    k2: SecretKey,
+}
+
+impl SecretNonce {
+    /// Creates the secret nonce from the given scalars, which must be uniformly random
+    /// and never reused.
+    pub fn from_scalars(k1: SecretKey, k2: SecretKey) -> SecretNonce {
+        SecretNonce { k1, k2 }
+    }
+
+    /// Generates a secret nonce and the corresponding public nonce using the given
+    /// random number generator.
+    #[cfg(feature = "random")]
+    pub fn generate_with_rng<R: Rng + ?Sized>(rng: &mut R) -> (SecretNonce, PublicNonce) {
+        let secret = SecretNonce::from_scalars(SecretKey::new(rng), SecretKey::new(rng));
+        let public = secret.public_nonce();
+        (secret, public)
+    }
+
+    /// Generates a secret nonce and the corresponding public nonce using a cryptographically
+    /// secure pseudo-random number generator.
+    #[cfg(feature = "random")]
+    pub fn generate() -> (SecretNonce, PublicNonce) {
+        SecretNonce::generate_with_rng(&mut rand::thread_rng())
+    }
+
+    /// Returns the public nonce corresponding to this secret nonce.
+    pub fn public_nonce(&self) -> PublicNonce {
+        let context = sign::shared_context();
+        PublicNonce {
+            r1: secp256k1::PublicKey::from_secret_key(&context, &self.k1),
+            r2: secp256k1::PublicKey::from_secret_key(&context, &self.k2),
+        }
+    }
+}
+
+/// The public nonce of the signer, which is sent to other participants before signing.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PublicNonce {
+    r1: secp256k1::PublicKey,
+    r2: secp256k1::PublicKey,
+}
+
+impl PublicNonce {
+    /// Serializes the public nonce as two compressed points.
+    pub fn to_bytes(&self) -> [u8; 66] {
+        let mut bytes = [0; 66];
+        bytes[..33].copy_from_slice(&self.r1.serialize());
+        bytes[33..].copy_from_slice(&self.r2.serialize());
+        bytes
+    }
+
+    /// Deserializes the public nonce from two compressed points.
+    pub fn from_bytes(bytes: &[u8]) -> Result<PublicNonce, Error> {
+        ensure!(bytes.len() == 66, MusigError::Encoding);
+        let point =
+            |b: &[u8]| secp256k1::PublicKey::from_slice(b).map_err(|_| MusigError::Encoding);
+        Ok(PublicNonce {
+            r1: point(&bytes[..33])?,
+            r2: point(&bytes[33..])?,
+        })
+    }
+}
+
+impl fmt::Display for PublicNonce {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&hex::encode(&self.to_bytes()[..]))
+    }
+}
+
+impl FromStr for PublicNonce {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = hex::decode(s)?;
+        PublicNonce::from_bytes(&bytes)
+    }
+}
+
+impl ::serde::Serialize for PublicNonce {
+    fn serialize<S>(&self, ser: S) -> ::std::result::Result<S::Ok, S::Error>
+    where
+        S: ::serde::Serializer,
+    {
+        ::serde_str::serialize(self, ser)
+    }
+}
+
+impl<'de> ::serde::Deserialize<'de> for PublicNonce {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: ::serde::Deserializer<'de>,
+    {
+        ::serde_str::deserialize(deserializer)
+    }
+}
+
+/// The commitment to the public nonce of the participant, which is sent before the nonce
+/// itself is revealed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct NonceCommitment(sha256::Hash);
+
+impl NonceCommitment {
+    /// Creates the commitment to the public nonce of the participant with the given
+    /// public key.
+    pub fn new(public_key: &PublicKey, nonce: &PublicNonce) -> NonceCommitment {
+        let mut engine = tagged_engine("MuSig/noncecommit");
+        engine.input(&public_key.key.serialize());
+        engine.input(&nonce.to_bytes());
+        NonceCommitment(sha256::Hash::from_engine(engine))
+    }
+
+    /// Returns the commitment bytes.
+    pub fn to_bytes(&self) -> [u8; 32] {
+        self.0.into_inner()
+    }
+
+    /// Creates the commitment from its bytes.
+    pub fn from_bytes(bytes: &[u8]) -> Result<NonceCommitment, Error> {
+        sha256::Hash::from_slice(bytes)
+            .map(NonceCommitment)
+            .map_err(|_| MusigError::Encoding.into())
+    }
+}
+
+impl fmt::Display for NonceCommitment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&hex::encode(self.to_bytes()))
+    }
+}
+
+impl FromStr for NonceCommitment {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = hex::decode(s)?;
+        NonceCommitment::from_bytes(&bytes)
+    }
+}
+
+impl ::serde::Serialize for NonceCommitment {
+    fn serialize<S>(&self, ser: S) -> ::std::result::Result<S::Ok, S::Error>
+    where
+        S: ::serde::Serializer,
+    {
+        ::serde_str::serialize(self, ser)
+    }
+}
+
+impl<'de> ::serde::Deserialize<'de> for NonceCommitment {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: ::serde::Deserializer<'de>,
+    {
+        ::serde_str::deserialize(deserializer)
+    }
+}
+
+/// The message of the first round of the nonce exchange.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NonceCommit {
+    /// The index of the participant in the key aggregation context.
+    pub signer: usize,
+    /// The commitment to the public nonce of the participant.
+    pub commitment: NonceCommitment,
+}
+
+/// The message of the second round of the nonce exchange.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NonceReveal {
+    /// The index of the participant in the key aggregation context.
+    pub signer: usize,
+    /// The public nonce of the participant.
+    pub nonce: PublicNonce,
+}
+
+/// The state of the two-round nonce exchange between the participants.
+#[derive(Debug, Clone)]
+pub struct NonceExchange<'a> {
+    key_agg: &'a KeyAggContext,
+    commitments: Vec<Option<NonceCommitment>>,
+    nonces: Vec<Option<PublicNonce>>,
+}
+
+impl<'a> NonceExchange<'a> {
+    /// Starts the nonce exchange between the participants of the given key aggregation
+    /// context.
+    pub fn new(key_agg: &'a KeyAggContext) -> NonceExchange<'a> {
+        let count = key_agg.keys.len();
+        NonceExchange {
+            key_agg,
+            commitments: vec![None; count],
+            nonces: vec![None; count],
+        }
+    }
+
+    /// Creates the commitment message of the participant with the given index.
+    pub fn commit(&self, signer: usize, nonce: &PublicNonce) -> Result<NonceCommit, Error> {
+        let public_key = self
+            .key_agg
+            .keys
+            .get(signer)
+            .ok_or(MusigError::UnknownKey)?;
+        Ok(NonceCommit {
+            signer,
+            commitment: NonceCommitment::new(public_key, nonce),
+        })
+    }
+
+    /// Adds the nonce commitment of the participant. The repeated commitment is ignored.
+    pub fn add_commitment(&mut self, message: &NonceCommit) -> Result<(), Error> {
+        let commitment = self
+            .commitments
+            .get_mut(message.signer)
+            .ok_or(MusigError::UnknownKey)?;
+        match commitment {
+            Some(commitment) => ensure!(
+                *commitment == message.commitment,
+                MusigError::ConflictingCommitment
+            ),
+            None => *commitment = Some(message.commitment),
+        }
+        Ok(())
+    }
+
+    /// Returns `true` if the commitments of all participants are received, so the nonces
+    /// can be revealed.
+    pub fn is_committed(&self) -> bool {
+        self.commitments.iter().all(Option::is_some)
+    }
+
+    /// Adds the revealed nonce of the participant and checks it against the commitment.
+    pub fn add_nonce(&mut self, message: &NonceReveal) -> Result<(), Error> {
+        let public_key = self
+            .key_agg
+            .keys
+            .get(message.signer)
+            .ok_or(MusigError::UnknownKey)?;
+        ensure!(self.is_committed(), MusigError::MissingCommitments);
+        ensure!(
+            self.commitments[message.signer]
+                == Some(NonceCommitment::new(public_key, &message.nonce)),
+            MusigError::CommitmentMismatch
+        );
+        self.nonces[message.signer] = Some(message.nonce);
+        Ok(())
+    }
+
+    /// Returns the revealed nonce of the participant with the given index.
+    pub fn nonce(&self, signer: usize) -> Option<PublicNonce> {
+        self.nonces.get(signer).copied().flatten()
+    }
+
+    /// Returns `true` if all participants have revealed their nonces.
+    pub fn is_complete(&self) -> bool {
+        self.nonces.iter().all(Option::is_some)
+    }
+
+    /// Aggregates the revealed nonces of all participants, so the signing [`Session`]
+    /// can be started.
+    ///
+    /// [`Session`]: struct.Session.html
+    pub fn aggregate_nonce(&self) -> Result<AggregateNonce, Error> {
+        let nonces = self
+            .nonces
+            .iter()
+            .copied()
+            .collect::<Option<Vec<_>>>()
+            .ok_or(MusigError::MissingNonces)?;
+        AggregateNonce::new(&nonces)
+    }
+}
+
+/// The sum of the public nonces of all participants.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AggregateNonce {
+    r1: secp256k1::PublicKey,
+    r2: secp256k1::PublicKey,
+}
+
+impl AggregateNonce {
+    /// Aggregates the public nonces of all participants.
+    pub fn new(nonces: &[PublicNonce]) -> Result<AggregateNonce, Error> {
+        ensure!(!nonces.is_empty(), MusigError::NoKeys);
+        let r1 = nonces.iter().map(|nonce| nonce.r1).collect::<Vec<_>>();
+        let r2 = nonces.iter().map(|nonce| nonce.r2).collect::<Vec<_>>();
+        Ok(AggregateNonce {
+            r1: sum_points(&r1)?,
+            r2: sum_points(&r2)?,
+        })
+    }
+
+    /// Serializes the aggregated nonce as two compressed points, so the coordinator
+    /// can send it to the participants.
+    pub fn to_bytes(&self) -> [u8; 66] {
+        PublicNonce {
+            r1: self.r1,
+            r2: self.r2,
+        }
+        .to_bytes()
+    }
+
+    /// Deserializes the aggregated nonce from two compressed points.
+    pub fn from_bytes(bytes: &[u8]) -> Result<AggregateNonce, Error> {
+        let PublicNonce { r1, r2 } = PublicNonce::from_bytes(bytes)?;
+        Ok(AggregateNonce { r1, r2 })
+    }
+}
+
+/// The signature share of a single participant.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PartialSignature(SecretKey);
+
+impl PartialSignature {
+    /// Serializes the partial signature as a 32-byte scalar.
+    pub fn to_bytes(&self) -> [u8; 32] {
+        let mut bytes = [0; 32];
+        bytes.copy_from_slice(&self.0[..]);
+        bytes
+    }
+
+    /// Deserializes the partial signature from a 32-byte scalar.
+    pub fn from_bytes(bytes: &[u8]) -> Result<PartialSignature, Error> {
+        SecretKey::from_slice(bytes)
+            .map(PartialSignature)
+            .map_err(|_| MusigError::Encoding.into())
+    }
+}
+
+/// The signing session for the specific message and the set of nonces.
+#[derive(Debug, Clone)]
+pub struct Session<'a> {
+    key_agg: &'a KeyAggContext,
+    message: Message,
+    nonce_coefficient: SecretKey,
+    final_nonce: secp256k1::PublicKey,
+    challenge: SecretKey,
+}
+
+impl<'a> Session<'a> {
+    /// Starts the signing session of the given message.
+    pub fn new(
+        key_agg: &'a KeyAggContext,
+        nonce: &AggregateNonce,
+        message: Message,
+    ) -> Result<Session<'a>, Error> {
+        let aggregated_key = key_agg.aggregated_key().serialize();
+
+        let mut engine = tagged_engine("MuSig/noncecoef");
+        engine.input(&nonce.to_bytes());
+        engine.input(&aggregated_key);
+        engine.input(&message[..]);
+        let nonce_coefficient = scalar_from_hash(sha256::Hash::from_engine(engine))?;
+
+        let final_nonce = sum_points(&[nonce.r1, mul_point(nonce.r2, &nonce_coefficient)?])?;
+
+        let mut engine = tagged_engine("BIP0340/challenge");
+        engine.input(&x_only(&final_nonce).serialize());
+        engine.input(&aggregated_key);
+        engine.input(&message[..]);
+        let challenge = scalar_from_hash(sha256::Hash::from_engine(engine))?;
+
+        Ok(Session {
+            key_agg,
+            message,
+            nonce_coefficient,
+            final_nonce,
+            challenge,
+        })
+    }
+
+    /// Creates the partial signature of the participant with the given secret key.
+    pub fn sign(
+        &self,
+        nonce: SecretNonce,
+        secret_key: &SecretKey,
+    ) -> Result<PartialSignature, Error> {
+        let context = sign::shared_context();
+        let public_key = secp256k1::PublicKey::from_secret_key(&context, secret_key);
+        let key_coefficient = self
+            .key_agg
+            .coefficient(&public_key)
+            .ok_or(MusigError::UnknownKey)?;
+
+        let SecretNonce { mut k1, mut k2 } = nonce;
+        if has_odd_y(&self.final_nonce) {
+            k1.negate_assign();
+            k2.negate_assign();
+        }
+        let mut d = *secret_key;
+        if has_odd_y(&self.key_agg.aggregate) {
+            d.negate_assign();
+        }
+
+        // s = k1 + b * k2 + e * a * d
+        k2.mul_assign(&self.nonce_coefficient[..])?;
+        d.mul_assign(&key_coefficient[..])?;
+        d.mul_assign(&self.challenge[..])?;
+        k1.add_assign(&k2[..])?;
+        k1.add_assign(&d[..])?;
+        Ok(PartialSignature(k1))
+    }
+
+    /// Verifies the partial signature of the participant with the given public key
+    /// and public nonce.
+    pub fn verify_partial_signature(
+        &self,
+        public_key: &PublicKey,
+        nonce: &PublicNonce,
+        signature: &PartialSignature,
+    ) -> Result<(), Error> {
+        let context = sign::verification_context();
+        let key_coefficient = self
+            .key_agg
+            .coefficient(&public_key.key)
+            .ok_or(MusigError::UnknownKey)?;
+
+        // s * G = R1 + b * R2 + e * a * P, with the parity adjustments.
+        let mut nonce_point =
+            sum_points(&[nonce.r1, mul_point(nonce.r2, &self.nonce_coefficient)?])?;
+        if has_odd_y(&self.final_nonce) {
+            nonce_point.negate_assign(context);
+        }
+        let mut key_point = mul_point(public_key.key, key_coefficient)?;
+        key_point.mul_assign(context, &self.challenge[..])?;
+        if has_odd_y(&self.key_agg.aggregate) {
+            key_point.negate_assign(context);
+        }
+
+        let expected = sum_points(&[nonce_point, key_point])?;
+        let actual = secp256k1::PublicKey::from_secret_key(&sign::shared_context(), &signature.0);
+        ensure!(expected == actual, MusigError::InvalidPartialSignature);
+        Ok(())
+    }
+
+    /// Aggregates the partial signatures of all participants into the final signature
+    /// and checks it against the aggregated public key.
+    pub fn aggregate(
+        &self,
+        signatures: &[PartialSignature],
+    ) -> Result<schnorrsig::Signature, Error> {
+        ensure!(
+            signatures.len() == self.key_agg.keys.len(),
+            MusigError::SignaturesCount {
+                expected: self.key_agg.keys.len(),
+                actual: signatures.len(),
+            }
+        );
+
+        let (first, rest) = signatures.split_first().unwrap();
+        let mut s = first.0;
+        for signature in rest {
+            s.add_assign(&signature.0[..])?;
+        }
+
+        let mut bytes = [0; 64];
+        bytes[..32].copy_from_slice(&x_only(&self.final_nonce).serialize());
+        bytes[32..].copy_from_slice(&s[..]);
+        let signature = schnorrsig::Signature::from_slice(&bytes)?;
+
+        sign::shared_context()
+            .schnorrsig_verify(&signature, &self.message, &self.key_agg.aggregated_key())
+            .map_err(|_| MusigError::InvalidPartialSignature)?;
+        Ok(signature)
+    }
+}
+
+fn sum_points(points: &[secp256k1::PublicKey]) -> Result<secp256k1::PublicKey, Error> {
+    ecc::sum_points(points).map_err(|_| MusigError::PointAtInfinity.into())
+}
+
+fn x_only(point: &secp256k1::PublicKey) -> schnorrsig::PublicKey {
+    schnorrsig::PublicKey::from_slice(&point.serialize()[1..]).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{network::constants::Network, PublicKey};
+    use secp256k1::{Message, SecretKey};
+
+    use super::{
+        AggregateNonce, KeyAggContext, MusigError, NonceCommit, NonceExchange, NonceReveal,
+        PartialSignature, PublicNonce, SecretNonce, Session,
+    };
+    use crate::{sign, test_data::deterministic_keypair, Error};
+
+    fn keys(count: usize) -> Vec<(PublicKey, SecretKey)> {
+        (0..count)
+            .map(|_| {
+                let (pk, sk) = deterministic_keypair(Network::Testnet);
+                (pk, sk.key)
+            })
+            .collect()
+    }
+
+    fn nonce() -> (SecretNonce, PublicNonce) {
+        let (_, k1) = deterministic_keypair(Network::Testnet);
+        let (_, k2) = deterministic_keypair(Network::Testnet);
+        let secret = SecretNonce::from_scalars(k1.key, k2.key);
+        let public = secret.public_nonce();
+        (secret, public)
+    }
+
+    #[test]
+    fn test_key_aggregation_bip327_vectors() {
+        let pk = |s: &str| s.parse::<PublicKey>().unwrap();
+        let x = [
+            pk("02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9"),
+            pk("03dff1d77f2a671c5f36183726db2341be58feae1da2deced843240f7b502ba659"),
+            pk("023590a94e768f8e1815c2f24b4d80a8e3149316c3518ce7b7ad338368d038ca66"),
+        ];
+        let cases = [
+            (
+                vec![x[0], x[1], x[2]],
+                "90539eede565f5d054f32cc0c220126889ed1e5d193baf15aef344fe59d4610c",
+            ),
+            (
+                vec![x[2], x[1], x[0]],
+                "6204de8b083426dc6eaf9502d27024d53fc826bf7d2012148a0575435df54b2b",
+            ),
+            (
+                vec![x[0], x[0], x[0]],
+                "b436e3bad62b8cd409969a224731c193d051162d8c5ae8b109306127da3aa935",
+            ),
+            (
+                vec![x[0], x[0], x[1], x[1]],
+                "69bc22bfa5d106306e48a20679de1d7389386124d07571d0d872686028c26a3e",
+            ),
+        ];
+        for (keys, expected) in &cases {
+            let key_agg = KeyAggContext::new(keys).unwrap();
+            assert_eq!(key_agg.aggregated_key().to_string(), *expected);
+        }
+        assert_eq!(
+            KeyAggContext::new(&[]).unwrap_err(),
+            Error::from(MusigError::NoKeys)
+        );
+    }
+
+    #[test]
+    fn test_sign_and_aggregate() {
+        let participants = keys(5);
+        let public_keys = participants.iter().map(|(pk, _)| *pk).collect::<Vec<_>>();
+        let key_agg = KeyAggContext::new(&public_keys).unwrap();
+        let message = Message::from_slice(&[0xab; 32]).unwrap();
+
+        let (secret_nonces, public_nonces): (Vec<_>, Vec<_>) =
+            participants.iter().map(|_| nonce()).unzip();
+        let agg_nonce = AggregateNonce::new(&public_nonces).unwrap();
+        assert_eq!(
+            AggregateNonce::from_bytes(&agg_nonce.to_bytes()).unwrap(),
+            agg_nonce
+        );
+        let session = Session::new(&key_agg, &agg_nonce, message).unwrap();
+
+        let partial_signatures = participants
+            .iter()
+            .zip(secret_nonces)
+            .zip(&public_nonces)
+            .map(|(((pk, sk), secret_nonce), public_nonce)| {
+                let signature = session.sign(secret_nonce, sk).unwrap();
+                session
+                    .verify_partial_signature(pk, public_nonce, &signature)
+                    .unwrap();
+                let bytes = signature.to_bytes();
+                assert_eq!(PartialSignature::from_bytes(&bytes).unwrap(), signature);
+                signature
+            })
+            .collect::<Vec<_>>();
+
+        let signature = session.aggregate(&partial_signatures).unwrap();
+        sign::shared_context()
+            .schnorrsig_verify(&signature, &message, &key_agg.aggregated_key())
+            .unwrap();
+
+        // A partial signature of another participant is rejected.
+        assert_eq!(
+            session.verify_partial_signature(
+                &public_keys[0],
+                &public_nonces[0],
+                &partial_signatures[1]
+            ),
+            Err(MusigError::InvalidPartialSignature.into())
+        );
+        assert_eq!(
+            session.aggregate(&partial_signatures[1..]),
+            Err(MusigError::SignaturesCount {
+                expected: 5,
+                actual: 4
+            }
+            .into())
+        );
+    }
+
+    #[test]
+    fn test_sign_with_unknown_key() {
+        let participants = keys(3);
+        let public_keys = participants[..2]
+            .iter()
+            .map(|(pk, _)| *pk)
+            .collect::<Vec<_>>();
+        let key_agg = KeyAggContext::new(&public_keys).unwrap();
+
+        let (secret_nonce, public_nonce) = nonce();
+        let agg_nonce = AggregateNonce::new(&[public_nonce]).unwrap();
+        let message = Message::from_slice(&[1; 32]).unwrap();
+        let session = Session::new(&key_agg, &agg_nonce, message).unwrap();
+        assert_eq!(
+            session.sign(secret_nonce, &participants[2].1),
+            Err(MusigError::UnknownKey.into())
+        );
+    }
+
+    #[test]
+    fn test_nonce_exchange() {
+        let participants = keys(3);
+        let public_keys = participants.iter().map(|(pk, _)| *pk).collect::<Vec<_>>();
+        let key_agg = KeyAggContext::new(&public_keys).unwrap();
+        let public_nonces = (0..3).map(|_| nonce().1).collect::<Vec<_>>();
+
+        let mut exchange = NonceExchange::new(&key_agg);
+        let commits = public_nonces
+            .iter()
+            .enumerate()
+            .map(|(signer, nonce)| exchange.commit(signer, nonce).unwrap())
+            .collect::<Vec<_>>();
+        let reveals = public_nonces
+            .iter()
+            .enumerate()
+            .map(|(signer, nonce)| NonceReveal {
+                signer,
+                nonce: *nonce,
+            })
+            .collect::<Vec<_>>();
+
+        // The messages are transferred over the network.
+        let json = serde_json::to_string(&commits).unwrap();
+        let commits: Vec<NonceCommit> = serde_json::from_str(&json).unwrap();
+        let json = serde_json::to_string(&reveals).unwrap();
+        let reveals: Vec<NonceReveal> = serde_json::from_str(&json).unwrap();
+
+        exchange.add_commitment(&commits[0]).unwrap();
+        exchange.add_commitment(&commits[0]).unwrap();
+        assert_eq!(
+            exchange.add_commitment(&NonceCommit {
+                signer: 0,
+                commitment: commits[1].commitment,
+            }),
+            Err(MusigError::ConflictingCommitment.into())
+        );
+        assert_eq!(
+            exchange.add_nonce(&reveals[0]),
+            Err(MusigError::MissingCommitments.into())
+        );
+        for commit in &commits[1..] {
+            exchange.add_commitment(commit).unwrap();
+        }
+        assert!(exchange.is_committed());
+        assert_eq!(
+            exchange.add_nonce(&NonceReveal {
+                signer: 1,
+                nonce: public_nonces[0],
+            }),
+            Err(MusigError::CommitmentMismatch.into())
+        );
+        assert_eq!(
+            exchange.add_nonce(&NonceReveal {
+                signer: 3,
+                nonce: public_nonces[0],
+            }),
+            Err(MusigError::UnknownKey.into())
+        );
+        exchange.add_nonce(&reveals[0]).unwrap();
+        assert_eq!(
+            exchange.aggregate_nonce(),
+            Err(MusigError::MissingNonces.into())
+        );
+        for reveal in &reveals[1..] {
+            exchange.add_nonce(reveal).unwrap();
+        }
+        assert!(exchange.is_complete());
+        assert_eq!(exchange.nonce(2), Some(public_nonces[2]));
+        assert_eq!(
+            exchange.aggregate_nonce().unwrap(),
+            AggregateNonce::new(&public_nonces).unwrap()
+        );
+        assert_eq!(
+            public_nonces[0].to_string().parse::<PublicNonce>().unwrap(),
+            public_nonces[0]
+        );
+    }
+}
@@ -17,20 +17,29 @@
 use bitcoin::{
     blockdata::{
         script::{Builder, Script},
-        transaction::TxIn,
+        transaction::{Transaction, TxIn},
     },
     network::constants::Network,
-    util::{address::Address, key::PublicKey, psbt::serialize::Serialize},
+    util::{
+        address::Address,
+        key::{PrivateKey, PublicKey},
+        psbt::serialize::Serialize,
+    },
 };
 use secp256k1::{self, All, Secp256k1, SecretKey};
 
+use std::sync::Arc;
+
 use crate::{
-    sign, Hash, Hash160, InputSignature, InputSignatureRef, Sha256dHash, TxInRef, UnspentTxOutValue,
+    sign::{self, StoredSecretKey},
+    Error, ExternalSigner, ExtraEntropy, Hash, Hash160, InputSignature, InputSignatureRef,
+    Sha256dHash, SigningReport, TxInRef, TxOutProvider, UnspentTxOutValue, WitnessError,
 };
 
 /// Creates a bitcoin address for the corresponding public key and the bitcoin network.
 pub fn address(pk: &PublicKey, network: Network) -> Address {
-    Address::p2wpkh(pk, network)
+    Address::from_script(&script_pubkey(pk), network)
+        .expect("`P2WPK` script pubkey is a correct witness program")
 }
 
 /// Creates a script pubkey for the corresponding public key.
@@ -43,19 +52,85 @@ pub fn script_pubkey(pk: &PublicKey) -> Script {
         .into_script()
 }
 
+/// The content of the spent `P2WPK` input witness.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputWitness {
+    /// The input signature.
+    pub signature: InputSignature,
+    /// The public key of the signer.
+    pub public_key: PublicKey,
+}
+
+/// Parses the witness of the spent `P2WPK` input, it is the inverse of the
+/// [`spend_input`][spend-input] method. This can be used to audit transactions seen
+/// in the blockchain.
+///
+/// The public key must be compressed, since the `P2WPK` script pubkey commits to the hash
+/// of the compressed key, and the uncompressed keys in the witness are non-standard.
+///
+/// [spend-input]: struct.InputSigner.html#method.spend_input
+pub fn parse_witness(witness: &[Vec<u8>]) -> Result<InputWitness, Error> {
+    ensure!(witness.len() == 2, WitnessError::ItemsCount(witness.len()));
+    let signature = InputSignature::from_bytes(witness[0].clone())
+        .map_err(|_| WitnessError::InvalidSignature(0))?;
+    let public_key = PublicKey::from_slice(&witness[1])
+        .ok()
+        .filter(|public_key| public_key.compressed)
+        .ok_or(WitnessError::InvalidPublicKey)?;
+    Ok(InputWitness {
+        signature,
+        public_key,
+    })
+}
+
 /// An input signer.
 #[derive(Debug)]
 pub struct InputSigner {
-    context: Secp256k1<All>,
+    context: Arc<Secp256k1<All>>,
+    entropy: ExtraEntropy,
     public_key: PublicKey,
     network: Network,
 }
 
 impl InputSigner {
     /// Creates an input signer for the given public key and network.
+    ///
+    /// The signer uses the secp256k1 engine shared by all signers, so the creation
+    /// of the signer is cheap.
     pub fn new(public_key: PublicKey, network: Network) -> InputSigner {
+        InputSigner::with_context(public_key, network, sign::shared_context())
+    }
+
+    /// Creates an input signer for the compressed public key derived from the given secret key.
+    pub fn from_secret_key(secret_key: &SecretKey, network: Network) -> InputSigner {
+        let context = sign::shared_context();
+        let public_key = PublicKey {
+            compressed: true,
+            key: secp256k1::PublicKey::from_secret_key(&context, secret_key),
+        };
+        InputSigner::with_context(public_key, network, context)
+    }
+
+    /// Creates an input signer for the compressed public key derived from the given private key.
+    /// Returns an error if the private key belongs to the other network.
+    pub fn from_private_key(
+        private_key: &PrivateKey,
+        network: Network,
+    ) -> Result<InputSigner, Error> {
+        sign::check_network(private_key.network, network)?;
+        Ok(InputSigner::from_secret_key(&private_key.key, network))
+    }
+
+    /// Creates an input signer for the given public key and network, which uses the given
+    /// secp256k1 engine.
+    pub fn with_context(
+        public_key: PublicKey,
+        network: Network,
+        context: Arc<Secp256k1<All>>,
+    ) -> InputSigner {
         InputSigner {
-            context: Secp256k1::new(),
+            context,
+            entropy: ExtraEntropy::None,
             public_key,
             network,
         }
@@ -67,8 +142,37 @@ impl InputSigner {
     }
 
     /// Returns a mutable reference to the secp256k1 engine, used to execute all signature operations.
+    ///
+    /// If the engine is shared with other signers, it is cloned first.
     pub fn secp256k1_context_mut(&mut self) -> &mut Secp256k1<All> {
-        &mut self.context
+        Arc::make_mut(&mut self.context)
+    }
+
+    /// Returns the extra entropy mixed into the nonces of the signatures.
+    pub fn extra_entropy(&self) -> ExtraEntropy {
+        self.entropy
+    }
+
+    /// Sets the extra entropy mixed into the nonces of the signatures. By default,
+    /// the signatures are fully deterministic.
+    pub fn set_extra_entropy(&mut self, entropy: ExtraEntropy) -> &mut Self {
+        self.entropy = entropy;
+        self
+    }
+
+    /// Returns the public key of the signer.
+    pub fn public_key(&self) -> &PublicKey {
+        &self.public_key
+    }
+
+    /// Returns the address of the outputs spendable by this signer in the signer network.
+    pub fn address(&self) -> Address {
+        address(&self.public_key, self.network)
+    }
+
+    /// Returns the script pubkey of the outputs spendable by this signer.
+    pub fn script_pubkey(&self) -> Script {
+        script_pubkey(&self.public_key)
     }
 
     /// Computes the [`BIP-143`][bip-143] compliant sighash for a [`SIGHASH_ALL`][sighash_all]
@@ -88,6 +192,10 @@ impl InputSigner {
     /// Under the hood this method signs [`sighash`][signature-hash] for the given input with the
     /// given secret key.
     ///
+    /// If the given value refers to the spent output itself, its script pubkey must be
+    /// the `P2WPK` script pubkey of the signer public key, otherwise the signature would be
+    /// useless, so the method returns an error instead.
+    ///
     /// [bip-143]: https://github.com/bitcoin/bips/blob/master/bip-0143.mediawiki
     /// [signature-hash]: struct.InputSigner.html#signature_hash
     pub fn sign_input<'a, 'b, V: Into<UnspentTxOutValue<'b>>>(
@@ -95,9 +203,122 @@ impl InputSigner {
         txin: TxInRef<'a>,
         value: V,
         secret_key: &SecretKey,
-    ) -> Result<InputSignature, secp256k1::Error> {
+    ) -> Result<InputSignature, Error> {
+        let value = value.into();
+        if let Some(prev_script_pubkey) = value.script_pubkey(txin) {
+            ensure!(
+                *prev_script_pubkey == script_pubkey(&self.public_key),
+                Error::ScriptMismatch
+            );
+        }
+        let script = self.witness_script();
+        sign::sign_input(
+            &self.context,
+            txin,
+            &script,
+            value,
+            secret_key,
+            self.entropy,
+        )
+    }
+
+    /// Computes the [`BIP-143`][bip-143] compliant signature for the given input with
+    /// the given external signer, such as a hardware security module.
+    ///
+    /// The public key of the signer must be the public key of this input signer.
+    ///
+    /// [bip-143]: https://github.com/bitcoin/bips/blob/master/bip-0143.mediawiki
+    pub fn sign_input_external<'a, 'b, V, S>(
+        &mut self,
+        txin: TxInRef<'a>,
+        value: V,
+        signer: &S,
+    ) -> Result<InputSignature, Error>
+    where
+        V: Into<UnspentTxOutValue<'b>>,
+        S: ExternalSigner + ?Sized,
+    {
+        ensure!(
+            signer.public_key() == self.public_key,
+            Error::ScriptMismatch
+        );
+        let value = value.into();
+        if let Some(prev_script_pubkey) = value.script_pubkey(txin) {
+            ensure!(
+                *prev_script_pubkey == script_pubkey(&self.public_key),
+                Error::ScriptMismatch
+            );
+        }
         let script = self.witness_script();
-        sign::sign_input(&mut self.context, txin, &script, value, secret_key)
+        sign::sign_input_external(txin, &script, value, signer)
+    }
+
+    /// Computes the [`BIP-143`][bip-143] compliant signatures for all inputs of the given
+    /// transaction, the values of the spent outputs are given in the input order.
+    ///
+    /// The result is the same as of the [`sign_input`][sign-input] calls for each input,
+    /// but the sighash components shared by the inputs are computed only once.
+    ///
+    /// [bip-143]: https://github.com/bitcoin/bips/blob/master/bip-0143.mediawiki
+    /// [sign-input]: #method.sign_input
+    pub fn sign_all_inputs<'b, V>(
+        &mut self,
+        transaction: &Transaction,
+        values: &[V],
+        secret_key: &SecretKey,
+    ) -> Result<Vec<InputSignature>, Error>
+    where
+        V: Clone + Into<UnspentTxOutValue<'b>>,
+    {
+        sign::sign_all_inputs(
+            &self.context,
+            transaction,
+            &self.witness_script(),
+            &script_pubkey(&self.public_key),
+            values,
+            secret_key,
+            self.entropy,
+        )
+    }
+
+    /// Computes the sighashes for all inputs of the given transaction in the same way as
+    /// the [`sign_all_inputs`][sign-all-inputs] method does, but does not sign them.
+    ///
+    /// The returned report contains the spent outputs, their values, the sighashes
+    /// and the transaction outputs, so the signing request can be reviewed before
+    /// the actual signing.
+    ///
+    /// [sign-all-inputs]: #method.sign_all_inputs
+    pub fn dry_run<'b, V>(
+        &mut self,
+        transaction: &Transaction,
+        values: &[V],
+    ) -> Result<SigningReport, Error>
+    where
+        V: Clone + Into<UnspentTxOutValue<'b>>,
+    {
+        sign::signing_report(
+            transaction,
+            &self.witness_script(),
+            &script_pubkey(&self.public_key),
+            values,
+        )
+    }
+
+    /// Computes the [`BIP-143`][bip-143] compliant signature for the given input,
+    /// the spent output is looked up in the given provider.
+    ///
+    /// [bip-143]: https://github.com/bitcoin/bips/blob/master/bip-0143.mediawiki
+    pub fn sign_input_with_provider<'a, P: TxOutProvider + ?Sized>(
+        &mut self,
+        txin: TxInRef<'a>,
+        provider: &P,
+        secret_key: &SecretKey,
+    ) -> Result<InputSignature, Error> {
+        let prev_output = txin
+            .prev_output(provider)
+            .ok_or(Error::UnknownOutput(txin.input().previous_output))?;
+        self.sign_input(txin, prev_output, secret_key)
     }
 
     /// Checks correctness of the signature for the given input.
@@ -107,18 +328,18 @@ impl InputSigner {
         value: V,
         public_key: &PublicKey,
         signature: S,
-    ) -> Result<(), secp256k1::Error>
+    ) -> Result<(), Error>
     where
         V: Into<UnspentTxOutValue<'b>>,
         S: Into<InputSignatureRef<'c>>,
     {
         sign::verify_input_signature(
-            &self.context,
+            sign::verification_context(),
             txin,
             &self.witness_script(),
             value,
             public_key,
-            signature.into().content(),
+            signature,
         )
     }
 
@@ -136,6 +357,82 @@ impl InputSigner {
     }
 }
 
+/// An input signer, which holds the secret key, so the signing methods do not need it.
+#[derive(Debug)]
+pub struct OwnedInputSigner {
+    signer: InputSigner,
+    secret_key: StoredSecretKey,
+}
+
+impl OwnedInputSigner {
+    /// Creates an input signer for the given secret key and network.
+    pub fn new(secret_key: SecretKey, network: Network) -> OwnedInputSigner {
+        OwnedInputSigner {
+            signer: InputSigner::from_secret_key(&secret_key, network),
+            secret_key: StoredSecretKey::new(secret_key),
+        }
+    }
+
+    /// Creates an input signer for the given private key and network. Returns an error
+    /// if the private key belongs to the other network.
+    pub fn from_private_key(
+        private_key: PrivateKey,
+        network: Network,
+    ) -> Result<OwnedInputSigner, Error> {
+        sign::check_network(private_key.network, network)?;
+        Ok(OwnedInputSigner::new(private_key.key, network))
+    }
+
+    /// Returns a reference to the underlying signer, which can be used to compute the addresses
+    /// and verify the signatures.
+    pub fn signer(&self) -> &InputSigner {
+        &self.signer
+    }
+
+    /// Computes the [`BIP-143`][bip-143] compliant signature for the given input.
+    /// [Read more...][sign-input]
+    ///
+    /// [bip-143]: https://github.com/bitcoin/bips/blob/master/bip-0143.mediawiki
+    /// [sign-input]: struct.InputSigner.html#method.sign_input
+    pub fn sign_input<'a, 'b, V: Into<UnspentTxOutValue<'b>>>(
+        &mut self,
+        txin: TxInRef<'a>,
+        value: V,
+    ) -> Result<InputSignature, Error> {
+        self.signer.sign_input(txin, value, self.secret_key.get())
+    }
+
+    /// Computes the [`BIP-143`][bip-143] compliant signatures for all inputs of the given
+    /// transaction. [Read more...][sign-all-inputs]
+    ///
+    /// [bip-143]: https://github.com/bitcoin/bips/blob/master/bip-0143.mediawiki
+    /// [sign-all-inputs]: struct.InputSigner.html#method.sign_all_inputs
+    pub fn sign_all_inputs<'b, V>(
+        &mut self,
+        transaction: &Transaction,
+        values: &[V],
+    ) -> Result<Vec<InputSignature>, Error>
+    where
+        V: Clone + Into<UnspentTxOutValue<'b>>,
+    {
+        self.signer
+            .sign_all_inputs(transaction, values, self.secret_key.get())
+    }
+
+    /// Computes the [`BIP-143`][bip-143] compliant signature for the given input,
+    /// the spent output is looked up in the given provider.
+    ///
+    /// [bip-143]: https://github.com/bitcoin/bips/blob/master/bip-0143.mediawiki
+    pub fn sign_input_with_provider<'a, P: TxOutProvider + ?Sized>(
+        &mut self,
+        txin: TxInRef<'a>,
+        provider: &P,
+    ) -> Result<InputSignature, Error> {
+        self.signer
+            .sign_input_with_provider(txin, provider, self.secret_key.get())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use bitcoin::{
@@ -145,12 +442,43 @@ mod tests {
         network::constants::Network,
     };
 
+    use std::collections::HashMap;
+
     use crate::{
-        p2wpk,
-        test_data::{btc_tx_from_hex, keypair_from_wif},
-        TxInRef,
+        ecc, p2wpk, sign,
+        test_data::{
+            btc_tx_from_hex, fake_funding_tx, keypair_from_wif, keypair_from_wif_for_network,
+        },
+        Error, ExtraEntropy, TxInRef, WitnessError,
     };
 
+    #[test]
+    fn test_address_test_networks() {
+        let wif = "cPHmynxvqfr7sXsJcohiGzoPGBShggxL6VWUdW14skohFZ1LQoeV";
+        let expected = vec![
+            (Network::Testnet, "tb1q"),
+            (Network::Regtest, "bcrt1q"),
+            (Network::Signet, "tb1q"),
+        ];
+        for (network, prefix) in expected {
+            let (pk, sk) = keypair_from_wif_for_network(wif, network);
+            assert_eq!(sk.network, network);
+            assert_eq!(sk.to_wif(), wif);
+
+            let address = p2wpk::address(&pk, network);
+            assert_eq!(address.network, network);
+            assert!(address.to_string().starts_with(prefix));
+            assert_eq!(address.script_pubkey(), p2wpk::script_pubkey(&pk));
+
+            let signer = p2wpk::InputSigner::new(pk, network);
+            assert_eq!(signer.public_key(), &pk);
+            let from_secret_key = p2wpk::InputSigner::from_secret_key(&sk.key, network);
+            assert_eq!(from_secret_key.public_key(), &pk);
+            assert_eq!(signer.address(), address);
+            assert_eq!(signer.script_pubkey(), address.script_pubkey());
+        }
+    }
+
     #[test]
     fn test_native_segwit() {
         let (pk, sk) = keypair_from_wif("cPHmynxvqfr7sXsJcohiGzoPGBShggxL6VWUdW14skohFZ1LQoeV");
@@ -206,5 +534,206 @@ mod tests {
              3090163dd0c282dbc84f2200000000",
         );
         assert_eq!(transaction, expected_tx);
+        // Parses the witness back.
+        let witness = p2wpk::parse_witness(&transaction.input[0].witness).unwrap();
+        assert_eq!(witness.public_key, pk);
+        signer
+            .verify_input(
+                TxInRef::new(&transaction, 0),
+                &prev_tx,
+                &witness.public_key,
+                &witness.signature,
+            )
+            .expect("Signature should be correct");
+        // The uncompressed public key does not match the witness program.
+        let mut witness = transaction.input[0].witness.clone();
+        witness[1] = pk.key.serialize_uncompressed().to_vec();
+        assert_eq!(
+            p2wpk::parse_witness(&witness),
+            Err(Error::Witness(WitnessError::InvalidPublicKey))
+        );
+    }
+
+    #[test]
+    fn test_sign_input_with_provider() {
+        let (pk, sk) = keypair_from_wif("cPHmynxvqfr7sXsJcohiGzoPGBShggxL6VWUdW14skohFZ1LQoeV");
+        let prev_tx = btc_tx_from_hex(
+            "02000000000101beccab33bc72bfc81b63fdec8a4a9a4719e4418bdb7b20e47b02074dc42f2d800000000\
+             017160014f3b1b3819c1290cd5d675c1319dc7d9d98d571bcfeffffff02dceffa0200000000160014368c\
+             6b7c38f0ff0839bf78d77544da96cb685bf28096980000000000160014284175e336fa10865fb4d1351c9\
+             e18e730f5d6f90247304402207c893c85d75e2230dde04f5a1e2c83c4f0b7d93213372746eb2227b06826\
+             0d840220705484b6ec70a8fc0d1f80c3a98079602595351b7a9bca7caddb9a6adb0a3440012103150514f\
+             05f3e3f40c7b404b16f8a09c2c71bad3ba8da5dd1e411a7069cc080a004b91300",
+        );
+        let outpoint = OutPoint {
+            txid: prev_tx.txid(),
+            vout: 1,
+        };
+        let transaction = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: outpoint,
+                script_sig: Script::default(),
+                sequence: 0xFFFF_FFFF,
+                witness: Vec::default(),
+            }],
+            output: vec![TxOut {
+                value: 0,
+                script_pubkey: Builder::new().push_opcode(OP_RETURN).into_script(),
+            }],
+        };
+        let txin = TxInRef::new(&transaction, 0);
+
+        let mut signer = p2wpk::InputSigner::new(pk, Network::Testnet);
+        let expected_signature = signer.sign_input(txin, &prev_tx, &sk.key).unwrap();
+        // Looks up the spent output in the previous transaction itself.
+        let signature = signer
+            .sign_input_with_provider(txin, &prev_tx, &sk.key)
+            .unwrap();
+        assert_eq!(signature, expected_signature);
+        // Looks up the spent output in the map.
+        let mut outputs = HashMap::new();
+        assert_eq!(
+            signer.sign_input_with_provider(txin, &outputs, &sk.key),
+            Err(Error::UnknownOutput(outpoint))
+        );
+        outputs.insert(outpoint, prev_tx.output[1].clone());
+        let signature = signer
+            .sign_input_with_provider(txin, &outputs, &sk.key)
+            .unwrap();
+        assert_eq!(signature, expected_signature);
+        // The spent output must pay to the signer public key.
+        assert_eq!(
+            signer.sign_input(txin, &prev_tx.output[0], &sk.key),
+            Err(Error::ScriptMismatch)
+        );
+    }
+
+    #[test]
+    fn test_signer_network_mismatch() {
+        let (pk, mut sk) = keypair_from_wif("cPHmynxvqfr7sXsJcohiGzoPGBShggxL6VWUdW14skohFZ1LQoeV");
+        let signer = p2wpk::InputSigner::from_private_key(&sk, Network::Regtest).unwrap();
+        assert_eq!(signer.public_key(), &pk);
+        assert_eq!(signer.address().network, Network::Regtest);
+        assert!(p2wpk::OwnedInputSigner::from_private_key(sk, Network::Signet).is_ok());
+
+        sk.network = Network::Bitcoin;
+        let error = Error::NetworkMismatch {
+            expected: Network::Testnet,
+            actual: Network::Bitcoin,
+        };
+        assert_eq!(
+            p2wpk::InputSigner::from_private_key(&sk, Network::Testnet).unwrap_err(),
+            error
+        );
+        assert_eq!(
+            p2wpk::OwnedInputSigner::from_private_key(sk, Network::Testnet).unwrap_err(),
+            error
+        );
+    }
+
+    #[test]
+    fn test_owned_input_signer() {
+        let (pk, sk) = keypair_from_wif("cPHmynxvqfr7sXsJcohiGzoPGBShggxL6VWUdW14skohFZ1LQoeV");
+        let prev_tx = fake_funding_tx(&p2wpk::script_pubkey(&pk), 50_000);
+        let transaction = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(prev_tx.txid(), 0),
+                script_sig: Script::default(),
+                sequence: 0xFFFF_FFFF,
+                witness: Vec::default(),
+            }],
+            output: vec![TxOut::default()],
+        };
+        let txin = TxInRef::new(&transaction, 0);
+
+        let mut owned = p2wpk::OwnedInputSigner::new(sk.key, Network::Testnet);
+        assert_eq!(owned.signer().public_key(), &pk);
+        assert!(!format!("{:?}", owned).contains(&sk.key.to_string()));
+
+        let signature = owned.sign_input(txin, &prev_tx).unwrap();
+        let mut signer = p2wpk::InputSigner::new(pk, Network::Testnet);
+        assert_eq!(
+            signature,
+            signer.sign_input(txin, &prev_tx, &sk.key).unwrap()
+        );
+        assert_eq!(
+            owned.sign_input_with_provider(txin, &prev_tx).unwrap(),
+            signature
+        );
+        assert_eq!(
+            owned.sign_all_inputs(&transaction, &[&prev_tx]).unwrap(),
+            vec![signature]
+        );
+    }
+
+    #[test]
+    fn test_extra_entropy() {
+        let (public_key, private_key) =
+            keypair_from_wif("cPHmynxvqfr7sXsJcohiGzoPGBShggxL6VWUdW14skohFZ1LQoeV");
+        let transaction = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::default(),
+                script_sig: Script::default(),
+                sequence: 0xFFFF_FFFF,
+                witness: Vec::default(),
+            }],
+            output: vec![TxOut {
+                value: 0,
+                script_pubkey: Builder::new().push_opcode(OP_RETURN).into_script(),
+            }],
+        };
+        let txin = TxInRef::new(&transaction, 0);
+        let mut signer = p2wpk::InputSigner::new(public_key, Network::Testnet);
+        assert_eq!(signer.extra_entropy(), ExtraEntropy::None);
+        let deterministic = signer.sign_input(txin, 1_000, &private_key.key).unwrap();
+
+        let entropy = ExtraEntropy::Fixed([7; 32]);
+        signer.set_extra_entropy(entropy);
+        assert_eq!(signer.extra_entropy(), entropy);
+        let signature = signer.sign_input(txin, 1_000, &private_key.key).unwrap();
+        assert_ne!(signature, deterministic);
+        assert_eq!(
+            signer.sign_input(txin, 1_000, &private_key.key).unwrap(),
+            signature
+        );
+        signer
+            .verify_input(txin, 1_000, &public_key, &signature)
+            .unwrap();
+
+        // The nonce is derived in the same way as in `libsecp256k1`, which grinds the low `R`
+        // signatures with the counter as the extra entropy.
+        let context = sign::shared_context();
+        let mut counter_entropy = [0; 32];
+        counter_entropy[0] = 1;
+        let is_low_r = |signature: &secp256k1::Signature| signature.serialize_compact()[0] < 0x80;
+        let mut checked = 0;
+        for index in 0_u8..64 {
+            let message = secp256k1::Message::from_slice(&[index; 32]).unwrap();
+            let with_entropy =
+                ecc::sign_with_extra_data(&context, &message, &private_key.key, &counter_entropy)
+                    .unwrap();
+            if !is_low_r(&context.sign(&message, &private_key.key)) && is_low_r(&with_entropy) {
+                assert_eq!(context.sign_low_r(&message, &private_key.key), with_entropy);
+                checked += 1;
+            }
+        }
+        assert!(checked > 0);
+
+        #[cfg(feature = "random")]
+        {
+            signer.set_extra_entropy(ExtraEntropy::Random);
+            let first = signer.sign_input(txin, 1_000, &private_key.key).unwrap();
+            let second = signer.sign_input(txin, 1_000, &private_key.key).unwrap();
+            assert_ne!(first, second);
+            signer
+                .verify_input(txin, 1_000, &public_key, &first)
+                .unwrap();
+        }
     }
 }
@@ -15,39 +15,89 @@
 //! A native `P2WSH` input signer.
 
 use bitcoin::{
-    blockdata::script::Script, blockdata::transaction::TxIn, network::constants::Network,
-    util::address::Address, PublicKey,
+    blockdata::script::Script, blockdata::transaction::Transaction, network::constants::Network,
+    util::address::Address, PrivateKey, PublicKey,
 };
-use secp256k1::{self, All, Secp256k1, SecretKey};
+use secp256k1::{self, All, Message, Secp256k1, SecretKey};
+
+use std::sync::Arc;
 
 use crate::{
-    multisig::RedeemScript, sign, InputSignature, InputSignatureRef, Sha256dHash, TxInRef,
-    UnspentTxOutValue,
+    multisig::RedeemScript,
+    sign::{self, StoredSecretKey},
+    Error, ExternalSigner, ExtraEntropy, InputSignature, InputSignatureRef, Sha256dHash,
+    SigningReport, TxInRef, TxOutProvider, UnspentTxOutValue, WitnessError,
 };
 
 /// Creates a bitcoin address for the corresponding redeem script and the bitcoin network.
 pub fn address(redeem_script: &RedeemScript, network: Network) -> Address {
-    Address::p2wsh(&redeem_script.0, network)
+    Address::p2wsh(&redeem_script.script, network)
 }
 
 /// Creates a script pubkey for the corresponding redeem script.
 pub fn script_pubkey(redeem_script: &RedeemScript) -> Script {
-    redeem_script.0.to_v0_p2wsh()
+    redeem_script.script_pubkey()
+}
+
+/// The content of the spent `P2WSH` input witness.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputWitness {
+    /// The signatures in the same order as in the witness.
+    pub signatures: Vec<InputSignature>,
+    /// The redeem script embedded into the witness.
+    pub redeem_script: RedeemScript,
+}
+
+/// Parses the witness of the spent `P2WSH` multisig input, it is the inverse of the
+/// [`spend_input`][spend-input] method. This can be used to audit transactions seen
+/// in the blockchain.
+///
+/// [spend-input]: struct.InputSigner.html#method.spend_input
+pub fn parse_witness(witness: &[Vec<u8>]) -> Result<InputWitness, Error> {
+    ensure!(witness.len() >= 2, WitnessError::ItemsCount(witness.len()));
+    let (script, items) = witness.split_last().unwrap();
+    let (dummy, signatures) = items.split_first().unwrap();
+    ensure!(dummy.is_empty(), WitnessError::NoDummyItem);
+
+    let redeem_script = RedeemScript::from_script(Script::from(script.clone()))?;
+    let signatures = signatures
+        .iter()
+        .enumerate()
+        .map(|(index, signature)| {
+            InputSignature::from_bytes(signature.clone())
+                .map_err(|_| WitnessError::InvalidSignature(index + 1))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(InputWitness {
+        signatures,
+        redeem_script,
+    })
 }
 
 /// An input signer.
 #[derive(Debug)]
 pub struct InputSigner {
-    context: Secp256k1<All>,
-    script: RedeemScript,
+    context: Arc<Secp256k1<All>>,
+    entropy: ExtraEntropy,
+    redeem_script: RedeemScript,
 }
 
 impl InputSigner {
     /// Creates an input signer for the given redeem script.
+    ///
+    /// The signer uses the secp256k1 engine shared by all signers, so the creation
+    /// of the signer is cheap.
     pub fn new(script: RedeemScript) -> InputSigner {
+        InputSigner::with_context(script, sign::shared_context())
+    }
+
+    /// Creates an input signer for the given redeem script, which uses the given
+    /// secp256k1 engine.
+    pub fn with_context(script: RedeemScript, context: Arc<Secp256k1<All>>) -> InputSigner {
         InputSigner {
-            context: Secp256k1::new(),
-            script,
+            context,
+            entropy: ExtraEntropy::None,
+            redeem_script: script,
         }
     }
 
@@ -57,8 +107,37 @@ impl InputSigner {
     }
 
     /// Returns a mutable reference to the secp256k1 engine, used to execute all signature operations.
+    ///
+    /// If the engine is shared with other signers, it is cloned first.
     pub fn secp256k1_context_mut(&mut self) -> &mut Secp256k1<All> {
-        &mut self.context
+        Arc::make_mut(&mut self.context)
+    }
+
+    /// Returns the extra entropy mixed into the nonces of the signatures.
+    pub fn extra_entropy(&self) -> ExtraEntropy {
+        self.entropy
+    }
+
+    /// Sets the extra entropy mixed into the nonces of the signatures. By default,
+    /// the signatures are fully deterministic.
+    pub fn set_extra_entropy(&mut self, entropy: ExtraEntropy) -> &mut Self {
+        self.entropy = entropy;
+        self
+    }
+
+    /// Returns the redeem script of the signer.
+    pub fn redeem_script(&self) -> &RedeemScript {
+        &self.redeem_script
+    }
+
+    /// Returns the address of the outputs spendable by this signer in the given network.
+    pub fn address(&self, network: Network) -> Address {
+        address(&self.redeem_script, network)
+    }
+
+    /// Returns the script pubkey of the outputs spendable by this signer.
+    pub fn script_pubkey(&self) -> Script {
+        script_pubkey(&self.redeem_script)
     }
 
     /// Computes the [`BIP-143`][bip-143] compliant sighash for a [`SIGHASH_ALL`][sighash_all]
@@ -71,13 +150,18 @@ impl InputSigner {
         txin: TxInRef<'a>,
         value: V,
     ) -> Sha256dHash {
-        sign::signature_hash(txin, &self.script.0, value)
+        sign::signature_hash(txin, &self.redeem_script.script, value)
     }
 
     /// Computes the [`BIP-143`][bip-143] compliant signature for the given input.
     /// Under the hood this method signs [`sighash`][signature-hash] for the given input with the
     /// given secret key.
     ///
+    /// The public key of the given secret key must be one of the redeem script participants,
+    /// otherwise the signature would be useless, so the method returns an error instead.
+    /// For the same reason, if the given value refers to the spent output itself, its script
+    /// pubkey must be the `P2WSH` script pubkey of the redeem script.
+    ///
     /// [bip-143]: https://github.com/bitcoin/bips/blob/master/bip-0143.mediawiki
     /// [signature-hash]: struct.InputSigner.html#signature_hash
     pub fn sign_input<'a, 'b, V: Into<UnspentTxOutValue<'b>>>(
@@ -85,8 +169,122 @@ impl InputSigner {
         txin: TxInRef<'a>,
         value: V,
         secret_key: &SecretKey,
-    ) -> Result<InputSignature, secp256k1::Error> {
-        sign::sign_input(&mut self.context, txin, &self.script.0, value, secret_key)
+    ) -> Result<InputSignature, Error> {
+        self.check_secret_key(secret_key)?;
+        let value = value.into();
+        if let Some(prev_script_pubkey) = value.script_pubkey(txin) {
+            ensure!(
+                *prev_script_pubkey == script_pubkey(&self.redeem_script),
+                Error::ScriptMismatch
+            );
+        }
+        sign::sign_input(
+            &self.context,
+            txin,
+            &self.redeem_script.script,
+            value,
+            secret_key,
+            self.entropy,
+        )
+    }
+
+    /// Computes the [`BIP-143`][bip-143] compliant signature for the given input with
+    /// the given external signer, such as a hardware security module.
+    ///
+    /// The public key of the signer must be one of the redeem script participants.
+    ///
+    /// [bip-143]: https://github.com/bitcoin/bips/blob/master/bip-0143.mediawiki
+    pub fn sign_input_external<'a, 'b, V, S>(
+        &mut self,
+        txin: TxInRef<'a>,
+        value: V,
+        signer: &S,
+    ) -> Result<InputSignature, Error>
+    where
+        V: Into<UnspentTxOutValue<'b>>,
+        S: ExternalSigner + ?Sized,
+    {
+        ensure!(
+            self.redeem_script.key_index(&signer.public_key()).is_some(),
+            Error::KeyNotInScript
+        );
+        let value = value.into();
+        if let Some(prev_script_pubkey) = value.script_pubkey(txin) {
+            ensure!(
+                *prev_script_pubkey == script_pubkey(&self.redeem_script),
+                Error::ScriptMismatch
+            );
+        }
+        sign::sign_input_external(txin, &self.redeem_script.script, value, signer)
+    }
+
+    /// Computes the [`BIP-143`][bip-143] compliant signatures for all inputs of the given
+    /// transaction, the values of the spent outputs are given in the input order.
+    ///
+    /// The result is the same as of the [`sign_input`][sign-input] calls for each input,
+    /// but the sighash components shared by the inputs are computed only once.
+    ///
+    /// [bip-143]: https://github.com/bitcoin/bips/blob/master/bip-0143.mediawiki
+    /// [sign-input]: #method.sign_input
+    pub fn sign_all_inputs<'b, V>(
+        &mut self,
+        transaction: &Transaction,
+        values: &[V],
+        secret_key: &SecretKey,
+    ) -> Result<Vec<InputSignature>, Error>
+    where
+        V: Clone + Into<UnspentTxOutValue<'b>>,
+    {
+        self.check_secret_key(secret_key)?;
+        sign::sign_all_inputs(
+            &self.context,
+            transaction,
+            &self.redeem_script.script,
+            &script_pubkey(&self.redeem_script),
+            values,
+            secret_key,
+            self.entropy,
+        )
+    }
+
+    /// Computes the sighashes for all inputs of the given transaction in the same way as
+    /// the [`sign_all_inputs`][sign-all-inputs] method does, but does not sign them.
+    ///
+    /// The returned report contains the spent outputs, their values, the sighashes
+    /// and the transaction outputs, so the signing request can be reviewed before
+    /// the actual signing.
+    ///
+    /// [sign-all-inputs]: #method.sign_all_inputs
+    pub fn dry_run<'b, V>(
+        &mut self,
+        transaction: &Transaction,
+        values: &[V],
+    ) -> Result<SigningReport, Error>
+    where
+        V: Clone + Into<UnspentTxOutValue<'b>>,
+    {
+        sign::signing_report(
+            transaction,
+            &self.redeem_script.script,
+            &script_pubkey(&self.redeem_script),
+            values,
+        )
+    }
+
+    /// Computes the [`BIP-143`][bip-143] compliant signature for the given input,
+    /// the spent output is looked up in the given provider.
+    ///
+    /// [bip-143]: https://github.com/bitcoin/bips/blob/master/bip-0143.mediawiki
+    pub fn sign_input_with_provider<'a, P: TxOutProvider + ?Sized>(
+        &mut self,
+        txin: TxInRef<'a>,
+        provider: &P,
+        secret_key: &SecretKey,
+    ) -> Result<InputSignature, Error> {
+        let prev_output = txin
+            .prev_output(provider)
+            .ok_or(Error::UnknownOutput(txin.input().previous_output))?;
+        self.sign_input(txin, prev_output, secret_key)
     }
 
     /// Checks correctness of the signature for the given input.
@@ -96,53 +294,333 @@ impl InputSigner {
         value: V,
         public_key: &PublicKey,
         signature: S,
-    ) -> Result<(), secp256k1::Error>
+    ) -> Result<(), Error>
     where
         V: Into<UnspentTxOutValue<'b>>,
         S: Into<InputSignatureRef<'c>>,
     {
         sign::verify_input_signature(
-            &self.context,
+            sign::verification_context(),
             txin,
-            &self.script.0,
+            &self.redeem_script.script,
             value,
             public_key,
-            signature.into().content(),
+            signature,
         )
     }
 
-    /// Collects the given input signatures into the witness data for the given transaction input. Thus, the input becomes spent.
-    pub fn spend_input<I: IntoIterator<Item = InputSignature>>(
+    /// Collects the given input signatures into the witness data for the input with the given
+    /// index. Thus, the input becomes spent.
+    ///
+    /// The number of signatures must be equal to the quorum, and the signatures must be ordered
+    /// in the same way as the corresponding public keys in the redeem script, otherwise
+    /// the witness will be rejected by the network, so the method returns an error instead.
+    pub fn spend_input<'a, V, I>(
         &self,
-        input: &mut TxIn,
+        transaction: &mut Transaction,
+        index: usize,
+        value: V,
+        signatures: I,
+    ) -> Result<(), Error>
+    where
+        V: Into<UnspentTxOutValue<'a>>,
+        I: IntoIterator<Item = InputSignature>,
+    {
+        let signatures = signatures.into_iter().collect::<Vec<_>>();
+        let txin = TxInRef::checked_new(transaction, index).ok_or(Error::NoSuchInput(index))?;
+        self.check_signatures(txin, value, &signatures)?;
+        transaction.input[index].witness = self.witness_data(signatures);
+        Ok(())
+    }
+
+    /// Merges the given input signatures into the witness data of the input with the given
+    /// index, which may already contain some signatures collected by the previous calls.
+    /// Returns the number of signatures still required to reach the quorum.
+    ///
+    /// The signatures are verified and ordered in the same way as the corresponding public
+    /// keys in the redeem script, and the signatures made by the same public key are
+    /// deduplicated, so the method can be called repeatedly with the same signatures.
+    /// This allows the participants to sign the raw transaction in turn.
+    pub fn append_signatures<'a, V, I>(
+        &self,
+        transaction: &mut Transaction,
+        index: usize,
+        value: V,
         signatures: I,
-    ) {
-        input.witness = self.witness_data(signatures.into_iter().map(Into::into));
+    ) -> Result<usize, Error>
+    where
+        V: Into<UnspentTxOutValue<'a>>,
+        I: IntoIterator<Item = InputSignature>,
+    {
+        let txin = TxInRef::checked_new(transaction, index).ok_or(Error::NoSuchInput(index))?;
+        let witness = &txin.input().witness;
+        let existing = if witness.is_empty() {
+            Vec::default()
+        } else {
+            let witness = parse_witness(witness)?;
+            ensure!(
+                witness.redeem_script == self.redeem_script,
+                Error::ScriptMismatch
+            );
+            witness.signatures
+        };
+
+        let sighash = sign::signature_hash(txin, &self.redeem_script.script, value);
+        let message = Message::from_slice(&sighash[..])?;
+        // Finds the position of the public key, which made the given signature.
+        let key_position = |signature: &InputSignature| {
+            sign::check_sighash_all(signature.into()).ok()?;
+            let signature = signature.to_signature().ok().filter(sign::is_low_s)?;
+            self.redeem_script.keys().iter().position(|key| {
+                sign::verification_context()
+                    .verify(&message, &signature, &key.public_key.key)
+                    .is_ok()
+            })
+        };
+
+        let mut collected = vec![None; self.redeem_script.keys().len()];
+        for (index, signature) in existing.into_iter().enumerate() {
+            // The witness items are counted from the dummy one.
+            let position =
+                key_position(&signature).ok_or(WitnessError::InvalidSignature(index + 1))?;
+            collected[position].get_or_insert(signature);
+        }
+        for (index, signature) in signatures.into_iter().enumerate() {
+            let position = key_position(&signature).ok_or(Error::InvalidSignature(index))?;
+            collected[position].get_or_insert(signature);
+        }
+
+        let signatures = collected
+            .into_iter()
+            .flatten()
+            .take(self.redeem_script.quorum())
+            .collect::<Vec<_>>();
+        let missing = self.redeem_script.quorum() - signatures.len();
+        transaction.input[index].witness = self.witness_data(signatures);
+        Ok(missing)
+    }
+
+    /// Checks signatures in the same way as the `OP_CHECKMULTISIG` opcode does.
+    pub(crate) fn check_signatures<'a, 'b, V: Into<UnspentTxOutValue<'b>>>(
+        &self,
+        txin: TxInRef<'a>,
+        value: V,
+        signatures: &[InputSignature],
+    ) -> Result<(), Error> {
+        let quorum = self.redeem_script.quorum();
+        ensure!(
+            signatures.len() == quorum,
+            Error::SignaturesCount {
+                quorum,
+                actual: signatures.len(),
+            }
+        );
+
+        let sighash = sign::signature_hash(txin, &self.redeem_script.script, value);
+        let message = Message::from_slice(&sighash[..])?;
+        let mut public_keys = self.redeem_script.keys().iter().map(|key| &key.public_key);
+        let mut matched_keys = Vec::with_capacity(signatures.len());
+        for (index, signature) in signatures.iter().enumerate() {
+            ensure!(
+                !signatures[..index].contains(signature),
+                Error::DuplicateSignature(index)
+            );
+            ensure!(
+                sign::check_sighash_all(signature.into()).is_ok(),
+                Error::InvalidSignature(index)
+            );
+            let signature = signature
+                .to_signature()
+                .ok()
+                .filter(sign::is_low_s)
+                .ok_or(Error::InvalidSignature(index))?;
+            let is_valid_for = |public_key: &PublicKey| {
+                sign::verification_context()
+                    .verify(&message, &signature, &public_key.key)
+                    .is_ok()
+            };
+            // Each signature must match one of the remaining public keys.
+            if let Some(public_key) = public_keys.by_ref().find(|key| is_valid_for(key)) {
+                matched_keys.push(public_key);
+                continue;
+            }
+            let is_duplicate = matched_keys.iter().any(|key| is_valid_for(key));
+            return Err(if is_duplicate {
+                Error::DuplicatePublicKey(index)
+            } else {
+                Error::InvalidSignature(index)
+            });
+        }
+        Ok(())
     }
 
-    fn witness_data<I: IntoIterator<Item = Vec<u8>>>(&self, signatures: I) -> Vec<Vec<u8>> {
-        let mut witness_stack = vec![Vec::default()];
-        witness_stack.extend(signatures);
-        witness_stack.push(self.script.0[..].to_vec());
+    fn check_secret_key(&self, secret_key: &SecretKey) -> Result<(), Error> {
+        let public_key =
+            secp256k1::PublicKey::from_secret_key(&self.context, secret_key).serialize();
+        ensure!(
+            self.redeem_script
+                .keys()
+                .iter()
+                .any(|key| key.bytes == public_key),
+            Error::KeyNotInScript
+        );
+        Ok(())
+    }
+
+    fn witness_data(&self, signatures: Vec<InputSignature>) -> Vec<Vec<u8>> {
+        // The witness stack consists of the dummy item, the signatures and the redeem script.
+        let mut witness_stack = Vec::with_capacity(signatures.len() + 2);
+        witness_stack.push(Vec::default());
+        witness_stack.extend(signatures.into_iter().map(Vec::from));
+        witness_stack.push(self.redeem_script.script.to_bytes());
         witness_stack
     }
 }
 
+/// An input signer, which holds the secret key of one of the redeem script participants,
+/// so the signing methods do not need it.
+#[derive(Debug)]
+pub struct OwnedInputSigner {
+    signer: InputSigner,
+    secret_key: StoredSecretKey,
+    /// The network of the private key, if the signer was created from the tagged key.
+    network: Option<Network>,
+}
+
+impl OwnedInputSigner {
+    /// Creates an input signer for the given redeem script and secret key. The public key
+    /// of the secret key must be one of the redeem script participants.
+    pub fn new(script: RedeemScript, secret_key: SecretKey) -> Result<OwnedInputSigner, Error> {
+        let signer = InputSigner::new(script);
+        signer.check_secret_key(&secret_key)?;
+        Ok(OwnedInputSigner {
+            signer,
+            secret_key: StoredSecretKey::new(secret_key),
+            network: None,
+        })
+    }
+
+    /// Creates an input signer for the given redeem script and private key. The public key
+    /// of the private key must be one of the redeem script participants.
+    ///
+    /// The signer remembers the network of the private key, so the [`address`][address]
+    /// method refuses to create the addresses of the other networks.
+    ///
+    /// [address]: #method.address
+    pub fn from_private_key(
+        script: RedeemScript,
+        private_key: PrivateKey,
+    ) -> Result<OwnedInputSigner, Error> {
+        let mut signer = OwnedInputSigner::new(script, private_key.key)?;
+        signer.network = Some(private_key.network);
+        Ok(signer)
+    }
+
+    /// Returns a reference to the underlying signer, which can be used to compute the addresses,
+    /// verify the signatures and collect them into the witness.
+    pub fn signer(&self) -> &InputSigner {
+        &self.signer
+    }
+
+    /// Returns the address of the outputs spendable by this signer in the given network.
+    /// Returns an error if the signer private key belongs to the other network.
+    pub fn address(&self, network: Network) -> Result<Address, Error> {
+        if let Some(actual) = self.network {
+            sign::check_network(actual, network)?;
+        }
+        Ok(self.signer.address(network))
+    }
+
+    /// Computes the [`BIP-143`][bip-143] compliant signature for the given input.
+    /// [Read more...][sign-input]
+    ///
+    /// [bip-143]: https://github.com/bitcoin/bips/blob/master/bip-0143.mediawiki
+    /// [sign-input]: struct.InputSigner.html#method.sign_input
+    pub fn sign_input<'a, 'b, V: Into<UnspentTxOutValue<'b>>>(
+        &mut self,
+        txin: TxInRef<'a>,
+        value: V,
+    ) -> Result<InputSignature, Error> {
+        self.signer.sign_input(txin, value, self.secret_key.get())
+    }
+
+    /// Computes the [`BIP-143`][bip-143] compliant signatures for all inputs of the given
+    /// transaction. [Read more...][sign-all-inputs]
+    ///
+    /// [bip-143]: https://github.com/bitcoin/bips/blob/master/bip-0143.mediawiki
+    /// [sign-all-inputs]: struct.InputSigner.html#method.sign_all_inputs
+    pub fn sign_all_inputs<'b, V>(
+        &mut self,
+        transaction: &Transaction,
+        values: &[V],
+    ) -> Result<Vec<InputSignature>, Error>
+    where
+        V: Clone + Into<UnspentTxOutValue<'b>>,
+    {
+        self.signer
+            .sign_all_inputs(transaction, values, self.secret_key.get())
+    }
+
+    /// Computes the [`BIP-143`][bip-143] compliant signature for the given input,
+    /// the spent output is looked up in the given provider.
+    ///
+    /// [bip-143]: https://github.com/bitcoin/bips/blob/master/bip-0143.mediawiki
+    pub fn sign_input_with_provider<'a, P: TxOutProvider + ?Sized>(
+        &mut self,
+        txin: TxInRef<'a>,
+        provider: &P,
+    ) -> Result<InputSignature, Error> {
+        self.signer
+            .sign_input_with_provider(txin, provider, self.secret_key.get())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use bitcoin::{
         blockdata::opcodes::all::OP_RETURN,
         blockdata::script::{Builder, Script},
-        blockdata::transaction::{OutPoint, Transaction, TxIn, TxOut},
+        blockdata::transaction::{OutPoint, SigHashType, Transaction, TxIn, TxOut},
+        network::constants::Network,
     };
+    use secp256k1::Secp256k1;
+
+    use std::{ptr, sync::Arc};
 
     use crate::{
         multisig::RedeemScriptBuilder,
-        p2wsh,
-        test_data::{btc_tx_from_hex, keypair_from_wif},
-        InputSignatureRef, TxInRef,
+        p2wsh, sign,
+        test_data::{btc_tx_from_hex, fake_funding_tx, keypair_from_wif},
+        Amount, Error, ExternalSigner, ExtraEntropy, InputSignature, InputSignatureRef,
+        SignatureError, TxInRef, UnspentTxOutValue, WitnessError,
     };
 
+    /// The external signer, which returns the high `S` signatures or signs the wrong data.
+    struct TestSigner {
+        public_key: bitcoin::PublicKey,
+        secret_key: secp256k1::SecretKey,
+        faulty: bool,
+    }
+
+    impl ExternalSigner for TestSigner {
+        fn public_key(&self) -> bitcoin::PublicKey {
+            self.public_key
+        }
+
+        fn sign_sighash(&self, sighash: &[u8; 32]) -> Result<Vec<u8>, Error> {
+            let data = if self.faulty { [0; 32] } else { *sighash };
+            let message = secp256k1::Message::from_slice(&data).unwrap();
+            let signature = sign::shared_context().sign(&message, &self.secret_key);
+            // Negates the `S` value.
+            let mut compact = signature.serialize_compact();
+            let mut s = secp256k1::SecretKey::from_slice(&compact[32..]).unwrap();
+            s.negate_assign();
+            compact[32..].copy_from_slice(&s[..]);
+            let signature = secp256k1::Signature::from_compact(&compact).unwrap();
+            Ok(signature.serialize_der().to_vec())
+        }
+    }
+
     #[test]
     fn test_multisig_native_segwit() {
         let keypairs = vec![
@@ -224,7 +702,9 @@ mod tests {
                 signature
             })
             .collect::<Vec<_>>();
-        signer.spend_input(&mut transaction.input[0], signatures);
+        signer
+            .spend_input(&mut transaction, 0, &prev_tx, signatures)
+            .unwrap();
         // Checks output.
         assert_eq!(
             transaction,
@@ -283,4 +763,577 @@ mod tests {
             )
             .expect("Signature should be correct");
     }
+
+    #[test]
+    fn test_spend_input_incorrect_signatures() {
+        let keypairs = vec![
+            "cPHmynxvqfr7sXsJcohiGzoPGBShggxL6VWUdW14skohFZ1LQoeV",
+            "cTtSTL1stvg2tmK349WTmQDfHLMLqkkxwuo8ZJeQov9zEhtYtb4u",
+            "cQZZ7WvJUb6hXxCq9SF6516vb9bavQRPn2t3g9LFUtAoZuY7vNFk",
+        ]
+        .into_iter()
+        .map(keypair_from_wif)
+        .collect::<Vec<_>>();
+        let redeem_script = RedeemScriptBuilder::with_public_keys(keypairs.iter().map(|x| x.0))
+            .quorum(2)
+            .to_script()
+            .unwrap();
+        let mut transaction = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::default(),
+                script_sig: Script::default(),
+                sequence: 0xFFFF_FFFF,
+                witness: Vec::default(),
+            }],
+            output: vec![TxOut {
+                value: 0,
+                script_pubkey: Builder::new().push_opcode(OP_RETURN).into_script(),
+            }],
+        };
+        let value = 10_000;
+
+        let mut signer = p2wsh::InputSigner::new(redeem_script);
+        let signatures = keypairs[0..2]
+            .iter()
+            .map(|keypair| {
+                let txin = TxInRef::new(&transaction, 0);
+                signer.sign_input(txin, value, &keypair.1.key).unwrap()
+            })
+            .collect::<Vec<_>>();
+        // The signing key must belong to the redeem script.
+        let (_, foreign_key) =
+            keypair_from_wif("cMs8EwSJwfQ5DrVqYcDgjKV52k3DrGZhK1MDNrabY16WxPjvACgG");
+        assert_eq!(
+            signer.sign_input(TxInRef::new(&transaction, 0), value, &foreign_key.key),
+            Err(Error::KeyNotInScript)
+        );
+
+        assert_eq!(
+            signer.spend_input(&mut transaction, 0, value, signatures[0..1].to_vec()),
+            Err(Error::SignaturesCount {
+                quorum: 2,
+                actual: 1
+            })
+        );
+        let reversed = signatures.iter().rev().cloned().collect::<Vec<_>>();
+        assert_eq!(
+            signer.spend_input(&mut transaction, 0, value, reversed),
+            Err(Error::InvalidSignature(1))
+        );
+        assert_eq!(
+            signer.spend_input(&mut transaction, 0, value + 1, signatures.clone()),
+            Err(Error::InvalidSignature(0))
+        );
+        assert_eq!(
+            signer.spend_input(&mut transaction, 1, value, signatures.clone()),
+            Err(Error::NoSuchInput(1))
+        );
+        let duplicates = vec![signatures[0].clone(), signatures[0].clone()];
+        assert_eq!(
+            signer.spend_input(&mut transaction, 0, value, duplicates),
+            Err(Error::DuplicateSignature(1))
+        );
+        // The `SIGHASH_ALL` signature with the different sighash type byte is invalid.
+        let retagged = vec![
+            InputSignature::new(signatures[0].content(), SigHashType::AllPlusAnyoneCanPay).unwrap(),
+            signatures[1].clone(),
+        ];
+        assert_eq!(
+            signer.spend_input(&mut transaction, 0, value, retagged),
+            Err(Error::InvalidSignature(0))
+        );
+        // The other signature made by the same key.
+        let other_signature = signer
+            .set_extra_entropy(ExtraEntropy::Fixed([1; 32]))
+            .sign_input(TxInRef::new(&transaction, 0), value, &keypairs[0].1.key)
+            .unwrap();
+        assert_ne!(other_signature, signatures[0]);
+        let same_key = vec![signatures[0].clone(), other_signature];
+        assert_eq!(
+            signer.spend_input(&mut transaction, 0, value, same_key),
+            Err(Error::DuplicatePublicKey(1))
+        );
+        assert!(transaction.input[0].witness.is_empty());
+
+        signer
+            .spend_input(&mut transaction, 0, value, signatures.clone())
+            .unwrap();
+        assert_eq!(transaction.input[0].witness.len(), 4);
+    }
+
+    #[test]
+    fn test_sign_all_inputs() {
+        let (public_key, private_key) =
+            keypair_from_wif("cPHmynxvqfr7sXsJcohiGzoPGBShggxL6VWUdW14skohFZ1LQoeV");
+        let redeem_script = RedeemScriptBuilder::with_public_keys(vec![public_key])
+            .to_script()
+            .unwrap();
+        let prev_output = TxOut {
+            value: 3_000,
+            script_pubkey: p2wsh::script_pubkey(&redeem_script),
+        };
+        let transaction = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: (0..3)
+                .map(|vout| TxIn {
+                    previous_output: OutPoint {
+                        txid: Default::default(),
+                        vout,
+                    },
+                    script_sig: Script::default(),
+                    sequence: 0xFFFF_FFFF,
+                    witness: Vec::default(),
+                })
+                .collect(),
+            output: vec![TxOut {
+                value: 5_000,
+                script_pubkey: Builder::new().push_opcode(OP_RETURN).into_script(),
+            }],
+        };
+
+        let mut signer = p2wsh::InputSigner::new(redeem_script);
+        let values = [
+            UnspentTxOutValue::from(1_000),
+            2_000.into(),
+            (&prev_output).into(),
+        ];
+        let signatures = signer
+            .sign_all_inputs(&transaction, &values, &private_key.key)
+            .unwrap();
+        assert_eq!(signatures.len(), 3);
+        for (index, (signature, value)) in signatures.iter().zip(values.iter()).enumerate() {
+            let txin = TxInRef::new(&transaction, index);
+            assert_eq!(
+                signer.sign_input(txin, *value, &private_key.key).unwrap(),
+                *signature
+            );
+        }
+
+        assert_eq!(
+            signer.sign_all_inputs(&transaction, &[1_000, 2_000], &private_key.key),
+            Err(Error::OutputsCount {
+                inputs: 3,
+                outputs: 2
+            })
+        );
+        let foreign_output = TxOut {
+            value: 3_000,
+            script_pubkey: Script::default(),
+        };
+        assert_eq!(
+            signer.sign_all_inputs(
+                &transaction,
+                &[&prev_output, &prev_output, &foreign_output],
+                &private_key.key
+            ),
+            Err(Error::input(2, Error::ScriptMismatch))
+        );
+    }
+
+    #[test]
+    fn test_dry_run() {
+        let (public_key, private_key) =
+            keypair_from_wif("cPHmynxvqfr7sXsJcohiGzoPGBShggxL6VWUdW14skohFZ1LQoeV");
+        let redeem_script = RedeemScriptBuilder::with_public_keys(vec![public_key])
+            .to_script()
+            .unwrap();
+        let transaction = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: (0..2)
+                .map(|vout| TxIn {
+                    previous_output: OutPoint {
+                        txid: Default::default(),
+                        vout,
+                    },
+                    script_sig: Script::default(),
+                    sequence: 0xFFFF_FFFF,
+                    witness: Vec::default(),
+                })
+                .collect(),
+            output: vec![
+                TxOut {
+                    value: 2_500,
+                    script_pubkey: p2wsh::script_pubkey(&redeem_script),
+                },
+                TxOut {
+                    value: 0,
+                    script_pubkey: Builder::new().push_opcode(OP_RETURN).into_script(),
+                },
+            ],
+        };
+
+        let mut signer = p2wsh::InputSigner::new(redeem_script.clone());
+        let report = signer.dry_run(&transaction, &[1_000, 2_000]).unwrap();
+        assert_eq!(report.outputs, transaction.output);
+        assert_eq!(report.input_value(), Amount::from_sat(3_000));
+        assert_eq!(report.output_value(), Amount::from_sat(2_500));
+        assert_eq!(report.fee(), Some(Amount::from_sat(500)));
+        assert_eq!(
+            report.destination(0, Network::Testnet),
+            Some(p2wsh::address(&redeem_script, Network::Testnet))
+        );
+        assert_eq!(report.destination(1, Network::Testnet), None);
+
+        for (index, input) in report.inputs.iter().enumerate() {
+            let txin = TxInRef::new(&transaction, index);
+            assert_eq!(input.outpoint, txin.input().previous_output);
+            assert_eq!(
+                input.sighash,
+                signer.signature_hash(txin, input.value.as_sat())
+            );
+            // The reported sighash is the one being signed.
+            let signature = signer
+                .sign_input(txin, input.value.as_sat(), &private_key.key)
+                .unwrap();
+            let message = secp256k1::Message::from_slice(&input.sighash[..]).unwrap();
+            sign::verification_context()
+                .verify(
+                    &message,
+                    &signature.to_signature().unwrap(),
+                    &public_key.key,
+                )
+                .unwrap();
+        }
+
+        assert_eq!(
+            signer.dry_run(&transaction, &[1_000]),
+            Err(Error::OutputsCount {
+                inputs: 2,
+                outputs: 1
+            })
+        );
+    }
+
+    #[test]
+    fn test_signers_share_context() {
+        let (public_key, _) =
+            keypair_from_wif("cPHmynxvqfr7sXsJcohiGzoPGBShggxL6VWUdW14skohFZ1LQoeV");
+        let redeem_script = RedeemScriptBuilder::with_public_keys(vec![public_key])
+            .to_script()
+            .unwrap();
+
+        let first = p2wsh::InputSigner::new(redeem_script.clone());
+        let mut second = p2wsh::InputSigner::new(redeem_script.clone());
+        assert!(ptr::eq(
+            first.secp256k1_context(),
+            second.secp256k1_context()
+        ));
+        // The mutable access detaches the engine from the shared one.
+        let _ = second.secp256k1_context_mut();
+        assert!(!ptr::eq(
+            first.secp256k1_context(),
+            second.secp256k1_context()
+        ));
+
+        let context = Arc::new(Secp256k1::new());
+        let third = p2wsh::InputSigner::with_context(redeem_script, Arc::clone(&context));
+        assert!(ptr::eq(third.secp256k1_context(), context.as_ref()));
+    }
+
+    #[test]
+    fn test_signer_address() {
+        let (public_key, _) =
+            keypair_from_wif("cPHmynxvqfr7sXsJcohiGzoPGBShggxL6VWUdW14skohFZ1LQoeV");
+        let redeem_script = RedeemScriptBuilder::with_public_keys(vec![public_key])
+            .to_script()
+            .unwrap();
+
+        let signer = p2wsh::InputSigner::new(redeem_script.clone());
+        assert_eq!(signer.redeem_script(), &redeem_script);
+        let address = signer.address(Network::Testnet);
+        assert_eq!(address, p2wsh::address(&redeem_script, Network::Testnet));
+        assert_eq!(signer.script_pubkey(), address.script_pubkey());
+    }
+
+    #[test]
+    fn test_parse_witness() {
+        let keypairs = vec![
+            "cPHmynxvqfr7sXsJcohiGzoPGBShggxL6VWUdW14skohFZ1LQoeV",
+            "cTtSTL1stvg2tmK349WTmQDfHLMLqkkxwuo8ZJeQov9zEhtYtb4u",
+        ]
+        .into_iter()
+        .map(keypair_from_wif)
+        .collect::<Vec<_>>();
+        let redeem_script = RedeemScriptBuilder::with_public_keys(keypairs.iter().map(|x| x.0))
+            .to_script()
+            .unwrap();
+        let mut transaction = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::default(),
+                script_sig: Script::default(),
+                sequence: 0xFFFF_FFFF,
+                witness: Vec::default(),
+            }],
+            output: vec![TxOut {
+                value: 0,
+                script_pubkey: Builder::new().push_opcode(OP_RETURN).into_script(),
+            }],
+        };
+
+        let mut signer = p2wsh::InputSigner::new(redeem_script.clone());
+        let signatures = keypairs
+            .iter()
+            .map(|keypair| {
+                let txin = TxInRef::new(&transaction, 0);
+                signer.sign_input(txin, 1_000, &keypair.1.key).unwrap()
+            })
+            .collect::<Vec<_>>();
+        signer
+            .spend_input(&mut transaction, 0, 1_000, signatures.clone())
+            .unwrap();
+
+        let witness = p2wsh::parse_witness(&transaction.input[0].witness).unwrap();
+        assert_eq!(witness.signatures, signatures);
+        assert_eq!(witness.redeem_script, redeem_script);
+        // Incorrect witnesses.
+        let mut items = transaction.input[0].witness.clone();
+        items[2].pop();
+        assert_eq!(
+            p2wsh::parse_witness(&items),
+            Err(Error::Witness(WitnessError::InvalidSignature(2)))
+        );
+        items[0].push(0);
+        assert_eq!(
+            p2wsh::parse_witness(&items),
+            Err(Error::Witness(WitnessError::NoDummyItem))
+        );
+        assert_eq!(
+            p2wsh::parse_witness(&items[0..1]),
+            Err(Error::Witness(WitnessError::ItemsCount(1)))
+        );
+    }
+
+    #[test]
+    fn test_owned_signer_network_mismatch() {
+        let (pk, mut sk) = keypair_from_wif("cPHmynxvqfr7sXsJcohiGzoPGBShggxL6VWUdW14skohFZ1LQoeV");
+        let redeem_script = RedeemScriptBuilder::with_public_keys(vec![pk])
+            .to_script()
+            .unwrap();
+
+        let signer = p2wsh::OwnedInputSigner::new(redeem_script.clone(), sk.key).unwrap();
+        assert!(signer.address(Network::Bitcoin).is_ok());
+        let signer = p2wsh::OwnedInputSigner::from_private_key(redeem_script.clone(), sk).unwrap();
+        assert_eq!(
+            signer.address(Network::Regtest).unwrap(),
+            p2wsh::address(&redeem_script, Network::Regtest)
+        );
+        assert_eq!(
+            signer.address(Network::Bitcoin).unwrap_err(),
+            Error::NetworkMismatch {
+                expected: Network::Bitcoin,
+                actual: Network::Testnet,
+            }
+        );
+
+        sk.network = Network::Bitcoin;
+        let signer = p2wsh::OwnedInputSigner::from_private_key(redeem_script, sk).unwrap();
+        assert!(signer.address(Network::Bitcoin).is_ok());
+        assert!(signer.address(Network::Signet).is_err());
+    }
+
+    #[test]
+    fn test_owned_input_signer() {
+        let keypairs = vec![
+            "cPHmynxvqfr7sXsJcohiGzoPGBShggxL6VWUdW14skohFZ1LQoeV",
+            "cTtSTL1stvg2tmK349WTmQDfHLMLqkkxwuo8ZJeQov9zEhtYtb4u",
+        ]
+        .into_iter()
+        .map(keypair_from_wif)
+        .collect::<Vec<_>>();
+        let redeem_script = RedeemScriptBuilder::with_public_keys(vec![keypairs[0].0])
+            .to_script()
+            .unwrap();
+        assert_eq!(
+            p2wsh::OwnedInputSigner::new(redeem_script.clone(), keypairs[1].1.key).unwrap_err(),
+            Error::KeyNotInScript
+        );
+
+        let prev_tx = fake_funding_tx(&p2wsh::script_pubkey(&redeem_script), 50_000);
+        let transaction = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(prev_tx.txid(), 0),
+                script_sig: Script::default(),
+                sequence: 0xFFFF_FFFF,
+                witness: Vec::default(),
+            }],
+            output: vec![TxOut::default()],
+        };
+        let txin = TxInRef::new(&transaction, 0);
+
+        let mut owned =
+            p2wsh::OwnedInputSigner::new(redeem_script.clone(), keypairs[0].1.key).unwrap();
+        assert_eq!(owned.signer().redeem_script(), &redeem_script);
+        let signature = owned.sign_input(txin, &prev_tx).unwrap();
+        owned
+            .signer()
+            .verify_input(txin, &prev_tx, &keypairs[0].0, &signature)
+            .unwrap();
+        assert_eq!(
+            owned.sign_input_with_provider(txin, &prev_tx).unwrap(),
+            signature
+        );
+        assert_eq!(
+            owned.sign_all_inputs(&transaction, &[&prev_tx]).unwrap(),
+            vec![signature]
+        );
+    }
+
+    #[test]
+    fn test_append_signatures() {
+        let keypairs = vec![
+            "cPHmynxvqfr7sXsJcohiGzoPGBShggxL6VWUdW14skohFZ1LQoeV",
+            "cTtSTL1stvg2tmK349WTmQDfHLMLqkkxwuo8ZJeQov9zEhtYtb4u",
+            "cQZZ7WvJUb6hXxCq9SF6516vb9bavQRPn2t3g9LFUtAoZuY7vNFk",
+        ]
+        .into_iter()
+        .map(keypair_from_wif)
+        .collect::<Vec<_>>();
+        let redeem_script = RedeemScriptBuilder::with_public_keys(keypairs.iter().map(|x| x.0))
+            .quorum(2)
+            .to_script()
+            .unwrap();
+        let mut signer = p2wsh::InputSigner::new(redeem_script.clone());
+
+        let prev_tx = fake_funding_tx(&p2wsh::script_pubkey(&redeem_script), 50_000);
+        let mut transaction = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(prev_tx.txid(), 0),
+                script_sig: Script::default(),
+                sequence: 0xFFFF_FFFF,
+                witness: Vec::default(),
+            }],
+            output: vec![TxOut::default()],
+        };
+        let signatures = keypairs
+            .iter()
+            .map(|(_, sk)| {
+                let txin = TxInRef::new(&transaction, 0);
+                signer.sign_input(txin, &prev_tx, &sk.key).unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        // The participants sign in turn, and the repeated calls do not change the witness.
+        for _ in 0..2 {
+            let missing = signer
+                .append_signatures(&mut transaction, 0, &prev_tx, vec![signatures[2].clone()])
+                .unwrap();
+            assert_eq!(missing, 1);
+            let witness = p2wsh::parse_witness(&transaction.input[0].witness).unwrap();
+            assert_eq!(witness.signatures, vec![signatures[2].clone()]);
+        }
+        let missing = signer
+            .append_signatures(
+                &mut transaction,
+                0,
+                &prev_tx,
+                vec![signatures[0].clone(), signatures[2].clone()],
+            )
+            .unwrap();
+        assert_eq!(missing, 0);
+        let witness = p2wsh::parse_witness(&transaction.input[0].witness).unwrap();
+        assert_eq!(
+            witness.signatures,
+            vec![signatures[0].clone(), signatures[2].clone()]
+        );
+        let txin = TxInRef::new(&transaction, 0);
+        signer
+            .check_signatures(txin, &prev_tx, &witness.signatures)
+            .unwrap();
+
+        // The signature of the other transaction is rejected.
+        let mut other = transaction.clone();
+        other.lock_time = 1;
+        let foreign = signer
+            .sign_input(TxInRef::new(&other, 0), &prev_tx, &keypairs[1].1.key)
+            .unwrap();
+        assert_eq!(
+            signer.append_signatures(&mut transaction, 0, &prev_tx, vec![foreign]),
+            Err(Error::InvalidSignature(0))
+        );
+
+        // The witness of the other redeem script is rejected.
+        let other_script = RedeemScriptBuilder::with_public_keys(vec![keypairs[0].0])
+            .to_script()
+            .unwrap();
+        assert_eq!(
+            p2wsh::InputSigner::new(other_script).append_signatures(
+                &mut transaction,
+                0,
+                &prev_tx,
+                vec![signatures[0].clone()]
+            ),
+            Err(Error::ScriptMismatch)
+        );
+    }
+
+    #[test]
+    fn test_sign_input_external() {
+        let keypairs = vec![
+            "cPHmynxvqfr7sXsJcohiGzoPGBShggxL6VWUdW14skohFZ1LQoeV",
+            "cTtSTL1stvg2tmK349WTmQDfHLMLqkkxwuo8ZJeQov9zEhtYtb4u",
+            "cQZZ7WvJUb6hXxCq9SF6516vb9bavQRPn2t3g9LFUtAoZuY7vNFk",
+        ]
+        .into_iter()
+        .map(keypair_from_wif)
+        .collect::<Vec<_>>();
+        let redeem_script =
+            RedeemScriptBuilder::with_public_keys(keypairs[..2].iter().map(|x| x.0))
+                .quorum(2)
+                .to_script()
+                .unwrap();
+        let mut signer = p2wsh::InputSigner::new(redeem_script.clone());
+        let prev_tx = fake_funding_tx(&p2wsh::script_pubkey(&redeem_script), 50_000);
+        let transaction = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(prev_tx.txid(), 0),
+                script_sig: Script::default(),
+                sequence: 0xFFFF_FFFF,
+                witness: Vec::default(),
+            }],
+            output: vec![TxOut::default()],
+        };
+        let txin = TxInRef::new(&transaction, 0);
+        let external = |index: usize, faulty| TestSigner {
+            public_key: keypairs[index].0,
+            secret_key: keypairs[index].1.key,
+            faulty,
+        };
+
+        // The high `S` signature is normalized.
+        let signature = signer
+            .sign_input_external(txin, &prev_tx, &external(0, false))
+            .unwrap();
+        signer
+            .verify_input(txin, &prev_tx, &keypairs[0].0, &signature)
+            .unwrap();
+        assert_eq!(
+            signature,
+            signer
+                .sign_input(txin, &prev_tx, &keypairs[0].1.key)
+                .unwrap()
+        );
+
+        assert_eq!(
+            signer.sign_input_external(txin, &prev_tx, &external(1, true)),
+            Err(Error::from(SignatureError::from(
+                secp256k1::Error::IncorrectSignature
+            )))
+        );
+        assert_eq!(
+            signer.sign_input_external(txin, &prev_tx, &external(2, false)),
+            Err(Error::KeyNotInScript)
+        );
+    }
 }
@@ -0,0 +1,645 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Helpers for the [payjoin][bip-78] protocol, in which the receiver of the payment
+//! contributes its own input to the transaction.
+//!
+//! The protocol consists of the following steps:
+//!
+//! 1. The sender creates and signs the original transaction, and sends it as a PSBT.
+//! 2. The receiver checks it with [`check_original`], and creates the proposal with
+//!    [`create_proposal`], which adds the receiver input and increases the receiver output
+//!    by its value. Then the receiver signs its input with [`sign_p2wpk_input`] or
+//!    [`finalize_p2wsh_input`] and sends the proposal back.
+//! 3. The sender checks the proposal with [`check_proposal`], signs its inputs again
+//!    in the same way and broadcasts the extracted transaction.
+//!
+//! The transport (the HTTP endpoint of the receiver) is out of the scope of this module.
+//!
+//! [bip-78]: https://github.com/bitcoin/bips/blob/master/bip-0078.mediawiki
+//! [`check_original`]: fn.check_original.html
+//! [`create_proposal`]: fn.create_proposal.html
+//! [`check_proposal`]: fn.check_proposal.html
+//! [`sign_p2wpk_input`]: fn.sign_p2wpk_input.html
+//! [`finalize_p2wsh_input`]: fn.finalize_p2wsh_input.html
+
+use bitcoin::{
+    blockdata::{
+        script::Script,
+        transaction::{OutPoint, TxIn, TxOut},
+    },
+    util::psbt::{self, PartiallySignedTransaction},
+};
+use secp256k1::SecretKey;
+use thiserror::Error;
+
+use std::mem;
+
+use crate::{p2wpk, p2wsh, Amount, Error, InputSignature, TxInRef};
+
+/// Possible errors of the payjoin protocol.
+#[derive(Debug, Copy, Clone, Error, PartialEq)]
+pub enum PayjoinError {
+    /// The input of the original transaction is not finalized.
+    #[error("The input is not finalized.")]
+    NotFinalized,
+    /// The sender input is still signed in the proposal.
+    #[error("The sender input must not be signed in the proposal.")]
+    SenderInputSigned,
+    /// The version or lock time of the transaction is changed by the receiver.
+    #[error("The version or lock time of the transaction is changed.")]
+    TransactionChanged,
+    /// The proposal does not contain the input of the original transaction.
+    #[error("The proposal does not contain the input {0} of the original transaction.")]
+    MissingInput(OutPoint),
+    /// The proposal does not contain the sender output of the original transaction,
+    /// or decreases its value.
+    #[error("The proposal does not contain the output {0} of the original transaction.")]
+    MissingOutput(usize),
+    /// The additional fee paid by the sender exceeds the maximum.
+    #[error("The additional fee {actual} exceeds the maximum {max}.")]
+    FeeTooHigh {
+        /// The maximum additional fee.
+        max: u64,
+        /// The additional fee of the proposal.
+        actual: u64,
+    },
+    /// The sender fee output is decreased by more than the fee is increased, so the difference
+    /// goes to the receiver instead of the miners.
+    #[error(
+        "The fee output is decreased by {decrease}, but the fee is increased by {additional_fee}."
+    )]
+    FeeOutputDecrease {
+        /// The decrease of the fee output value.
+        decrease: u64,
+        /// The additional fee of the proposal.
+        additional_fee: u64,
+    },
+    /// The transaction has no output with the given index.
+    #[error("The transaction has no output with index {0}.")]
+    NoSuchOutput(usize),
+}
+
+/// The parameters of the sender, which are used to check the proposal.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SenderParams {
+    /// The script pubkey of the payment receiver, whose output may be changed by the receiver.
+    pub payee_script_pubkey: Script,
+    /// The maximum fee, which the sender is ready to pay in addition to the original fee.
+    pub max_additional_fee: Amount,
+    /// The index of the sender output in the original transaction, from which the additional
+    /// fee can be paid.
+    pub fee_output: Option<usize>,
+}
+
+/// Checks the original transaction received from the sender: all inputs must be finalized
+/// and have the spent outputs. Returns the fee of the original transaction.
+pub fn check_original(original: &PartiallySignedTransaction) -> Result<u64, Error> {
+    for (index, input) in original.inputs.iter().enumerate() {
+        ensure!(
+            input.final_script_sig.is_some() || input.final_script_witness.is_some(),
+            Error::input(index, PayjoinError::NotFinalized)
+        );
+    }
+    fee(original)
+}
+
+/// Creates the payjoin proposal from the original transaction: appends the given receiver
+/// input and increases the value of the receiver output with the given index by the value
+/// of the spent output.
+///
+/// The sender inputs of the proposal are cleared, since the sender signs them again.
+/// The receiver input is the last one, it has to be signed by the receiver, and its
+/// witness has to be put into the `final_script_witness` field.
+pub fn create_proposal(
+    original: &PartiallySignedTransaction,
+    receiver_input: (OutPoint, TxOut),
+    receiver_output: usize,
+) -> Result<PartiallySignedTransaction, Error> {
+    let mut proposal = original.clone();
+    proposal.global.xpub.clear();
+
+    let output = proposal
+        .global
+        .unsigned_tx
+        .output
+        .get_mut(receiver_output)
+        .ok_or(PayjoinError::NoSuchOutput(receiver_output))?;
+    output.value = output
+        .value
+        .checked_add(receiver_input.1.value)
+        .ok_or(Error::ValueOverflow)?;
+
+    for input in &mut proposal.inputs {
+        let witness_utxo = input.witness_utxo.take();
+        let non_witness_utxo = input.non_witness_utxo.take();
+        *input = psbt::Input {
+            witness_utxo,
+            non_witness_utxo,
+            ..psbt::Input::default()
+        };
+    }
+
+    let (outpoint, spent_output) = receiver_input;
+    proposal.global.unsigned_tx.input.push(TxIn {
+        previous_output: outpoint,
+        script_sig: Script::default(),
+        sequence: original.global.unsigned_tx.input[0].sequence,
+        witness: Vec::default(),
+    });
+    proposal.inputs.push(psbt::Input {
+        witness_utxo: Some(spent_output),
+        ..psbt::Input::default()
+    });
+    Ok(proposal)
+}
+
+/// Checks the proposal received from the receiver:
+///
+/// - The version and lock time of the transaction are not changed.
+/// - All original inputs are present with the same sequence and are not signed,
+///   and the receiver inputs are finalized.
+/// - All original outputs, except the payee output, are present, and only the fee output
+///   value is decreased by at most the maximum additional fee.
+/// - The fee is increased by at most the maximum additional fee, and at least by
+///   the decrease of the fee output, so the receiver cannot take the sender funds.
+///
+/// After the check, the sender signs its inputs of the proposal.
+pub fn check_proposal(
+    original: &PartiallySignedTransaction,
+    proposal: &PartiallySignedTransaction,
+    params: &SenderParams,
+) -> Result<(), Error> {
+    let original_tx = &original.global.unsigned_tx;
+    let proposal_tx = &proposal.global.unsigned_tx;
+    ensure!(
+        original_tx.version == proposal_tx.version
+            && original_tx.lock_time == proposal_tx.lock_time,
+        PayjoinError::TransactionChanged
+    );
+
+    for txin in &original_tx.input {
+        let position = proposal_tx
+            .input
+            .iter()
+            .position(|input| input.previous_output == txin.previous_output)
+            .ok_or(PayjoinError::MissingInput(txin.previous_output))?;
+        ensure!(
+            proposal_tx.input[position].sequence == txin.sequence,
+            PayjoinError::TransactionChanged
+        );
+    }
+    for (index, (txin, input)) in proposal_tx.input.iter().zip(&proposal.inputs).enumerate() {
+        let is_signed = input.final_script_sig.is_some() || input.final_script_witness.is_some();
+        let is_sender_input = original_tx
+            .input
+            .iter()
+            .any(|input| input.previous_output == txin.previous_output);
+        if is_sender_input {
+            ensure!(
+                !is_signed,
+                Error::input(index, PayjoinError::SenderInputSigned)
+            );
+        } else {
+            ensure!(is_signed, Error::input(index, PayjoinError::NotFinalized));
+        }
+    }
+
+    let mut fee_output_decrease = 0;
+    // Each original output must match a separate proposal output.
+    let mut is_matched = vec![false; proposal_tx.output.len()];
+    for (index, output) in original_tx.output.iter().enumerate() {
+        if output.script_pubkey == params.payee_script_pubkey {
+            continue;
+        }
+        let find = |same_value: bool| {
+            proposal_tx
+                .output
+                .iter()
+                .zip(&is_matched)
+                .position(|(o, is_matched)| {
+                    !is_matched
+                        && o.script_pubkey == output.script_pubkey
+                        && (!same_value || o.value == output.value)
+                })
+        };
+        // The unchanged outputs are preferred, if the outputs share the script pubkey.
+        let position = find(true)
+            .or_else(|| find(false))
+            .ok_or(PayjoinError::MissingOutput(index))?;
+        is_matched[position] = true;
+        let new_value = proposal_tx.output[position].value;
+        if new_value < output.value {
+            ensure!(
+                params.fee_output == Some(index),
+                PayjoinError::MissingOutput(index)
+            );
+            fee_output_decrease = output.value - new_value;
+        }
+    }
+
+    let additional_fee = fee(proposal)?.saturating_sub(fee(original)?);
+    let actual = additional_fee.max(fee_output_decrease);
+    ensure!(
+        actual <= params.max_additional_fee.as_sat(),
+        PayjoinError::FeeTooHigh {
+            max: params.max_additional_fee.as_sat(),
+            actual,
+        }
+    );
+    ensure!(
+        fee_output_decrease <= additional_fee,
+        PayjoinError::FeeOutputDecrease {
+            decrease: fee_output_decrease,
+            additional_fee,
+        }
+    );
+    Ok(())
+}
+
+/// Signs the `P2WPK` input with the given index of the PSBT and puts its witness into
+/// the `final_script_witness` field. The spent output is taken from the PSBT input and
+/// must match the signer.
+///
+/// The receiver signs its input of the proposal in this way, and the sender signs its
+/// inputs again after the proposal is checked.
+pub fn sign_p2wpk_input(
+    psbt: &mut PartiallySignedTransaction,
+    index: usize,
+    signer: &mut p2wpk::InputSigner,
+    secret_key: &SecretKey,
+) -> Result<(), Error> {
+    let spent_output = checked_spent_output(psbt, index)?;
+    let transaction = &psbt.global.unsigned_tx;
+    let signature =
+        signer.sign_input(TxInRef::new(transaction, index), &spent_output, secret_key)?;
+    let mut txin = transaction.input[index].clone();
+    signer.spend_input(&mut txin, signature);
+    psbt.inputs[index].final_script_witness = Some(txin.witness);
+    Ok(())
+}
+
+/// Puts the witness of the `P2WSH` multisig input with the given index of the PSBT into
+/// the `final_script_witness` field. The spent output is taken from the PSBT input and
+/// must match the redeem script of the signer.
+///
+/// The signatures must be made by the participants for the unsigned transaction of the PSBT.
+/// They are checked in the same way as the [`spend_input`][spend-input] method does.
+///
+/// [spend-input]: ../p2wsh/struct.InputSigner.html#method.spend_input
+pub fn finalize_p2wsh_input<I>(
+    psbt: &mut PartiallySignedTransaction,
+    index: usize,
+    signer: &p2wsh::InputSigner,
+    signatures: I,
+) -> Result<(), Error>
+where
+    I: IntoIterator<Item = InputSignature>,
+{
+    let spent_output = checked_spent_output(psbt, index)?;
+    ensure!(
+        signer.redeem_script().matches_output(&spent_output),
+        Error::ScriptMismatch
+    );
+    let mut transaction = psbt.global.unsigned_tx.clone();
+    signer.spend_input(&mut transaction, index, &spent_output, signatures)?;
+    let witness = mem::take(&mut transaction.input[index].witness);
+    psbt.inputs[index].final_script_witness = Some(witness);
+    Ok(())
+}
+
+/// Returns the output spent by the input with the given index, or an error if the input
+/// or its spent output is missing.
+fn checked_spent_output(psbt: &PartiallySignedTransaction, index: usize) -> Result<TxOut, Error> {
+    let txin = psbt
+        .global
+        .unsigned_tx
+        .input
+        .get(index)
+        .filter(|_| index < psbt.inputs.len())
+        .ok_or(Error::NoSuchInput(index))?;
+    spent_output(psbt, index)
+        .cloned()
+        .ok_or(Error::UnknownOutput(txin.previous_output))
+}
+
+/// Returns the output spent by the input with the given index.
+fn spent_output(psbt: &PartiallySignedTransaction, index: usize) -> Option<&TxOut> {
+    let input = psbt.inputs.get(index)?;
+    input.witness_utxo.as_ref().or_else(|| {
+        let outpoint = psbt.global.unsigned_tx.input.get(index)?.previous_output;
+        input
+            .non_witness_utxo
+            .as_ref()?
+            .output
+            .get(outpoint.vout as usize)
+    })
+}
+
+fn fee(psbt: &PartiallySignedTransaction) -> Result<u64, Error> {
+    let tx = &psbt.global.unsigned_tx;
+    let mut available = 0_u64;
+    for (index, txin) in tx.input.iter().enumerate() {
+        let output = spent_output(psbt, index)
+            .ok_or_else(|| Error::input(index, Error::UnknownOutput(txin.previous_output)))?;
+        available = available
+            .checked_add(output.value)
+            .ok_or(Error::ValueOverflow)?;
+    }
+    let required = tx.output.iter().map(|output| output.value).sum::<u64>();
+    available
+        .checked_sub(required)
+        .ok_or(Error::InsufficientFunds {
+            available,
+            required,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{
+        blockdata::transaction::{OutPoint, Transaction, TxIn, TxOut},
+        network::constants::Network,
+        util::psbt::PartiallySignedTransaction,
+        PrivateKey, PublicKey, Script,
+    };
+
+    use super::{
+        check_original, check_proposal, create_proposal, finalize_p2wsh_input, sign_p2wpk_input,
+        PayjoinError, SenderParams,
+    };
+    use crate::{
+        multisig::RedeemScriptBuilder,
+        p2wpk, p2wsh,
+        test_data::{deterministic_keypair, fake_funding_tx, keypair_from_wif},
+        verify::verify_transaction,
+        Amount, Error, TxInRef,
+    };
+
+    struct Fixture {
+        original: PartiallySignedTransaction,
+        sender: (PublicKey, PrivateKey),
+        receiver: (PublicKey, PrivateKey),
+        receiver_input: (OutPoint, TxOut),
+        params: SenderParams,
+    }
+
+    fn fixture() -> Fixture {
+        let (sender_pk, sender_sk) =
+            keypair_from_wif("cPHmynxvqfr7sXsJcohiGzoPGBShggxL6VWUdW14skohFZ1LQoeV");
+        let (receiver_pk, receiver_sk) =
+            keypair_from_wif("cTtSTL1stvg2tmK349WTmQDfHLMLqkkxwuo8ZJeQov9zEhtYtb4u");
+        let sender_script = p2wpk::script_pubkey(&sender_pk);
+        let receiver_script = p2wpk::script_pubkey(&receiver_pk);
+
+        let sender_funding = fake_funding_tx(&sender_script, 100_000);
+        let receiver_funding = fake_funding_tx(&receiver_script, 50_000);
+        let mut transaction = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(sender_funding.txid(), 0),
+                script_sig: Script::default(),
+                sequence: 0xFFFF_FFFD,
+                witness: Vec::default(),
+            }],
+            output: vec![
+                TxOut {
+                    value: 60_000,
+                    script_pubkey: receiver_script.clone(),
+                },
+                TxOut {
+                    value: 39_000,
+                    script_pubkey: sender_script,
+                },
+            ],
+        };
+        let mut signer = p2wpk::InputSigner::new(sender_pk, Network::Testnet);
+        let signature = signer
+            .sign_input(
+                TxInRef::new(&transaction, 0),
+                &sender_funding,
+                &sender_sk.key,
+            )
+            .unwrap();
+        signer.spend_input(&mut transaction.input[0], signature);
+        let witness = std::mem::take(&mut transaction.input[0].witness);
+
+        let mut original = PartiallySignedTransaction::from_unsigned_tx(transaction).unwrap();
+        original.inputs[0].witness_utxo = Some(sender_funding.output[0].clone());
+        original.inputs[0].final_script_witness = Some(witness);
+
+        Fixture {
+            original,
+            sender: (sender_pk, sender_sk),
+            receiver: (receiver_pk, receiver_sk),
+            receiver_input: (
+                OutPoint::new(receiver_funding.txid(), 0),
+                receiver_funding.output[0].clone(),
+            ),
+            params: SenderParams {
+                payee_script_pubkey: receiver_script,
+                max_additional_fee: Amount::from_sat(500),
+                fee_output: Some(1),
+            },
+        }
+    }
+
+    #[test]
+    fn test_payjoin_flow() {
+        let Fixture {
+            original,
+            sender,
+            receiver,
+            receiver_input,
+            params,
+        } = fixture();
+        assert_eq!(check_original(&original).unwrap(), 1_000);
+
+        let mut proposal = create_proposal(&original, receiver_input.clone(), 0).unwrap();
+        assert_eq!(proposal.global.unsigned_tx.input.len(), 2);
+        assert_eq!(proposal.global.unsigned_tx.output[0].value, 110_000);
+        assert!(proposal.inputs[0].final_script_witness.is_none());
+        assert!(proposal.inputs[0].witness_utxo.is_some());
+        // The sender pays the additional fee for the receiver input.
+        proposal.global.unsigned_tx.output[1].value -= 300;
+
+        // The receiver input is not signed yet.
+        assert_eq!(
+            check_proposal(&original, &proposal, &params),
+            Err(Error::input(1, PayjoinError::NotFinalized))
+        );
+        let mut receiver_signer = p2wpk::InputSigner::new(receiver.0, Network::Testnet);
+        // The receiver cannot sign the sender input.
+        assert_eq!(
+            sign_p2wpk_input(&mut proposal, 0, &mut receiver_signer, &receiver.1.key),
+            Err(Error::ScriptMismatch)
+        );
+        sign_p2wpk_input(&mut proposal, 1, &mut receiver_signer, &receiver.1.key).unwrap();
+        check_proposal(&original, &proposal, &params).unwrap();
+
+        // The sender signs its input again and broadcasts the transaction.
+        let mut sender_signer = p2wpk::InputSigner::new(sender.0, Network::Testnet);
+        assert_eq!(
+            sign_p2wpk_input(&mut proposal, 2, &mut sender_signer, &sender.1.key),
+            Err(Error::NoSuchInput(2))
+        );
+        sign_p2wpk_input(&mut proposal, 0, &mut sender_signer, &sender.1.key).unwrap();
+        let spent_outputs = vec![
+            original.inputs[0].witness_utxo.clone().unwrap(),
+            receiver_input.1,
+        ];
+        verify_transaction(&proposal.extract_tx(), &spent_outputs).unwrap();
+    }
+
+    #[test]
+    fn test_payjoin_p2wsh_receiver() {
+        let Fixture {
+            original, params, ..
+        } = fixture();
+        let keypairs = (0..3)
+            .map(|_| deterministic_keypair(Network::Testnet))
+            .collect::<Vec<_>>();
+        let redeem_script = RedeemScriptBuilder::with_public_keys(keypairs.iter().map(|x| x.0))
+            .quorum(2)
+            .to_script()
+            .unwrap();
+        let funding = fake_funding_tx(&p2wsh::script_pubkey(&redeem_script), 50_000);
+        let receiver_input = (OutPoint::new(funding.txid(), 0), funding.output[0].clone());
+
+        let mut proposal = create_proposal(&original, receiver_input.clone(), 0).unwrap();
+        proposal.global.unsigned_tx.output[1].value -= 400;
+        let mut signer = p2wsh::InputSigner::new(redeem_script);
+        let signatures = keypairs[1..3]
+            .iter()
+            .map(|(_, private_key)| {
+                let txin = TxInRef::new(&proposal.global.unsigned_tx, 1);
+                signer
+                    .sign_input(txin, &receiver_input.1, &private_key.key)
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            finalize_p2wsh_input(&mut proposal, 1, &signer, signatures[..1].to_vec()),
+            Err(Error::SignaturesCount {
+                quorum: 2,
+                actual: 1
+            })
+        );
+        assert_eq!(
+            finalize_p2wsh_input(&mut proposal, 0, &signer, signatures.clone()),
+            Err(Error::ScriptMismatch)
+        );
+        finalize_p2wsh_input(&mut proposal, 1, &signer, signatures).unwrap();
+        check_proposal(&original, &proposal, &params).unwrap();
+
+        let witness = proposal.inputs[1].final_script_witness.as_ref().unwrap();
+        assert_eq!(witness.len(), 4);
+        let parsed = p2wsh::parse_witness(witness).unwrap();
+        assert_eq!(&parsed.redeem_script, signer.redeem_script());
+    }
+
+    #[test]
+    fn test_payjoin_malicious_proposals() {
+        let Fixture {
+            original,
+            receiver_input,
+            params,
+            ..
+        } = fixture();
+        let mut proposal = create_proposal(&original, receiver_input, 0).unwrap();
+        proposal.inputs[1].final_script_witness = Some(vec![vec![0; 72], vec![0; 33]]);
+
+        let mut too_expensive = proposal.clone();
+        too_expensive.global.unsigned_tx.output[1].value -= 1_000;
+        assert_eq!(
+            check_proposal(&original, &too_expensive, &params),
+            Err(Error::from(PayjoinError::FeeTooHigh {
+                max: 500,
+                actual: 1_000
+            }))
+        );
+
+        // The decrease of the fee output is paid to the receiver instead of the miners.
+        let mut diverted_fee = proposal.clone();
+        diverted_fee.global.unsigned_tx.output[1].value -= 300;
+        diverted_fee.global.unsigned_tx.output[0].value += 300;
+        assert_eq!(
+            check_proposal(&original, &diverted_fee, &params),
+            Err(Error::from(PayjoinError::FeeOutputDecrease {
+                decrease: 300,
+                additional_fee: 0
+            }))
+        );
+        // Only the part of the decrease goes to the miners.
+        diverted_fee.global.unsigned_tx.output[0].value -= 100;
+        assert_eq!(
+            check_proposal(&original, &diverted_fee, &params),
+            Err(Error::from(PayjoinError::FeeOutputDecrease {
+                decrease: 300,
+                additional_fee: 100
+            }))
+        );
+
+        let mut stolen_change = proposal.clone();
+        stolen_change.global.unsigned_tx.output.pop();
+        assert_eq!(
+            check_proposal(&original, &stolen_change, &params),
+            Err(Error::from(PayjoinError::MissingOutput(1)))
+        );
+
+        let mut changed = proposal.clone();
+        changed.global.unsigned_tx.lock_time = 1;
+        assert_eq!(
+            check_proposal(&original, &changed, &params),
+            Err(Error::from(PayjoinError::TransactionChanged))
+        );
+
+        let mut dropped_input = proposal.clone();
+        dropped_input.global.unsigned_tx.input.remove(0);
+        dropped_input.inputs.remove(0);
+        assert_eq!(
+            check_proposal(&original, &dropped_input, &params),
+            Err(Error::from(PayjoinError::MissingInput(
+                original.global.unsigned_tx.input[0].previous_output
+            )))
+        );
+
+        // The sender outputs with the same script pubkey are matched separately, so one
+        // of them cannot be redirected to the payee.
+        let mut split_change = original.clone();
+        let change = &mut split_change.global.unsigned_tx.output;
+        change[1].value = 20_000;
+        change.push(TxOut {
+            value: 19_000,
+            ..change[1].clone()
+        });
+        split_change.outputs.push(Default::default());
+        let mut proposal = create_proposal(&split_change, fixture().receiver_input, 0).unwrap();
+        proposal.inputs[1].final_script_witness = Some(vec![vec![0; 72], vec![0; 33]]);
+        check_proposal(&split_change, &proposal, &params).unwrap();
+        let removed = proposal.global.unsigned_tx.output.remove(2);
+        proposal.outputs.remove(2);
+        proposal.global.unsigned_tx.output[0].value += removed.value;
+        assert_eq!(
+            check_proposal(&split_change, &proposal, &params),
+            Err(Error::from(PayjoinError::MissingOutput(2)))
+        );
+
+        assert_eq!(
+            create_proposal(&original, fixture().receiver_input, 2),
+            Err(Error::from(PayjoinError::NoSuchOutput(2)))
+        );
+    }
+}
@@ -0,0 +1,282 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A self-describing payload of the `OP_RETURN` output, which is used to anchor
+//! the Exonum blockchain state to the bitcoin blockchain.
+//!
+//! The payload is encoded as follows:
+//!
+//! ```text
+//! "EXONUM" | version: u8 | kind: u8 | body | checksum: [u8; 4]
+//! ```
+//!
+//! The checksum is the first four bytes of the double SHA-256 hash of the preceding
+//! bytes. The payloads with the unknown body kinds are decoded as the raw data,
+//! so the older decoders do not reject the payloads of the newer kinds.
+
+use bitcoin::blockdata::{
+    opcodes::all::OP_RETURN,
+    script::{Builder, Instruction, Script},
+};
+use bitcoin_hashes::sha256;
+use thiserror::Error;
+
+use std::convert::TryInto;
+
+use crate::{policy::MAX_OP_RETURN_RELAY, Error, Hash, Sha256dHash};
+
+/// The magic prefix of the payload.
+pub const PAYLOAD_MAGIC: &[u8] = b"EXONUM";
+/// The current version of the payload encoding.
+pub const PAYLOAD_VERSION: u8 = 1;
+/// The maximum size of the encoded payload, which fits into the standard `OP_RETURN` output.
+pub const MAX_PAYLOAD_SIZE: usize = MAX_OP_RETURN_RELAY - 3;
+
+/// The kind of the anchoring payload body.
+const ANCHOR_KIND: u8 = 0;
+/// The size of the checksum.
+const CHECKSUM_SIZE: usize = 4;
+/// The size of the magic prefix, the version and the kind.
+const HEADER_SIZE: usize = 8;
+
+/// Possible errors of the payload decoding.
+#[derive(Debug, Copy, Clone, Error, PartialEq)]
+pub enum PayloadError {
+    /// The script is not an `OP_RETURN` script with a single data push.
+    #[error("The script is not an `OP_RETURN` script with a single data push.")]
+    NotOpReturn,
+    /// The payload does not start with the magic prefix.
+    #[error("The payload does not start with the magic prefix.")]
+    Magic,
+    /// The payload version is not supported.
+    #[error("The payload version {0} is not supported.")]
+    UnsupportedVersion(u8),
+    /// The payload checksum does not match.
+    #[error("The payload checksum does not match.")]
+    Checksum,
+    /// The payload body has an incorrect length for its kind.
+    #[error("The payload body has an incorrect length.")]
+    BodyLength,
+    /// The encoded payload exceeds the maximum size.
+    #[error("The encoded payload size {0} exceeds the maximum size.")]
+    TooLarge(usize),
+}
+
+/// The payload of the `OP_RETURN` output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Payload {
+    /// The anchor of the Exonum block.
+    Anchor {
+        /// The height of the anchored block.
+        block_height: u64,
+        /// The hash of the anchored block.
+        block_hash: sha256::Hash,
+    },
+    /// The payload of the kind, which is unknown to this version of the codec.
+    Unknown {
+        /// The body kind.
+        kind: u8,
+        /// The raw body.
+        data: Vec<u8>,
+    },
+}
+
+impl Payload {
+    /// Encodes the payload into bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = PAYLOAD_MAGIC.to_vec();
+        bytes.push(PAYLOAD_VERSION);
+        match self {
+            Payload::Anchor {
+                block_height,
+                block_hash,
+            } => {
+                bytes.push(ANCHOR_KIND);
+                bytes.extend_from_slice(&block_height.to_le_bytes());
+                bytes.extend_from_slice(&block_hash[..]);
+            }
+            Payload::Unknown { kind, data } => {
+                bytes.push(*kind);
+                bytes.extend_from_slice(data);
+            }
+        }
+        let checksum = checksum(&bytes);
+        bytes.extend_from_slice(&checksum);
+        bytes
+    }
+
+    /// Decodes the payload from bytes.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Payload, Error> {
+        ensure!(
+            bytes.len() >= HEADER_SIZE + CHECKSUM_SIZE && bytes.starts_with(PAYLOAD_MAGIC),
+            PayloadError::Magic
+        );
+        let version = bytes[PAYLOAD_MAGIC.len()];
+        ensure!(
+            version == PAYLOAD_VERSION,
+            PayloadError::UnsupportedVersion(version)
+        );
+        let (content, checksum_bytes) = bytes.split_at(bytes.len() - CHECKSUM_SIZE);
+        ensure!(checksum(content) == checksum_bytes, PayloadError::Checksum);
+
+        let kind = content[HEADER_SIZE - 1];
+        let body = &content[HEADER_SIZE..];
+        let payload = match kind {
+            ANCHOR_KIND => {
+                ensure!(body.len() == 8 + 32, PayloadError::BodyLength);
+                let (height, hash) = body.split_at(8);
+                Payload::Anchor {
+                    block_height: u64::from_le_bytes(height.try_into().unwrap()),
+                    block_hash: sha256::Hash::from_slice(hash).unwrap(),
+                }
+            }
+            kind => Payload::Unknown {
+                kind,
+                data: body.to_vec(),
+            },
+        };
+        Ok(payload)
+    }
+
+    /// Creates the `OP_RETURN` script pubkey with the encoded payload.
+    pub fn to_script(&self) -> Result<Script, Error> {
+        let bytes = self.to_bytes();
+        ensure!(
+            bytes.len() <= MAX_PAYLOAD_SIZE,
+            PayloadError::TooLarge(bytes.len())
+        );
+        Ok(Builder::new()
+            .push_opcode(OP_RETURN)
+            .push_slice(&bytes)
+            .into_script())
+    }
+
+    /// Decodes the payload from the `OP_RETURN` script pubkey.
+    pub fn from_script(script: &Script) -> Result<Payload, Error> {
+        let mut instructions = script.instructions();
+        ensure!(
+            script.is_op_return() && instructions.next().is_some(),
+            PayloadError::NotOpReturn
+        );
+        match (instructions.next(), instructions.next()) {
+            (Some(Ok(Instruction::PushBytes(bytes))), None) => Payload::from_bytes(bytes),
+            _ => Err(PayloadError::NotOpReturn.into()),
+        }
+    }
+}
+
+fn checksum(bytes: &[u8]) -> [u8; CHECKSUM_SIZE] {
+    let hash = Sha256dHash::hash(bytes);
+    hash[..CHECKSUM_SIZE].try_into().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::blockdata::{
+        opcodes::all::OP_RETURN,
+        script::{Builder, Script},
+    };
+    use bitcoin_hashes::{sha256, Hash};
+
+    use super::{Payload, PayloadError, MAX_PAYLOAD_SIZE};
+    use crate::Error;
+
+    #[test]
+    fn test_payload_anchor_roundtrip() {
+        let payload = Payload::Anchor {
+            block_height: 1_000,
+            block_hash: sha256::Hash::hash(b"block"),
+        };
+        let bytes = payload.to_bytes();
+        assert_eq!(bytes.len(), 52);
+        assert_eq!(&bytes[..8], b"EXONUM\x01\x00");
+        assert_eq!(&bytes[8..16], &1_000_u64.to_le_bytes());
+        assert_eq!(Payload::from_bytes(&bytes).unwrap(), payload);
+
+        let script = payload.to_script().unwrap();
+        assert!(script.is_op_return());
+        assert_eq!(Payload::from_script(&script).unwrap(), payload);
+    }
+
+    #[test]
+    fn test_payload_unknown_kind() {
+        let payload = Payload::Unknown {
+            kind: 7,
+            data: vec![1, 2, 3],
+        };
+        let script = payload.to_script().unwrap();
+        assert_eq!(Payload::from_script(&script).unwrap(), payload);
+
+        let payload = Payload::Unknown {
+            kind: 7,
+            data: vec![0; MAX_PAYLOAD_SIZE],
+        };
+        assert_eq!(
+            payload.to_script(),
+            Err(Error::from(PayloadError::TooLarge(MAX_PAYLOAD_SIZE + 12)))
+        );
+    }
+
+    #[test]
+    fn test_payload_errors() {
+        let payload = Payload::Anchor {
+            block_height: 1,
+            block_hash: sha256::Hash::hash(b"block"),
+        };
+        let bytes = payload.to_bytes();
+
+        let mut corrupted = bytes.clone();
+        corrupted[20] ^= 1;
+        assert_eq!(
+            Payload::from_bytes(&corrupted),
+            Err(Error::from(PayloadError::Checksum))
+        );
+        let mut newer = bytes.clone();
+        newer[6] = 2;
+        assert_eq!(
+            Payload::from_bytes(&newer),
+            Err(Error::from(PayloadError::UnsupportedVersion(2)))
+        );
+        assert_eq!(
+            Payload::from_bytes(&bytes[1..]),
+            Err(Error::from(PayloadError::Magic))
+        );
+        // The anchor body is truncated, but the checksum is correct.
+        let truncated = Payload::Unknown {
+            kind: 0,
+            data: vec![0; 8],
+        };
+        assert_eq!(
+            Payload::from_bytes(&truncated.to_bytes()),
+            Err(Error::from(PayloadError::BodyLength))
+        );
+
+        let scripts = vec![
+            Script::default(),
+            Builder::new().push_opcode(OP_RETURN).into_script(),
+            Builder::new().push_slice(&bytes).into_script(),
+            Builder::new()
+                .push_opcode(OP_RETURN)
+                .push_slice(&bytes)
+                .push_slice(&bytes)
+                .into_script(),
+        ];
+        for script in scripts {
+            assert_eq!(
+                Payload::from_script(&script),
+                Err(Error::from(PayloadError::NotOpReturn))
+            );
+        }
+    }
+}
@@ -0,0 +1,139 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The [`ExternalSigner`][external-signer] backed by the `PKCS#11` token, such as
+//! a hardware security module. The signature hashes are signed inside the token,
+//! so the secret key never leaves it.
+//!
+//! The token must support the `CKM_ECDSA` mechanism for the `secp256k1` curve.
+//!
+//! [external-signer]: ../trait.ExternalSigner.html
+
+use bitcoin::PublicKey;
+use cryptoki::{
+    mechanism::Mechanism,
+    object::{Attribute, AttributeType, ObjectClass, ObjectHandle},
+    session::Session,
+};
+use secp256k1::Signature;
+
+use crate::{Error, ExternalSigner};
+
+/// The signer, which uses the key pair stored in the `PKCS#11` token.
+#[derive(Debug)]
+pub struct Pkcs11Signer {
+    session: Session,
+    private_key: ObjectHandle,
+    public_key: PublicKey,
+}
+
+impl Pkcs11Signer {
+    /// Creates a signer for the key pair with the given label. The session must be
+    /// already logged in as the user of the token.
+    pub fn new(session: Session, label: &str) -> Result<Pkcs11Signer, Error> {
+        let private_key = find_object(&session, ObjectClass::PRIVATE_KEY, label)?;
+        let public_key_object = find_object(&session, ObjectClass::PUBLIC_KEY, label)?;
+        let attributes = session
+            .get_attributes(public_key_object, &[AttributeType::EcPoint])
+            .map_err(pkcs11_error)?;
+        let point = attributes
+            .into_iter()
+            .find_map(|attribute| match attribute {
+                Attribute::EcPoint(point) => Some(point),
+                _ => None,
+            })
+            .ok_or_else(|| Error::ExternalSigner("The public key has no EC point.".to_owned()))?;
+        let public_key = PublicKey {
+            compressed: true,
+            key: parse_ec_point(&point)?,
+        };
+        Ok(Pkcs11Signer {
+            session,
+            private_key,
+            public_key,
+        })
+    }
+
+    /// Returns a reference to the token session.
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+}
+
+impl ExternalSigner for Pkcs11Signer {
+    fn public_key(&self) -> PublicKey {
+        self.public_key
+    }
+
+    fn sign_sighash(&self, sighash: &[u8; 32]) -> Result<Vec<u8>, Error> {
+        // The `CKM_ECDSA` mechanism signs the given digest as is and returns `r || s`.
+        let signature = self
+            .session
+            .sign(&Mechanism::Ecdsa, self.private_key, sighash)
+            .map_err(pkcs11_error)?;
+        let signature = Signature::from_compact(&signature)
+            .map_err(|e| Error::ExternalSigner(e.to_string()))?;
+        Ok(signature.serialize_der().to_vec())
+    }
+}
+
+fn find_object(session: &Session, class: ObjectClass, label: &str) -> Result<ObjectHandle, Error> {
+    let template = [
+        Attribute::Class(class),
+        Attribute::Label(label.as_bytes().to_vec()),
+    ];
+    session
+        .find_objects(&template)
+        .map_err(pkcs11_error)?
+        .into_iter()
+        .next()
+        .ok_or_else(|| Error::ExternalSigner(format!("No {} with label `{}`.", class, label)))
+}
+
+/// Parses the `CKA_EC_POINT` value, which is usually the DER encoded octet string with
+/// the point, but some tokens return the raw point.
+fn parse_ec_point(bytes: &[u8]) -> Result<secp256k1::PublicKey, Error> {
+    secp256k1::PublicKey::from_slice(bytes)
+        .or_else(|e| match bytes {
+            [0x04, len, point @ ..] if usize::from(*len) == point.len() => {
+                secp256k1::PublicKey::from_slice(point)
+            }
+            _ => Err(e),
+        })
+        .map_err(|e| Error::ExternalSigner(e.to_string()))
+}
+
+fn pkcs11_error(error: cryptoki::error::Error) -> Error {
+    Error::ExternalSigner(error.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_ec_point;
+    use crate::test_data::deterministic_keypair;
+
+    use bitcoin::network::constants::Network;
+
+    #[test]
+    fn test_parse_ec_point() {
+        let public_key = deterministic_keypair(Network::Testnet).0.key;
+        let point = public_key.serialize_uncompressed();
+        assert_eq!(parse_ec_point(&point).unwrap(), public_key);
+
+        let mut octet_string = vec![0x04, 65];
+        octet_string.extend_from_slice(&point);
+        assert_eq!(parse_ec_point(&octet_string).unwrap(), public_key);
+        assert!(parse_ec_point(&octet_string[..66]).is_err());
+    }
+}
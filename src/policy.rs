@@ -0,0 +1,1052 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Checks of transactions against the default relay policy of Bitcoin Core.
+//!
+//! Transactions which violate this policy are valid according to the consensus rules,
+//! but they are not relayed by the network nodes, so they will hardly ever be mined.
+
+use bitcoin::{
+    blockdata::{
+        opcodes::all::OP_PUSHNUM_16,
+        script::{Instruction, Script},
+        transaction::{OutPoint, Transaction, TxIn, TxOut},
+    },
+    consensus,
+};
+use thiserror::Error;
+
+use crate::{
+    script::{self, ScriptType},
+    tapscript::{self, TAPSCRIPT_LEAF_VERSION},
+    Error, FeeRate, TxOutProvider,
+};
+
+/// The maximum weight of the standard transaction.
+pub const MAX_STANDARD_TX_WEIGHT: usize = 400_000;
+/// The minimal size of the standard transaction without witness data.
+pub const MIN_STANDARD_TX_NONWITNESS_SIZE: usize = 65;
+/// The maximum version of the standard transaction.
+pub const MAX_STANDARD_TX_VERSION: i32 = 3;
+/// The version of the topologically restricted until confirmation (`TRUC`) transactions,
+/// as defined by [`BIP-431`][bip-431].
+///
+/// [bip-431]: https://github.com/bitcoin/bips/blob/master/bip-0431.mediawiki
+pub const TRUC_VERSION: i32 = 3;
+/// The maximum virtual size of the `TRUC` transaction.
+pub const TRUC_MAX_VSIZE: usize = 10_000;
+/// The maximum virtual size of the `TRUC` transaction with the unconfirmed parent.
+pub const TRUC_CHILD_MAX_VSIZE: usize = 1_000;
+/// The maximum size of the standard input script.
+pub const MAX_STANDARD_SCRIPT_SIG_SIZE: usize = 1_650;
+/// The maximum size of the standard `P2WSH` witness script.
+pub const MAX_STANDARD_P2WSH_SCRIPT_SIZE: usize = 3_600;
+/// The maximum number of the standard `P2WSH` witness items, excluding the witness script.
+pub const MAX_STANDARD_P2WSH_STACK_ITEMS: usize = 100;
+/// The maximum size of the standard `P2WSH` witness item, excluding the witness script.
+pub const MAX_STANDARD_P2WSH_STACK_ITEM_SIZE: usize = 80;
+/// The maximum size of the tapscript witness item, excluding the leaf script and
+/// the control block.
+pub const MAX_STANDARD_TAPSCRIPT_STACK_ITEM_SIZE: usize = 80;
+/// The maximum size of the standard `OP_RETURN` output script before Bitcoin Core 30,
+/// which is still relayed by the nodes with the older policy.
+pub const MAX_OP_RETURN_RELAY: usize = 83;
+/// The default maximum total size of the `OP_RETURN` output scripts of the transaction
+/// since Bitcoin Core 30.
+pub const DEFAULT_MAX_DATA_CARRIER_SIZE: usize = 100_000;
+/// The maximum total weight of the transactions package.
+pub const MAX_PACKAGE_WEIGHT: usize = 404_000;
+/// The default fee rate that Bitcoin Core uses to compute the dust threshold.
+pub const DUST_RELAY_FEE_RATE: FeeRate = FeeRate::from_sat_per_kvb(3_000);
+
+/// The size of the input which spends a witness program, in virtual bytes,
+/// as assumed by the Bitcoin Core dust threshold.
+const WITNESS_INPUT_SIZE: u64 = 32 + 4 + 1 + 107 / 4 + 4;
+/// The size of the input which spends a legacy output, as assumed by the Bitcoin Core
+/// dust threshold.
+const LEGACY_INPUT_SIZE: u64 = 32 + 4 + 1 + 107 + 4;
+/// The mask of the leaf version in the first byte of the taproot control block.
+const TAPROOT_LEAF_MASK: u8 = 0xfe;
+
+/// A violation of the relay policy.
+#[derive(Debug, Copy, Clone, Error, PartialEq)]
+pub enum PolicyViolation {
+    /// The transaction version is not standard.
+    #[error("The transaction version {0} is not standard.")]
+    Version(i32),
+    /// The transaction weight exceeds the standard limit.
+    #[error("The transaction weight {0} exceeds the standard limit.")]
+    Weight(usize),
+    /// The transaction is too small.
+    #[error("The transaction size without witness {0} is too small.")]
+    TooSmall(usize),
+    /// The input script is too large.
+    #[error("The script sig of input {0} is too large.")]
+    ScriptSigSize(usize),
+    /// The input script contains opcodes other than pushes.
+    #[error("The script sig of input {0} is not push only.")]
+    ScriptSigNotPushOnly(usize),
+    /// The `P2WSH` witness script is too large.
+    #[error("The witness script of input {0} is too large.")]
+    WitnessScriptSize(usize),
+    /// The `P2WSH` witness has too many items.
+    #[error("The witness of input {0} has too many items.")]
+    WitnessItemsCount(usize),
+    /// The `P2WSH` or tapscript witness item is too large.
+    #[error("The witness of input {0} has too large items.")]
+    WitnessItemSize(usize),
+    /// The taproot witness contains the annex, which is reserved for the future upgrades.
+    #[error("The witness of input {0} contains the annex.")]
+    Annex(usize),
+    /// The output value is below the dust threshold.
+    #[error("The value of output {0} is below the dust threshold.")]
+    Dust(usize),
+    /// The total size of the `OP_RETURN` output scripts up to the given output exceeds
+    /// the data carrier size.
+    #[error("The `OP_RETURN` scripts up to output {0} exceed the data carrier size.")]
+    OpReturnSize(usize),
+    /// The transaction has more than one `OP_RETURN` output.
+    #[error("The transaction has more than one `OP_RETURN` output.")]
+    MultipleOpReturn,
+    /// The virtual size of the `TRUC` transaction exceeds the limit.
+    #[error("The virtual size {0} of the TRUC transaction exceeds the limit.")]
+    TrucSize(usize),
+    /// The virtual size of the `TRUC` transaction with the unconfirmed parent exceeds
+    /// the limit.
+    #[error("The virtual size {0} of the TRUC child transaction exceeds the limit.")]
+    TrucChildSize(usize),
+    /// The `TRUC` transaction has more than one unconfirmed ancestor.
+    #[error("The TRUC transaction has {0} unconfirmed ancestors instead of at most one.")]
+    TrucAncestors(usize),
+    /// The `TRUC` transaction spends the unconfirmed non-`TRUC` one, or vice versa.
+    #[error("The TRUC and non-TRUC transactions are mixed in the unconfirmed chain.")]
+    TrucVersionMismatch,
+    /// The total weight of the package exceeds the standard limit.
+    #[error("The package weight {0} exceeds the standard limit.")]
+    PackageWeight(usize),
+    /// The child transaction does not spend any output of the parent one.
+    #[error("The child transaction does not spend any output of the parent one.")]
+    NotChild,
+    /// The transaction has more than one dust output.
+    #[error("The transaction has {0} dust outputs instead of at most one.")]
+    EphemeralDustCount(usize),
+    /// The transaction with the ephemeral dust output pays a fee.
+    #[error("The transaction with the ephemeral dust pays fee {0} instead of zero.")]
+    EphemeralDustFee(u64),
+    /// The ephemeral dust output is not spent by the child transaction.
+    #[error("The ephemeral dust output {0} is not spent by the child transaction.")]
+    UnspentEphemeralDust(usize),
+    /// The outpoint is spent by both transactions of the package.
+    #[error("The outpoint {0} is spent by both transactions of the package.")]
+    PackageConflict(OutPoint),
+    /// The package fee rate is below the required one.
+    #[error("The package fee {fee} is below the required {required} satoshis.")]
+    PackageFee {
+        /// The total fee of the package transactions.
+        fee: u64,
+        /// The fee required by the given fee rate.
+        required: u64,
+    },
+}
+
+/// The relay policy options, which the node operators can change.
+///
+/// The default options are the ones of Bitcoin Core 30, which relays any number
+/// of the `OP_RETURN` outputs up to [`DEFAULT_MAX_DATA_CARRIER_SIZE`][default-size] bytes
+/// in total. Use [`RelayPolicy::legacy`][legacy] to check the transactions against
+/// the policy of the older nodes.
+///
+/// [default-size]: constant.DEFAULT_MAX_DATA_CARRIER_SIZE.html
+/// [legacy]: #method.legacy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RelayPolicy {
+    /// The maximum total size of the `OP_RETURN` output scripts of the transaction,
+    /// that is the `-datacarriersize` option. Zero means that the `OP_RETURN` outputs
+    /// are not relayed at all.
+    pub max_data_carrier_size: usize,
+    /// Whether the transaction may have more than one `OP_RETURN` output.
+    pub multiple_op_returns: bool,
+}
+
+impl RelayPolicy {
+    /// Returns the policy of the nodes before Bitcoin Core 30, which relay only one
+    /// `OP_RETURN` output of at most [`MAX_OP_RETURN_RELAY`][max-op-return] bytes.
+    ///
+    /// [max-op-return]: constant.MAX_OP_RETURN_RELAY.html
+    pub fn legacy() -> RelayPolicy {
+        RelayPolicy {
+            max_data_carrier_size: MAX_OP_RETURN_RELAY,
+            multiple_op_returns: false,
+        }
+    }
+
+    /// Checks the given transaction against this relay policy and returns all found
+    /// violations.
+    ///
+    /// The outputs spent by the transaction are looked up in the given provider, so
+    /// the witness of each input is checked according to the type of the spent output.
+    /// The dust outputs, including the zero-value [pay-to-anchor][p2a] ones, are reported
+    /// as well; use [`check_ephemeral_dust`][ephemeral-dust] to check whether the package
+    /// is allowed to relay them.
+    ///
+    /// [p2a]: ../script/fn.p2a_script_pubkey.html
+    /// [ephemeral-dust]: fn.check_ephemeral_dust.html
+    pub fn check_transaction<P: TxOutProvider + ?Sized>(
+        &self,
+        transaction: &Transaction,
+        provider: &P,
+    ) -> Result<Vec<PolicyViolation>, Error> {
+        let mut violations = Vec::new();
+
+        if transaction.version < 1 || transaction.version > MAX_STANDARD_TX_VERSION {
+            violations.push(PolicyViolation::Version(transaction.version));
+        }
+        let weight = transaction.get_weight();
+        if weight > MAX_STANDARD_TX_WEIGHT {
+            violations.push(PolicyViolation::Weight(weight));
+        }
+        let vsize = (weight + 3) / 4;
+        if transaction.version == TRUC_VERSION && vsize > TRUC_MAX_VSIZE {
+            violations.push(PolicyViolation::TrucSize(vsize));
+        }
+        let stripped_size = {
+            let mut transaction = transaction.clone();
+            for input in &mut transaction.input {
+                input.witness.clear();
+            }
+            bitcoin::consensus::serialize(&transaction).len()
+        };
+        if stripped_size < MIN_STANDARD_TX_NONWITNESS_SIZE {
+            violations.push(PolicyViolation::TooSmall(stripped_size));
+        }
+
+        for (index, input) in transaction.input.iter().enumerate() {
+            if input.script_sig.len() > MAX_STANDARD_SCRIPT_SIG_SIZE {
+                violations.push(PolicyViolation::ScriptSigSize(index));
+            }
+            if !is_push_only(&input.script_sig) {
+                violations.push(PolicyViolation::ScriptSigNotPushOnly(index));
+            }
+            let spent_output = provider
+                .tx_out(&input.previous_output)
+                .ok_or(Error::UnknownOutput(input.previous_output))?;
+            check_witness(index, input, &spent_output.script_pubkey, &mut violations);
+        }
+
+        let mut op_returns = 0;
+        let mut data_carrier_size = 0;
+        for (index, output) in transaction.output.iter().enumerate() {
+            if output.script_pubkey.is_op_return() {
+                op_returns += 1;
+                data_carrier_size += output.script_pubkey.len();
+                if data_carrier_size > self.max_data_carrier_size {
+                    violations.push(PolicyViolation::OpReturnSize(index));
+                }
+            } else if is_dust(output, DUST_RELAY_FEE_RATE) {
+                violations.push(PolicyViolation::Dust(index));
+            }
+        }
+        if op_returns > 1 && !self.multiple_op_returns {
+            violations.push(PolicyViolation::MultipleOpReturn);
+        }
+        Ok(violations)
+    }
+}
+
+impl Default for RelayPolicy {
+    fn default() -> RelayPolicy {
+        RelayPolicy {
+            max_data_carrier_size: DEFAULT_MAX_DATA_CARRIER_SIZE,
+            multiple_op_returns: true,
+        }
+    }
+}
+
+/// Checks the given transaction against the default [relay policy][relay-policy] and returns
+/// all found violations. The outputs spent by the transaction are looked up in the given
+/// provider.
+///
+/// [relay-policy]: struct.RelayPolicy.html
+pub fn check_transaction<P: TxOutProvider + ?Sized>(
+    transaction: &Transaction,
+    provider: &P,
+) -> Result<Vec<PolicyViolation>, Error> {
+    RelayPolicy::default().check_transaction(transaction, provider)
+}
+
+/// Checks the witness of the input, which spends the output with the given script pubkey.
+fn check_witness(
+    index: usize,
+    input: &TxIn,
+    script_pubkey: &Script,
+    violations: &mut Vec<PolicyViolation>,
+) {
+    match script::classify(script_pubkey) {
+        ScriptType::P2wsh => check_p2wsh_witness(index, &input.witness, violations),
+        // The nested witness program is the redeem script pushed by the script sig.
+        ScriptType::P2sh => {
+            let redeem_script = input
+                .script_sig
+                .instructions()
+                .last()
+                .and_then(|instruction| match instruction {
+                    Ok(Instruction::PushBytes(bytes)) => Some(Script::from(bytes.to_vec())),
+                    _ => None,
+                });
+            if matches!(redeem_script, Some(script) if script.is_v0_p2wsh()) {
+                check_p2wsh_witness(index, &input.witness, violations);
+            }
+        }
+        ScriptType::P2tr => check_taproot_witness(index, &input.witness, violations),
+        _ => {}
+    }
+}
+
+fn check_p2wsh_witness(index: usize, witness: &[Vec<u8>], violations: &mut Vec<PolicyViolation>) {
+    let (witness_script, items) = match witness.split_last() {
+        Some(split) => split,
+        None => return,
+    };
+    if witness_script.len() > MAX_STANDARD_P2WSH_SCRIPT_SIZE {
+        violations.push(PolicyViolation::WitnessScriptSize(index));
+    }
+    if items.len() > MAX_STANDARD_P2WSH_STACK_ITEMS {
+        violations.push(PolicyViolation::WitnessItemsCount(index));
+    }
+    if items
+        .iter()
+        .any(|item| item.len() > MAX_STANDARD_P2WSH_STACK_ITEM_SIZE)
+    {
+        violations.push(PolicyViolation::WitnessItemSize(index));
+    }
+}
+
+fn check_taproot_witness(index: usize, witness: &[Vec<u8>], violations: &mut Vec<PolicyViolation>) {
+    if tapscript::annex(witness).is_some() {
+        violations.push(PolicyViolation::Annex(index));
+        return;
+    }
+    // The key path spending has the only signature, and the script path one ends with
+    // the leaf script and the control block.
+    if let [items @ .., _leaf_script, control_block] = witness {
+        let is_tapscript = matches!(
+            control_block.first(),
+            Some(byte) if byte & TAPROOT_LEAF_MASK == TAPSCRIPT_LEAF_VERSION
+        );
+        if is_tapscript
+            && items
+                .iter()
+                .any(|item| item.len() > MAX_STANDARD_TAPSCRIPT_STACK_ITEM_SIZE)
+        {
+            violations.push(PolicyViolation::WitnessItemSize(index));
+        }
+    }
+}
+
+/// Returns the minimal value of the output with the given script pubkey which is not
+/// considered as dust by Bitcoin Core with the given dust relay fee rate, that is
+/// the cost of the output and of the input spending it. Provably unspendable outputs
+/// have no dust threshold.
+///
+/// The threshold is `u64::MAX` if its computation overflows.
+pub fn dust_threshold(script_pubkey: &Script, dust_relay_fee: FeeRate) -> u64 {
+    if script_pubkey.is_provably_unspendable() {
+        return 0;
+    }
+    let output = TxOut {
+        value: 0,
+        script_pubkey: script_pubkey.clone(),
+    };
+    let output_size = consensus::serialize(&output).len() as u64;
+    let input_size = if script_pubkey.is_witness_program() {
+        WITNESS_INPUT_SIZE
+    } else {
+        LEGACY_INPUT_SIZE
+    };
+    dust_relay_fee
+        .fee_vb(output_size + input_size)
+        .unwrap_or(u64::MAX)
+}
+
+/// Checks whether the given output is dust for the given dust relay fee rate, that is
+/// its value is less than the [dust threshold][dust-threshold]. Use the
+/// [`DUST_RELAY_FEE_RATE`][dust-relay-fee-rate] for the default relay policy.
+///
+/// [dust-threshold]: fn.dust_threshold.html
+/// [dust-relay-fee-rate]: constant.DUST_RELAY_FEE_RATE.html
+pub fn is_dust(output: &TxOut, dust_relay_fee: FeeRate) -> bool {
+    output.value < dust_threshold(&output.script_pubkey, dust_relay_fee)
+}
+
+/// Checks the topology restrictions of the [`TRUC`][truc] transactions and returns all
+/// found violations. The given unconfirmed ancestors are all transactions from the mempool
+/// or the same package, which outputs are spent by the given transaction directly
+/// or through other ancestors.
+///
+/// The `TRUC` transaction may have at most one unconfirmed ancestor, which must be
+/// the `TRUC` transaction as well, and the size of such a child transaction is limited
+/// to [`TRUC_CHILD_MAX_VSIZE`][child-vsize]. The non-`TRUC` transaction must not spend
+/// the unconfirmed `TRUC` one.
+///
+/// Note that the unconfirmed `TRUC` transaction may have only one unconfirmed child,
+/// so the fee bumping child should replace the existing one, if any.
+///
+/// [truc]: constant.TRUC_VERSION.html
+/// [child-vsize]: constant.TRUC_CHILD_MAX_VSIZE.html
+pub fn check_truc(
+    transaction: &Transaction,
+    unconfirmed_ancestors: &[Transaction],
+) -> Vec<PolicyViolation> {
+    let mut violations = Vec::new();
+    let is_truc = transaction.version == TRUC_VERSION;
+    let parents = unconfirmed_ancestors
+        .iter()
+        .filter(|ancestor| {
+            let txid = ancestor.txid();
+            transaction
+                .input
+                .iter()
+                .any(|input| input.previous_output.txid == txid)
+        })
+        .collect::<Vec<_>>();
+    if parents
+        .iter()
+        .any(|parent| (parent.version == TRUC_VERSION) != is_truc)
+    {
+        violations.push(PolicyViolation::TrucVersionMismatch);
+    }
+    if !is_truc {
+        return violations;
+    }
+
+    if unconfirmed_ancestors.len() > 1 {
+        violations.push(PolicyViolation::TrucAncestors(unconfirmed_ancestors.len()));
+    }
+    let vsize = (transaction.get_weight() + 3) / 4;
+    if !parents.is_empty() && vsize > TRUC_CHILD_MAX_VSIZE {
+        violations.push(PolicyViolation::TrucChildSize(vsize));
+    }
+    violations
+}
+
+/// Checks the child-pays-for-parent package, which is going to be broadcast together,
+/// against the package relay policy and returns all found violations.
+///
+/// The child must spend at least one output of the parent, and both transactions must
+/// not spend the same outpoints. The package fee rate, that is the total fee divided
+/// by the total virtual size, must reach the given one.
+/// The outputs spent by the parent and the outputs spent by the child, except the parent
+/// ones, are looked up in the given provider.
+///
+/// The transactions are not checked individually, so use [`check_transaction`][check]
+/// for each of them as well.
+///
+/// [check]: fn.check_transaction.html
+pub fn check_package<P: TxOutProvider + ?Sized>(
+    parent: &Transaction,
+    child: &Transaction,
+    provider: &P,
+    fee_rate: FeeRate,
+) -> Result<Vec<PolicyViolation>, Error> {
+    let mut violations = Vec::new();
+
+    let weight = parent.get_weight() + child.get_weight();
+    if weight > MAX_PACKAGE_WEIGHT {
+        violations.push(PolicyViolation::PackageWeight(weight));
+    }
+    let parent_txid = parent.txid();
+    if child
+        .input
+        .iter()
+        .all(|input| input.previous_output.txid != parent_txid)
+    {
+        violations.push(PolicyViolation::NotChild);
+    }
+    for input in &child.input {
+        let outpoint = input.previous_output;
+        if parent
+            .input
+            .iter()
+            .any(|input| input.previous_output == outpoint)
+        {
+            violations.push(PolicyViolation::PackageConflict(outpoint));
+        }
+    }
+
+    let fee = transaction_fee(parent, provider)?
+        .checked_add(transaction_fee(
+            child,
+            &PackageOutputs { parent, provider },
+        )?)
+        .ok_or(Error::ValueOverflow)?;
+    let vsize = (parent.get_weight() as u64 + 3) / 4 + (child.get_weight() as u64 + 3) / 4;
+    let required = fee_rate.fee_vb(vsize).unwrap_or(u64::MAX);
+    if fee < required {
+        violations.push(PolicyViolation::PackageFee { fee, required });
+    }
+    Ok(violations)
+}
+
+/// Checks the ephemeral dust rules for the parent transaction with dust outputs, such as
+/// the zero-value [pay-to-anchor][p2a] one, and returns all found violations.
+///
+/// The parent may have at most one dust output, it must pay no fee and the child must
+/// spend its dust output, so the dust never enters the UTXO set. The outputs spent
+/// by the parent are looked up in the given provider. The parent without dust outputs
+/// has no violations.
+///
+/// Such a package is relayed only as a whole, so use [`check_package`][check-package]
+/// as well.
+///
+/// [p2a]: ../script/fn.p2a_script_pubkey.html
+/// [check-package]: fn.check_package.html
+pub fn check_ephemeral_dust<P: TxOutProvider + ?Sized>(
+    parent: &Transaction,
+    child: &Transaction,
+    provider: &P,
+) -> Result<Vec<PolicyViolation>, Error> {
+    let mut violations = Vec::new();
+
+    let dust_outputs = parent
+        .output
+        .iter()
+        .enumerate()
+        .filter(|(_, output)| is_dust(output, DUST_RELAY_FEE_RATE))
+        .map(|(index, _)| index)
+        .collect::<Vec<_>>();
+    if dust_outputs.is_empty() {
+        return Ok(violations);
+    }
+    if dust_outputs.len() > 1 {
+        violations.push(PolicyViolation::EphemeralDustCount(dust_outputs.len()));
+    }
+    let fee = transaction_fee(parent, provider)?;
+    if fee > 0 {
+        violations.push(PolicyViolation::EphemeralDustFee(fee));
+    }
+    let parent_txid = parent.txid();
+    for index in dust_outputs {
+        let outpoint = OutPoint::new(parent_txid, index as u32);
+        if child
+            .input
+            .iter()
+            .all(|input| input.previous_output != outpoint)
+        {
+            violations.push(PolicyViolation::UnspentEphemeralDust(index));
+        }
+    }
+    Ok(violations)
+}
+
+/// The outputs of the parent transaction along with the outputs of the given provider.
+struct PackageOutputs<'a, P: ?Sized> {
+    parent: &'a Transaction,
+    provider: &'a P,
+}
+
+impl<P: TxOutProvider + ?Sized> TxOutProvider for PackageOutputs<'_, P> {
+    fn tx_out(&self, outpoint: &OutPoint) -> Option<&TxOut> {
+        self.parent
+            .tx_out(outpoint)
+            .or_else(|| self.provider.tx_out(outpoint))
+    }
+}
+
+fn transaction_fee<P: TxOutProvider + ?Sized>(
+    transaction: &Transaction,
+    provider: &P,
+) -> Result<u64, Error> {
+    let available = transaction.input.iter().try_fold(0_u64, |sum, input| {
+        let output = provider
+            .tx_out(&input.previous_output)
+            .ok_or(Error::UnknownOutput(input.previous_output))?;
+        sum.checked_add(output.value).ok_or(Error::ValueOverflow)
+    })?;
+    let required = transaction.output.iter().try_fold(0_u64, |sum, output| {
+        sum.checked_add(output.value).ok_or(Error::ValueOverflow)
+    })?;
+    available
+        .checked_sub(required)
+        .ok_or(Error::InsufficientFunds {
+            available,
+            required,
+        })
+}
+
+fn is_push_only(script: &Script) -> bool {
+    script.instructions().all(|instruction| match instruction {
+        Ok(Instruction::PushBytes(_)) => true,
+        Ok(Instruction::Op(op)) => op.into_u8() <= OP_PUSHNUM_16.into_u8(),
+        Err(_) => false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{
+        blockdata::{
+            opcodes::all::{OP_CHECKSIG, OP_RETURN},
+            script::{Builder, Script},
+            transaction::{OutPoint, Transaction, TxIn, TxOut},
+        },
+        network::constants::Network,
+    };
+
+    use std::{collections::HashMap, slice};
+
+    use crate::{
+        p2wpk,
+        policy::{
+            check_ephemeral_dust, check_package, check_transaction, check_truc, dust_threshold,
+            is_dust, PolicyViolation, RelayPolicy, DUST_RELAY_FEE_RATE,
+        },
+        script::p2a_script_pubkey,
+        tapscript::{push_annex, TapscriptMultisigBuilder, ANNEX_TAG},
+        test_data::deterministic_keypair,
+        Error, FeeRate,
+    };
+
+    #[test]
+    fn test_check_transaction() {
+        let (public_key, _) = deterministic_keypair(Network::Testnet);
+        let op_return = TxOut {
+            value: 0,
+            script_pubkey: Builder::new()
+                .push_opcode(OP_RETURN)
+                .push_slice(b"Hello Exonum!")
+                .into_script(),
+        };
+        let payment = TxOut {
+            value: 1_000,
+            script_pubkey: p2wpk::script_pubkey(&public_key),
+        };
+        let mut transaction = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::default(),
+                script_sig: Script::default(),
+                sequence: 0xFFFF_FFFF,
+                witness: vec![vec![0; 72], public_key.to_bytes()],
+            }],
+            output: vec![payment.clone(), op_return.clone()],
+        };
+        let mut unspent_outputs = HashMap::new();
+        assert_eq!(
+            check_transaction(&transaction, &unspent_outputs),
+            Err(Error::UnknownOutput(OutPoint::default()))
+        );
+        unspent_outputs.insert(OutPoint::default(), payment);
+        assert!(check_transaction(&transaction, &unspent_outputs)
+            .unwrap()
+            .is_empty());
+
+        transaction.version = 4;
+        transaction.input[0].script_sig = Builder::new().push_opcode(OP_CHECKSIG).into_script();
+        transaction.output[0].value = 100;
+        transaction.output.push(op_return);
+        assert_eq!(
+            check_transaction(&transaction, &unspent_outputs).unwrap(),
+            vec![
+                PolicyViolation::Version(4),
+                PolicyViolation::ScriptSigNotPushOnly(0),
+                PolicyViolation::Dust(0),
+            ]
+        );
+        // The nodes before Bitcoin Core 30 relay only one `OP_RETURN` output.
+        assert_eq!(
+            RelayPolicy::legacy()
+                .check_transaction(&transaction, &unspent_outputs)
+                .unwrap(),
+            vec![
+                PolicyViolation::Version(4),
+                PolicyViolation::ScriptSigNotPushOnly(0),
+                PolicyViolation::Dust(0),
+                PolicyViolation::MultipleOpReturn,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_is_dust() {
+        let (public_key, _) = deterministic_keypair(Network::Testnet);
+        let p2wpk = p2wpk::script_pubkey(&public_key);
+        let p2pkh = Script::new_p2pkh(&public_key.pubkey_hash());
+        // The well-known thresholds of Bitcoin Core.
+        assert_eq!(dust_threshold(&p2wpk, DUST_RELAY_FEE_RATE), 294);
+        assert_eq!(dust_threshold(&p2pkh, DUST_RELAY_FEE_RATE), 546);
+
+        let output = TxOut {
+            value: 294,
+            script_pubkey: p2wpk,
+        };
+        assert!(!is_dust(&output, DUST_RELAY_FEE_RATE));
+        assert!(is_dust(&output, FeeRate::from_sat_per_vb(10).unwrap()));
+        assert!(is_dust(
+            &TxOut {
+                value: 293,
+                ..output.clone()
+            },
+            DUST_RELAY_FEE_RATE
+        ));
+        assert!(!is_dust(&output, FeeRate::ZERO));
+        assert!(is_dust(&output, FeeRate::from_sat_per_kvb(u64::MAX)));
+
+        let op_return = TxOut {
+            value: 0,
+            script_pubkey: Builder::new().push_opcode(OP_RETURN).into_script(),
+        };
+        assert!(!is_dust(&op_return, DUST_RELAY_FEE_RATE));
+    }
+
+    #[test]
+    fn test_check_transaction_p2wsh_witness() {
+        let transaction = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::default(),
+                script_sig: Script::default(),
+                sequence: 0xFFFF_FFFF,
+                witness: vec![vec![], vec![0; 81], vec![0; 3_601]],
+            }],
+            output: vec![TxOut {
+                value: 0,
+                script_pubkey: Builder::new()
+                    .push_opcode(OP_RETURN)
+                    .push_slice(&[0; 82])
+                    .into_script(),
+            }],
+        };
+        let witness_script = Script::from(vec![0; 3_601]);
+        let mut unspent_outputs = HashMap::new();
+        unspent_outputs.insert(
+            OutPoint::default(),
+            TxOut {
+                value: 10_000,
+                script_pubkey: witness_script.to_v0_p2wsh(),
+            },
+        );
+        assert_eq!(
+            check_transaction(&transaction, &unspent_outputs).unwrap(),
+            vec![
+                PolicyViolation::WitnessScriptSize(0),
+                PolicyViolation::WitnessItemSize(0),
+            ]
+        );
+        // The `OP_RETURN` script is larger than the limit of the older nodes.
+        assert_eq!(
+            RelayPolicy::legacy()
+                .check_transaction(&transaction, &unspent_outputs)
+                .unwrap(),
+            vec![
+                PolicyViolation::WitnessScriptSize(0),
+                PolicyViolation::WitnessItemSize(0),
+                PolicyViolation::OpReturnSize(0),
+            ]
+        );
+        let policy = RelayPolicy {
+            max_data_carrier_size: 0,
+            ..RelayPolicy::default()
+        };
+        assert_eq!(
+            policy
+                .check_transaction(&transaction, &unspent_outputs)
+                .unwrap()
+                .last(),
+            Some(&PolicyViolation::OpReturnSize(0))
+        );
+
+        // The same witness is not checked as the `P2WSH` one for the other outputs.
+        let (public_key, _) = deterministic_keypair(Network::Testnet);
+        unspent_outputs
+            .get_mut(&OutPoint::default())
+            .unwrap()
+            .script_pubkey = p2wpk::script_pubkey(&public_key);
+        assert!(check_transaction(&transaction, &unspent_outputs)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_check_transaction_taproot_witness() {
+        let keys = (0..3)
+            .map(|_| deterministic_keypair(Network::Testnet).0)
+            .collect::<Vec<_>>();
+        let multisig = TapscriptMultisigBuilder::with_quorum(3)
+            .public_key(keys[0])
+            .public_key(keys[1])
+            .public_key(keys[2])
+            .to_script()
+            .unwrap();
+        let internal_key = multisig.public_keys()[0];
+        // The leaf script is larger than the `P2WSH` stack item limit.
+        assert!(multisig.script().len() > 80);
+
+        let mut witness = vec![vec![1; 64]; 3];
+        witness.push(multisig.script().to_bytes());
+        witness.push(multisig.control_block(&internal_key).unwrap());
+        let mut transaction = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::default(),
+                script_sig: Script::default(),
+                sequence: 0xFFFF_FFFF,
+                witness,
+            }],
+            output: vec![TxOut {
+                value: 10_000,
+                script_pubkey: p2wpk::script_pubkey(&keys[0]),
+            }],
+        };
+        let mut unspent_outputs = HashMap::new();
+        unspent_outputs.insert(
+            OutPoint::default(),
+            TxOut {
+                value: 20_000,
+                script_pubkey: multisig.script_pubkey(&internal_key).unwrap(),
+            },
+        );
+        assert!(check_transaction(&transaction, &unspent_outputs)
+            .unwrap()
+            .is_empty());
+
+        // The tapscript stack items are limited.
+        transaction.input[0].witness[0] = vec![1; 81];
+        assert_eq!(
+            check_transaction(&transaction, &unspent_outputs).unwrap(),
+            vec![PolicyViolation::WitnessItemSize(0)]
+        );
+        // The annex is not relayed.
+        transaction.input[0].witness[0] = vec![1; 64];
+        push_annex(&mut transaction.input[0].witness, vec![ANNEX_TAG]).unwrap();
+        assert_eq!(
+            check_transaction(&transaction, &unspent_outputs).unwrap(),
+            vec![PolicyViolation::Annex(0)]
+        );
+    }
+
+    #[test]
+    fn test_check_package() {
+        let (public_key, _) = deterministic_keypair(Network::Testnet);
+        let script_pubkey = p2wpk::script_pubkey(&public_key);
+        let funding = OutPoint::new(Default::default(), 0);
+        let input = |previous_output| TxIn {
+            previous_output,
+            script_sig: Script::default(),
+            sequence: 0xFFFF_FFFF,
+            witness: vec![vec![0; 72], public_key.to_bytes()],
+        };
+        let output = |value| TxOut {
+            value,
+            script_pubkey: script_pubkey.clone(),
+        };
+        let mut unspent_outputs = HashMap::new();
+        unspent_outputs.insert(funding, output(100_000));
+
+        // The parent pays no fee, so the child pays for both transactions.
+        let parent = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![input(funding)],
+            output: vec![output(100_000)],
+        };
+        let child = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![input(OutPoint::new(parent.txid(), 0))],
+            output: vec![output(95_000)],
+        };
+        let vsize = (parent.get_weight() as u64 + 3) / 4 + (child.get_weight() as u64 + 3) / 4;
+        let fee_rate = 5_000 / vsize;
+        let fee_rate_vb = |fee_rate| FeeRate::from_sat_per_vb(fee_rate).unwrap();
+        assert!(
+            check_package(&parent, &child, &unspent_outputs, fee_rate_vb(fee_rate))
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(
+            check_package(&parent, &child, &unspent_outputs, fee_rate_vb(fee_rate + 1)).unwrap(),
+            vec![PolicyViolation::PackageFee {
+                fee: 5_000,
+                required: vsize * (fee_rate + 1),
+            }]
+        );
+
+        // The unrelated transaction spending the same outpoint.
+        let conflicting = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![input(funding)],
+            output: vec![output(90_000)],
+        };
+        assert_eq!(
+            check_package(&parent, &conflicting, &unspent_outputs, FeeRate::ZERO).unwrap(),
+            vec![
+                PolicyViolation::NotChild,
+                PolicyViolation::PackageConflict(funding)
+            ]
+        );
+        assert_eq!(
+            check_package(&child, &parent, &unspent_outputs, FeeRate::ZERO),
+            Err(Error::UnknownOutput(child.input[0].previous_output))
+        );
+    }
+
+    #[test]
+    fn test_check_ephemeral_dust() {
+        let (public_key, _) = deterministic_keypair(Network::Testnet);
+        let script_pubkey = p2wpk::script_pubkey(&public_key);
+        let funding = OutPoint::new(Default::default(), 0);
+        let input = |previous_output| TxIn {
+            previous_output,
+            script_sig: Script::default(),
+            sequence: 0xFFFF_FFFD,
+            witness: vec![vec![0; 72], public_key.to_bytes()],
+        };
+        let anchor = TxOut {
+            value: 0,
+            script_pubkey: p2a_script_pubkey(),
+        };
+        assert_eq!(
+            dust_threshold(&anchor.script_pubkey, DUST_RELAY_FEE_RATE),
+            240
+        );
+        let mut unspent_outputs = HashMap::new();
+        unspent_outputs.insert(
+            funding,
+            TxOut {
+                value: 100_000,
+                script_pubkey: script_pubkey.clone(),
+            },
+        );
+
+        let parent = Transaction {
+            version: 3,
+            lock_time: 0,
+            input: vec![input(funding)],
+            output: vec![
+                TxOut {
+                    value: 100_000,
+                    script_pubkey: script_pubkey.clone(),
+                },
+                anchor.clone(),
+            ],
+        };
+        assert_eq!(
+            check_transaction(&parent, &unspent_outputs).unwrap(),
+            vec![PolicyViolation::Dust(1)]
+        );
+        let child = Transaction {
+            version: 3,
+            lock_time: 0,
+            input: vec![input(OutPoint::new(parent.txid(), 1))],
+            output: vec![],
+        };
+        assert!(check_ephemeral_dust(&parent, &child, &unspent_outputs)
+            .unwrap()
+            .is_empty());
+
+        // The fee paying parent with two dust outputs, one of which is left unspent.
+        let mut parent = parent;
+        parent.output[0].value = 99_000;
+        parent.output.push(anchor);
+        let child = Transaction {
+            input: vec![input(OutPoint::new(parent.txid(), 1))],
+            ..child
+        };
+        assert_eq!(
+            check_ephemeral_dust(&parent, &child, &unspent_outputs).unwrap(),
+            vec![
+                PolicyViolation::EphemeralDustCount(2),
+                PolicyViolation::EphemeralDustFee(1_000),
+                PolicyViolation::UnspentEphemeralDust(2),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_truc() {
+        let (public_key, _) = deterministic_keypair(Network::Testnet);
+        let input = |previous_output| TxIn {
+            previous_output,
+            script_sig: Script::default(),
+            sequence: 0xFFFF_FFFD,
+            witness: vec![vec![0; 72], public_key.to_bytes()],
+        };
+        let transaction = |version, inputs: Vec<TxIn>, outputs| Transaction {
+            version,
+            lock_time: 0,
+            input: inputs,
+            output: vec![
+                TxOut {
+                    value: 10_000,
+                    script_pubkey: p2wpk::script_pubkey(&public_key),
+                };
+                outputs
+            ],
+        };
+
+        let mut unspent_outputs = HashMap::new();
+        unspent_outputs.insert(
+            OutPoint::default(),
+            TxOut {
+                value: 20_000,
+                script_pubkey: p2wpk::script_pubkey(&public_key),
+            },
+        );
+        let parent = transaction(3, vec![input(OutPoint::default())], 1);
+        assert!(check_transaction(&parent, &unspent_outputs)
+            .unwrap()
+            .is_empty());
+        assert!(check_truc(&parent, &[]).is_empty());
+        let child = transaction(3, vec![input(OutPoint::new(parent.txid(), 0))], 1);
+        assert!(check_truc(&child, slice::from_ref(&parent)).is_empty());
+
+        // The child size is limited.
+        let large_child = transaction(3, vec![input(OutPoint::new(parent.txid(), 0))], 40);
+        let vsize = (large_child.get_weight() + 3) / 4;
+        assert!(check_truc(&large_child, &[]).is_empty());
+        assert_eq!(
+            check_truc(&large_child, slice::from_ref(&parent)),
+            vec![PolicyViolation::TrucChildSize(vsize)]
+        );
+        // The grandchild has two unconfirmed ancestors.
+        let grandchild = transaction(3, vec![input(OutPoint::new(child.txid(), 0))], 1);
+        assert_eq!(
+            check_truc(&grandchild, &[parent.clone(), child.clone()]),
+            vec![PolicyViolation::TrucAncestors(2)]
+        );
+        // The TRUC and non-TRUC transactions are not mixed.
+        let child_v2 = transaction(2, vec![input(OutPoint::new(parent.txid(), 0))], 1);
+        assert_eq!(
+            check_truc(&child_v2, slice::from_ref(&parent)),
+            vec![PolicyViolation::TrucVersionMismatch]
+        );
+        let parent_v2 = transaction(2, vec![input(OutPoint::default())], 1);
+        let child = transaction(3, vec![input(OutPoint::new(parent_v2.txid(), 0))], 1);
+        assert_eq!(
+            check_truc(&child, &[parent_v2]),
+            vec![PolicyViolation::TrucVersionMismatch]
+        );
+
+        let large = transaction(3, vec![input(OutPoint::default())], 400);
+        let vsize = (large.get_weight() + 3) / 4;
+        assert_eq!(
+            check_transaction(&large, &unspent_outputs).unwrap(),
+            vec![PolicyViolation::TrucSize(vsize)]
+        );
+    }
+}
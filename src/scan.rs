@@ -0,0 +1,356 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Helpers for finding the transaction outputs which pay to the watched scripts.
+//!
+//! # Examples
+//!
+//! ```
+//! # #[cfg(feature = "random")]
+//! # fn main() {
+//! use bitcoin::network::constants::Network;
+//! use btc_transaction_utils::{
+//!     multisig::RedeemScriptBuilder, scan::OutputScanner, test_data::secp_gen_keypair,
+//! };
+//!
+//! let public_keys = (0..3).map(|_| secp_gen_keypair(Network::Testnet).0);
+//! let redeem_script = RedeemScriptBuilder::with_public_keys(public_keys)
+//!     .quorum(2)
+//!     .to_script()
+//!     .unwrap();
+//! // Looks for the outputs which fund the multisig address.
+//! let scanner = OutputScanner::from(&redeem_script);
+//! # let transaction = btc_transaction_utils::test_data::btc_tx_from_hex(
+//! #     "02000000000101beccab33bc72bfc81b63fdec8a4a9a4719e4418bdb7b20e47b0\
+//! #      2074dc42f2d800000000017160014f3b1b3819c1290cd5d675c1319dc7d9d98d5\
+//! #      71bcfeffffff02dceffa0200000000160014368c6b7c38f0ff0839bf78d77544d\
+//! #      a96cb685bf28096980000000000160014284175e336fa10865fb4d1351c9e18e7\
+//! #      30f5d6f90247304402207c893c85d75e2230dde04f5a1e2c83c4f0b7d93213372\
+//! #      746eb2227b068260d840220705484b6ec70a8fc0d1f80c3a98079602595351b7a\
+//! #      9bca7caddb9a6adb0a3440012103150514f05f3e3f40c7b404b16f8a09c2c71ba\
+//! #      d3ba8da5dd1e411a7069cc080a004b91300",
+//! # );
+//! for (outpoint, output) in scanner.scan_transaction(&transaction) {
+//!     println!("Found {} satoshis at {}", output.value, outpoint);
+//! }
+//! # }
+//! # #[cfg(not(feature = "random"))]
+//! # fn main() {}
+//! ```
+
+use bitcoin::{
+    blockdata::{
+        block::Block,
+        script::Script,
+        transaction::{OutPoint, Transaction, TxOut},
+    },
+    PublicKey, Txid,
+};
+
+use std::collections::{HashMap, HashSet};
+
+use crate::{multisig::RedeemScript, p2wpk, p2wsh, TxOutProvider};
+
+/// The watched output spent by the transaction input.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpentOutput {
+    /// The outpoint of the spent output.
+    pub outpoint: OutPoint,
+    /// The spent output itself.
+    pub output: TxOut,
+    /// The identifier of the spending transaction.
+    pub txid: Txid,
+    /// The index of the spending input.
+    pub input: usize,
+}
+
+/// The relevant changes found in the block.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BlockChanges {
+    /// The new outputs which pay to the watched scripts, in the block order.
+    pub received: Vec<(OutPoint, TxOut)>,
+    /// The watched outputs spent by the block transactions, in the block order.
+    pub spent: Vec<SpentOutput>,
+}
+
+/// A set of the watched script pubkeys.
+#[derive(Debug, Clone, Default)]
+pub struct OutputScanner {
+    script_pubkeys: HashSet<Script>,
+}
+
+impl OutputScanner {
+    /// Creates a scanner without watched scripts.
+    pub fn new() -> OutputScanner {
+        OutputScanner::default()
+    }
+
+    /// Creates a scanner for the given script pubkeys.
+    pub fn with_script_pubkeys<I: IntoIterator<Item = Script>>(script_pubkeys: I) -> OutputScanner {
+        OutputScanner {
+            script_pubkeys: script_pubkeys.into_iter().collect(),
+        }
+    }
+
+    /// Adds a new script pubkey to watch.
+    pub fn script_pubkey(&mut self, script_pubkey: Script) -> &mut OutputScanner {
+        self.script_pubkeys.insert(script_pubkey);
+        self
+    }
+
+    /// Adds the `P2WSH` script pubkey of the given redeem script to watch.
+    pub fn redeem_script(&mut self, redeem_script: &RedeemScript) -> &mut OutputScanner {
+        self.script_pubkey(p2wsh::script_pubkey(redeem_script))
+    }
+
+    /// Adds the `P2WPK` script pubkey of the given public key to watch.
+    pub fn public_key(&mut self, public_key: &PublicKey) -> &mut OutputScanner {
+        self.script_pubkey(p2wpk::script_pubkey(public_key))
+    }
+
+    /// Returns an iterator over the watched script pubkeys in an arbitrary order.
+    pub fn script_pubkeys(&self) -> impl Iterator<Item = &Script> {
+        self.script_pubkeys.iter()
+    }
+
+    /// Checks if the given script pubkey is watched.
+    pub fn is_watched(&self, script_pubkey: &Script) -> bool {
+        self.script_pubkeys.contains(script_pubkey)
+    }
+
+    /// Returns outputs of the given transaction which pay to the watched scripts.
+    pub fn scan_transaction(&self, transaction: &Transaction) -> Vec<(OutPoint, TxOut)> {
+        let txid = transaction.txid();
+        transaction
+            .output
+            .iter()
+            .enumerate()
+            .filter(|(_, output)| self.is_watched(&output.script_pubkey))
+            .map(|(vout, output)| (OutPoint::new(txid, vout as u32), output.clone()))
+            .collect()
+    }
+
+    /// Returns outputs of all the block transactions which pay to the watched scripts.
+    pub fn scan_block(&self, block: &Block) -> Vec<(OutPoint, TxOut)> {
+        block
+            .txdata
+            .iter()
+            .flat_map(|transaction| self.scan_transaction(transaction))
+            .collect()
+    }
+
+    /// Returns the outputs of the block transactions which pay to the watched scripts, and
+    /// the inputs which spend the watched outputs. The watched outputs are looked up
+    /// in the given provider, for example in the [`UtxoSet`][utxo-set], and among
+    /// the outputs received by the preceding transactions of the same block.
+    ///
+    /// [utxo-set]: ../utxo/struct.UtxoSet.html
+    pub fn scan_block_changes<P: TxOutProvider + ?Sized>(
+        &self,
+        block: &Block,
+        watched_outputs: &P,
+    ) -> BlockChanges {
+        let mut changes = BlockChanges::default();
+        let mut received_outputs = HashMap::new();
+        for transaction in &block.txdata {
+            let txid = transaction.txid();
+            for (index, input) in transaction.input.iter().enumerate() {
+                let outpoint = input.previous_output;
+                let output = received_outputs
+                    .get(&outpoint)
+                    .or_else(|| watched_outputs.tx_out(&outpoint));
+                if let Some(output) = output {
+                    changes.spent.push(SpentOutput {
+                        outpoint,
+                        output: output.clone(),
+                        txid,
+                        input: index,
+                    });
+                }
+            }
+            for (outpoint, output) in self.scan_transaction(transaction) {
+                received_outputs.insert(outpoint, output.clone());
+                changes.received.push((outpoint, output));
+            }
+        }
+        changes
+    }
+}
+
+impl<'a> From<&'a RedeemScript> for OutputScanner {
+    fn from(redeem_script: &'a RedeemScript) -> OutputScanner {
+        OutputScanner::with_script_pubkeys(Some(p2wsh::script_pubkey(redeem_script)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{
+        blockdata::{
+            block::{Block, BlockHeader},
+            script::Script,
+            transaction::{OutPoint, Transaction, TxIn, TxOut},
+        },
+        network::constants::Network,
+    };
+
+    use std::collections::HashMap;
+
+    use crate::{
+        multisig::RedeemScriptBuilder,
+        p2wpk, p2wsh,
+        scan::{OutputScanner, SpentOutput},
+        test_data::deterministic_keypair,
+    };
+
+    fn transaction_with_outputs(lock_time: u32, outputs: Vec<TxOut>) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time,
+            input: vec![TxIn {
+                previous_output: OutPoint::default(),
+                script_sig: Script::default(),
+                sequence: 0xFFFF_FFFF,
+                witness: Vec::default(),
+            }],
+            output: outputs,
+        }
+    }
+
+    #[test]
+    fn test_output_scanner() {
+        let keypairs = (0..3)
+            .map(|_| deterministic_keypair(Network::Testnet))
+            .collect::<Vec<_>>();
+        let redeem_script = RedeemScriptBuilder::with_public_keys(keypairs.iter().map(|x| x.0))
+            .quorum(2)
+            .to_script()
+            .unwrap();
+        let multisig_output = TxOut {
+            value: 1_000,
+            script_pubkey: p2wsh::script_pubkey(&redeem_script),
+        };
+        let wallet_output = TxOut {
+            value: 2_000,
+            script_pubkey: p2wpk::script_pubkey(&keypairs[0].0),
+        };
+        let foreign_output = TxOut {
+            value: 3_000,
+            script_pubkey: p2wpk::script_pubkey(&keypairs[1].0),
+        };
+
+        let first_tx =
+            transaction_with_outputs(0, vec![foreign_output.clone(), multisig_output.clone()]);
+        let second_tx = transaction_with_outputs(1, vec![wallet_output.clone(), foreign_output]);
+
+        let mut scanner = OutputScanner::from(&redeem_script);
+        assert_eq!(
+            scanner.scan_transaction(&first_tx),
+            vec![(OutPoint::new(first_tx.txid(), 1), multisig_output.clone())]
+        );
+        assert!(scanner.scan_transaction(&second_tx).is_empty());
+
+        scanner.public_key(&keypairs[0].0);
+        let block = Block {
+            header: BlockHeader {
+                version: 1,
+                prev_blockhash: Default::default(),
+                merkle_root: Default::default(),
+                time: 0,
+                bits: 0,
+                nonce: 0,
+            },
+            txdata: vec![first_tx.clone(), second_tx.clone()],
+        };
+        assert_eq!(
+            scanner.scan_block(&block),
+            vec![
+                (OutPoint::new(first_tx.txid(), 1), multisig_output),
+                (OutPoint::new(second_tx.txid(), 0), wallet_output),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_scan_block_changes() {
+        let (public_key, _) = deterministic_keypair(Network::Testnet);
+        let (foreign_key, _) = deterministic_keypair(Network::Testnet);
+        let wallet_output = TxOut {
+            value: 2_000,
+            script_pubkey: p2wpk::script_pubkey(&public_key),
+        };
+        let foreign_output = TxOut {
+            value: 3_000,
+            script_pubkey: p2wpk::script_pubkey(&foreign_key),
+        };
+        let spending_tx = |previous_output, output: &TxOut| Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output,
+                script_sig: Script::default(),
+                sequence: 0xFFFF_FFFF,
+                witness: Vec::default(),
+            }],
+            output: vec![output.clone()],
+        };
+
+        // The output received before the block.
+        let known_outpoint = OutPoint::new(Default::default(), 7);
+        let mut watched_outputs = HashMap::new();
+        watched_outputs.insert(known_outpoint, wallet_output.clone());
+
+        let first_tx = spending_tx(known_outpoint, &wallet_output);
+        let first_outpoint = OutPoint::new(first_tx.txid(), 0);
+        // Spends the output received in the same block.
+        let second_tx = spending_tx(first_outpoint, &foreign_output);
+        let foreign_tx = spending_tx(OutPoint::new(Default::default(), 8), &foreign_output);
+        let block = Block {
+            header: BlockHeader {
+                version: 1,
+                prev_blockhash: Default::default(),
+                merkle_root: Default::default(),
+                time: 0,
+                bits: 0,
+                nonce: 0,
+            },
+            txdata: vec![first_tx.clone(), foreign_tx, second_tx.clone()],
+        };
+
+        let mut scanner = OutputScanner::new();
+        scanner.public_key(&public_key);
+        let changes = scanner.scan_block_changes(&block, &watched_outputs);
+        assert_eq!(
+            changes.received,
+            vec![(first_outpoint, wallet_output.clone())]
+        );
+        assert_eq!(
+            changes.spent,
+            vec![
+                SpentOutput {
+                    outpoint: known_outpoint,
+                    output: wallet_output.clone(),
+                    txid: first_tx.txid(),
+                    input: 0,
+                },
+                SpentOutput {
+                    outpoint: first_outpoint,
+                    output: wallet_output,
+                    txid: second_tx.txid(),
+                    input: 0,
+                },
+            ]
+        );
+    }
+}
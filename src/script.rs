@@ -0,0 +1,271 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Helpers for the human-readable representation of scripts.
+//!
+//! The assembly representation is a space-separated list of tokens. Data pushes are
+//! written as hex strings, the empty push as `OP_0` and other opcodes by their names,
+//! for example `OP_PUSHNUM_2 <public key> <public key> OP_PUSHNUM_2 OP_CHECKMULTISIG`.
+//!
+//! The [`classify`][classify] function determines the kind of the output script.
+//!
+//! [classify]: fn.classify.html
+
+use bitcoin::blockdata::{
+    opcodes::{self, all::OP_PUSHNUM_1},
+    script::{Builder, Instruction, Script},
+};
+
+use crate::Error;
+
+/// The size of the taproot output key.
+const TAPROOT_KEY_SIZE: usize = 32;
+/// The witness program of the pay-to-anchor output.
+const P2A_PROGRAM: [u8; 2] = [0x4e, 0x73];
+
+/// The token used for the invalid instruction in the script assembly.
+const INVALID_TOKEN: &str = "[invalid]";
+
+/// The kind of the output script.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ScriptType {
+    /// Pay to public key hash.
+    P2pkh,
+    /// Pay to script hash.
+    P2sh,
+    /// Pay to witness public key hash.
+    P2wpkh,
+    /// Pay to witness script hash.
+    P2wsh,
+    /// Pay to taproot, that is the witness program of version 1.
+    P2tr,
+    /// Pay to anchor, that is the keyless output for the fee bumping.
+    P2a,
+    /// Provably unspendable output with the `OP_RETURN` data.
+    OpReturn,
+    /// Any other script.
+    NonStandard,
+}
+
+/// Returns the kind of the given output script.
+pub fn classify(script_pubkey: &Script) -> ScriptType {
+    if script_pubkey.is_p2pkh() {
+        ScriptType::P2pkh
+    } else if script_pubkey.is_p2sh() {
+        ScriptType::P2sh
+    } else if script_pubkey.is_v0_p2wpkh() {
+        ScriptType::P2wpkh
+    } else if script_pubkey.is_v0_p2wsh() {
+        ScriptType::P2wsh
+    } else if is_p2tr(script_pubkey) {
+        ScriptType::P2tr
+    } else if is_p2a(script_pubkey) {
+        ScriptType::P2a
+    } else if script_pubkey.is_op_return() {
+        ScriptType::OpReturn
+    } else {
+        ScriptType::NonStandard
+    }
+}
+
+fn is_p2tr(script_pubkey: &Script) -> bool {
+    let bytes = script_pubkey.as_bytes();
+    bytes.len() == TAPROOT_KEY_SIZE + 2
+        && bytes[0] == OP_PUSHNUM_1.into_u8()
+        && bytes[1] == TAPROOT_KEY_SIZE as u8
+}
+
+/// Returns the pay-to-anchor (`P2A`) script pubkey, that is `OP_1 <4e73>`.
+///
+/// The output can be spent by anyone with the empty witness, so it is used as the anchor
+/// for the child-pays-for-parent fee bumping. The zero-value anchor is allowed by the relay
+/// policy only as the ephemeral dust, see [`check_ephemeral_dust`][check-ephemeral-dust].
+///
+/// [check-ephemeral-dust]: ../policy/fn.check_ephemeral_dust.html
+pub fn p2a_script_pubkey() -> Script {
+    Builder::new()
+        .push_opcode(OP_PUSHNUM_1)
+        .push_slice(&P2A_PROGRAM)
+        .into_script()
+}
+
+/// Checks whether the given script is the pay-to-anchor one.
+pub fn is_p2a(script_pubkey: &Script) -> bool {
+    let bytes = script_pubkey.as_bytes();
+    bytes.len() == P2A_PROGRAM.len() + 2
+        && bytes[0] == OP_PUSHNUM_1.into_u8()
+        && bytes[1] == P2A_PROGRAM.len() as u8
+        && bytes[2..] == P2A_PROGRAM
+}
+
+/// Returns the assembly representation of the given script.
+///
+/// If the script contains an invalid instruction, for example a truncated push,
+/// the representation ends with the `[invalid]` token.
+pub fn script_to_asm(script: &Script) -> String {
+    let tokens = script
+        .instructions()
+        .map(|instruction| match instruction {
+            Ok(Instruction::PushBytes(&[])) => "OP_0".to_owned(),
+            Ok(Instruction::PushBytes(bytes)) => hex::encode(bytes),
+            Ok(Instruction::Op(op)) => format!("{:?}", op),
+            Err(_) => INVALID_TOKEN.to_owned(),
+        })
+        .collect::<Vec<_>>();
+    tokens.join(" ")
+}
+
+/// Reconstructs the script from its assembly representation.
+///
+/// Data pushes are encoded with the minimal push opcodes, so the scripts with non-minimal
+/// pushes are not restored byte to byte.
+pub fn script_from_asm(asm: &str) -> Result<Script, Error> {
+    let mut builder = Builder::new();
+    for token in asm.split_whitespace() {
+        builder = if token == "OP_0" || token == "OP_FALSE" {
+            builder.push_slice(&[])
+        } else if token.starts_with("OP_") {
+            let op = opcode_by_name(token).ok_or_else(|| Error::AsmToken(token.to_owned()))?;
+            builder.push_opcode(op)
+        } else {
+            let bytes = hex::decode(token).map_err(|_| Error::AsmToken(token.to_owned()))?;
+            builder.push_slice(&bytes)
+        };
+    }
+    Ok(builder.into_script())
+}
+
+fn opcode_by_name(name: &str) -> Option<opcodes::All> {
+    (0..=u8::MAX)
+        .map(opcodes::All::from)
+        // Push opcodes must not be used without the pushed data.
+        .filter(|op| op.into_u8() > opcodes::all::OP_PUSHDATA4.into_u8())
+        .find(|op| format!("{:?}", op) == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{
+        blockdata::{
+            opcodes::all::{OP_PUSHNUM_1, OP_RETURN},
+            script::{Builder, Script},
+        },
+        network::constants::Network,
+        util::address::Address,
+    };
+
+    use crate::{
+        multisig::RedeemScriptBuilder,
+        p2wpk, p2wsh,
+        script::{classify, p2a_script_pubkey, script_from_asm, script_to_asm, ScriptType},
+        test_data::deterministic_keypair,
+        Error,
+    };
+
+    #[test]
+    fn test_classify() {
+        let (public_key, _) = deterministic_keypair(Network::Testnet);
+        let redeem_script = RedeemScriptBuilder::with_public_keys(vec![public_key])
+            .to_script()
+            .unwrap();
+
+        let cases = vec![
+            (
+                Address::p2pkh(&public_key, Network::Testnet).script_pubkey(),
+                ScriptType::P2pkh,
+            ),
+            (
+                Address::p2sh(redeem_script.as_ref(), Network::Testnet).script_pubkey(),
+                ScriptType::P2sh,
+            ),
+            (p2wpk::script_pubkey(&public_key), ScriptType::P2wpkh),
+            (p2wsh::script_pubkey(&redeem_script), ScriptType::P2wsh),
+            (
+                Builder::new()
+                    .push_opcode(OP_PUSHNUM_1)
+                    .push_slice(&[0xab; 32])
+                    .into_script(),
+                ScriptType::P2tr,
+            ),
+            (p2a_script_pubkey(), ScriptType::P2a),
+            (
+                Builder::new()
+                    .push_opcode(OP_PUSHNUM_1)
+                    .push_slice(&[0x4e, 0x74])
+                    .into_script(),
+                ScriptType::NonStandard,
+            ),
+            (
+                Builder::new()
+                    .push_opcode(OP_RETURN)
+                    .push_slice(b"Hello")
+                    .into_script(),
+                ScriptType::OpReturn,
+            ),
+            (redeem_script.script.clone(), ScriptType::NonStandard),
+            (Script::new(), ScriptType::NonStandard),
+        ];
+        for (script, expected) in cases {
+            assert_eq!(classify(&script), expected, "{}", script_to_asm(&script));
+        }
+    }
+
+    #[test]
+    fn test_script_asm_roundtrip() {
+        let public_keys = (0..2)
+            .map(|_| deterministic_keypair(Network::Testnet).0)
+            .collect::<Vec<_>>();
+        let redeem_script = RedeemScriptBuilder::with_public_keys(public_keys.iter().cloned())
+            .quorum(2)
+            .to_script()
+            .unwrap();
+
+        let asm = script_to_asm(redeem_script.as_ref());
+        assert_eq!(
+            asm,
+            format!(
+                "OP_PUSHNUM_2 {} {} OP_PUSHNUM_2 OP_CHECKMULTISIG",
+                hex::encode(public_keys[0].to_bytes()),
+                hex::encode(public_keys[1].to_bytes())
+            )
+        );
+        assert_eq!(&script_from_asm(&asm).unwrap(), redeem_script.as_ref());
+
+        let script = Builder::new()
+            .push_opcode(OP_RETURN)
+            .push_slice(&[])
+            .push_slice(b"Hello")
+            .into_script();
+        let asm = script_to_asm(&script);
+        assert_eq!(asm, "OP_RETURN OP_0 48656c6c6f");
+        assert_eq!(script_from_asm(&asm).unwrap(), script);
+    }
+
+    #[test]
+    fn test_script_from_asm_errors() {
+        assert_eq!(
+            script_from_asm("OP_DUP OP_UNKNOWN"),
+            Err(Error::AsmToken("OP_UNKNOWN".to_owned()))
+        );
+        assert_eq!(
+            script_from_asm("OP_PUSHBYTES_1"),
+            Err(Error::AsmToken("OP_PUSHBYTES_1".to_owned()))
+        );
+        assert_eq!(
+            script_from_asm("0a0"),
+            Err(Error::AsmToken("0a0".to_owned()))
+        );
+        assert_eq!(script_to_asm(&vec![0x02, 0xab].into()), "[invalid]");
+    }
+}
@@ -0,0 +1,763 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A coordinator of the multisig signing process.
+//!
+//! The session state is serializable, so it can be exchanged between the participants
+//! over the network. Each participant adds signatures to the session, and as soon
+//! as the quorum is reached for every input, the session can be finalized into
+//! the fully signed transaction.
+//!
+//! The [`SignatureRequest`][request] and [`SignatureResponse`][response] messages are
+//! the canonical wire format of a single signing round, in which the coordinator asks
+//! the participants to sign the given input.
+//!
+//! [request]: struct.SignatureRequest.html
+//! [response]: struct.SignatureResponse.html
+
+use bitcoin::{
+    blockdata::transaction::Transaction, util::psbt::PartiallySignedTransaction, PublicKey,
+};
+use secp256k1::SecretKey;
+use serde::{Deserialize, Serialize};
+
+use std::collections::BTreeMap;
+
+use crate::{
+    multisig::RedeemScript, p2wsh, sign, Amount, BtcTransaction, Error, ExtraEntropy,
+    InputSignature, Sha256dHash, TxInRef, TxOutProvider,
+};
+
+/// The serializable state of the multisig signing session.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SigningSession {
+    /// The unsigned transaction.
+    transaction: BtcTransaction,
+    /// The redeem script which locks the outputs spent by the transaction.
+    redeem_script: RedeemScript,
+    /// The values of the outputs spent by the transaction inputs.
+    prev_values: Vec<u64>,
+    /// The collected signatures for each input, keyed by the index of the public key
+    /// in the redeem script.
+    signatures: Vec<BTreeMap<usize, InputSignature>>,
+}
+
+impl SigningSession {
+    /// Creates a new session for the given unsigned transaction, whose inputs spend outputs
+    /// with the given values locked by the given redeem script.
+    pub fn new(
+        transaction: Transaction,
+        redeem_script: RedeemScript,
+        prev_values: Vec<Amount>,
+    ) -> Result<SigningSession, Error> {
+        ensure!(
+            transaction.input.len() == prev_values.len(),
+            Error::OutputsCount {
+                inputs: transaction.input.len(),
+                outputs: prev_values.len(),
+            }
+        );
+        let signatures = vec![BTreeMap::default(); prev_values.len()];
+        Ok(SigningSession {
+            transaction: transaction.into(),
+            redeem_script,
+            prev_values: prev_values.into_iter().map(Amount::as_sat).collect(),
+            signatures,
+        })
+    }
+
+    /// Creates a new session for the given unsigned transaction, the values of the spent
+    /// outputs are looked up in the given provider.
+    pub fn with_provider<P: TxOutProvider + ?Sized>(
+        transaction: Transaction,
+        redeem_script: RedeemScript,
+        provider: &P,
+    ) -> Result<SigningSession, Error> {
+        let prev_values = transaction
+            .input
+            .iter()
+            .map(|input| {
+                provider
+                    .tx_out(&input.previous_output)
+                    .map(|output| Amount::from_sat(output.value))
+                    .ok_or(Error::UnknownOutput(input.previous_output))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        SigningSession::new(transaction, redeem_script, prev_values)
+    }
+
+    /// Returns a reference to the unsigned transaction.
+    pub fn transaction(&self) -> &Transaction {
+        self.transaction.as_ref()
+    }
+
+    /// Returns a reference to the redeem script.
+    pub fn redeem_script(&self) -> &RedeemScript {
+        &self.redeem_script
+    }
+
+    /// Returns the number of signatures required for each input.
+    pub fn quorum(&self) -> usize {
+        self.redeem_script.quorum()
+    }
+
+    /// Verifies the signature of the given participant for the given input and adds it
+    /// to the session.
+    pub fn add_signature(
+        &mut self,
+        input: usize,
+        public_key: &PublicKey,
+        signature: InputSignature,
+    ) -> Result<(), Error> {
+        let txin =
+            TxInRef::checked_new(self.transaction(), input).ok_or(Error::NoSuchInput(input))?;
+        let key_index = self
+            .redeem_script
+            .key_index(public_key)
+            .ok_or(Error::UnknownPublicKey)?;
+
+        sign::verify_input_signature(
+            sign::verification_context(),
+            txin,
+            &self.redeem_script.script,
+            self.prev_values[input],
+            public_key,
+            &signature,
+        )
+        .map_err(|error| Error::input(input, error))?;
+        self.signatures[input].insert(key_index, signature);
+        Ok(())
+    }
+
+    /// Signs all inputs with the given secret key and adds the signatures to the session.
+    /// Returns the created signatures in the input order, so they can be sent to the other
+    /// participants.
+    pub fn sign(&mut self, secret_key: &SecretKey) -> Result<Vec<InputSignature>, Error> {
+        let mut signer = p2wsh::InputSigner::new(self.redeem_script.clone());
+        let public_key = PublicKey {
+            compressed: true,
+            key: secp256k1::PublicKey::from_secret_key(signer.secp256k1_context(), secret_key),
+        };
+
+        let signatures = (0..self.signatures.len())
+            .map(|input| {
+                let txin = TxInRef::new(self.transaction(), input);
+                signer.sign_input(txin, self.prev_values[input], secret_key)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        for (input, signature) in signatures.iter().enumerate() {
+            self.add_signature(input, &public_key, signature.clone())?;
+        }
+        Ok(signatures)
+    }
+
+    /// Returns the number of valid signatures collected for the given input.
+    pub fn signatures_count(&self, input: usize) -> usize {
+        self.signatures.get(input).map_or(0, BTreeMap::len)
+    }
+
+    /// Returns `true` if the quorum is reached for every input.
+    pub fn is_complete(&self) -> bool {
+        let quorum = self.quorum();
+        self.signatures
+            .iter()
+            .all(|signatures| signatures.len() >= quorum)
+    }
+
+    /// Finalizes the session and returns the fully signed transaction.
+    pub fn finalize(self) -> Result<Transaction, Error> {
+        let quorum = self.quorum();
+        let signer = p2wsh::InputSigner::new(self.redeem_script);
+        let mut transaction = Transaction::from(self.transaction);
+        for (input, signatures) in self.signatures.into_iter().enumerate() {
+            ensure!(
+                signatures.len() >= quorum,
+                Error::input(
+                    input,
+                    Error::SignaturesCount {
+                        quorum,
+                        actual: signatures.len(),
+                    }
+                )
+            );
+            // Signatures must be ordered in the same way as public keys in the redeem script.
+            let signatures = signatures.into_values().take(quorum);
+            let value = self.prev_values[input];
+            signer
+                .spend_input(&mut transaction, input, value, signatures)
+                .map_err(|error| Error::input(input, error))?;
+        }
+        Ok(transaction)
+    }
+}
+
+/// The serializable signing state of a single input, which spends the output locked by
+/// the multisig redeem script. It tracks the participants which have provided the valid
+/// signatures, so the coordinator can persist the state and resume the collection later.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PartiallySignedInput {
+    /// The redeem script which locks the spent output.
+    redeem_script: RedeemScript,
+    /// The value of the spent output.
+    prev_value: u64,
+    /// The collected signatures keyed by the index of the public key in the redeem script.
+    signatures: BTreeMap<usize, InputSignature>,
+}
+
+impl PartiallySignedInput {
+    /// Creates the state for the input, which spends the output with the given value locked
+    /// by the given redeem script.
+    pub fn new(redeem_script: RedeemScript, prev_value: Amount) -> PartiallySignedInput {
+        PartiallySignedInput {
+            redeem_script,
+            prev_value: prev_value.as_sat(),
+            signatures: BTreeMap::default(),
+        }
+    }
+
+    /// Returns a reference to the redeem script.
+    pub fn redeem_script(&self) -> &RedeemScript {
+        &self.redeem_script
+    }
+
+    /// Returns the value of the spent output.
+    pub fn prev_value(&self) -> Amount {
+        Amount::from_sat(self.prev_value)
+    }
+
+    /// Verifies the signature of the given participant for the given input and adds it
+    /// to the state. The signature of the same participant is replaced.
+    pub fn add_signature(
+        &mut self,
+        txin: TxInRef<'_>,
+        public_key: &PublicKey,
+        signature: InputSignature,
+    ) -> Result<(), Error> {
+        let key_index = self
+            .redeem_script
+            .key_index(public_key)
+            .ok_or(Error::UnknownPublicKey)?;
+        p2wsh::InputSigner::new(self.redeem_script.clone()).verify_input(
+            txin,
+            self.prev_value,
+            public_key,
+            &signature,
+        )?;
+        self.signatures.insert(key_index, signature);
+        Ok(())
+    }
+
+    /// Returns the public keys of the participants, which have provided the signatures,
+    /// in the redeem script order.
+    pub fn signers(&self) -> Vec<PublicKey> {
+        let public_keys = self.redeem_script.content().public_keys;
+        self.signatures
+            .keys()
+            .map(|index| public_keys[*index])
+            .collect()
+    }
+
+    /// Returns the number of signatures still required to reach the quorum.
+    pub fn missing_signatures(&self) -> usize {
+        self.redeem_script
+            .quorum()
+            .saturating_sub(self.signatures.len())
+    }
+
+    /// Returns `true` if the quorum is reached.
+    pub fn is_complete(&self) -> bool {
+        self.missing_signatures() == 0
+    }
+
+    /// Collects the signatures into the witness data of the input with the given index.
+    pub fn spend_input(&self, transaction: &mut Transaction, index: usize) -> Result<(), Error> {
+        let quorum = self.redeem_script.quorum();
+        ensure!(
+            self.signatures.len() >= quorum,
+            Error::SignaturesCount {
+                quorum,
+                actual: self.signatures.len(),
+            }
+        );
+        // Signatures must be ordered in the same way as public keys in the redeem script.
+        let signatures = self.signatures.values().take(quorum).cloned();
+        p2wsh::InputSigner::new(self.redeem_script.clone()).spend_input(
+            transaction,
+            index,
+            self.prev_value,
+            signatures,
+        )
+    }
+}
+
+/// The transaction to be signed in the signing round.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SigningPayload {
+    /// The unsigned transaction, encoded as hex.
+    Transaction(BtcTransaction),
+    /// The partially signed transaction, encoded as base64.
+    Psbt(#[serde(with = "serde_str")] PartiallySignedTransaction),
+}
+
+impl SigningPayload {
+    /// Returns a reference to the unsigned transaction.
+    pub fn transaction(&self) -> &Transaction {
+        match self {
+            SigningPayload::Transaction(transaction) => transaction.as_ref(),
+            SigningPayload::Psbt(psbt) => &psbt.global.unsigned_tx,
+        }
+    }
+}
+
+/// The request to sign the transaction input, which spends the output locked by
+/// the multisig redeem script.
+///
+/// The request carries the signature hash computed by the coordinator, so the participants
+/// can check that they sign the same data before producing the signature.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SignatureRequest {
+    /// The transaction to be signed.
+    payload: SigningPayload,
+    /// The index of the input to be signed.
+    input: usize,
+    /// The redeem script which locks the spent output.
+    redeem_script: RedeemScript,
+    /// The value of the spent output.
+    prev_value: u64,
+    /// The `SIGHASH_ALL` signature hash of the input.
+    #[serde(with = "serde_str")]
+    sighash: Sha256dHash,
+}
+
+impl SignatureRequest {
+    /// Creates the request to sign the given input of the unsigned transaction, which spends
+    /// the output with the given value.
+    pub fn new(
+        transaction: Transaction,
+        input: usize,
+        redeem_script: RedeemScript,
+        prev_value: Amount,
+    ) -> Result<SignatureRequest, Error> {
+        SignatureRequest::with_payload(
+            SigningPayload::Transaction(transaction.into()),
+            input,
+            redeem_script,
+            prev_value.as_sat(),
+        )
+    }
+
+    /// Creates the request to sign the given input of the partially signed transaction.
+    /// The value of the spent output is taken from the witness UTXO of the input.
+    pub fn from_psbt(
+        psbt: PartiallySignedTransaction,
+        input: usize,
+        redeem_script: RedeemScript,
+    ) -> Result<SignatureRequest, Error> {
+        let txin = psbt
+            .global
+            .unsigned_tx
+            .input
+            .get(input)
+            .ok_or(Error::NoSuchInput(input))?;
+        let prev_value = psbt
+            .inputs
+            .get(input)
+            .and_then(|input| input.witness_utxo.as_ref())
+            .map(|output| output.value)
+            .ok_or(Error::UnknownOutput(txin.previous_output))?;
+        SignatureRequest::with_payload(SigningPayload::Psbt(psbt), input, redeem_script, prev_value)
+    }
+
+    fn with_payload(
+        payload: SigningPayload,
+        input: usize,
+        redeem_script: RedeemScript,
+        prev_value: u64,
+    ) -> Result<SignatureRequest, Error> {
+        let txin =
+            TxInRef::checked_new(payload.transaction(), input).ok_or(Error::NoSuchInput(input))?;
+        let sighash = sign::signature_hash(txin, &redeem_script.script, prev_value);
+        Ok(SignatureRequest {
+            payload,
+            input,
+            redeem_script,
+            prev_value,
+            sighash,
+        })
+    }
+
+    /// Returns a reference to the transaction to be signed.
+    pub fn payload(&self) -> &SigningPayload {
+        &self.payload
+    }
+
+    /// Returns a reference to the unsigned transaction.
+    pub fn transaction(&self) -> &Transaction {
+        self.payload.transaction()
+    }
+
+    /// Returns the index of the input to be signed.
+    pub fn input(&self) -> usize {
+        self.input
+    }
+
+    /// Returns a reference to the redeem script.
+    pub fn redeem_script(&self) -> &RedeemScript {
+        &self.redeem_script
+    }
+
+    /// Returns the value of the spent output.
+    pub fn prev_value(&self) -> Amount {
+        Amount::from_sat(self.prev_value)
+    }
+
+    /// Returns the signature hash of the input.
+    pub fn sighash(&self) -> Sha256dHash {
+        self.sighash
+    }
+
+    /// Checks that the signature hash matches the transaction, the input and the spent
+    /// output of the request. The requests received from the network must be verified
+    /// before signing, the [`sign`][sign] method does it automatically.
+    ///
+    /// [sign]: #method.sign
+    pub fn verify(&self) -> Result<(), Error> {
+        let txin = TxInRef::checked_new(self.transaction(), self.input)
+            .ok_or(Error::NoSuchInput(self.input))?;
+        let sighash = sign::signature_hash(txin, &self.redeem_script.script, self.prev_value);
+        ensure!(sighash == self.sighash, Error::SighashMismatch);
+        Ok(())
+    }
+
+    /// Verifies the request and signs the input with the given secret key.
+    pub fn sign(&self, secret_key: &SecretKey) -> Result<SignatureResponse, Error> {
+        self.verify()?;
+        let context = sign::shared_context();
+        let public_key = PublicKey {
+            compressed: true,
+            key: secp256k1::PublicKey::from_secret_key(&context, secret_key),
+        };
+        ensure!(
+            self.redeem_script.key_index(&public_key).is_some(),
+            Error::KeyNotInScript
+        );
+        let txin = TxInRef::new(self.transaction(), self.input);
+        let signature = sign::sign_input(
+            &context,
+            txin,
+            &self.redeem_script.script,
+            self.prev_value,
+            secret_key,
+            ExtraEntropy::None,
+        )?;
+        Ok(SignatureResponse {
+            input: self.input,
+            sighash: self.sighash,
+            public_key,
+            signature,
+        })
+    }
+
+    /// Checks that the response matches the request and contains the valid signature
+    /// of one of the redeem script participants.
+    pub fn verify_response(&self, response: &SignatureResponse) -> Result<(), Error> {
+        ensure!(
+            response.input == self.input && response.sighash == self.sighash,
+            Error::SighashMismatch
+        );
+        ensure!(
+            self.redeem_script.key_index(&response.public_key).is_some(),
+            Error::UnknownPublicKey
+        );
+        let txin = TxInRef::checked_new(self.transaction(), self.input)
+            .ok_or(Error::NoSuchInput(self.input))?;
+        sign::verify_input_signature(
+            sign::verification_context(),
+            txin,
+            &self.redeem_script.script,
+            self.prev_value,
+            &response.public_key,
+            &response.signature,
+        )
+    }
+}
+
+/// The response of the participant to the [`SignatureRequest`][request].
+///
+/// [request]: struct.SignatureRequest.html
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SignatureResponse {
+    /// The index of the signed input.
+    pub input: usize,
+    /// The signature hash of the signed input.
+    #[serde(with = "serde_str")]
+    pub sighash: Sha256dHash,
+    /// The public key of the participant.
+    #[serde(with = "serde_str")]
+    pub public_key: PublicKey,
+    /// The produced signature.
+    pub signature: InputSignature,
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{
+        blockdata::{
+            opcodes::all::OP_RETURN,
+            script::{Builder, Script},
+            transaction::{OutPoint, SigHashType, Transaction, TxIn, TxOut},
+        },
+        network::constants::Network,
+        util::psbt::PartiallySignedTransaction,
+    };
+
+    use crate::{
+        multisig::RedeemScriptBuilder,
+        p2wsh,
+        session::{PartiallySignedInput, SignatureRequest, SigningSession},
+        test_data::deterministic_keypair,
+        Amount, Error, InputSignature, InputSignatureRef, SignatureError, TxInRef,
+    };
+
+    #[test]
+    fn test_signing_session() {
+        let keypairs = (0..3)
+            .map(|_| deterministic_keypair(Network::Testnet))
+            .collect::<Vec<_>>();
+        let redeem_script = RedeemScriptBuilder::with_public_keys(keypairs.iter().map(|x| x.0))
+            .quorum(2)
+            .to_script()
+            .unwrap();
+        let transaction = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: (0..2)
+                .map(|vout| TxIn {
+                    previous_output: OutPoint::new(Default::default(), vout),
+                    script_sig: Script::default(),
+                    sequence: 0xFFFF_FFFF,
+                    witness: Vec::default(),
+                })
+                .collect(),
+            output: vec![TxOut {
+                value: 0,
+                script_pubkey: Builder::new().push_opcode(OP_RETURN).into_script(),
+            }],
+        };
+        let prev_values = vec![Amount::from_sat(10_000), Amount::from_sat(20_000)];
+
+        let mut session =
+            SigningSession::new(transaction, redeem_script.clone(), prev_values.clone()).unwrap();
+        // The last participant signs first.
+        let signatures = session.sign(&keypairs[2].1.key).unwrap();
+        assert_eq!(session.signatures_count(0), 1);
+        assert!(!session.is_complete());
+        assert_eq!(
+            session.clone().finalize(),
+            Err(Error::input(
+                0,
+                Error::SignaturesCount {
+                    quorum: 2,
+                    actual: 1
+                }
+            ))
+        );
+        // Signatures from the foreign keys or for the wrong input are rejected.
+        let (foreign_key, _) = deterministic_keypair(Network::Testnet);
+        assert_eq!(
+            session.add_signature(0, &foreign_key, signatures[0].clone()),
+            Err(Error::UnknownPublicKey)
+        );
+        assert_eq!(
+            session.add_signature(1, &keypairs[2].0, signatures[0].clone()),
+            Err(Error::input(1, secp256k1::Error::IncorrectSignature))
+        );
+        // The signatures of the other sighash types would be rejected by the network.
+        let retagged =
+            InputSignature::new(signatures[0].content(), SigHashType::AllPlusAnyoneCanPay).unwrap();
+        assert_eq!(
+            session.add_signature(0, &keypairs[2].0, retagged),
+            Err(Error::input(
+                0,
+                SignatureError::UnsupportedSighashType(SigHashType::AllPlusAnyoneCanPay)
+            ))
+        );
+        // The session is transferred to the first participant.
+        let json = serde_json::to_string(&session).unwrap();
+        let mut session: SigningSession = serde_json::from_str(&json).unwrap();
+        session.sign(&keypairs[0].1.key).unwrap();
+        assert!(session.is_complete());
+        // Checks that the signatures are ordered by the public keys.
+        let transaction = session.finalize().unwrap();
+        let signer = p2wsh::InputSigner::new(redeem_script);
+        for (index, value) in prev_values.into_iter().enumerate() {
+            let witness = &transaction.input[index].witness;
+            assert_eq!(witness.len(), 4);
+            for (signature, keypair) in witness[1..3].iter().zip(&[&keypairs[0], &keypairs[2]]) {
+                signer
+                    .verify_input(
+                        TxInRef::new(&transaction, index),
+                        value,
+                        &keypair.0,
+                        InputSignatureRef::from_bytes(signature).unwrap(),
+                    )
+                    .unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_partially_signed_input() {
+        let keypairs = (0..3)
+            .map(|_| deterministic_keypair(Network::Testnet))
+            .collect::<Vec<_>>();
+        let redeem_script = RedeemScriptBuilder::with_public_keys(keypairs.iter().map(|x| x.0))
+            .quorum(2)
+            .to_script()
+            .unwrap();
+        let mut transaction = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Default::default(), 0),
+                script_sig: Script::default(),
+                sequence: 0xFFFF_FFFF,
+                witness: Vec::default(),
+            }],
+            output: vec![TxOut {
+                value: 0,
+                script_pubkey: Builder::new().push_opcode(OP_RETURN).into_script(),
+            }],
+        };
+        let mut signer = p2wsh::InputSigner::new(redeem_script.clone());
+        let signatures = keypairs
+            .iter()
+            .map(|(_, sk)| {
+                let txin = TxInRef::new(&transaction, 0);
+                signer.sign_input(txin, 10_000, &sk.key).unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        let mut input = PartiallySignedInput::new(redeem_script, Amount::from_sat(10_000));
+        let unsigned = transaction.clone();
+        let txin = TxInRef::new(&unsigned, 0);
+        input
+            .add_signature(txin, &keypairs[2].0, signatures[2].clone())
+            .unwrap();
+        assert_eq!(
+            input.add_signature(txin, &keypairs[1].0, signatures[2].clone()),
+            Err(Error::from(secp256k1::Error::IncorrectSignature))
+        );
+        assert_eq!(input.signers(), vec![keypairs[2].0]);
+        assert_eq!(input.missing_signatures(), 1);
+        assert_eq!(
+            input.spend_input(&mut transaction, 0),
+            Err(Error::SignaturesCount {
+                quorum: 2,
+                actual: 1
+            })
+        );
+
+        // The state is persisted and restored by the coordinator.
+        let json = serde_json::to_string(&input).unwrap();
+        let mut input: PartiallySignedInput = serde_json::from_str(&json).unwrap();
+        input
+            .add_signature(txin, &keypairs[0].0, signatures[0].clone())
+            .unwrap();
+        assert_eq!(input.signers(), vec![keypairs[0].0, keypairs[2].0]);
+        assert!(input.is_complete());
+        input.spend_input(&mut transaction, 0).unwrap();
+        assert_eq!(transaction.input[0].witness.len(), 4);
+    }
+
+    #[test]
+    fn test_signature_request() {
+        let keypairs = (0..3)
+            .map(|_| deterministic_keypair(Network::Testnet))
+            .collect::<Vec<_>>();
+        let redeem_script = RedeemScriptBuilder::with_public_keys(keypairs.iter().map(|x| x.0))
+            .quorum(2)
+            .to_script()
+            .unwrap();
+        let transaction = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Default::default(), 0),
+                script_sig: Script::default(),
+                sequence: 0xFFFF_FFFF,
+                witness: Vec::default(),
+            }],
+            output: vec![TxOut {
+                value: 0,
+                script_pubkey: Builder::new().push_opcode(OP_RETURN).into_script(),
+            }],
+        };
+        let prev_value = Amount::from_sat(10_000);
+        let request =
+            SignatureRequest::new(transaction.clone(), 0, redeem_script.clone(), prev_value)
+                .unwrap();
+        assert_eq!(
+            SignatureRequest::new(transaction.clone(), 1, redeem_script.clone(), prev_value),
+            Err(Error::NoSuchInput(1))
+        );
+
+        // The request is sent to the participant.
+        let json = serde_json::to_string(&request).unwrap();
+        let received: SignatureRequest = serde_json::from_str(&json).unwrap();
+        assert_eq!(received, request);
+        let response = received.sign(&keypairs[1].1.key).unwrap();
+        let (_, foreign_key) = deterministic_keypair(Network::Testnet);
+        assert_eq!(received.sign(&foreign_key.key), Err(Error::KeyNotInScript));
+        // The response is sent back to the coordinator.
+        let json = serde_json::to_string(&response).unwrap();
+        let response = serde_json::from_str(&json).unwrap();
+        request.verify_response(&response).unwrap();
+        let mut input = PartiallySignedInput::new(redeem_script.clone(), prev_value);
+        input
+            .add_signature(
+                TxInRef::new(&transaction, 0),
+                &response.public_key,
+                response.signature,
+            )
+            .unwrap();
+
+        // The tampered request is rejected by the participant.
+        let mut value = serde_json::to_value(&request).unwrap();
+        value["prev_value"] = 20_000.into();
+        let tampered: SignatureRequest = serde_json::from_value(value).unwrap();
+        assert_eq!(
+            tampered.sign(&keypairs[1].1.key),
+            Err(Error::SighashMismatch)
+        );
+
+        // The spent value of the PSBT input is taken from the witness UTXO.
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(transaction).unwrap();
+        assert_eq!(
+            SignatureRequest::from_psbt(psbt.clone(), 0, redeem_script.clone()),
+            Err(Error::UnknownOutput(OutPoint::new(Default::default(), 0)))
+        );
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: prev_value.as_sat(),
+            script_pubkey: p2wsh::script_pubkey(&redeem_script),
+        });
+        let psbt_request = SignatureRequest::from_psbt(psbt, 0, redeem_script).unwrap();
+        assert_eq!(psbt_request.sighash(), request.sighash());
+        let json = serde_json::to_string(&psbt_request).unwrap();
+        let received: SignatureRequest = serde_json::from_str(&json).unwrap();
+        assert_eq!(received, psbt_request);
+        received.sign(&keypairs[0].1.key).unwrap();
+    }
+}
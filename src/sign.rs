@@ -14,42 +14,228 @@
 
 //! Helper functions to create and verify segwit input signatures with the sighash all type.
 
+pub use self::adaptor::{AdaptorError, AdaptorSignature};
+
 use bitcoin::{
-    blockdata::script::Script, blockdata::transaction::SigHashType,
-    util::bip143::SighashComponents, PublicKey,
+    blockdata::script::Script,
+    blockdata::transaction::{OutPoint, SigHashType, Transaction, TxOut},
+    network::constants::Network,
+    util::{address::Address, bip143::SigHashCache},
+    PublicKey,
+};
+use once_cell::sync::Lazy;
+use secp256k1::{
+    self,
+    recovery::{RecoverableSignature, RecoveryId},
+    All, Message, Secp256k1, SecretKey, Signature, Signing, Verification, VerifyOnly,
+};
+use thiserror::Error;
+
+use std::{
+    borrow::ToOwned,
+    convert::TryFrom,
+    fmt, ptr,
+    str::FromStr,
+    sync::{atomic, Arc},
 };
-use secp256k1::{self, Message, Secp256k1, SecretKey, Signature, Signing, Verification};
 
-use std::borrow::ToOwned;
+use crate::{ecc, Amount, Error, Hash, Sha256dHash, TxInRef, UnspentTxOutValue};
 
-use crate::{Sha256dHash, TxInRef, UnspentTxOutValue};
+mod adaptor;
 
 /// A signature data with the embedded sighash type byte.
-#[derive(Debug, Clone, PartialEq)]
-pub struct InputSignature(Vec<u8>);
+///
+/// The data is kept in the inline buffer of the [`MAX_LEN`][max-len] bytes, so the signature
+/// does not allocate on the heap.
+///
+/// [max-len]: #associatedconstant.MAX_LEN
+#[derive(Clone)]
+pub struct InputSignature {
+    len: u8,
+    bytes: [u8; InputSignature::MAX_LEN],
+}
 
 impl InputSignature {
+    /// The maximum length of the strictly DER encoded signature with the sighash type byte.
+    pub const MAX_LEN: usize = 73;
+
     /// Constructs input signature from the given signature data and the given sighash type.
-    pub fn new(mut inner: Vec<u8>, sighash_type: SigHashType) -> InputSignature {
-        inner.push(sighash_type as u8);
-        InputSignature(inner)
+    ///
+    /// The signature data is not checked, so use the [`checked_new`][checked-new] constructor
+    /// for the data from the untrusted sources. Only the data longer than `MAX_LEN - 1` bytes,
+    /// which does not fit into the buffer, is rejected.
+    ///
+    /// [checked-new]: #method.checked_new
+    pub fn new(inner: &[u8], sighash_type: SigHashType) -> Result<InputSignature, Error> {
+        ensure!(
+            inner.len() < InputSignature::MAX_LEN,
+            SignatureError::TooLong(inner.len())
+        );
+        Ok(InputSignature::with_sighash_type(inner, sighash_type))
+    }
+
+    /// Tries to construct input signature from the given signature data and the given
+    /// sighash type. The signature data must be strictly DER encoded.
+    pub fn checked_new(inner: &[u8], sighash_type: SigHashType) -> Result<InputSignature, Error> {
+        // The longer data cannot be strictly DER encoded, so it is rejected before copying.
+        ensure!(
+            inner.len() < InputSignature::MAX_LEN,
+            SignatureError::NotStrictDer
+        );
+        let signature = InputSignature::with_sighash_type(inner, sighash_type);
+        InputSignatureRef::from_bytes(signature.as_ref())?;
+        Ok(signature)
+    }
+
+    /// Constructs input signature from the secp256k1 signature and the given sighash type.
+    pub fn from_signature(signature: &Signature, sighash_type: SigHashType) -> InputSignature {
+        InputSignature::with_sighash_type(&signature.serialize_der(), sighash_type)
+    }
+
+    /// Appends the sighash type byte to the given content, which must fit into the buffer.
+    fn with_sighash_type(content: &[u8], sighash_type: SigHashType) -> InputSignature {
+        debug_assert!(content.len() < InputSignature::MAX_LEN);
+        let mut signature = InputSignature::from_slice(content);
+        signature.bytes[content.len()] = sighash_type as u8;
+        signature.len += 1;
+        signature
+    }
+
+    /// Copies the given bytes into the inline buffer, they must fit into it.
+    fn from_slice(bytes: &[u8]) -> InputSignature {
+        let mut signature = InputSignature {
+            len: bytes.len() as u8,
+            bytes: [0; InputSignature::MAX_LEN],
+        };
+        signature.bytes[..bytes.len()].copy_from_slice(bytes);
+        signature
+    }
+
+    /// Returns the signature content as the secp256k1 signature.
+    pub fn to_signature(&self) -> Result<Signature, Error> {
+        Signature::from_der(self.content())
+            .map_err(SignatureError::from)
+            .map_err(Error::from)
     }
 
     /// Tries to construct input signature from the raw bytes.
-    pub fn from_bytes(bytes: Vec<u8>) -> Result<InputSignature, secp256k1::Error> {
-        InputSignatureRef::from_bytes(bytes.as_ref())?;
-        Ok(InputSignature(bytes))
+    /// [Read more...][from-bytes]
+    ///
+    /// [from-bytes]: struct.InputSignatureRef.html#method.from_bytes
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<InputSignature, Error> {
+        InputSignatureRef::from_bytes(bytes.as_ref()).map(InputSignature::from)
     }
 
     /// Returns the signature content in canonical form.
     pub fn content(&self) -> &[u8] {
-        self.0.split_last().unwrap().1
+        self.as_ref().split_last().unwrap().1
     }
 
     /// Returns a sighash type of the given input signature.
+    ///
+    /// The unknown sighash type bytes are treated as `SIGHASH_ALL` according to the consensus
+    /// rules, use the [`try_sighash_type`][try-sighash-type] method to reject them.
+    ///
+    /// [try-sighash-type]: #method.try_sighash_type
     pub fn sighash_type(&self) -> SigHashType {
-        let byte = *self.0.last().unwrap();
-        SigHashType::from_u32(u32::from(byte))
+        let byte = *self.as_ref().last().unwrap();
+        SigHashType::from_u32_consensus(u32::from(byte))
+    }
+
+    /// Returns a sighash type of the given input signature, or an error if the sighash type
+    /// byte is not one of the defined types.
+    pub fn try_sighash_type(&self) -> Result<SigHashType, SignatureError> {
+        try_sighash_type(self.as_ref())
+    }
+
+    /// Constructs input signature from the 64-byte compact signature and the given
+    /// sighash type. The signature is normalized to the low `S` form.
+    pub fn from_compact(bytes: &[u8], sighash_type: SigHashType) -> Result<InputSignature, Error> {
+        let mut signature = Signature::from_compact(bytes).map_err(SignatureError::from)?;
+        signature.normalize_s();
+        Ok(InputSignature::from_signature(&signature, sighash_type))
+    }
+
+    /// Returns the signature content in the 64-byte compact form.
+    pub fn to_compact(&self) -> Result<[u8; 64], Error> {
+        self.to_signature()
+            .map(|signature| signature.serialize_compact())
+    }
+
+    /// Constructs input signature from the recoverable signature and the given sighash type.
+    /// The recovery id is dropped and the signature is normalized to the low `S` form.
+    pub fn from_recoverable(
+        signature: &RecoverableSignature,
+        sighash_type: SigHashType,
+    ) -> InputSignature {
+        let mut signature = signature.to_standard();
+        signature.normalize_s();
+        InputSignature::from_signature(&signature, sighash_type)
+    }
+
+    /// Returns the recoverable form of the signature, which recovery id is found from
+    /// the signed message and the public key of the signer.
+    pub fn to_recoverable(
+        &self,
+        message: &Message,
+        public_key: &PublicKey,
+    ) -> Result<RecoverableSignature, Error> {
+        let compact = self.to_compact()?;
+        let context = verification_context();
+        (0..4)
+            .filter_map(|id| {
+                let id = RecoveryId::from_i32(id).ok()?;
+                RecoverableSignature::from_compact(&compact, id).ok()
+            })
+            .find(|signature| context.recover(message, signature).ok() == Some(public_key.key))
+            .ok_or_else(|| SignatureError::from(secp256k1::Error::IncorrectSignature).into())
+    }
+}
+
+impl fmt::Debug for InputSignature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("InputSignature")
+            .field(&self.as_ref())
+            .finish()
+    }
+}
+
+impl PartialEq for InputSignature {
+    fn eq(&self, other: &InputSignature) -> bool {
+        self.as_ref() == other.as_ref()
+    }
+}
+
+impl fmt::Display for InputSignature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&hex::encode(self.as_ref()))
+    }
+}
+
+impl FromStr for InputSignature {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = hex::decode(s)?;
+        InputSignature::from_bytes(bytes)
+    }
+}
+
+impl ::serde::Serialize for InputSignature {
+    fn serialize<S>(&self, ser: S) -> ::std::result::Result<S::Ok, S::Error>
+    where
+        S: ::serde::Serializer,
+    {
+        ::serde_str::serialize(self, ser)
+    }
+}
+
+impl<'de> ::serde::Deserialize<'de> for InputSignature {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: ::serde::Deserializer<'de>,
+    {
+        ::serde_str::deserialize(deserializer)
     }
 }
 
@@ -77,11 +263,19 @@ pub struct InputSignatureRef<'a>(&'a [u8]);
 
 impl<'a> InputSignatureRef<'a> {
     /// Tries to construct input signature from the raw bytes.
-    pub fn from_bytes(bytes: &'a [u8]) -> Result<InputSignatureRef<'a>, secp256k1::Error> {
-        let (_sighash_type, content) = bytes
-            .split_last()
-            .ok_or_else(|| secp256k1::Error::InvalidMessage)?;
-        Signature::from_der(content)?;
+    ///
+    /// The signature must be strictly DER encoded as required by the [`BIP-66`][bip-66]
+    /// and must end with a defined sighash type byte.
+    ///
+    /// [bip-66]: https://github.com/bitcoin/bips/blob/master/bip-0066.mediawiki
+    pub fn from_bytes(bytes: &'a [u8]) -> Result<InputSignatureRef<'a>, Error> {
+        let (&sighash_type, content) = bytes.split_last().ok_or(SignatureError::Empty)?;
+        ensure!(is_strict_der(content), SignatureError::NotStrictDer);
+        ensure!(
+            is_defined_sighash_type(sighash_type),
+            SignatureError::UnknownSighashType(sighash_type)
+        );
+        Signature::from_der(content).map_err(SignatureError::from)?;
         Ok(InputSignatureRef(bytes))
     }
 
@@ -91,21 +285,48 @@ impl<'a> InputSignatureRef<'a> {
     }
 
     /// Returns a sighash type of the given input signature.
+    ///
+    /// The unknown sighash type bytes are treated as `SIGHASH_ALL` according to the consensus
+    /// rules, use the [`try_sighash_type`][try-sighash-type] method to reject them.
+    ///
+    /// [try-sighash-type]: #method.try_sighash_type
     pub fn sighash_type(&self) -> SigHashType {
         let byte = *self.0.last().unwrap();
-        SigHashType::from_u32(u32::from(byte))
+        SigHashType::from_u32_consensus(u32::from(byte))
+    }
+
+    /// Returns a sighash type of the given input signature, or an error if the sighash type
+    /// byte is not one of the defined types.
+    pub fn try_sighash_type(&self) -> Result<SigHashType, SignatureError> {
+        try_sighash_type(self.0)
+    }
+}
+
+impl TryFrom<Vec<u8>> for InputSignature {
+    type Error = Error;
+
+    fn try_from(bytes: Vec<u8>) -> Result<Self, Self::Error> {
+        InputSignature::from_bytes(bytes)
+    }
+}
+
+impl<'a> TryFrom<&'a [u8]> for InputSignatureRef<'a> {
+    type Error = Error;
+
+    fn try_from(bytes: &'a [u8]) -> Result<Self, Self::Error> {
+        InputSignatureRef::from_bytes(bytes)
     }
 }
 
 impl From<InputSignature> for Vec<u8> {
     fn from(s: InputSignature) -> Self {
-        s.0
+        s.as_ref().to_vec()
     }
 }
 
 impl AsRef<[u8]> for InputSignature {
     fn as_ref(&self) -> &[u8] {
-        self.0.as_ref()
+        &self.bytes[..usize::from(self.len)]
     }
 }
 
@@ -116,8 +337,8 @@ impl<'a> AsRef<[u8]> for InputSignatureRef<'a> {
 }
 
 impl<'a> From<&'a InputSignature> for InputSignatureRef<'a> {
-    fn from(s: &'a InputSignature) -> InputSignatureRef {
-        InputSignatureRef(s.0.as_ref())
+    fn from(s: &'a InputSignature) -> InputSignatureRef<'a> {
+        InputSignatureRef(s.as_ref())
     }
 }
 
@@ -129,10 +350,54 @@ impl<'a> From<InputSignatureRef<'a>> for Vec<u8> {
 
 impl<'a> From<InputSignatureRef<'a>> for InputSignature {
     fn from(s: InputSignatureRef<'a>) -> InputSignature {
-        InputSignature(s.0.to_owned())
+        // The signature reference is always strictly DER encoded, so it fits into the buffer.
+        InputSignature::from_slice(s.0)
     }
 }
 
+/// The secret key held by the key-holding input signers. The key is hidden from the debug
+/// output and is overwritten on drop, on a best-effort basis, since the copies made
+/// by the callers or the compiler are out of reach.
+pub(crate) struct StoredSecretKey(SecretKey);
+
+impl StoredSecretKey {
+    pub(crate) fn new(secret_key: SecretKey) -> StoredSecretKey {
+        StoredSecretKey(secret_key)
+    }
+
+    pub(crate) fn get(&self) -> &SecretKey {
+        &self.0
+    }
+}
+
+impl fmt::Debug for StoredSecretKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("StoredSecretKey(..)")
+    }
+}
+
+impl Drop for StoredSecretKey {
+    #[allow(unsafe_code)]
+    fn drop(&mut self) {
+        // The volatile write is not elided as a dead store. The pointer is obtained from
+        // the mutable reference, so it is valid and aligned.
+        unsafe { ptr::write_volatile(&mut self.0, secp256k1::key::ONE_KEY) };
+        atomic::compiler_fence(atomic::Ordering::SeqCst);
+    }
+}
+
+/// Checks that the private key of the given network can be used in the expected one.
+/// The WIF encodings of the test networks are indistinguishable, so the test networks
+/// are considered compatible.
+pub(crate) fn check_network(actual: Network, expected: Network) -> Result<(), Error> {
+    let is_test_network = |network| network != Network::Bitcoin;
+    ensure!(
+        actual == expected || is_test_network(actual) && is_test_network(expected),
+        Error::NetworkMismatch { expected, actual }
+    );
+    Ok(())
+}
+
 /// Computes the [`BIP-143`][bip-143] compliant sighash for a [`SIGHASH_ALL`][sighash_all]
 /// signature for the given input.
 ///
@@ -144,57 +409,474 @@ pub fn signature_hash<'a, 'b, V: Into<UnspentTxOutValue<'b>>>(
     value: V,
 ) -> Sha256dHash {
     let value = value.into().balance(txin);
-    SighashComponents::new(txin.transaction)
-        .sighash_all(txin.as_ref(), script, value)
+    SigHashCache::new(txin.transaction)
+        .signature_hash(txin.index(), script, value, SigHashType::All)
         .as_hash()
 }
 
+/// Computes the [`BIP-143`][bip-143] compliant sighashes for a [`SIGHASH_ALL`][sighash_all]
+/// signatures for all inputs of the given transaction. The script codes and the values
+/// of the spent outputs are given in the input order.
+///
+/// The result is the same as of the `signature_hash` calls for each input, but the sighash
+/// components shared by the inputs are computed only once, so it is suitable to prepare
+/// the sighashes for the external signers.
+///
+/// [bip-143]: https://github.com/bitcoin/bips/blob/master/bip-0143.mediawiki
+/// [sighash_all]: https://bitcoin.org/en/developer-guide#signature-hash-types
+pub fn signature_hashes<'b, V>(
+    transaction: &Transaction,
+    inputs: &[(Script, V)],
+) -> Result<Vec<Sha256dHash>, Error>
+where
+    V: Clone + Into<UnspentTxOutValue<'b>>,
+{
+    ensure!(
+        transaction.input.len() == inputs.len(),
+        Error::OutputsCount {
+            inputs: transaction.input.len(),
+            outputs: inputs.len(),
+        }
+    );
+
+    let mut cache = SigHashCache::new(transaction);
+    let sighashes = inputs
+        .iter()
+        .enumerate()
+        .map(|(index, (script, value))| {
+            let value = value
+                .clone()
+                .into()
+                .balance(TxInRef::new(transaction, index));
+            cache
+                .signature_hash(index, script, value, SigHashType::All)
+                .as_hash()
+        })
+        .collect();
+    Ok(sighashes)
+}
+
+/// The secp256k1 context shared by the input signers, since its creation is expensive.
+static SHARED_CONTEXT: Lazy<Arc<Secp256k1<All>>> = Lazy::new(|| Arc::new(Secp256k1::new()));
+
+/// Returns the secp256k1 context shared by all input signers created without the explicit
+/// context.
+pub(crate) fn shared_context() -> Arc<Secp256k1<All>> {
+    Arc::clone(&SHARED_CONTEXT)
+}
+
+/// The verification-only secp256k1 context, which is cheaper than the full one.
+static VERIFICATION_CONTEXT: Lazy<Secp256k1<VerifyOnly>> = Lazy::new(Secp256k1::verification_only);
+
+/// Returns the verification-only secp256k1 context, used to check signatures.
+pub(crate) fn verification_context() -> &'static Secp256k1<VerifyOnly> {
+    &VERIFICATION_CONTEXT
+}
+
 /// Computes the [`BIP-143`][bip-143] compliant signature for the given input.
 /// [Read more...][signature-hash]
 ///
 /// [bip-143]: https://github.com/bitcoin/bips/blob/master/bip-0143.mediawiki
 /// [signature-hash]: fn.signature_hash.html
 pub fn sign_input<'a, 'b, C, V>(
-    context: &mut Secp256k1<C>,
+    context: &Secp256k1<C>,
     txin: TxInRef<'a>,
     script: &Script,
     value: V,
     secret_key: &SecretKey,
-) -> Result<InputSignature, secp256k1::Error>
+    entropy: ExtraEntropy,
+) -> Result<InputSignature, Error>
 where
     C: Signing,
     V: Into<UnspentTxOutValue<'b>>,
 {
     // Computes sighash.
     let sighash = signature_hash(txin, script, value);
-    // Makes signature.
+    sign_sighash(context, &sighash, secret_key, entropy)
+}
+
+/// Computes signatures for all inputs of the transaction, which spend outputs locked
+/// by the given script pubkey. The sighash components shared by the inputs are computed
+/// only once.
+pub(crate) fn sign_all_inputs<'b, C, V>(
+    context: &Secp256k1<C>,
+    transaction: &Transaction,
+    script: &Script,
+    script_pubkey: &Script,
+    values: &[V],
+    secret_key: &SecretKey,
+    entropy: ExtraEntropy,
+) -> Result<Vec<InputSignature>, Error>
+where
+    C: Signing,
+    V: Clone + Into<UnspentTxOutValue<'b>>,
+{
+    signing_report(transaction, script, script_pubkey, values)?
+        .inputs
+        .iter()
+        .map(|input| sign_sighash(context, &input.sighash, secret_key, entropy))
+        .collect()
+}
+
+/// Computes the sighashes of all inputs of the transaction, which spend outputs locked
+/// by the given script pubkey, in the same way as the `sign_all_inputs` function does,
+/// but without signing them.
+pub(crate) fn signing_report<'b, V>(
+    transaction: &Transaction,
+    script: &Script,
+    script_pubkey: &Script,
+    values: &[V],
+) -> Result<SigningReport, Error>
+where
+    V: Clone + Into<UnspentTxOutValue<'b>>,
+{
+    ensure!(
+        transaction.input.len() == values.len(),
+        Error::OutputsCount {
+            inputs: transaction.input.len(),
+            outputs: values.len(),
+        }
+    );
+
+    let mut cache = SigHashCache::new(transaction);
+    let inputs = values
+        .iter()
+        .cloned()
+        .enumerate()
+        .map(|(index, value)| {
+            let txin = TxInRef::new(transaction, index);
+            let value = value.into();
+            if let Some(prev_script_pubkey) = value.script_pubkey(txin) {
+                ensure!(
+                    prev_script_pubkey == script_pubkey,
+                    Error::input(index, Error::ScriptMismatch)
+                );
+            }
+            let value = value.balance(txin);
+            let sighash = cache
+                .signature_hash(index, script, value, SigHashType::All)
+                .as_hash();
+            Ok(InputReport {
+                outpoint: txin.input().previous_output,
+                value: Amount::from_sat(value),
+                sighash,
+            })
+        })
+        .collect::<Result<_, Error>>()?;
+    Ok(SigningReport {
+        inputs,
+        outputs: transaction.output.clone(),
+    })
+}
+
+/// The input, which would be signed by the signer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputReport {
+    /// The spent output.
+    pub outpoint: OutPoint,
+    /// The value of the spent output.
+    pub value: Amount,
+    /// The sighash, which would be signed.
+    pub sighash: Sha256dHash,
+}
+
+/// The report of the dry-run signing, which contains everything the signer would commit
+/// to, so the signing request can be reviewed before the actual signing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SigningReport {
+    /// The inputs in the transaction input order.
+    pub inputs: Vec<InputReport>,
+    /// The outputs of the transaction, which are the destinations of the funds.
+    pub outputs: Vec<TxOut>,
+}
+
+impl SigningReport {
+    /// Returns the total value of the spent outputs.
+    pub fn input_value(&self) -> Amount {
+        self.inputs
+            .iter()
+            .fold(Amount::ZERO, |total, input| total + input.value)
+    }
+
+    /// Returns the total value of the transaction outputs.
+    pub fn output_value(&self) -> Amount {
+        self.outputs.iter().fold(Amount::ZERO, |total, output| {
+            total + Amount::from_sat(output.value)
+        })
+    }
+
+    /// Returns the transaction fee, or `None` if the outputs exceed the inputs.
+    pub fn fee(&self) -> Option<Amount> {
+        self.input_value().checked_sub(self.output_value())
+    }
+
+    /// Returns the address of the output with the given index on the given network,
+    /// or `None` if the output script is not an address.
+    ///
+    /// # Panics
+    ///
+    /// - If the output index is out of range.
+    pub fn destination(&self, index: usize, network: Network) -> Option<Address> {
+        Address::from_script(&self.outputs[index].script_pubkey, network)
+    }
+}
+
+/// The extra entropy mixed into the `RFC 6979` nonce of the signatures.
+///
+/// The signatures are deterministic without the extra entropy, so the fault attacks, which
+/// compare the correct and the faulty signatures of the same data, can reveal the secret
+/// key. With the fresh random entropy, as modern signers use, each signature gets the new
+/// nonce, but the nonce still depends on the secret key and the message, so the weak random
+/// number generator does not compromise the key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExtraEntropy {
+    /// The plain `RFC 6979` nonce without the extra entropy.
+    None,
+    /// The given 32 bytes are mixed into the nonce of each signature.
+    Fixed([u8; 32]),
+    /// The fresh 32 random bytes are mixed into the nonce of each signature.
+    #[cfg(feature = "random")]
+    Random,
+}
+
+// The `#[default]` attribute for enum variants requires Rust 1.62.
+#[allow(clippy::derivable_impls)]
+impl Default for ExtraEntropy {
+    fn default() -> ExtraEntropy {
+        ExtraEntropy::None
+    }
+}
+
+fn sign_sighash<C: Signing>(
+    context: &Secp256k1<C>,
+    sighash: &Sha256dHash,
+    secret_key: &SecretKey,
+    entropy: ExtraEntropy,
+) -> Result<InputSignature, Error> {
+    sign_raw_sighash(
+        context,
+        &sighash.into_inner(),
+        secret_key,
+        SigHashType::All,
+        entropy,
+    )
+}
+
+/// Signs the given 32-byte signature hash computed by the caller and embeds the given
+/// sighash type byte into the signature. The signature is normalized to the low `S` form.
+///
+/// This is the low-level function for the pipelines, which compute the sighashes elsewhere,
+/// so it is up to the caller to ensure that the sighash matches the sighash type.
+/// Use the [`signature_hashes`][signature-hashes] function to compute them for
+/// the `SIGHASH_ALL` signatures.
+///
+/// [signature-hashes]: fn.signature_hashes.html
+pub fn sign_raw_sighash<C: Signing>(
+    context: &Secp256k1<C>,
+    sighash: &[u8; 32],
+    secret_key: &SecretKey,
+    sighash_type: SigHashType,
+    entropy: ExtraEntropy,
+) -> Result<InputSignature, Error> {
+    let msg = Message::from_slice(&sighash[..])?;
+    let mut signature = match entropy {
+        ExtraEntropy::None => context.sign(&msg, secret_key),
+        ExtraEntropy::Fixed(data) => ecc::sign_with_extra_data(context, &msg, secret_key, &data)?,
+        #[cfg(feature = "random")]
+        ExtraEntropy::Random => {
+            let data = rand::Rng::gen::<[u8; 32]>(&mut rand::thread_rng());
+            ecc::sign_with_extra_data(context, &msg, secret_key, &data)?
+        }
+    };
+    // High-S signatures are non-standard and will not be relayed by the network.
+    signature.normalize_s();
+    Ok(InputSignature::from_signature(&signature, sighash_type))
+}
+
+/// The signer which keeps the secret key outside of the crate, for example in a hardware
+/// security module or a hardware wallet. It signs the signature hashes computed by the crate
+/// and returns only the signatures.
+pub trait ExternalSigner {
+    /// Returns the public key of the signer.
+    fn public_key(&self) -> PublicKey;
+
+    /// Signs the given 32-byte signature hash and returns the DER encoded `ECDSA` signature
+    /// without the sighash type byte.
+    fn sign_sighash(&self, sighash: &[u8; 32]) -> Result<Vec<u8>, Error>;
+}
+
+/// Computes the [`BIP-143`][bip-143] compliant signature for the given input with the given
+/// external signer. The returned signature is normalized to the low `S` form and verified,
+/// so the faulty signer cannot produce the invalid witness.
+///
+/// [bip-143]: https://github.com/bitcoin/bips/blob/master/bip-0143.mediawiki
+pub(crate) fn sign_input_external<'a, 'b, V, S>(
+    txin: TxInRef<'a>,
+    script: &Script,
+    value: V,
+    signer: &S,
+) -> Result<InputSignature, Error>
+where
+    V: Into<UnspentTxOutValue<'b>>,
+    S: ExternalSigner + ?Sized,
+{
+    let sighash = signature_hash(txin, script, value);
+    let der = signer.sign_sighash(&sighash.into_inner())?;
+    let mut signature = Signature::from_der(&der).map_err(SignatureError::from)?;
+    signature.normalize_s();
+
     let msg = Message::from_slice(&sighash[..])?;
-    let signature = context.sign(&msg, secret_key).serialize_der().to_vec();
-    Ok(InputSignature::new(signature, SigHashType::All))
+    verification_context()
+        .verify(&msg, &signature, &signer.public_key().key)
+        .map_err(SignatureError::from)?;
+    Ok(InputSignature::from_signature(&signature, SigHashType::All))
 }
 
-/// Checks correctness of the signature for the given input.
-/// [Read more...][signature-hash]
+/// Checks correctness of the signature with the sighash type byte for the given input.
+/// Signatures with the high `S` value are rejected, since they are non-standard.
+/// The sighash is computed for the `SIGHASH_ALL` type, so the signatures of the other types
+/// are rejected as well. [Read more...][signature-hash]
 ///
 /// [signature-hash]: https://github.com/bitcoin/bips/blob/master/bip-0143.mediawiki
-pub fn verify_input_signature<'a, 'b, C, V>(
+pub fn verify_input_signature<'a, 'b, 'c, C, V, S>(
     context: &Secp256k1<C>,
     txin: TxInRef<'a>,
     script: &Script,
     value: V,
     public_key: &PublicKey,
-    signature: &[u8],
-) -> Result<(), secp256k1::Error>
+    signature: S,
+) -> Result<(), Error>
 where
     C: Verification,
     V: Into<UnspentTxOutValue<'b>>,
+    S: Into<InputSignatureRef<'c>>,
 {
+    let signature = signature.into();
+    check_sighash_all(signature)?;
     // Computes sighash.
     let sighash = signature_hash(txin, script, value);
     // Verifies signature.
     let msg = Message::from_slice(&sighash[..])?;
-    let sign = Signature::from_der(signature)?;
-    context.verify(&msg, &sign, &public_key.key)
+    let sign = Signature::from_der(signature.content()).map_err(SignatureError::from)?;
+    ensure!(is_low_s(&sign), SignatureError::HighS);
+    context.verify(&msg, &sign, &public_key.key)?;
+    Ok(())
+}
+
+/// Checks that the signature has the low `S` value as required by the [`BIP-62`][bip-62].
+///
+/// [bip-62]: https://github.com/bitcoin/bips/blob/master/bip-0062.mediawiki#low-s-values-in-signatures
+pub fn is_low_s(signature: &Signature) -> bool {
+    let mut normalized = *signature;
+    normalized.normalize_s();
+    normalized == *signature
+}
+
+/// Checks that the signature without the sighash type byte is encoded according
+/// to the strict DER rules.
+fn is_strict_der(signature: &[u8]) -> bool {
+    // Format: 0x30 [total-length] 0x02 [R-length] [R] 0x02 [S-length] [S]
+    let len = signature.len();
+    if !(8..=72).contains(&len) {
+        return false;
+    }
+    // A signature is of type compound and the length covers the entire signature.
+    if signature[0] != 0x30 || usize::from(signature[1]) != len - 2 {
+        return false;
+    }
+    let len_r = usize::from(signature[3]);
+    // Make sure the length of the S element is still inside the signature.
+    if 5 + len_r >= len {
+        return false;
+    }
+    let len_s = usize::from(signature[5 + len_r]);
+    // Verify that the length of the signature matches the sum of the length of the elements.
+    if len_r + len_s + 6 != len {
+        return false;
+    }
+    let is_valid_integer = |marker: u8, integer: &[u8]| {
+        // Integers must be non-empty and non-negative, and must not be padded with zero bytes
+        // unless it is required to make them non-negative.
+        marker == 0x02
+            && !integer.is_empty()
+            && integer[0] & 0x80 == 0
+            && !(integer.len() > 1 && integer[0] == 0x00 && integer[1] & 0x80 == 0)
+    };
+    is_valid_integer(signature[2], &signature[4..4 + len_r])
+        && is_valid_integer(signature[4 + len_r], &signature[6 + len_r..])
+}
+
+/// Returns the defined sighash type of the signature with the trailing sighash type byte.
+fn try_sighash_type(signature: &[u8]) -> Result<SigHashType, SignatureError> {
+    let &byte = signature.last().ok_or(SignatureError::Empty)?;
+    ensure!(
+        is_defined_sighash_type(byte),
+        SignatureError::UnknownSighashType(byte)
+    );
+    Ok(SigHashType::from_u32_consensus(u32::from(byte)))
+}
+
+/// Checks that the signature has the `SIGHASH_ALL` type, which is the only type of
+/// the sighashes computed by the crate.
+pub(crate) fn check_sighash_all(signature: InputSignatureRef) -> Result<(), SignatureError> {
+    let sighash_type = signature.try_sighash_type()?;
+    ensure!(
+        sighash_type == SigHashType::All,
+        SignatureError::UnsupportedSighashType(sighash_type)
+    );
+    Ok(())
+}
+
+/// Checks that the given byte is one of the defined sighash types, possibly combined with
+/// the `SIGHASH_ANYONECANPAY` flag.
+fn is_defined_sighash_type(byte: u8) -> bool {
+    const SIGHASH_ANYONECANPAY: u8 = 0x80;
+
+    let base_type = byte & !SIGHASH_ANYONECANPAY;
+    (SigHashType::All as u8..=SigHashType::Single as u8).contains(&base_type)
+}
+
+/// Possible errors related to the input signatures.
+#[derive(Debug, Copy, Clone, Error, PartialEq)]
+pub enum SignatureError {
+    /// The signature is empty.
+    #[error("The signature is empty.")]
+    Empty,
+    /// The signature is not strictly DER encoded.
+    #[error("The signature is not strictly DER encoded.")]
+    NotStrictDer,
+    /// The signature data does not fit into the input signature.
+    #[error("The signature data is too long: {0} bytes.")]
+    TooLong(usize),
+    /// The signature ends with an undefined sighash type byte.
+    #[error("The signature has an unknown sighash type {0:#04x}.")]
+    UnknownSighashType(u8),
+    /// The signature has a defined sighash type other than `SIGHASH_ALL`.
+    #[error("The signature has the {0} type, while only SIGHASH_ALL is supported.")]
+    UnsupportedSighashType(SigHashType),
+    /// The signature has the high `S` value.
+    #[error("The signature has the high S value.")]
+    HighS,
+    /// An error in the secp256k1 engine.
+    #[error(transparent)]
+    Secp256k1(#[from] secp256k1::Error),
+}
+
+/// Possible errors during the witness parsing.
+#[derive(Debug, Copy, Clone, Error, PartialEq)]
+pub enum WitnessError {
+    /// The witness has an unexpected number of items.
+    #[error("The witness has an unexpected number of items: {0}.")]
+    ItemsCount(usize),
+    /// The first item of the multisig witness is not empty.
+    #[error("The first item of the multisig witness is not empty.")]
+    NoDummyItem,
+    /// The witness item with the given index is not a correct signature.
+    #[error("The witness item {0} is not a correct signature.")]
+    InvalidSignature(usize),
+    /// The witness contains an incorrect public key.
+    #[error("The witness contains an incorrect public key.")]
+    InvalidPublicKey,
 }
 
 #[test]
@@ -204,6 +886,50 @@ fn test_input_signature_ref_incorrect() {
     InputSignature::from_bytes(bytes.to_vec()).expect_err("Signature should be incorrect");
 }
 
+#[test]
+fn test_input_signature_ref_not_strict() {
+    let bytes = ::hex::decode(
+        "304402201538279618a4626653775069b43d4315c7d2ff30008d339d0ed31ff41e628e71022028f3182fc39df\
+         28201ca4d7d489aece7bc5bc6bfe05b09b6a9d3b70bf5f3743101",
+    )
+    .unwrap();
+    assert_eq!(
+        InputSignatureRef::from_bytes(&[]),
+        Err(Error::Signature(SignatureError::Empty))
+    );
+    // Undefined sighash types.
+    for &sighash_type in &[0x00, 0x04, 0x41, 0x84] {
+        let mut bytes = bytes.clone();
+        *bytes.last_mut().unwrap() = sighash_type;
+        assert_eq!(
+            InputSignatureRef::from_bytes(&bytes),
+            Err(Error::Signature(SignatureError::UnknownSighashType(
+                sighash_type
+            )))
+        );
+    }
+    // Defined sighash types.
+    for &sighash_type in &[0x01, 0x02, 0x03, 0x81, 0x82, 0x83] {
+        let mut bytes = bytes.clone();
+        *bytes.last_mut().unwrap() = sighash_type;
+        InputSignatureRef::from_bytes(&bytes).expect("Sighash type should be correct");
+    }
+    // The excessive padding of the R value.
+    let mut padded = vec![0x30, 0x45, 0x02, 0x21, 0x00];
+    padded.extend_from_slice(&bytes[4..]);
+    assert_eq!(
+        InputSignatureRef::from_bytes(&padded),
+        Err(Error::Signature(SignatureError::NotStrictDer))
+    );
+    // The wrong total length.
+    let mut wrong_length = bytes.clone();
+    wrong_length[1] += 1;
+    assert_eq!(
+        InputSignatureRef::from_bytes(&wrong_length),
+        Err(Error::Signature(SignatureError::NotStrictDer))
+    );
+}
+
 #[test]
 fn test_input_signature_ref_correct() {
     let bytes = ::hex::decode(
@@ -214,3 +940,359 @@ fn test_input_signature_ref_correct() {
     InputSignatureRef::from_bytes(&bytes).expect("Signature should be correct");
     InputSignature::from_bytes(bytes).expect("Signature should be correct");
 }
+
+#[test]
+fn test_signature_hashes() {
+    use bitcoin::blockdata::transaction::{OutPoint, Transaction, TxIn, TxOut};
+
+    let transaction = Transaction {
+        version: 2,
+        lock_time: 0,
+        input: (0..3)
+            .map(|vout| TxIn {
+                previous_output: OutPoint::new(Default::default(), vout),
+                script_sig: Script::default(),
+                sequence: 0xFFFF_FFFF,
+                witness: Vec::default(),
+            })
+            .collect(),
+        output: vec![TxOut::default()],
+    };
+    let prev_tx = Transaction {
+        output: vec![
+            TxOut {
+                value: 3_000,
+                script_pubkey: Script::default(),
+            };
+            3
+        ],
+        ..transaction.clone()
+    };
+    let scripts = (0..3_u8)
+        .map(|index| Script::from(vec![index; 25]))
+        .collect::<Vec<_>>();
+    let inputs = vec![
+        (scripts[0].clone(), UnspentTxOutValue::from(1_000)),
+        (scripts[1].clone(), UnspentTxOutValue::from(2_000)),
+        (scripts[2].clone(), UnspentTxOutValue::PrevTx(&prev_tx)),
+    ];
+
+    let sighashes = signature_hashes(&transaction, &inputs).unwrap();
+    assert_eq!(sighashes.len(), 3);
+    for (index, (sighash, (script, value))) in sighashes.iter().zip(&inputs).enumerate() {
+        let txin = TxInRef::new(&transaction, index);
+        assert_eq!(*sighash, signature_hash(txin, script, *value));
+    }
+    assert_eq!(
+        signature_hashes(&transaction, &inputs[..2]),
+        Err(Error::OutputsCount {
+            inputs: 3,
+            outputs: 2
+        })
+    );
+}
+
+#[test]
+fn test_sign_raw_sighash() {
+    use bitcoin::blockdata::transaction::{OutPoint, Transaction, TxIn, TxOut};
+
+    let (public_key, private_key) =
+        crate::test_data::deterministic_keypair(bitcoin::network::constants::Network::Testnet);
+    let transaction = Transaction {
+        version: 2,
+        lock_time: 0,
+        input: vec![TxIn {
+            previous_output: OutPoint::default(),
+            script_sig: Script::default(),
+            sequence: 0xFFFF_FFFF,
+            witness: Vec::default(),
+        }],
+        output: vec![TxOut::default()],
+    };
+    let txin = TxInRef::new(&transaction, 0);
+    let script = Script::from(vec![0; 25]);
+    let context = Secp256k1::new();
+
+    // The raw signature of the computed sighash is the same as the input signature.
+    let sighash = signature_hash(txin, &script, 1_000).into_inner();
+    let signature = sign_raw_sighash(
+        &context,
+        &sighash,
+        &private_key.key,
+        SigHashType::All,
+        ExtraEntropy::None,
+    )
+    .unwrap();
+    assert_eq!(
+        signature,
+        sign_input(
+            &context,
+            txin,
+            &script,
+            1_000,
+            &private_key.key,
+            ExtraEntropy::None
+        )
+        .unwrap()
+    );
+
+    // The given sighash type is embedded as is.
+    let signature = sign_raw_sighash(
+        &context,
+        &sighash,
+        &private_key.key,
+        SigHashType::SinglePlusAnyoneCanPay,
+        ExtraEntropy::Fixed([1; 32]),
+    )
+    .unwrap();
+    assert_eq!(
+        signature.sighash_type(),
+        SigHashType::SinglePlusAnyoneCanPay
+    );
+    let msg = Message::from_slice(&sighash).unwrap();
+    context
+        .verify(&msg, &signature.to_signature().unwrap(), &public_key.key)
+        .unwrap();
+}
+
+#[test]
+fn test_verify_input_signature_high_s() {
+    use bitcoin::blockdata::transaction::{OutPoint, Transaction, TxIn, TxOut};
+
+    // The order of the secp256k1 curve.
+    const CURVE_ORDER: [u8; 32] = [
+        0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+        0xFE, 0xBA, 0xAE, 0xDC, 0xE6, 0xAF, 0x48, 0xA0, 0x3B, 0xBF, 0xD2, 0x5E, 0x8C, 0xD0, 0x36,
+        0x41, 0x41,
+    ];
+
+    let (public_key, secret_key) =
+        crate::test_data::deterministic_keypair(bitcoin::network::constants::Network::Testnet);
+    let transaction = Transaction {
+        version: 2,
+        lock_time: 0,
+        input: vec![TxIn {
+            previous_output: OutPoint::default(),
+            script_sig: Script::default(),
+            sequence: 0xFFFF_FFFF,
+            witness: Vec::default(),
+        }],
+        output: vec![TxOut::default()],
+    };
+    let txin = TxInRef::new(&transaction, 0);
+    let script = Script::default();
+
+    let context = Secp256k1::new();
+    let signature = sign_input(
+        &context,
+        txin,
+        &script,
+        1_000,
+        &secret_key.key,
+        ExtraEntropy::None,
+    )
+    .unwrap();
+    let low_s = Signature::from_der(signature.content()).unwrap();
+    assert!(is_low_s(&low_s));
+    verify_input_signature(&context, txin, &script, 1_000, &public_key, &signature).unwrap();
+
+    // Computes `S' = N - S`, which also satisfies the signature equation.
+    let mut compact = low_s.serialize_compact();
+    let mut borrow = 0_i16;
+    for i in (0..32).rev() {
+        let diff = i16::from(CURVE_ORDER[i]) - i16::from(compact[32 + i]) - borrow;
+        borrow = if diff < 0 { 1 } else { 0 };
+        compact[32 + i] = (diff + (borrow << 8)) as u8;
+    }
+    let high_s = Signature::from_compact(&compact).unwrap();
+    assert!(!is_low_s(&high_s));
+    assert_eq!(
+        verify_input_signature(
+            &context,
+            txin,
+            &script,
+            1_000,
+            &public_key,
+            &InputSignature::from_signature(&high_s, SigHashType::All)
+        ),
+        Err(Error::Signature(SignatureError::HighS))
+    );
+}
+
+#[test]
+fn test_input_signature_compact_and_recoverable() {
+    let (public_key, secret_key) =
+        crate::test_data::keypair_from_wif("cPHmynxvqfr7sXsJcohiGzoPGBShggxL6VWUdW14skohFZ1LQoeV");
+    let context = Secp256k1::new();
+    let message = Message::from_slice(&[5; 32]).unwrap();
+    let signature = context.sign(&message, &secret_key.key);
+    let input_signature = InputSignature::from_signature(&signature, SigHashType::Single);
+    assert_eq!(input_signature.to_signature().unwrap(), signature);
+    assert_eq!(input_signature.sighash_type(), SigHashType::Single);
+
+    let compact = input_signature.to_compact().unwrap();
+    assert_eq!(compact, signature.serialize_compact());
+    assert_eq!(
+        InputSignature::from_compact(&compact, SigHashType::Single).unwrap(),
+        input_signature
+    );
+    assert_eq!(
+        InputSignature::from_compact(&compact[1..], SigHashType::All),
+        Err(Error::Signature(SignatureError::Secp256k1(
+            secp256k1::Error::InvalidSignature
+        )))
+    );
+
+    let recoverable = input_signature
+        .to_recoverable(&message, &public_key)
+        .unwrap();
+    assert_eq!(
+        context.recover(&message, &recoverable).unwrap(),
+        public_key.key
+    );
+    assert_eq!(
+        InputSignature::from_recoverable(&recoverable, SigHashType::Single),
+        input_signature
+    );
+    let expected = context.sign_recoverable(&message, &secret_key.key);
+    assert_eq!(recoverable, expected);
+
+    let other_message = Message::from_slice(&[6; 32]).unwrap();
+    assert!(input_signature
+        .to_recoverable(&other_message, &public_key)
+        .is_err());
+}
+
+#[test]
+fn test_input_signature_checked_new() {
+    let bytes = ::hex::decode(
+        "304402201538279618a4626653775069b43d4315c7d2ff30008d339d0ed31ff41e628e71022028f3182fc39df\
+         28201ca4d7d489aece7bc5bc6bfe05b09b6a9d3b70bf5f3743101",
+    )
+    .unwrap();
+    let content = bytes[..bytes.len() - 1].to_vec();
+    let signature = InputSignature::checked_new(&content, SigHashType::All).unwrap();
+    assert_eq!(signature, InputSignature::try_from(bytes.clone()).unwrap());
+    assert_eq!(
+        InputSignatureRef::try_from(&bytes[..]).unwrap(),
+        InputSignatureRef::from(&signature)
+    );
+
+    assert_eq!(
+        InputSignature::checked_new(&content[1..], SigHashType::All),
+        Err(Error::Signature(SignatureError::NotStrictDer))
+    );
+    assert_eq!(
+        InputSignature::try_from(content),
+        Err(Error::Signature(SignatureError::NotStrictDer))
+    );
+}
+
+#[test]
+fn test_input_signature_inline_buffer() {
+    let bytes = ::hex::decode(
+        "304402201538279618a4626653775069b43d4315c7d2ff30008d339d0ed31ff41e628e71022028f3182fc39df\
+         28201ca4d7d489aece7bc5bc6bfe05b09b6a9d3b70bf5f3743101",
+    )
+    .unwrap();
+    let signature = InputSignature::from_bytes(bytes.clone()).unwrap();
+    assert_eq!(signature.as_ref(), &bytes[..]);
+    assert_eq!(Vec::from(signature.clone()), bytes);
+    assert_eq!(
+        format!("{:?}", signature),
+        format!("InputSignature({:?})", bytes)
+    );
+    // The padding of the buffer does not affect the equality.
+    let mut other = signature.clone();
+    other.bytes[InputSignature::MAX_LEN - 1] = 0xff;
+    assert_eq!(signature, other);
+
+    assert_eq!(
+        InputSignature::checked_new(&[0; InputSignature::MAX_LEN], SigHashType::All),
+        Err(Error::Signature(SignatureError::NotStrictDer))
+    );
+    // The unchecked constructor rejects the data, which does not fit into the buffer.
+    let content = &bytes[..bytes.len() - 1];
+    assert_eq!(
+        InputSignature::new(content, SigHashType::All).unwrap(),
+        signature
+    );
+    assert_eq!(
+        InputSignature::new(&[0; InputSignature::MAX_LEN], SigHashType::All),
+        Err(Error::Signature(SignatureError::TooLong(
+            InputSignature::MAX_LEN
+        )))
+    );
+}
+
+#[test]
+fn test_input_signature_try_sighash_type() {
+    use crate::p2wpk;
+    use bitcoin::blockdata::transaction::{OutPoint, Transaction, TxIn, TxOut};
+
+    let (public_key, private_key) =
+        crate::test_data::keypair_from_wif("cPHmynxvqfr7sXsJcohiGzoPGBShggxL6VWUdW14skohFZ1LQoeV");
+    let transaction = Transaction {
+        version: 2,
+        lock_time: 0,
+        input: vec![TxIn {
+            previous_output: OutPoint::default(),
+            script_sig: Script::default(),
+            sequence: 0xFFFF_FFFF,
+            witness: Vec::default(),
+        }],
+        output: vec![TxOut::default()],
+    };
+    let txin = TxInRef::new(&transaction, 0);
+    let mut signer = p2wpk::InputSigner::new(public_key, bitcoin::Network::Testnet);
+    let signature = signer.sign_input(txin, 1_000, &private_key.key).unwrap();
+    assert_eq!(signature.try_sighash_type(), Ok(SigHashType::All));
+    assert_eq!(
+        InputSignatureRef::from(&signature).try_sighash_type(),
+        Ok(SigHashType::All)
+    );
+    signer
+        .verify_input(txin, 1_000, &public_key, &signature)
+        .unwrap();
+
+    // The unknown sighash type is treated as `SIGHASH_ALL` by the consensus rules.
+    let mut bytes = Vec::from(signature);
+    *bytes.last_mut().unwrap() = 0x04;
+    let unknown = InputSignature::from_slice(&bytes);
+    assert_eq!(unknown.sighash_type(), SigHashType::All);
+    assert_eq!(
+        unknown.try_sighash_type(),
+        Err(SignatureError::UnknownSighashType(0x04))
+    );
+    assert_eq!(
+        signer.verify_input(txin, 1_000, &public_key, &unknown),
+        Err(Error::Signature(SignatureError::UnknownSighashType(0x04)))
+    );
+    assert_eq!(
+        verify_input_signature(
+            verification_context(),
+            txin,
+            &Address::p2pkh(&public_key, bitcoin::Network::Testnet).script_pubkey(),
+            1_000,
+            &public_key,
+            &unknown,
+        ),
+        Err(Error::Signature(SignatureError::UnknownSighashType(0x04)))
+    );
+
+    // The `SIGHASH_ALL` signature tagged with the other defined type does not match
+    // the sighash of that type.
+    for &sighash_type in &[
+        SigHashType::None,
+        SigHashType::Single,
+        SigHashType::AllPlusAnyoneCanPay,
+    ] {
+        let retagged = InputSignature::new(unknown.content(), sighash_type).unwrap();
+        assert_eq!(
+            signer.verify_input(txin, 1_000, &public_key, &retagged),
+            Err(Error::Signature(SignatureError::UnsupportedSighashType(
+                sighash_type
+            )))
+        );
+    }
+}
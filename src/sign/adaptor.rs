@@ -0,0 +1,369 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! ECDSA adaptor signatures.
+
+use bitcoin_hashes::{sha256, Hash, HashEngine};
+use secp256k1::{Message, PublicKey, SecretKey, Signature};
+use thiserror::Error;
+
+use super::shared_context;
+use crate::{
+    ecc::{
+        invert_scalar, mul_point, scalar_from_bytes, scalar_from_hash, sum_points, tagged_engine,
+    },
+    Error,
+};
+
+/// Possible errors of the adaptor signature operations.
+#[derive(Debug, Copy, Clone, Error, PartialEq)]
+pub enum AdaptorError {
+    /// The adaptor signature encoding is incorrect.
+    #[error("Incorrect encoding of the adaptor signature.")]
+    Encoding,
+    /// The proof that the adaptor signature is encrypted with the given key is incorrect.
+    #[error("The adaptor signature is not encrypted with the given key.")]
+    InvalidProof,
+    /// The adaptor signature is not made by the given key for the given message.
+    #[error("The adaptor signature does not match the public key or message.")]
+    InvalidSignature,
+    /// The signature is not a decryption of the adaptor signature.
+    #[error("The signature is not a decryption of the adaptor signature.")]
+    Mismatch,
+}
+
+/// An ECDSA signature encrypted with the public encryption key.
+///
+/// Anyone who knows the corresponding decryption key can turn the adaptor signature into
+/// the valid ECDSA signature, and anyone who sees both the adaptor signature and the
+/// decrypted signature learns the decryption key. This is the building block of atomic swaps:
+/// publishing a signature for one transaction reveals the secret required for another one.
+///
+/// The adaptor signature contains the discrete logarithm equality proof, so the receiver
+/// can check that it is encrypted with the expected key before revealing anything.
+///
+/// # Examples
+///
+/// ```
+/// use btc_transaction_utils::AdaptorSignature;
+/// use secp256k1::{Message, PublicKey, Secp256k1, SecretKey};
+///
+/// let context = Secp256k1::new();
+/// let secret_key = SecretKey::from_slice(&[1; 32]).unwrap();
+/// let public_key = PublicKey::from_secret_key(&context, &secret_key);
+/// let decryption_key = SecretKey::from_slice(&[2; 32]).unwrap();
+/// let encryption_key = PublicKey::from_secret_key(&context, &decryption_key);
+/// let message = Message::from_slice(&[3; 32]).unwrap();
+///
+/// let adaptor = AdaptorSignature::encrypt(&message, &secret_key, &encryption_key).unwrap();
+/// adaptor.verify(&message, &public_key, &encryption_key).unwrap();
+///
+/// let signature = adaptor.decrypt(&decryption_key).unwrap();
+/// context.verify(&message, &signature, &public_key).unwrap();
+/// assert_eq!(adaptor.recover(&signature, &encryption_key).unwrap(), decryption_key);
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AdaptorSignature {
+    /// The nonce point multiplied by the encryption key, its `x` coordinate is the `r` value.
+    nonce: PublicKey,
+    /// The nonce point.
+    nonce_base: PublicKey,
+    s: SecretKey,
+    proof_challenge: SecretKey,
+    proof_response: SecretKey,
+}
+
+impl AdaptorSignature {
+    /// The length of the serialized adaptor signature.
+    pub const LEN: usize = 162;
+
+    /// Creates the adaptor signature of the message encrypted with the given key.
+    ///
+    /// The nonce is derived deterministically from the secret key, message and
+    /// encryption key.
+    pub fn encrypt(
+        message: &Message,
+        secret_key: &SecretKey,
+        encryption_key: &PublicKey,
+    ) -> Result<AdaptorSignature, Error> {
+        let context = shared_context();
+
+        let mut engine = tagged_engine("ECDSAadaptor/nonce");
+        engine.input(&secret_key[..]);
+        engine.input(&message[..]);
+        engine.input(&encryption_key.serialize());
+        let k = scalar_from_hash(sha256::Hash::from_engine(engine))?;
+
+        let nonce = mul_point(*encryption_key, &k)?;
+        let nonce_base = PublicKey::from_secret_key(&context, &k);
+        let r = x_coordinate(&nonce)?;
+
+        // s' = k^-1 * (m + r * x)
+        let m = scalar_from_bytes(message_bytes(message))?;
+        let mut s = *secret_key;
+        s.mul_assign(&r[..])?;
+        s.add_assign(&m[..])?;
+        s.mul_assign(&invert_scalar(&k)[..])?;
+
+        let (proof_challenge, proof_response) =
+            prove_dleq(&k, encryption_key, &nonce_base, &nonce)?;
+        Ok(AdaptorSignature {
+            nonce,
+            nonce_base,
+            s,
+            proof_challenge,
+            proof_response,
+        })
+    }
+
+    /// Checks that the adaptor signature is made by the given public key for the given
+    /// message and is encrypted with the given encryption key.
+    pub fn verify(
+        &self,
+        message: &Message,
+        public_key: &PublicKey,
+        encryption_key: &PublicKey,
+    ) -> Result<(), Error> {
+        self.verify_proof(encryption_key)?;
+
+        // s' * R' = m * G + r * X
+        let r = x_coordinate(&self.nonce)?;
+        let m = scalar_from_bytes(message_bytes(message))?;
+        let expected = sum_points(&[
+            PublicKey::from_secret_key(&shared_context(), &m),
+            mul_point(*public_key, &r)?,
+        ])
+        .map_err(|_| AdaptorError::InvalidSignature)?;
+        let actual = mul_point(self.nonce_base, &self.s)?;
+        ensure!(expected == actual, AdaptorError::InvalidSignature);
+        Ok(())
+    }
+
+    /// Decrypts the adaptor signature into the ECDSA signature with the low `S` value.
+    pub fn decrypt(&self, decryption_key: &SecretKey) -> Result<Signature, Error> {
+        let mut s = self.s;
+        s.mul_assign(&invert_scalar(decryption_key)[..])?;
+
+        let mut compact = [0; 64];
+        compact[..32].copy_from_slice(&x_coordinate(&self.nonce)?[..]);
+        compact[32..].copy_from_slice(&s[..]);
+        let mut signature = Signature::from_compact(&compact)?;
+        signature.normalize_s();
+        Ok(signature)
+    }
+
+    /// Recovers the decryption key from the signature decrypted from this adaptor signature.
+    pub fn recover(
+        &self,
+        signature: &Signature,
+        encryption_key: &PublicKey,
+    ) -> Result<SecretKey, Error> {
+        let compact = signature.serialize_compact();
+        ensure!(
+            compact[..32] == x_coordinate(&self.nonce)?[..],
+            AdaptorError::Mismatch
+        );
+        let s = SecretKey::from_slice(&compact[32..])?;
+
+        // y = s' / s, up to the sign of `s`.
+        let mut decryption_key = self.s;
+        decryption_key.mul_assign(&invert_scalar(&s)[..])?;
+        let context = shared_context();
+        if PublicKey::from_secret_key(&context, &decryption_key) != *encryption_key {
+            decryption_key.negate_assign();
+        }
+        ensure!(
+            PublicKey::from_secret_key(&context, &decryption_key) == *encryption_key,
+            AdaptorError::Mismatch
+        );
+        Ok(decryption_key)
+    }
+
+    /// Serializes the adaptor signature into [`LEN`](#associatedconstant.LEN) bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(Self::LEN);
+        bytes.extend_from_slice(&self.nonce.serialize());
+        bytes.extend_from_slice(&self.nonce_base.serialize());
+        bytes.extend_from_slice(&self.s[..]);
+        bytes.extend_from_slice(&self.proof_challenge[..]);
+        bytes.extend_from_slice(&self.proof_response[..]);
+        bytes
+    }
+
+    /// Deserializes the adaptor signature.
+    pub fn from_bytes(bytes: &[u8]) -> Result<AdaptorSignature, Error> {
+        ensure!(bytes.len() == Self::LEN, AdaptorError::Encoding);
+        let point = |b: &[u8]| PublicKey::from_slice(b).map_err(|_| AdaptorError::Encoding);
+        let scalar = |b: &[u8]| SecretKey::from_slice(b).map_err(|_| AdaptorError::Encoding);
+        Ok(AdaptorSignature {
+            nonce: point(&bytes[..33])?,
+            nonce_base: point(&bytes[33..66])?,
+            s: scalar(&bytes[66..98])?,
+            proof_challenge: scalar(&bytes[98..130])?,
+            proof_response: scalar(&bytes[130..])?,
+        })
+    }
+
+    /// Checks that `nonce_base = k * G` and `nonce = k * Y` for the same `k`.
+    fn verify_proof(&self, encryption_key: &PublicKey) -> Result<(), Error> {
+        let mut minus_challenge = self.proof_challenge;
+        minus_challenge.negate_assign();
+
+        // A1 = z * G - e * R', A2 = z * Y - e * R
+        let context = shared_context();
+        let commitments = [
+            sum_points(&[
+                PublicKey::from_secret_key(&context, &self.proof_response),
+                mul_point(self.nonce_base, &minus_challenge)?,
+            ]),
+            sum_points(&[
+                mul_point(*encryption_key, &self.proof_response)?,
+                mul_point(self.nonce, &minus_challenge)?,
+            ]),
+        ];
+        let (a1, a2) = match commitments {
+            [Ok(a1), Ok(a2)] => (a1, a2),
+            _ => return Err(AdaptorError::InvalidProof.into()),
+        };
+
+        let challenge = dleq_challenge(encryption_key, &self.nonce_base, &self.nonce, &a1, &a2)?;
+        ensure!(
+            challenge == self.proof_challenge,
+            AdaptorError::InvalidProof
+        );
+        Ok(())
+    }
+}
+
+/// Creates the Chaum-Pedersen proof that `log_G(nonce_base) == log_Y(nonce) == k`.
+fn prove_dleq(
+    k: &SecretKey,
+    encryption_key: &PublicKey,
+    nonce_base: &PublicKey,
+    nonce: &PublicKey,
+) -> Result<(SecretKey, SecretKey), Error> {
+    let mut engine = tagged_engine("DLEQ/nonce");
+    engine.input(&k[..]);
+    engine.input(&encryption_key.serialize());
+    engine.input(&nonce.serialize());
+    let t = scalar_from_hash(sha256::Hash::from_engine(engine))?;
+
+    let a1 = PublicKey::from_secret_key(&shared_context(), &t);
+    let a2 = mul_point(*encryption_key, &t)?;
+    let challenge = dleq_challenge(encryption_key, nonce_base, nonce, &a1, &a2)?;
+
+    // z = t + e * k
+    let mut response = *k;
+    response.mul_assign(&challenge[..])?;
+    response.add_assign(&t[..])?;
+    Ok((challenge, response))
+}
+
+fn dleq_challenge(
+    encryption_key: &PublicKey,
+    nonce_base: &PublicKey,
+    nonce: &PublicKey,
+    a1: &PublicKey,
+    a2: &PublicKey,
+) -> Result<SecretKey, Error> {
+    let mut engine = tagged_engine("DLEQ");
+    for point in &[encryption_key, nonce_base, nonce, a1, a2] {
+        engine.input(&point.serialize());
+    }
+    Ok(scalar_from_hash(sha256::Hash::from_engine(engine))?)
+}
+
+/// Returns the `x` coordinate of the point modulo the curve order.
+fn x_coordinate(point: &PublicKey) -> Result<SecretKey, Error> {
+    let mut bytes = [0; 32];
+    bytes.copy_from_slice(&point.serialize()[1..]);
+    Ok(scalar_from_bytes(bytes)?)
+}
+
+fn message_bytes(message: &Message) -> [u8; 32] {
+    let mut bytes = [0; 32];
+    bytes.copy_from_slice(&message[..]);
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use secp256k1::{Message, PublicKey, SecretKey};
+
+    use super::{AdaptorError, AdaptorSignature};
+    use crate::{
+        sign::{self, is_low_s},
+        Error,
+    };
+
+    fn keypair(byte: u8) -> (PublicKey, SecretKey) {
+        let sk = SecretKey::from_slice(&[byte; 32]).unwrap();
+        (PublicKey::from_secret_key(&sign::shared_context(), &sk), sk)
+    }
+
+    #[test]
+    fn test_adaptor_signature_roundtrip() {
+        let (public_key, secret_key) = keypair(7);
+        let (encryption_key, decryption_key) = keypair(9);
+        let message = Message::from_slice(&[0x42; 32]).unwrap();
+
+        let adaptor = AdaptorSignature::encrypt(&message, &secret_key, &encryption_key).unwrap();
+        adaptor
+            .verify(&message, &public_key, &encryption_key)
+            .unwrap();
+        assert_eq!(
+            AdaptorSignature::from_bytes(&adaptor.to_bytes()).unwrap(),
+            adaptor
+        );
+
+        let signature = adaptor.decrypt(&decryption_key).unwrap();
+        assert!(is_low_s(&signature));
+        sign::verification_context()
+            .verify(&message, &signature, &public_key)
+            .unwrap();
+        assert_eq!(
+            adaptor.recover(&signature, &encryption_key).unwrap(),
+            decryption_key
+        );
+    }
+
+    #[test]
+    fn test_adaptor_signature_wrong_keys() {
+        let (public_key, secret_key) = keypair(7);
+        let (encryption_key, _) = keypair(9);
+        let (other_key, other_secret) = keypair(11);
+        let message = Message::from_slice(&[0x42; 32]).unwrap();
+
+        let adaptor = AdaptorSignature::encrypt(&message, &secret_key, &encryption_key).unwrap();
+        assert_eq!(
+            adaptor.verify(&message, &public_key, &other_key),
+            Err(Error::from(AdaptorError::InvalidProof))
+        );
+        assert_eq!(
+            adaptor.verify(&message, &other_key, &encryption_key),
+            Err(Error::from(AdaptorError::InvalidSignature))
+        );
+        let other_message = Message::from_slice(&[0x43; 32]).unwrap();
+        assert_eq!(
+            adaptor.verify(&other_message, &public_key, &encryption_key),
+            Err(Error::from(AdaptorError::InvalidSignature))
+        );
+
+        // Decrypting with a wrong key gives an invalid signature.
+        let signature = adaptor.decrypt(&other_secret).unwrap();
+        assert!(sign::verification_context()
+            .verify(&message, &signature, &public_key)
+            .is_err());
+    }
+}
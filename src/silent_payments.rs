@@ -0,0 +1,948 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The sender side of the [silent payments][bip-352].
+//!
+//! The silent payment address contains the scan and spend public keys of the recipient,
+//! and the sender derives a unique taproot output for each payment from the secret keys
+//! of the transaction inputs, so the payments can not be linked to the address.
+//!
+//! Only the inputs spending the `P2WPKH`, `P2SH-P2WPKH`, `P2PKH` and taproot key path
+//! outputs are eligible for the derivation. The multisig `P2WSH` inputs of the
+//! [`TransactionBuilder`] are not eligible, so the payments are added by the
+//! [`append_outputs`] function to the transaction spending at least one eligible input,
+//! which inputs must not change afterwards.
+//!
+//! [bip-352]: https://github.com/bitcoin/bips/blob/master/bip-0352.mediawiki
+//! [`TransactionBuilder`]: ../builder/struct.TransactionBuilder.html
+//! [`append_outputs`]: fn.append_outputs.html
+
+use bitcoin::{
+    blockdata::{
+        script::{Builder, Script},
+        transaction::{OutPoint, Transaction, TxOut},
+    },
+    consensus::encode::serialize,
+    network::constants::Network,
+};
+use bitcoin_hashes::{sha256, Hash, HashEngine};
+use secp256k1::{PublicKey, SecretKey};
+use thiserror::Error;
+
+use std::fmt;
+
+use crate::{
+    ecc::{has_odd_y, mul_point, scalar_from_hash, tagged_engine},
+    sign, Amount, Error,
+};
+
+/// The version of the silent payment addresses created by this module.
+const ADDRESS_VERSION: u8 = 0;
+/// The address version reserved for the changes incompatible with the current senders.
+const INCOMPATIBLE_VERSION: u8 = 31;
+/// The length of the scan and spend public keys in the address.
+const KEYS_LEN: usize = 66;
+/// The maximum length of the silent payment address.
+const MAX_ADDRESS_LEN: usize = 1023;
+const BECH32M_CONST: u32 = 0x2bc8_30a3;
+const CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+/// Possible errors of the silent payment address parsing.
+#[derive(Debug, Copy, Clone, Error, PartialEq)]
+pub enum SilentPaymentError {
+    /// The address is not a correct bech32m string.
+    #[error("The silent payment address is not a correct bech32m string.")]
+    Encoding,
+    /// The human readable part of the address is unknown.
+    #[error("Unknown silent payment address prefix.")]
+    Prefix,
+    /// The address belongs to another network.
+    #[error("The silent payment address does not belong to the {0} network.")]
+    Network(Network),
+    /// The address version is not supported.
+    #[error("Unsupported silent payment address version {0}.")]
+    Version(u8),
+    /// The address contains incorrect public keys.
+    #[error("The silent payment address contains incorrect public keys.")]
+    PublicKey,
+    /// There are no eligible inputs or the sum of their keys is zero.
+    #[error("There are no eligible inputs to derive the silent payment outputs.")]
+    NoInputs,
+}
+
+/// The silent payment address.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SilentPaymentAddress {
+    /// The public key, which is used to scan for the payments.
+    pub scan_key: PublicKey,
+    /// The public key, which is used to spend the payments.
+    pub spend_key: PublicKey,
+    /// The network of the address.
+    pub network: Network,
+}
+
+impl SilentPaymentAddress {
+    /// Parses the silent payment address and checks that it belongs to the given network.
+    ///
+    /// The signet addresses share the prefix with the testnet ones, so the network
+    /// of the parsed address is the given one. The addresses of the future versions are
+    /// accepted, and only the keys are read from them, as the specification requires.
+    pub fn parse(s: &str, network: Network) -> Result<SilentPaymentAddress, Error> {
+        ensure!(s.len() <= MAX_ADDRESS_LEN, SilentPaymentError::Encoding);
+        let is_mixed_case =
+            s.chars().any(|c| c.is_ascii_lowercase()) && s.chars().any(|c| c.is_ascii_uppercase());
+        ensure!(!is_mixed_case, SilentPaymentError::Encoding);
+        let s = s.to_ascii_lowercase();
+
+        let separator = s.rfind('1').ok_or(SilentPaymentError::Encoding)?;
+        let (hrp, data) = (&s[..separator], &s[separator + 1..]);
+        ensure!(
+            matches!(hrp, "sp" | "tsp" | "sprt"),
+            SilentPaymentError::Prefix
+        );
+        ensure!(
+            hrp == Self::hrp(network),
+            SilentPaymentError::Network(network)
+        );
+        let data = data
+            .bytes()
+            .map(|c| CHARSET.iter().position(|&x| x == c).map(|v| v as u8))
+            .collect::<Option<Vec<_>>>()
+            .ok_or(SilentPaymentError::Encoding)?;
+        ensure!(data.len() > 6, SilentPaymentError::Encoding);
+        ensure!(
+            polymod(&[hrp_expand(hrp), data.clone()].concat()) == BECH32M_CONST,
+            SilentPaymentError::Encoding
+        );
+
+        let version = data[0];
+        ensure!(
+            version != INCOMPATIBLE_VERSION,
+            SilentPaymentError::Version(version)
+        );
+        let payload = convert_bits(&data[1..data.len() - 6], 5, 8, false)
+            .ok_or(SilentPaymentError::Encoding)?;
+        // The future versions may append data to the keys, which is ignored.
+        let is_valid_len = if version == ADDRESS_VERSION {
+            payload.len() == KEYS_LEN
+        } else {
+            payload.len() >= KEYS_LEN
+        };
+        ensure!(is_valid_len, SilentPaymentError::PublicKey);
+        let key =
+            |bytes: &[u8]| PublicKey::from_slice(bytes).map_err(|_| SilentPaymentError::PublicKey);
+        Ok(SilentPaymentAddress {
+            scan_key: key(&payload[..33])?,
+            spend_key: key(&payload[33..KEYS_LEN])?,
+            network,
+        })
+    }
+
+    fn hrp(network: Network) -> &'static str {
+        match network {
+            Network::Bitcoin => "sp",
+            Network::Testnet | Network::Signet => "tsp",
+            Network::Regtest => "sprt",
+        }
+    }
+}
+
+impl fmt::Display for SilentPaymentAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut payload = self.scan_key.serialize().to_vec();
+        payload.extend_from_slice(&self.spend_key.serialize());
+        f.write_str(&encode(Self::hrp(self.network), ADDRESS_VERSION, &payload))
+    }
+}
+
+/// Encodes the address of the given version with the given payload into bech32m.
+fn encode(hrp: &str, version: u8, payload: &[u8]) -> String {
+    let mut data = vec![version];
+    data.extend(convert_bits(payload, 8, 5, true).unwrap());
+    let checksum = polymod(&[hrp_expand(hrp), data.clone(), vec![0; 6]].concat()) ^ BECH32M_CONST;
+    data.extend((0..6).map(|i| ((checksum >> (5 * (5 - i))) & 31) as u8));
+
+    let mut address = format!("{}1", hrp);
+    address.extend(
+        data.into_iter()
+            .map(|value| CHARSET[usize::from(value)] as char),
+    );
+    address
+}
+
+/// The secret key of the transaction input, which is eligible for the silent payments.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum InputKey {
+    /// The key of the `P2WPKH`, `P2SH-P2WPKH` or `P2PKH` input.
+    Ecdsa(SecretKey),
+    /// The key of the taproot input spent via the key path.
+    Taproot(SecretKey),
+}
+
+/// Derives the taproot script pubkeys paying to the given silent payment addresses,
+/// in the same order as the addresses.
+///
+/// The input keys are the secret keys of the eligible inputs, and the outpoints are
+/// the outpoints of all transaction inputs, including the ineligible ones.
+pub fn output_scripts(
+    input_keys: &[InputKey],
+    outpoints: &[OutPoint],
+    recipients: &[SilentPaymentAddress],
+) -> Result<Vec<Script>, Error> {
+    let context = sign::shared_context();
+    let mut keys = input_keys.iter().map(|input_key| match *input_key {
+        InputKey::Ecdsa(key) => key,
+        InputKey::Taproot(mut key) => {
+            if has_odd_y(&PublicKey::from_secret_key(&context, &key)) {
+                key.negate_assign();
+            }
+            key
+        }
+    });
+    let mut secret_sum = keys.next().ok_or(SilentPaymentError::NoInputs)?;
+    for key in keys {
+        secret_sum
+            .add_assign(&key[..])
+            .map_err(|_| SilentPaymentError::NoInputs)?;
+    }
+    let public_sum = PublicKey::from_secret_key(&context, &secret_sum);
+
+    let smallest_outpoint = outpoints
+        .iter()
+        .map(serialize)
+        .min()
+        .ok_or(SilentPaymentError::NoInputs)?;
+    let mut engine = tagged_engine("BIP0352/Inputs");
+    engine.input(&smallest_outpoint);
+    engine.input(&public_sum.serialize());
+    let input_hash = scalar_from_hash(sha256::Hash::from_engine(engine))?;
+
+    let mut tweak = secret_sum;
+    tweak.mul_assign(&input_hash[..])?;
+
+    let mut counters: Vec<(PublicKey, u32)> = Vec::new();
+    recipients
+        .iter()
+        .map(|recipient| {
+            let k = match counters
+                .iter_mut()
+                .find(|(key, _)| *key == recipient.scan_key)
+            {
+                Some((_, counter)) => {
+                    *counter += 1;
+                    *counter
+                }
+                None => {
+                    counters.push((recipient.scan_key, 0));
+                    0
+                }
+            };
+            let shared_secret = mul_point(recipient.scan_key, &tweak)?;
+
+            let mut engine = tagged_engine("BIP0352/SharedSecret");
+            engine.input(&shared_secret.serialize());
+            engine.input(&k.to_be_bytes());
+            let t_k = scalar_from_hash(sha256::Hash::from_engine(engine))?;
+
+            let mut output_key = recipient.spend_key;
+            output_key.add_exp_assign(sign::verification_context(), &t_k[..])?;
+            Ok(Builder::new()
+                .push_int(1)
+                .push_slice(&output_key.serialize()[1..])
+                .into_script())
+        })
+        .collect()
+}
+
+/// Appends the outputs paying the given amounts to the silent payment addresses to
+/// the transaction, which addresses must belong to the given network.
+///
+/// The outputs are derived from the outpoints of the transaction inputs, so all inputs
+/// must be added before, and the input keys are the secret keys of the eligible ones.
+/// If any recipient is incorrect, none of the outputs are added.
+pub fn append_outputs(
+    transaction: &mut Transaction,
+    input_keys: &[InputKey],
+    recipients: &[(SilentPaymentAddress, Amount)],
+    network: Network,
+) -> Result<(), Error> {
+    for (index, (address, _)) in recipients.iter().enumerate() {
+        ensure!(
+            address.network == network,
+            Error::RecipientNetwork {
+                index,
+                expected: network,
+                actual: address.network,
+            }
+        );
+    }
+    let outpoints = transaction
+        .input
+        .iter()
+        .map(|input| input.previous_output)
+        .collect::<Vec<_>>();
+    let addresses = recipients
+        .iter()
+        .map(|(address, _)| *address)
+        .collect::<Vec<_>>();
+    let scripts = output_scripts(input_keys, &outpoints, &addresses)?;
+    transaction
+        .output
+        .extend(
+            scripts
+                .into_iter()
+                .zip(recipients)
+                .map(|(script_pubkey, (_, amount))| TxOut {
+                    value: amount.as_sat(),
+                    script_pubkey,
+                }),
+        );
+    Ok(())
+}
+
+fn polymod(values: &[u8]) -> u32 {
+    const GENERATOR: [u32; 5] = [
+        0x3b6a_57b2,
+        0x2650_8e6d,
+        0x1ea1_19fa,
+        0x3d42_33dd,
+        0x2a14_62b3,
+    ];
+    let mut checksum = 1_u32;
+    for value in values {
+        let top = checksum >> 25;
+        checksum = (checksum & 0x01ff_ffff) << 5 ^ u32::from(*value);
+        for (i, generator) in GENERATOR.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                checksum ^= generator;
+            }
+        }
+    }
+    checksum
+}
+
+fn hrp_expand(hrp: &str) -> Vec<u8> {
+    let bytes = hrp.as_bytes();
+    let mut expanded = bytes.iter().map(|b| b >> 5).collect::<Vec<_>>();
+    expanded.push(0);
+    expanded.extend(bytes.iter().map(|b| b & 31));
+    expanded
+}
+
+fn convert_bits(data: &[u8], from: u32, to: u32, pad: bool) -> Option<Vec<u8>> {
+    let mut accumulator = 0_u32;
+    let mut bits = 0;
+    let max_value = (1 << to) - 1;
+    let mut result = Vec::with_capacity(data.len() * from as usize / to as usize + 1);
+    for value in data {
+        accumulator = accumulator << from | u32::from(*value);
+        bits += from;
+        while bits >= to {
+            bits -= to;
+            result.push((accumulator >> bits & max_value) as u8);
+        }
+    }
+    if pad {
+        if bits > 0 {
+            result.push((accumulator << (to - bits) & max_value) as u8);
+        }
+    } else if bits >= from || accumulator << (to - bits) & max_value != 0 {
+        return None;
+    }
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{
+        blockdata::transaction::{OutPoint, Transaction, TxIn, TxOut},
+        hash_types::Txid,
+        network::constants::Network,
+    };
+    use bitcoin_hashes::hex::FromHex;
+    use secp256k1::{PublicKey, SecretKey};
+
+    use super::{
+        append_outputs, encode, hrp_expand, output_scripts, polymod, InputKey,
+        SilentPaymentAddress, SilentPaymentError, BECH32M_CONST, CHARSET,
+    };
+    use crate::{
+        p2wpk, sign,
+        test_data::{deterministic_keypair, fake_funding_tx},
+        verify::verify_transaction,
+        Amount, Error, TxInRef,
+    };
+
+    /// The silent payment address with the scan and spend secret keys.
+    struct AddressVector {
+        scan_key: &'static str,
+        spend_key: &'static str,
+        addresses: &'static [&'static str],
+    }
+
+    /// The silent payment outputs created from the given inputs.
+    struct SendingVector {
+        comment: &'static str,
+        ecdsa_keys: &'static [&'static str],
+        taproot_keys: &'static [&'static str],
+        outpoints: &'static [(&'static str, u32)],
+        recipients: &'static [&'static str],
+        /// The possible sets of the output keys, which are empty if the sending fails.
+        outputs: &'static [&'static [&'static str]],
+    }
+
+    /// The address test vectors from BIP-352: the first address is made of the scan and
+    /// spend keys, and the other ones contain the labeled spend keys.
+    const ADDRESS_VECTORS: &[AddressVector] = &[
+        AddressVector {
+            scan_key: "0f694e068028a717f8af6b9411f9a133dd3565258714cc226594b34db90c1f2c",
+            spend_key: "9d6ad855ce3417ef84e836892e5a56392bfba05fa5d97ccea30e266f540e08b3",
+            addresses: &[
+                "sp1qqgste7k9hx0qftg6qmwlkqtwuy6cycyavzmzj85c6qdfhjdpdjtdgqjuexzk6murw56suy3e0rd2cgqvycxttddwsvgxe2usfpxumr70xc9pkqwv",
+                "sp1qqgste7k9hx0qftg6qmwlkqtwuy6cycyavzmzj85c6qdfhjdpdjtdgqjex54dmqmmv6rw353tsuqhs99ydvadxzrsy9nuvk74epvee55drs734pqq",
+                "sp1qqgste7k9hx0qftg6qmwlkqtwuy6cycyavzmzj85c6qdfhjdpdjtdgqsg59z2rppn4qlkx0yz9sdltmjv3j8zgcqadjn4ug98m3t6plujsq9qvu5n",
+                "sp1qqgste7k9hx0qftg6qmwlkqtwuy6cycyavzmzj85c6qdfhjdpdjtdgq7c2zfthc6x3a5yecwc52nxa0kfd20xuz08zyrjpfw4l2j257yq6qgnkdh5",
+            ],
+        },
+        AddressVector {
+            scan_key: "060b751d7892149006ed7b98606955a29fe284a1e900070c0971f5fb93dbf422",
+            spend_key: "9902c3c56e84002a7cd410113a9ab21d142be7f53cf5200720bb01314c5eb920",
+            addresses: &[
+                "sp1qqgrz6j0lcqnc04vxccydl0kpsj4frfje0ktmgcl2t346hkw30226xqupawdf48k8882j0strrvcmgg2kdawz53a54dd376ngdhak364hzcmynqtn",
+            ],
+        },
+        AddressVector {
+            scan_key: "11b7a82e06ca2648d5fded2366478078ec4fc9dc1d8ff487518226f229d768fd",
+            spend_key: "b8f87388cbb41934c50daca018901b00070a5ff6cc25a7e9e716a9d5b9e4d664",
+            addresses: &[
+                "sp1qqw6vczcfpdh5nf5y2ky99kmqae0tr30hgdfg88parz50cp80wd2wqqauj52ymtc4xdkmx3tgyhrsemg2g3303xk2gtzfy8h8ejet8fz8jcw23zua",
+                "sp1qqw6vczcfpdh5nf5y2ky99kmqae0tr30hgdfg88parz50cp80wd2wqqlv6saelkk5snl4wfutyxrchpzzwm8rjp3z6q7apna59z9huq4x754e5atr",
+            ],
+        },
+        AddressVector {
+            scan_key: "0000000000000000000000000000000000000000000000000000000000000002",
+            spend_key: "0000000000000000000000000000000000000000000000000000000000000001",
+            addresses: &[
+                "sp1qqtrqglu5g8kh6mfsg4qxa9wq0nv9cauwfwxw70984wkqnw2uwz0w2qnehen8a7wuhwk9tgrzjh8gwzc8q2dlekedec5djk0js9d3d7qhnq6lqj3s",
+            ],
+        },
+    ];
+
+    /// The sending test vectors from BIP-352, in which all inputs are eligible.
+    const SENDING_VECTORS: &[SendingVector] = &[
+        SendingVector {
+            comment: "Simple send: two inputs",
+            ecdsa_keys: &[
+                "eadc78165ff1f8ea94ad7cfdc54990738a4c53f6e0507b42154201b8e5dff3b1",
+                "93f5ed907ad5b2bdbbdcb5d9116ebc0a4e1f92f910d5260237fa45a9408aad16",
+            ],
+            taproot_keys: &[],
+            outpoints: &[
+                ("f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16", 0),
+                ("a1075db55d416d3ca199f55b6084e2115b9345e16c5cf302fc80e9d5fbf5d48d", 0),
+            ],
+            recipients: &[
+                "sp1qqgste7k9hx0qftg6qmwlkqtwuy6cycyavzmzj85c6qdfhjdpdjtdgqjuexzk6murw56suy3e0rd2cgqvycxttddwsvgxe2usfpxumr70xc9pkqwv",
+            ],
+            outputs: &[
+                &[
+                    "3e9fce73d4e77a4809908e3c3a2e54ee147b9312dc5044a193d1fc85de46e3c1",
+                ],
+            ],
+        },
+        SendingVector {
+            comment: "Outpoint ordering byte-lexicographically vs. vout-integer",
+            ecdsa_keys: &[
+                "eadc78165ff1f8ea94ad7cfdc54990738a4c53f6e0507b42154201b8e5dff3b1",
+                "93f5ed907ad5b2bdbbdcb5d9116ebc0a4e1f92f910d5260237fa45a9408aad16",
+            ],
+            taproot_keys: &[],
+            outpoints: &[
+                ("f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16", 1),
+                ("f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16", 256),
+            ],
+            recipients: &[
+                "sp1qqgste7k9hx0qftg6qmwlkqtwuy6cycyavzmzj85c6qdfhjdpdjtdgqjuexzk6murw56suy3e0rd2cgqvycxttddwsvgxe2usfpxumr70xc9pkqwv",
+            ],
+            outputs: &[
+                &[
+                    "a85ef8701394b517a4b35217c4bd37ac01ebeed4b008f8d0879f9e09ba95319c",
+                ],
+            ],
+        },
+        SendingVector {
+            comment: "Single recipient: taproot only inputs with even y-values",
+            ecdsa_keys: &[],
+            taproot_keys: &[
+                "eadc78165ff1f8ea94ad7cfdc54990738a4c53f6e0507b42154201b8e5dff3b1",
+                "fc8716a97a48ba9a05a98ae47b5cd201a25a7fd5d8b73c203c5f7b6b6b3b6ad7",
+            ],
+            outpoints: &[
+                ("f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16", 0),
+                ("a1075db55d416d3ca199f55b6084e2115b9345e16c5cf302fc80e9d5fbf5d48d", 0),
+            ],
+            recipients: &[
+                "sp1qqgste7k9hx0qftg6qmwlkqtwuy6cycyavzmzj85c6qdfhjdpdjtdgqjuexzk6murw56suy3e0rd2cgqvycxttddwsvgxe2usfpxumr70xc9pkqwv",
+            ],
+            outputs: &[
+                &[
+                    "de88bea8e7ffc9ce1af30d1132f910323c505185aec8eae361670421e749a1fb",
+                ],
+            ],
+        },
+        SendingVector {
+            comment: "Single recipient: taproot only with mixed even/odd y-values",
+            ecdsa_keys: &[],
+            taproot_keys: &[
+                "eadc78165ff1f8ea94ad7cfdc54990738a4c53f6e0507b42154201b8e5dff3b1",
+                "1d37787c2b7116ee983e9f9c13269df29091b391c04db94239e0d2bc2182c3bf",
+            ],
+            outpoints: &[
+                ("f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16", 0),
+                ("a1075db55d416d3ca199f55b6084e2115b9345e16c5cf302fc80e9d5fbf5d48d", 0),
+            ],
+            recipients: &[
+                "sp1qqgste7k9hx0qftg6qmwlkqtwuy6cycyavzmzj85c6qdfhjdpdjtdgqjuexzk6murw56suy3e0rd2cgqvycxttddwsvgxe2usfpxumr70xc9pkqwv",
+            ],
+            outputs: &[
+                &[
+                    "77cab7dd12b10259ee82c6ea4b509774e33e7078e7138f568092241bf26b99f1",
+                ],
+            ],
+        },
+        SendingVector {
+            comment: "Single recipient: taproot input with odd y-value and non-taproot input",
+            ecdsa_keys: &[
+                "8d4751f6e8a3586880fb66c19ae277969bd5aa06f61c4ee2f1e2486efdf666d3",
+            ],
+            taproot_keys: &[
+                "1d37787c2b7116ee983e9f9c13269df29091b391c04db94239e0d2bc2182c3bf",
+            ],
+            outpoints: &[
+                ("f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16", 0),
+                ("a1075db55d416d3ca199f55b6084e2115b9345e16c5cf302fc80e9d5fbf5d48d", 0),
+            ],
+            recipients: &[
+                "sp1qqgste7k9hx0qftg6qmwlkqtwuy6cycyavzmzj85c6qdfhjdpdjtdgqjuexzk6murw56suy3e0rd2cgqvycxttddwsvgxe2usfpxumr70xc9pkqwv",
+            ],
+            outputs: &[
+                &[
+                    "359358f59ee9e9eec3f00bdf4882570fd5c182e451aa2650b788544aff012a3a",
+                ],
+            ],
+        },
+        SendingVector {
+            comment: "Multiple outputs: multiple outputs, multiple recipients",
+            ecdsa_keys: &[
+                "eadc78165ff1f8ea94ad7cfdc54990738a4c53f6e0507b42154201b8e5dff3b1",
+                "0378e95685b74565fa56751b84a32dfd18545d10d691641b8372e32164fad66a",
+            ],
+            taproot_keys: &[],
+            outpoints: &[
+                ("f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16", 0),
+                ("a1075db55d416d3ca199f55b6084e2115b9345e16c5cf302fc80e9d5fbf5d48d", 0),
+            ],
+            recipients: &[
+                "sp1qqgste7k9hx0qftg6qmwlkqtwuy6cycyavzmzj85c6qdfhjdpdjtdgqjuexzk6murw56suy3e0rd2cgqvycxttddwsvgxe2usfpxumr70xc9pkqwv",
+                "sp1qqgrz6j0lcqnc04vxccydl0kpsj4frfje0ktmgcl2t346hkw30226xqupawdf48k8882j0strrvcmgg2kdawz53a54dd376ngdhak364hzcmynqtn",
+                "sp1qqgrz6j0lcqnc04vxccydl0kpsj4frfje0ktmgcl2t346hkw30226xqupawdf48k8882j0strrvcmgg2kdawz53a54dd376ngdhak364hzcmynqtn",
+            ],
+            outputs: &[
+                &[
+                    "2e847bb01d1b491da512ddd760b8509617ee38057003d6115d00ba562451323a",
+                    "841792c33c9dc6193e76744134125d40add8f2f4a96475f28ba150be032d64e8",
+                    "f207162b1a7abc51c42017bef055e9ec1efc3d3567cb720357e2b84325db33ac",
+                ],
+            ],
+        },
+        SendingVector {
+            comment: "Receiving with labels: label with odd parity",
+            ecdsa_keys: &[
+                "eadc78165ff1f8ea94ad7cfdc54990738a4c53f6e0507b42154201b8e5dff3b1",
+                "0378e95685b74565fa56751b84a32dfd18545d10d691641b8372e32164fad66a",
+            ],
+            taproot_keys: &[],
+            outpoints: &[
+                ("f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16", 0),
+                ("a1075db55d416d3ca199f55b6084e2115b9345e16c5cf302fc80e9d5fbf5d48d", 0),
+            ],
+            recipients: &[
+                "sp1qqgste7k9hx0qftg6qmwlkqtwuy6cycyavzmzj85c6qdfhjdpdjtdgqsg59z2rppn4qlkx0yz9sdltmjv3j8zgcqadjn4ug98m3t6plujsq9qvu5n",
+            ],
+            outputs: &[
+                &[
+                    "67626aebb3c4307cf0f6c39ca23247598fabf675ab783292eb2f81ae75ad1f8c",
+                ],
+            ],
+        },
+        SendingVector {
+            comment: "Multiple outputs with labels: un-labeled and labeled address; same recipient",
+            ecdsa_keys: &[
+                "eadc78165ff1f8ea94ad7cfdc54990738a4c53f6e0507b42154201b8e5dff3b1",
+                "0378e95685b74565fa56751b84a32dfd18545d10d691641b8372e32164fad66a",
+            ],
+            taproot_keys: &[],
+            outpoints: &[
+                ("f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16", 0),
+                ("a1075db55d416d3ca199f55b6084e2115b9345e16c5cf302fc80e9d5fbf5d48d", 0),
+            ],
+            recipients: &[
+                "sp1qqgste7k9hx0qftg6qmwlkqtwuy6cycyavzmzj85c6qdfhjdpdjtdgqaxww2fnhrx05cghth75n0qcj59e3e2anscr0q9wyknjxtxycg07y3pevyj",
+                "sp1qqgste7k9hx0qftg6qmwlkqtwuy6cycyavzmzj85c6qdfhjdpdjtdgqjuexzk6murw56suy3e0rd2cgqvycxttddwsvgxe2usfpxumr70xc9pkqwv",
+            ],
+            outputs: &[
+                &[
+                    "39f42624d5c32a77fda80ff0acee269afec601d3791803e80252ae04e4ffcf4c",
+                    "f207162b1a7abc51c42017bef055e9ec1efc3d3567cb720357e2b84325db33ac",
+                ],
+                &[
+                    "83dc944e61603137294829aed56c74c9b087d80f2c021b98a7fae5799000696c",
+                    "e976a58fbd38aeb4e6093d4df02e9c1de0c4513ae0c588cef68cda5b2f8834ca",
+                ],
+            ],
+        },
+        SendingVector {
+            comment: "Input keys sum up to zero / point at infinity: sending fails, receiver skips tx",
+            ecdsa_keys: &[
+                "a6df6a0bb448992a301df4258e06a89fe7cf7146f59ac3bd5ff26083acb22ceb",
+                "592095f44bb766d5cfe20bda71f9575ed2df6b9fb9addc7e5fdffe0923841456",
+            ],
+            taproot_keys: &[],
+            outpoints: &[
+                ("3a286147b25e16ae80aff406f2673c6e565418c40f45c071245cdebc8a94174e", 0),
+                ("3a286147b25e16ae80aff406f2673c6e565418c40f45c071245cdebc8a94174e", 1),
+            ],
+            recipients: &[
+                "sp1qqtrqglu5g8kh6mfsg4qxa9wq0nv9cauwfwxw70984wkqnw2uwz0w2qnehen8a7wuhwk9tgrzjh8gwzc8q2dlekedec5djk0js9d3d7qhnq6lqj3s",
+            ],
+            outputs: &[],
+        },
+    ];
+
+    fn keypair(byte: u8) -> (PublicKey, SecretKey) {
+        let sk = SecretKey::from_slice(&[byte; 32]).unwrap();
+        (PublicKey::from_secret_key(&sign::shared_context(), &sk), sk)
+    }
+
+    fn secret_key(hex: &str) -> SecretKey {
+        SecretKey::from_slice(&hex::decode(hex).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_bech32m_checksum() {
+        // Valid bech32m strings from BIP-350.
+        for s in &["a1lqfn3a", "abcdef1l7aum6echk45nj3s0wdvt2fg8x9yrzpqzd3ryx"] {
+            let separator = s.rfind('1').unwrap();
+            let data = s[separator + 1..]
+                .bytes()
+                .map(|c| CHARSET.iter().position(|&x| x == c).unwrap() as u8)
+                .collect::<Vec<_>>();
+            let values = [hrp_expand(&s[..separator]), data].concat();
+            assert_eq!(polymod(&values), BECH32M_CONST);
+        }
+    }
+
+    #[test]
+    fn test_address_vectors() {
+        let context = sign::shared_context();
+        for vector in ADDRESS_VECTORS {
+            let address = SilentPaymentAddress {
+                scan_key: PublicKey::from_secret_key(&context, &secret_key(vector.scan_key)),
+                spend_key: PublicKey::from_secret_key(&context, &secret_key(vector.spend_key)),
+                network: Network::Bitcoin,
+            };
+            assert_eq!(address.to_string(), vector.addresses[0]);
+
+            for s in vector.addresses {
+                let parsed = SilentPaymentAddress::parse(s, Network::Bitcoin).unwrap();
+                assert_eq!(parsed.scan_key, address.scan_key);
+                assert_eq!(parsed.to_string(), *s);
+            }
+        }
+    }
+
+    #[test]
+    fn test_address_roundtrip() {
+        let address = SilentPaymentAddress {
+            scan_key: keypair(1).0,
+            spend_key: keypair(2).0,
+            network: Network::Bitcoin,
+        };
+        let s = address.to_string();
+        assert!(s.starts_with("sp1q"));
+        assert_eq!(
+            SilentPaymentAddress::parse(&s.to_uppercase(), Network::Bitcoin).unwrap(),
+            address
+        );
+
+        let mut corrupted = s.clone().into_bytes();
+        let last = corrupted.len() - 1;
+        corrupted[last] = if corrupted[last] == b'q' { b'p' } else { b'q' };
+        assert_eq!(
+            SilentPaymentAddress::parse(&String::from_utf8(corrupted).unwrap(), Network::Bitcoin),
+            Err(Error::from(SilentPaymentError::Encoding))
+        );
+        assert_eq!(
+            SilentPaymentAddress::parse(&format!("bc{}", &s[2..]), Network::Bitcoin),
+            Err(Error::from(SilentPaymentError::Prefix))
+        );
+        assert_eq!(
+            SilentPaymentAddress::parse(&s, Network::Testnet),
+            Err(Error::from(SilentPaymentError::Network(Network::Testnet)))
+        );
+
+        for &(network, prefix) in &[
+            (Network::Testnet, "tsp1q"),
+            (Network::Signet, "tsp1q"),
+            (Network::Regtest, "sprt1q"),
+        ] {
+            let address = SilentPaymentAddress { network, ..address };
+            let s = address.to_string();
+            assert!(s.starts_with(prefix));
+            assert_eq!(SilentPaymentAddress::parse(&s, network).unwrap(), address);
+        }
+    }
+
+    #[test]
+    fn test_address_versions() {
+        let address = SilentPaymentAddress {
+            scan_key: keypair(1).0,
+            spend_key: keypair(2).0,
+            network: Network::Bitcoin,
+        };
+        let mut payload = address.scan_key.serialize().to_vec();
+        payload.extend_from_slice(&address.spend_key.serialize());
+        let mut extended_payload = payload.clone();
+        extended_payload.extend_from_slice(&[0xab; 20]);
+
+        // The future versions may contain more data, which is ignored.
+        for &version in &[1, 30] {
+            for payload in &[&payload, &extended_payload] {
+                let s = encode("sp", version, payload);
+                assert_eq!(
+                    SilentPaymentAddress::parse(&s, Network::Bitcoin).unwrap(),
+                    address
+                );
+            }
+        }
+        assert_eq!(
+            SilentPaymentAddress::parse(&encode("sp", 0, &extended_payload), Network::Bitcoin),
+            Err(Error::from(SilentPaymentError::PublicKey))
+        );
+        assert_eq!(
+            SilentPaymentAddress::parse(&encode("sp", 1, &payload[..65]), Network::Bitcoin),
+            Err(Error::from(SilentPaymentError::PublicKey))
+        );
+        assert_eq!(
+            SilentPaymentAddress::parse(&encode("sp", 31, &payload), Network::Bitcoin),
+            Err(Error::from(SilentPaymentError::Version(31)))
+        );
+    }
+
+    #[test]
+    fn test_sending_vectors() {
+        for vector in SENDING_VECTORS {
+            let input_keys = vector
+                .ecdsa_keys
+                .iter()
+                .map(|key| InputKey::Ecdsa(secret_key(key)))
+                .chain(
+                    vector
+                        .taproot_keys
+                        .iter()
+                        .map(|key| InputKey::Taproot(secret_key(key))),
+                )
+                .collect::<Vec<_>>();
+            let outpoints = vector
+                .outpoints
+                .iter()
+                .map(|&(txid, vout)| OutPoint::new(Txid::from_hex(txid).unwrap(), vout))
+                .collect::<Vec<_>>();
+            let recipients = vector
+                .recipients
+                .iter()
+                .map(|s| SilentPaymentAddress::parse(s, Network::Bitcoin).unwrap())
+                .collect::<Vec<_>>();
+
+            let scripts = match output_scripts(&input_keys, &outpoints, &recipients) {
+                Ok(scripts) => scripts,
+                Err(error) => {
+                    assert!(vector.outputs.is_empty(), "{}", vector.comment);
+                    assert_eq!(error, Error::from(SilentPaymentError::NoInputs));
+                    continue;
+                }
+            };
+            let mut outputs = scripts
+                .iter()
+                .map(|script| hex::encode(&script[2..]))
+                .collect::<Vec<_>>();
+            outputs.sort();
+            assert!(
+                vector.outputs.iter().any(|expected| {
+                    let mut expected = expected.to_vec();
+                    expected.sort_unstable();
+                    expected == outputs
+                }),
+                "{}",
+                vector.comment
+            );
+        }
+    }
+
+    #[test]
+    fn test_append_outputs() {
+        let keypairs = (0..2)
+            .map(|_| deterministic_keypair(Network::Testnet))
+            .collect::<Vec<_>>();
+        let funding_txs = keypairs
+            .iter()
+            .map(|(public_key, _)| fake_funding_tx(&p2wpk::script_pubkey(public_key), 60_000))
+            .collect::<Vec<_>>();
+        let spent_outputs = funding_txs
+            .iter()
+            .map(|funding_tx| funding_tx.output[0].clone())
+            .collect::<Vec<_>>();
+        let change = TxOut {
+            value: 9_000,
+            script_pubkey: p2wpk::script_pubkey(&keypairs[0].0),
+        };
+        let mut transaction = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: funding_txs
+                .iter()
+                .map(|funding_tx| TxIn {
+                    previous_output: OutPoint::new(funding_tx.txid(), 0),
+                    sequence: 0xFFFF_FFFF,
+                    ..TxIn::default()
+                })
+                .collect(),
+            output: vec![change.clone()],
+        };
+        let input_keys = keypairs
+            .iter()
+            .map(|(_, private_key)| InputKey::Ecdsa(private_key.key))
+            .collect::<Vec<_>>();
+        let address = SilentPaymentAddress {
+            scan_key: keypair(1).0,
+            spend_key: keypair(2).0,
+            network: Network::Testnet,
+        };
+        let recipients = [
+            (address, Amount::from_sat(70_000)),
+            (address, Amount::from_sat(40_000)),
+        ];
+
+        // The addresses of another network are rejected.
+        let mainnet_recipient = (
+            SilentPaymentAddress {
+                network: Network::Bitcoin,
+                ..address
+            },
+            Amount::from_sat(1_000),
+        );
+        assert_eq!(
+            append_outputs(
+                &mut transaction,
+                &input_keys,
+                &[recipients[0], mainnet_recipient],
+                Network::Testnet
+            ),
+            Err(Error::RecipientNetwork {
+                index: 1,
+                expected: Network::Testnet,
+                actual: Network::Bitcoin,
+            })
+        );
+        assert_eq!(
+            append_outputs(&mut transaction, &[], &recipients, Network::Testnet),
+            Err(Error::from(SilentPaymentError::NoInputs))
+        );
+        assert_eq!(transaction.output, vec![change.clone()]);
+
+        append_outputs(&mut transaction, &input_keys, &recipients, Network::Testnet).unwrap();
+        let outpoints = transaction
+            .input
+            .iter()
+            .map(|input| input.previous_output)
+            .collect::<Vec<_>>();
+        let scripts = output_scripts(&input_keys, &outpoints, &[address, address]).unwrap();
+        assert_ne!(scripts[0], scripts[1]);
+        assert_eq!(
+            transaction.output,
+            vec![
+                change,
+                TxOut {
+                    value: 70_000,
+                    script_pubkey: scripts[0].clone(),
+                },
+                TxOut {
+                    value: 40_000,
+                    script_pubkey: scripts[1].clone(),
+                },
+            ]
+        );
+
+        // The transaction spending the eligible inputs is signed as usual.
+        for (index, (public_key, private_key)) in keypairs.iter().enumerate() {
+            let mut signer = p2wpk::InputSigner::new(*public_key, Network::Testnet);
+            let signature = signer
+                .sign_input(
+                    TxInRef::new(&transaction, index),
+                    &spent_outputs[index],
+                    &private_key.key,
+                )
+                .unwrap();
+            signer.spend_input(&mut transaction.input[index], signature);
+        }
+        verify_transaction(&transaction, &spent_outputs).unwrap();
+    }
+
+    #[test]
+    fn test_append_outputs_vector() {
+        let vector = &SENDING_VECTORS[0];
+        let mut transaction = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vector
+                .outpoints
+                .iter()
+                .map(|&(txid, vout)| TxIn {
+                    previous_output: OutPoint::new(Txid::from_hex(txid).unwrap(), vout),
+                    ..TxIn::default()
+                })
+                .collect(),
+            output: Vec::new(),
+        };
+        let input_keys = vector
+            .ecdsa_keys
+            .iter()
+            .map(|key| InputKey::Ecdsa(secret_key(key)))
+            .collect::<Vec<_>>();
+        let address = SilentPaymentAddress::parse(vector.recipients[0], Network::Bitcoin).unwrap();
+
+        append_outputs(
+            &mut transaction,
+            &input_keys,
+            &[(address, Amount::from_sat(10_000))],
+            Network::Bitcoin,
+        )
+        .unwrap();
+        assert_eq!(transaction.output[0].value, 10_000);
+        assert_eq!(
+            hex::encode(&transaction.output[0].script_pubkey[2..]),
+            vector.outputs[0][0]
+        );
+    }
+}
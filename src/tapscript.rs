@@ -0,0 +1,860 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Helper functions to create the [tapscript] multisig leaf scripts, which use
+//! `OP_CHECKSIGADD` instead of `OP_CHECKMULTISIG`:
+//!
+//! ```text
+//! <pk1> OP_CHECKSIG <pk2> OP_CHECKSIGADD ... <pkN> OP_CHECKSIGADD <k> OP_NUMEQUAL
+//! ```
+//!
+//! The script is committed as the single leaf of the taproot output, or as a leaf of
+//! the [`TapTree`] together with other scripts. The `bitcoin` crate does not implement
+//! the taproot signature hash yet, so it is provided by the [`signature_hash`] function
+//! according to the [`BIP-341`][bip-341], including the optional annex.
+//!
+//! [tapscript]: https://github.com/bitcoin/bips/blob/master/bip-0342.mediawiki
+//! [bip-341]: https://github.com/bitcoin/bips/blob/master/bip-0341.mediawiki
+
+use bitcoin::{
+    blockdata::{
+        opcodes::{self, all::OP_CHECKSIG},
+        script::{Builder, Script},
+        transaction::{Transaction, TxOut},
+    },
+    consensus::encode::{serialize, Encodable},
+    PublicKey, VarInt,
+};
+use bitcoin_hashes::{sha256, Hash, HashEngine};
+use secp256k1::schnorrsig;
+
+use std::{fmt, str::FromStr};
+
+use crate::{ecc::tagged_engine, multisig::RedeemScriptError, sign, Error};
+
+/// The leaf version of the tapscript.
+pub const TAPSCRIPT_LEAF_VERSION: u8 = 0xc0;
+
+/// The first byte of the annex, which distinguishes it from the other witness elements.
+pub const ANNEX_TAG: u8 = 0x50;
+
+/// The default taproot signature hash type, which is equivalent to `SIGHASH_ALL`,
+/// but the signature has no trailing hash type byte.
+pub const SIGHASH_DEFAULT: u8 = 0x00;
+
+const OP_CHECKSIGADD: opcodes::All = opcodes::all::OP_RETURN_186;
+const SIGHASH_NONE: u8 = 0x02;
+const SIGHASH_SINGLE: u8 = 0x03;
+const SIGHASH_ANYONECANPAY: u8 = 0x80;
+
+/// The x-only public key, which is used in the taproot outputs and tapscripts.
+/// It is the 32-byte `x` coordinate of the point with an even `y` coordinate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct XOnlyPublicKey(schnorrsig::PublicKey);
+
+impl XOnlyPublicKey {
+    /// Tries to construct the x-only public key from the 32 raw bytes.
+    pub fn from_slice(bytes: &[u8]) -> Result<XOnlyPublicKey, Error> {
+        schnorrsig::PublicKey::from_slice(bytes)
+            .map(XOnlyPublicKey)
+            .map_err(Error::from)
+    }
+
+    /// Returns the 32-byte serialization of the public key.
+    pub fn serialize(&self) -> [u8; 32] {
+        self.0.serialize()
+    }
+}
+
+impl From<schnorrsig::PublicKey> for XOnlyPublicKey {
+    fn from(key: schnorrsig::PublicKey) -> XOnlyPublicKey {
+        XOnlyPublicKey(key)
+    }
+}
+
+impl From<XOnlyPublicKey> for schnorrsig::PublicKey {
+    fn from(key: XOnlyPublicKey) -> schnorrsig::PublicKey {
+        key.0
+    }
+}
+
+/// Drops the parity of the `y` coordinate, so the compressed keys differing only
+/// in the first byte are converted to the same x-only key.
+impl From<secp256k1::PublicKey> for XOnlyPublicKey {
+    fn from(key: secp256k1::PublicKey) -> XOnlyPublicKey {
+        XOnlyPublicKey(key.into())
+    }
+}
+
+/// Drops the parity of the `y` coordinate and the key compression flag.
+impl From<PublicKey> for XOnlyPublicKey {
+    fn from(key: PublicKey) -> XOnlyPublicKey {
+        key.key.into()
+    }
+}
+
+impl fmt::Display for XOnlyPublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&hex::encode(self.serialize()))
+    }
+}
+
+impl FromStr for XOnlyPublicKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = hex::decode(s)?;
+        XOnlyPublicKey::from_slice(&bytes)
+    }
+}
+
+impl ::serde::Serialize for XOnlyPublicKey {
+    fn serialize<S>(&self, ser: S) -> ::std::result::Result<S::Ok, S::Error>
+    where
+        S: ::serde::Serializer,
+    {
+        ::serde_str::serialize(self, ser)
+    }
+}
+
+impl<'de> ::serde::Deserialize<'de> for XOnlyPublicKey {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: ::serde::Deserializer<'de>,
+    {
+        ::serde_str::deserialize(deserializer)
+    }
+}
+
+/// The tapscript multisig leaf script.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TapscriptMultisig {
+    script: Script,
+    quorum: usize,
+    public_keys: Vec<schnorrsig::PublicKey>,
+}
+
+impl TapscriptMultisig {
+    /// Returns the leaf script.
+    pub fn script(&self) -> &Script {
+        &self.script
+    }
+
+    /// Returns the number of signatures required to spend the output.
+    pub fn quorum(&self) -> usize {
+        self.quorum
+    }
+
+    /// Returns the public keys of the participants in the script order.
+    pub fn public_keys(&self) -> &[schnorrsig::PublicKey] {
+        &self.public_keys
+    }
+
+    /// Returns the tagged hash of the leaf, which is the merkle root of the single leaf tree.
+    pub fn leaf_hash(&self) -> sha256::Hash {
+        leaf_hash(&self.script)
+    }
+
+    /// Returns the script pubkey of the taproot output with the given internal key
+    /// committing to this leaf script.
+    pub fn script_pubkey(&self, internal_key: &schnorrsig::PublicKey) -> Result<Script, Error> {
+        self.to_tree().script_pubkey(internal_key)
+    }
+
+    /// Returns the control block which proves that the leaf script is committed
+    /// to the output with the given internal key.
+    pub fn control_block(&self, internal_key: &schnorrsig::PublicKey) -> Result<Vec<u8>, Error> {
+        control_block(internal_key, &self.to_tree(), &self.script)
+    }
+
+    /// Returns the single leaf tree with this script.
+    pub fn to_tree(&self) -> TapTree {
+        TapTree::Leaf(self.script.clone())
+    }
+
+    /// Creates the witness stack for the script path spending from the signatures
+    /// of the given participants. Exactly the quorum of signatures must be given,
+    /// since `OP_NUMEQUAL` fails on the excess ones.
+    pub fn witness_data(
+        &self,
+        signatures: &[(schnorrsig::PublicKey, schnorrsig::Signature)],
+        internal_key: &schnorrsig::PublicKey,
+    ) -> Result<Vec<Vec<u8>>, Error> {
+        ensure!(
+            signatures.len() == self.quorum,
+            Error::SignaturesCount {
+                quorum: self.quorum,
+                actual: signatures.len(),
+            }
+        );
+
+        let mut stack = vec![Vec::new(); self.public_keys.len()];
+        for (index, (public_key, signature)) in signatures.iter().enumerate() {
+            let position = self
+                .public_keys
+                .iter()
+                .position(|key| key == public_key)
+                .ok_or(Error::UnknownPublicKey)?;
+            // The first key in the script consumes the topmost stack element.
+            let item = &mut stack[self.public_keys.len() - 1 - position];
+            ensure!(item.is_empty(), Error::DuplicatePublicKey(index));
+            *item = signature[..].to_vec();
+        }
+        stack.push(self.script.to_bytes());
+        stack.push(self.control_block(internal_key)?);
+        Ok(stack)
+    }
+}
+
+/// The taproot script tree, which leaves are the tapscripts.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TapTree {
+    /// The leaf with the tapscript.
+    Leaf(Script),
+    /// The branch with two subtrees.
+    Branch(Box<TapTree>, Box<TapTree>),
+}
+
+impl TapTree {
+    /// Creates the branch from the given subtrees.
+    pub fn branch(left: TapTree, right: TapTree) -> TapTree {
+        TapTree::Branch(Box::new(left), Box::new(right))
+    }
+
+    /// Returns the merkle root of the tree.
+    pub fn merkle_root(&self) -> sha256::Hash {
+        match self {
+            TapTree::Leaf(script) => leaf_hash(script),
+            TapTree::Branch(left, right) => branch_hash(left.merkle_root(), right.merkle_root()),
+        }
+    }
+
+    /// Returns the hashes of the sibling nodes on the path from the given leaf to the root,
+    /// starting from the deepest one, or `None` if there is no such leaf in the tree.
+    pub fn merkle_path(&self, leaf: &Script) -> Option<Vec<sha256::Hash>> {
+        match self {
+            TapTree::Leaf(script) if script == leaf => Some(Vec::new()),
+            TapTree::Leaf(_) => None,
+            TapTree::Branch(left, right) => {
+                let (mut path, sibling) = match left.merkle_path(leaf) {
+                    Some(path) => (path, right.merkle_root()),
+                    None => (right.merkle_path(leaf)?, left.merkle_root()),
+                };
+                path.push(sibling);
+                Some(path)
+            }
+        }
+    }
+
+    /// Returns the script pubkey of the taproot output with the given internal key
+    /// committing to this tree.
+    pub fn script_pubkey(&self, internal_key: &schnorrsig::PublicKey) -> Result<Script, Error> {
+        let (output_key, _) = output_key(internal_key, self.merkle_root())?;
+        Ok(Builder::new()
+            .push_int(1)
+            .push_slice(&output_key.serialize())
+            .into_script())
+    }
+}
+
+/// Returns the control block, which proves that the given leaf script is committed to
+/// the taproot output with the given internal key and script tree. The control block
+/// consists of the leaf version with the output key parity bit, the internal key and
+/// the merkle path of the leaf, and it is the last element of the script path witness.
+pub fn control_block(
+    internal_key: &schnorrsig::PublicKey,
+    tree: &TapTree,
+    leaf: &Script,
+) -> Result<Vec<u8>, Error> {
+    let path = tree.merkle_path(leaf).ok_or(Error::UnknownTapLeaf)?;
+    let (_, is_odd) = output_key(internal_key, tree.merkle_root())?;
+
+    let mut control_block = Vec::with_capacity(33 + 32 * path.len());
+    control_block.push(TAPSCRIPT_LEAF_VERSION | is_odd as u8);
+    control_block.extend_from_slice(&internal_key.serialize());
+    for node in path {
+        control_block.extend_from_slice(&node[..]);
+    }
+    Ok(control_block)
+}
+
+/// Returns the annex of the taproot input witness, if any. The annex is the last
+/// witness element starting with the [`ANNEX_TAG`] byte, given that there are at least
+/// two elements.
+pub fn annex(witness: &[Vec<u8>]) -> Option<&[u8]> {
+    match witness {
+        [_, .., last] if last.first() == Some(&ANNEX_TAG) => Some(last),
+        _ => None,
+    }
+}
+
+/// Appends the annex to the taproot spend witness. The annex must start with
+/// the [`ANNEX_TAG`] byte and must be passed to the [`signature_hash`] function
+/// when the signatures are created, since they commit to it.
+pub fn push_annex(witness: &mut Vec<Vec<u8>>, annex: Vec<u8>) -> Result<(), Error> {
+    ensure!(annex.first() == Some(&ANNEX_TAG), Error::InvalidAnnex);
+    ensure!(!witness.is_empty(), Error::InvalidAnnex);
+    witness.push(annex);
+    Ok(())
+}
+
+/// Computes the [`BIP-341`][bip-341] signature hash of the taproot input with the given index.
+///
+/// The outputs spent by all transaction inputs are required. The `leaf` script is given
+/// for the script path spending and the `annex` includes the [`ANNEX_TAG`] byte. The verifier
+/// can obtain the annex from the input witness via the [`annex`] function.
+///
+/// [bip-341]: https://github.com/bitcoin/bips/blob/master/bip-0341.mediawiki
+pub fn signature_hash(
+    transaction: &Transaction,
+    index: usize,
+    spent_outputs: &[TxOut],
+    sighash_type: u8,
+    leaf: Option<&Script>,
+    annex: Option<&[u8]>,
+) -> Result<sha256::Hash, Error> {
+    ensure!(index < transaction.input.len(), Error::NoSuchInput(index));
+    ensure!(
+        spent_outputs.len() == transaction.input.len(),
+        Error::OutputsCount {
+            inputs: transaction.input.len(),
+            outputs: spent_outputs.len(),
+        }
+    );
+    ensure!(
+        matches!(sighash_type, 0x00..=0x03 | 0x81..=0x83),
+        Error::InvalidSigHashType(sighash_type)
+    );
+    if let Some(annex) = annex {
+        ensure!(annex.first() == Some(&ANNEX_TAG), Error::InvalidAnnex);
+    }
+    let anyone_can_pay = sighash_type & SIGHASH_ANYONECANPAY != 0;
+    let output_type = sighash_type & 0x03;
+    let single_output = if output_type == SIGHASH_SINGLE {
+        Some(
+            transaction
+                .output
+                .get(index)
+                .ok_or(Error::NoSuchOutput(index))?,
+        )
+    } else {
+        None
+    };
+
+    // Writing to the hash engine never fails.
+    let mut engine = tagged_engine("TapSighash");
+    engine.input(&[0x00, sighash_type]);
+    transaction.version.consensus_encode(&mut engine).unwrap();
+    transaction.lock_time.consensus_encode(&mut engine).unwrap();
+    if !anyone_can_pay {
+        let mut prevouts = sha256::Hash::engine();
+        let mut amounts = sha256::Hash::engine();
+        let mut script_pubkeys = sha256::Hash::engine();
+        let mut sequences = sha256::Hash::engine();
+        for (input, spent_output) in transaction.input.iter().zip(spent_outputs) {
+            input
+                .previous_output
+                .consensus_encode(&mut prevouts)
+                .unwrap();
+            spent_output.value.consensus_encode(&mut amounts).unwrap();
+            spent_output
+                .script_pubkey
+                .consensus_encode(&mut script_pubkeys)
+                .unwrap();
+            input.sequence.consensus_encode(&mut sequences).unwrap();
+        }
+        for part in [prevouts, amounts, script_pubkeys, sequences].iter() {
+            engine.input(&sha256::Hash::from_engine(part.clone())[..]);
+        }
+    }
+    if output_type != SIGHASH_NONE && output_type != SIGHASH_SINGLE {
+        let mut outputs = sha256::Hash::engine();
+        for output in &transaction.output {
+            output.consensus_encode(&mut outputs).unwrap();
+        }
+        engine.input(&sha256::Hash::from_engine(outputs)[..]);
+    }
+
+    let spend_type = (leaf.is_some() as u8) << 1 | annex.is_some() as u8;
+    engine.input(&[spend_type]);
+    if anyone_can_pay {
+        let input = &transaction.input[index];
+        let spent_output = &spent_outputs[index];
+        input.previous_output.consensus_encode(&mut engine).unwrap();
+        spent_output.value.consensus_encode(&mut engine).unwrap();
+        spent_output
+            .script_pubkey
+            .consensus_encode(&mut engine)
+            .unwrap();
+        input.sequence.consensus_encode(&mut engine).unwrap();
+    } else {
+        (index as u32).consensus_encode(&mut engine).unwrap();
+    }
+    if let Some(annex) = annex {
+        let mut annex_engine = sha256::Hash::engine();
+        annex.to_vec().consensus_encode(&mut annex_engine).unwrap();
+        engine.input(&sha256::Hash::from_engine(annex_engine)[..]);
+    }
+    if let Some(output) = single_output {
+        engine.input(&sha256::Hash::hash(&serialize(output))[..]);
+    }
+    if let Some(leaf) = leaf {
+        engine.input(&leaf_hash(leaf)[..]);
+        // The key version and the code separator position, which is not used.
+        engine.input(&[0x00]);
+        engine.input(&u32::MAX.to_le_bytes());
+    }
+    Ok(sha256::Hash::from_engine(engine))
+}
+
+/// Returns the tagged hash of the tapscript leaf.
+fn leaf_hash(script: &Script) -> sha256::Hash {
+    let mut engine = tagged_engine("TapLeaf");
+    engine.input(&[TAPSCRIPT_LEAF_VERSION]);
+    engine.input(&serialize(&VarInt(script.len() as u64)));
+    engine.input(script.as_bytes());
+    sha256::Hash::from_engine(engine)
+}
+
+/// Returns the tagged hash of the branch, which children are sorted lexicographically.
+fn branch_hash(left: sha256::Hash, right: sha256::Hash) -> sha256::Hash {
+    let (first, second) = if left <= right {
+        (left, right)
+    } else {
+        (right, left)
+    };
+    let mut engine = tagged_engine("TapBranch");
+    engine.input(&first[..]);
+    engine.input(&second[..]);
+    sha256::Hash::from_engine(engine)
+}
+
+/// Returns the tweaked output key and its parity.
+fn output_key(
+    internal_key: &schnorrsig::PublicKey,
+    merkle_root: sha256::Hash,
+) -> Result<(schnorrsig::PublicKey, bool), Error> {
+    let mut engine = tagged_engine("TapTweak");
+    engine.input(&internal_key.serialize());
+    engine.input(&merkle_root[..]);
+    let tweak = sha256::Hash::from_engine(engine);
+
+    let mut output_key = *internal_key;
+    let is_odd = output_key.tweak_add_assign(sign::verification_context(), &tweak[..])?;
+    Ok((output_key, is_odd))
+}
+
+/// The tapscript multisig builder.
+#[derive(Debug, Default)]
+pub struct TapscriptMultisigBuilder {
+    quorum: usize,
+    public_keys: Vec<schnorrsig::PublicKey>,
+}
+
+impl TapscriptMultisigBuilder {
+    /// Creates builder.
+    pub fn new() -> TapscriptMultisigBuilder {
+        TapscriptMultisigBuilder::default()
+    }
+
+    /// Creates builder for the given quorum value.
+    pub fn with_quorum(quorum: usize) -> TapscriptMultisigBuilder {
+        TapscriptMultisigBuilder {
+            quorum,
+            public_keys: Vec::default(),
+        }
+    }
+
+    /// Adds a new public key. The full public keys are converted to the x-only ones.
+    pub fn public_key<K>(&mut self, pub_key: K) -> &mut TapscriptMultisigBuilder
+    where
+        K: Into<XOnlyPublicKey>,
+    {
+        self.public_keys.push(pub_key.into().into());
+        self
+    }
+
+    /// Sets the number of signatures required to spend the output.
+    pub fn quorum(&mut self, quorum: usize) -> &mut TapscriptMultisigBuilder {
+        self.quorum = quorum;
+        self
+    }
+
+    /// Finalizes the leaf script building.
+    pub fn to_script(&self) -> Result<TapscriptMultisig, Error> {
+        let total_count = self.public_keys.len();
+        ensure!(self.quorum > 0, RedeemScriptError::NoQuorum);
+        ensure!(total_count != 0, RedeemScriptError::NotEnoughPublicKeys);
+        ensure!(
+            total_count >= self.quorum,
+            RedeemScriptError::IncorrectQuorum
+        );
+
+        let mut builder = Builder::default();
+        for (index, key) in self.public_keys.iter().enumerate() {
+            let opcode = if index == 0 {
+                OP_CHECKSIG
+            } else {
+                OP_CHECKSIGADD
+            };
+            builder = builder.push_slice(&key.serialize()).push_opcode(opcode);
+        }
+        let script = builder
+            .push_int(self.quorum as i64)
+            .push_opcode(opcodes::all::OP_NUMEQUAL)
+            .into_script();
+        Ok(TapscriptMultisig {
+            script,
+            quorum: self.quorum,
+            public_keys: self.public_keys.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin_hashes::{sha256, Hash, HashEngine};
+    use secp256k1::{schnorrsig, Message, SecretKey};
+
+    use bitcoin::{
+        blockdata::{
+            script::Builder,
+            transaction::{OutPoint, Transaction, TxIn, TxOut},
+        },
+        Txid,
+    };
+
+    use super::{
+        annex, branch_hash, control_block, leaf_hash, push_annex, signature_hash, TapTree,
+        TapscriptMultisigBuilder, XOnlyPublicKey, SIGHASH_DEFAULT, TAPSCRIPT_LEAF_VERSION,
+    };
+    use crate::{
+        ecc::tagged_engine, multisig::RedeemScriptError, sign, test_data::keypair_from_wif, Error,
+    };
+
+    fn keypair(byte: u8) -> schnorrsig::KeyPair {
+        let sk = SecretKey::from_slice(&[byte; 32]).unwrap();
+        schnorrsig::KeyPair::from_secret_key(&sign::shared_context(), sk)
+    }
+
+    fn public_key(keypair: &schnorrsig::KeyPair) -> schnorrsig::PublicKey {
+        schnorrsig::PublicKey::from_keypair(&sign::shared_context(), keypair)
+    }
+
+    #[test]
+    fn test_tapscript_multisig_script() {
+        let keys = (1..=3).map(|i| public_key(&keypair(i))).collect::<Vec<_>>();
+        let mut builder = TapscriptMultisigBuilder::with_quorum(2);
+        for key in &keys {
+            builder.public_key(*key);
+        }
+        let multisig = builder.to_script().unwrap();
+
+        let mut expected = Vec::new();
+        for (i, key) in keys.iter().enumerate() {
+            expected.push(32);
+            expected.extend_from_slice(&key.serialize());
+            expected.push(if i == 0 { 0xac } else { 0xba });
+        }
+        expected.extend_from_slice(&[0x52, 0x9c]);
+        assert_eq!(multisig.script().as_bytes(), &expected[..]);
+        assert_eq!(multisig.quorum(), 2);
+        assert_eq!(multisig.public_keys(), &keys[..]);
+
+        assert_eq!(
+            TapscriptMultisigBuilder::with_quorum(4)
+                .public_key(keys[0])
+                .to_script(),
+            Err(Error::from(RedeemScriptError::IncorrectQuorum))
+        );
+        assert_eq!(
+            TapscriptMultisigBuilder::new()
+                .public_key(keys[0])
+                .to_script(),
+            Err(Error::from(RedeemScriptError::NoQuorum))
+        );
+    }
+
+    #[test]
+    fn test_tapscript_multisig_output() {
+        let keypairs = (1..=3).map(keypair).collect::<Vec<_>>();
+        let keys = keypairs.iter().map(public_key).collect::<Vec<_>>();
+        let mut builder = TapscriptMultisigBuilder::with_quorum(2);
+        for key in &keys {
+            builder.public_key(*key);
+        }
+        let multisig = builder.to_script().unwrap();
+        let internal_key = public_key(&keypair(42));
+
+        let script_pubkey = multisig.script_pubkey(&internal_key).unwrap();
+        assert_eq!(script_pubkey.len(), 34);
+        assert_eq!(&script_pubkey[..2], &[0x51, 0x20]);
+        let output_key = schnorrsig::PublicKey::from_slice(&script_pubkey[2..]).unwrap();
+
+        let control_block = multisig.control_block(&internal_key).unwrap();
+        assert_eq!(control_block[0] & 0xfe, TAPSCRIPT_LEAF_VERSION);
+        assert_eq!(&control_block[1..], &internal_key.serialize()[..]);
+
+        let mut engine = tagged_engine("TapTweak");
+        engine.input(&internal_key.serialize());
+        engine.input(&multisig.leaf_hash()[..]);
+        let tweak = sha256::Hash::from_engine(engine);
+        assert!(internal_key.tweak_add_check(
+            sign::verification_context(),
+            &output_key,
+            control_block[0] & 1 == 1,
+            tweak.into_inner(),
+        ));
+
+        let message = Message::from_slice(&[7; 32]).unwrap();
+        let context = sign::shared_context();
+        let signatures = [0, 2]
+            .iter()
+            .map(|&i| {
+                let signature = context.schnorrsig_sign_no_aux_rand(&message, &keypairs[i]);
+                (keys[i], signature)
+            })
+            .collect::<Vec<_>>();
+        let witness = multisig.witness_data(&signatures, &internal_key).unwrap();
+        assert_eq!(witness.len(), 5);
+        assert_eq!(witness[0], signatures[1].1[..].to_vec());
+        assert!(witness[1].is_empty());
+        assert_eq!(witness[2], signatures[0].1[..].to_vec());
+        assert_eq!(witness[3], multisig.script().to_bytes());
+        assert_eq!(witness[4], control_block);
+
+        assert_eq!(
+            multisig.witness_data(&signatures[..1], &internal_key),
+            Err(Error::SignaturesCount {
+                quorum: 2,
+                actual: 1
+            })
+        );
+        let duplicate = [signatures[0], signatures[0]];
+        assert_eq!(
+            multisig.witness_data(&duplicate, &internal_key),
+            Err(Error::DuplicatePublicKey(1))
+        );
+    }
+
+    #[test]
+    fn test_tap_tree_control_block() {
+        let scripts = (1..=3)
+            .map(|i| Builder::new().push_int(i).into_script())
+            .collect::<Vec<_>>();
+        let tree = TapTree::branch(
+            TapTree::Leaf(scripts[0].clone()),
+            TapTree::branch(
+                TapTree::Leaf(scripts[1].clone()),
+                TapTree::Leaf(scripts[2].clone()),
+            ),
+        );
+        let internal_key = public_key(&keypair(42));
+        let script_pubkey = tree.script_pubkey(&internal_key).unwrap();
+        let output_key = schnorrsig::PublicKey::from_slice(&script_pubkey[2..]).unwrap();
+
+        for (script, depth) in scripts.iter().zip(&[1, 2, 2]) {
+            let control_block = control_block(&internal_key, &tree, script).unwrap();
+            assert_eq!(control_block.len(), 33 + 32 * depth);
+            assert_eq!(control_block[0] & 0xfe, TAPSCRIPT_LEAF_VERSION);
+            assert_eq!(&control_block[1..33], &internal_key.serialize()[..]);
+
+            // Recompute the merkle root from the control block as the verifier does.
+            let root = control_block[33..]
+                .chunks(32)
+                .map(|node| sha256::Hash::from_slice(node).unwrap())
+                .fold(leaf_hash(script), branch_hash);
+            assert_eq!(root, tree.merkle_root());
+
+            let mut engine = tagged_engine("TapTweak");
+            engine.input(&internal_key.serialize());
+            engine.input(&root[..]);
+            let tweak = sha256::Hash::from_engine(engine);
+            assert!(internal_key.tweak_add_check(
+                sign::verification_context(),
+                &output_key,
+                control_block[0] & 1 == 1,
+                tweak.into_inner(),
+            ));
+        }
+
+        let unknown = Builder::new().push_int(4).into_script();
+        assert_eq!(
+            control_block(&internal_key, &tree, &unknown),
+            Err(Error::UnknownTapLeaf)
+        );
+    }
+
+    #[test]
+    fn test_signature_hash_annex() {
+        let internal_key = public_key(&keypair(42));
+        let leaf = Builder::new().push_int(1).into_script();
+        let tree = TapTree::Leaf(leaf.clone());
+        let spent_output = TxOut {
+            value: 50_000,
+            script_pubkey: tree.script_pubkey(&internal_key).unwrap(),
+        };
+        let transaction = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::default(),
+                script_sig: Default::default(),
+                sequence: 0xFFFF_FFFF,
+                witness: Vec::default(),
+            }],
+            output: vec![],
+        };
+        let spent_outputs = [spent_output];
+
+        let hash = |sighash_type, leaf, annex| {
+            signature_hash(&transaction, 0, &spent_outputs, sighash_type, leaf, annex)
+        };
+        let key_path = hash(SIGHASH_DEFAULT, None, None).unwrap();
+        let with_annex = hash(SIGHASH_DEFAULT, None, Some(&[0x50, 1])).unwrap();
+        let script_path = hash(SIGHASH_DEFAULT, Some(&leaf), None).unwrap();
+        assert_ne!(key_path, with_annex);
+        assert_ne!(key_path, script_path);
+        assert_ne!(
+            with_annex,
+            hash(SIGHASH_DEFAULT, None, Some(&[0x50, 2])).unwrap()
+        );
+        assert_ne!(key_path, hash(0x01, None, None).unwrap());
+
+        assert_eq!(
+            hash(SIGHASH_DEFAULT, None, Some(&[1])),
+            Err(Error::InvalidAnnex)
+        );
+        assert_eq!(hash(0x04, None, None), Err(Error::InvalidSigHashType(0x04)));
+        assert_eq!(hash(0x03, None, None), Err(Error::NoSuchOutput(0)));
+        assert_eq!(
+            signature_hash(&transaction, 1, &spent_outputs, 0, None, None),
+            Err(Error::NoSuchInput(1))
+        );
+
+        // The annex is detected only after the other witness elements.
+        let mut witness = vec![vec![0x50; 64]];
+        assert_eq!(annex(&witness), None);
+        assert_eq!(
+            push_annex(&mut witness, vec![0x51]),
+            Err(Error::InvalidAnnex)
+        );
+        push_annex(&mut witness, vec![0x50, 1]).unwrap();
+        assert_eq!(annex(&witness), Some(&[0x50, 1][..]));
+        assert_eq!(hash(SIGHASH_DEFAULT, None, annex(&witness)), Ok(with_annex));
+    }
+
+    #[test]
+    fn test_signature_hash_reference() {
+        // The reference values are computed by the later `bitcoin` crate versions.
+        let transaction = Transaction {
+            version: 2,
+            lock_time: 7,
+            input: (0..2)
+                .map(|i| TxIn {
+                    previous_output: OutPoint::new(Txid::from_inner([i + 1; 32]), i.into()),
+                    script_sig: Default::default(),
+                    sequence: 0xFFFF_FFFD,
+                    witness: Vec::default(),
+                })
+                .collect(),
+            output: vec![
+                TxOut {
+                    value: 1000,
+                    script_pubkey: vec![0x51].into(),
+                },
+                TxOut {
+                    value: 2000,
+                    script_pubkey: vec![0x52, 0x53].into(),
+                },
+            ],
+        };
+        let spent_outputs = (0..2)
+            .map(|i| TxOut {
+                value: 5000 + u64::from(i),
+                script_pubkey: vec![0x51, 0x20, i].into(),
+            })
+            .collect::<Vec<_>>();
+        let leaf = vec![0x51].into();
+        let annex = [0x50, 9, 9];
+
+        let cases = [
+            (
+                SIGHASH_DEFAULT,
+                None,
+                None,
+                "ab6ed623c672acc892e4c3bbfd63e1939bd7f745677d51ceb7d99abab58298ae",
+            ),
+            (
+                SIGHASH_DEFAULT,
+                Some(&leaf),
+                Some(&annex[..]),
+                "ee968ce0b61a28683f8440701cd3d4e7610e697bb6dfd51bfb9fa8641fa05b45",
+            ),
+            (
+                0x03,
+                None,
+                None,
+                "1bcc821fdaef29b9efadbd3043a08a48c63dbbd19460c443f61d72c475b72adc",
+            ),
+            (
+                0x83,
+                Some(&leaf),
+                Some(&annex[..]),
+                "cda2fa2b8a3c49617faac72eedbf352b380251f1e67082f5f7df5abba92419be",
+            ),
+        ];
+        for (sighash_type, leaf, annex, expected) in cases.iter() {
+            let hash = signature_hash(
+                &transaction,
+                1,
+                &spent_outputs,
+                *sighash_type,
+                *leaf,
+                *annex,
+            )
+            .unwrap();
+            assert_eq!(hex::encode(&hash[..]), *expected);
+        }
+    }
+
+    #[test]
+    fn test_x_only_public_key() {
+        let (public_key, _) =
+            keypair_from_wif("cPHmynxvqfr7sXsJcohiGzoPGBShggxL6VWUdW14skohFZ1LQoeV");
+        let x_only = XOnlyPublicKey::from(public_key);
+        assert_eq!(&x_only.serialize()[..], &public_key.key.serialize()[1..]);
+
+        let mut negated = public_key;
+        negated.key.negate_assign(&sign::shared_context());
+        assert_ne!(negated, public_key);
+        assert_eq!(XOnlyPublicKey::from(negated), x_only);
+
+        let hex = x_only.to_string();
+        assert_eq!(hex.parse::<XOnlyPublicKey>().unwrap(), x_only);
+        let json = serde_json::to_string(&x_only).unwrap();
+        assert_eq!(json, format!("\"{}\"", hex));
+        assert_eq!(
+            serde_json::from_str::<XOnlyPublicKey>(&json).unwrap(),
+            x_only
+        );
+        assert!(XOnlyPublicKey::from_slice(&[0; 32]).is_err());
+
+        let multisig = TapscriptMultisigBuilder::with_quorum(1)
+            .public_key(public_key)
+            .to_script()
+            .unwrap();
+        assert_eq!(multisig.public_keys(), &[x_only.into()]);
+    }
+}
@@ -0,0 +1,193 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Templates of the witness scripts for the common contracts, which are locked
+//! in the `P2WSH` outputs.
+
+pub mod htlc;
+pub mod lightning;
+pub mod recovery;
+pub mod tiered;
+pub mod timelock;
+pub mod weighted;
+
+use bitcoin::{
+    blockdata::{script::Script, transaction::Transaction},
+    PublicKey,
+};
+use secp256k1::SecretKey;
+
+use crate::{
+    sign, Error, ExtraEntropy, InputSignature, LockTime, Sequence, TxInRef, UnspentTxOutValue,
+};
+
+pub use crate::locktime::LOCKTIME_THRESHOLD;
+
+/// Adjusts the transaction lock time and the sequence of the input with the given index,
+/// so the input can spend an output locked by `OP_CHECKLOCKTIMEVERIFY` until the given
+/// lock time.
+///
+/// The lock time of the transaction is only increased, and it must be of the same kind
+/// (block height or timestamp) as the given one.
+pub fn set_lock_time(
+    transaction: &mut Transaction,
+    index: usize,
+    lock_time: LockTime,
+) -> Result<(), Error> {
+    ensure!(index < transaction.input.len(), Error::NoSuchInput(index));
+    let current = LockTime::from_consensus(transaction.lock_time);
+    ensure!(
+        current == LockTime::ZERO || current.is_same_unit(lock_time),
+        Error::LockTimeConflict
+    );
+
+    if !lock_time.is_implied_by(current) {
+        transaction.lock_time = lock_time.to_consensus_u32();
+    }
+    // The lock time is ignored if the sequence is final, and `OP_CHECKLOCKTIMEVERIFY`
+    // fails even for the zero lock time.
+    let input = &mut transaction.input[index];
+    if Sequence(input.sequence).is_final() {
+        input.sequence = Sequence::ENABLE_LOCK_TIME_NO_RBF.0;
+    }
+    Ok(())
+}
+
+/// Sets the sequence of the input with the given index to the given relative lock time
+/// in blocks, so the input can spend an output locked by `OP_CHECKSEQUENCEVERIFY`.
+///
+/// The relative lock times are enforced only for the transactions of version 2 or higher,
+/// so the transaction version is increased if needed.
+pub fn set_relative_lock_time(
+    transaction: &mut Transaction,
+    index: usize,
+    blocks: u16,
+) -> Result<(), Error> {
+    ensure!(index < transaction.input.len(), Error::NoSuchInput(index));
+    transaction.version = transaction.version.max(2);
+    transaction.input[index].sequence = Sequence::from_height(blocks).0;
+    Ok(())
+}
+
+/// Computes the [`BIP-143`][bip-143] compliant signature for the input, which spends
+/// the `P2WSH` output with the given witness script.
+///
+/// The public key of the given secret key must be one of the given keys, which can spend
+/// the output, otherwise the signature would be useless, so the function returns an error
+/// instead.
+///
+/// [bip-143]: https://github.com/bitcoin/bips/blob/master/bip-0143.mediawiki
+pub(crate) fn sign_input<'a, 'b, V: Into<UnspentTxOutValue<'b>>>(
+    script: &Script,
+    public_keys: &[&PublicKey],
+    txin: TxInRef<'a>,
+    value: V,
+    secret_key: &SecretKey,
+) -> Result<InputSignature, Error> {
+    let context = sign::shared_context();
+    let public_key = secp256k1::PublicKey::from_secret_key(&context, secret_key);
+    ensure!(
+        public_keys.iter().any(|key| key.key == public_key),
+        Error::KeyNotInScript
+    );
+    let value = value.into();
+    if let Some(prev_script_pubkey) = value.script_pubkey(txin) {
+        ensure!(
+            *prev_script_pubkey == script.to_v0_p2wsh(),
+            Error::ScriptMismatch
+        );
+    }
+    sign::sign_input(
+        &context,
+        txin,
+        script,
+        value,
+        secret_key,
+        ExtraEntropy::None,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::blockdata::transaction::{Transaction, TxIn};
+
+    use super::{set_lock_time, set_relative_lock_time};
+    use crate::{Error, LockTime, Sequence};
+
+    fn transaction(lock_time: u32, sequences: &[u32]) -> Transaction {
+        Transaction {
+            version: 1,
+            lock_time,
+            input: sequences
+                .iter()
+                .map(|&sequence| TxIn {
+                    sequence,
+                    ..TxIn::default()
+                })
+                .collect(),
+            output: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_set_lock_time() {
+        let mut tx = transaction(0, &[Sequence::MAX.0, Sequence::ENABLE_RBF_NO_LOCK_TIME.0]);
+        set_lock_time(&mut tx, 0, LockTime::Height(500)).unwrap();
+        assert_eq!(tx.lock_time, 500);
+        assert_eq!(tx.input[0].sequence, Sequence::ENABLE_LOCK_TIME_NO_RBF.0);
+
+        // The lock time is only increased, and the non-final sequence is kept.
+        set_lock_time(&mut tx, 1, LockTime::Height(400)).unwrap();
+        assert_eq!(tx.lock_time, 500);
+        assert_eq!(tx.input[1].sequence, Sequence::ENABLE_RBF_NO_LOCK_TIME.0);
+        set_lock_time(&mut tx, 1, LockTime::Height(600)).unwrap();
+        assert_eq!(tx.lock_time, 600);
+
+        let timestamp = LockTime::from_time(1_600_000_000).unwrap();
+        assert_eq!(
+            set_lock_time(&mut tx, 0, timestamp),
+            Err(Error::LockTimeConflict)
+        );
+        assert_eq!(
+            set_lock_time(&mut tx, 2, LockTime::Height(600)),
+            Err(Error::NoSuchInput(2))
+        );
+        assert_eq!(tx.lock_time, 600);
+
+        // The input becomes non-final even for the zero lock time.
+        let mut tx = transaction(0, &[Sequence::MAX.0]);
+        set_lock_time(&mut tx, 0, LockTime::ZERO).unwrap();
+        assert_eq!(tx.lock_time, 0);
+        assert_eq!(tx.input[0].sequence, Sequence::ENABLE_LOCK_TIME_NO_RBF.0);
+    }
+
+    #[test]
+    fn test_set_relative_lock_time() {
+        let mut tx = transaction(0, &[Sequence::MAX.0, Sequence::MAX.0]);
+        set_relative_lock_time(&mut tx, 1, 144).unwrap();
+        assert_eq!(tx.version, 2);
+        assert_eq!(tx.input[0].sequence, Sequence::MAX.0);
+        assert_eq!(tx.input[1].sequence, Sequence::from_height(144).0);
+
+        // The higher transaction version is kept.
+        tx.version = 3;
+        set_relative_lock_time(&mut tx, 0, 10).unwrap();
+        assert_eq!(tx.version, 3);
+        assert_eq!(tx.input[0].sequence, 10);
+        assert_eq!(
+            set_relative_lock_time(&mut tx, 2, 10),
+            Err(Error::NoSuchInput(2))
+        );
+    }
+}
@@ -0,0 +1,300 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A hash time locked contract.
+//!
+//! The output can be spent either by the recipient, who reveals the preimage of the payment
+//! hash, or by the refund key after the given absolute lock time:
+//!
+//! ```text
+//! OP_IF
+//!     OP_SIZE 32 OP_EQUALVERIFY OP_SHA256 <payment_hash> OP_EQUALVERIFY <recipient>
+//! OP_ELSE
+//!     <lock_time> OP_CHECKLOCKTIMEVERIFY OP_DROP <refund>
+//! OP_ENDIF
+//! OP_CHECKSIG
+//! ```
+
+use bitcoin::{
+    blockdata::{
+        opcodes::all::{
+            OP_CHECKSIG, OP_CLTV, OP_DROP, OP_ELSE, OP_ENDIF, OP_EQUALVERIFY, OP_IF, OP_SHA256,
+            OP_SIZE,
+        },
+        script::{Builder, Script},
+    },
+    network::constants::Network,
+    util::address::Address,
+    PublicKey,
+};
+use bitcoin_hashes::{sha256, Hash};
+use secp256k1::SecretKey;
+
+use crate::{Error, InputSignature, LockTime, TxInRef, UnspentTxOutValue};
+
+/// The size of the payment preimage in bytes.
+pub const PREIMAGE_SIZE: usize = 32;
+
+/// The hash time locked contract.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Htlc {
+    /// The SHA-256 hash of the payment preimage.
+    pub payment_hash: sha256::Hash,
+    /// The public key of the recipient, who can spend the output with the preimage.
+    pub recipient: PublicKey,
+    /// The public key, which can spend the output after the lock time.
+    pub refund: PublicKey,
+    /// The absolute lock time as a block height or a unix timestamp, after which
+    /// the output can be refunded.
+    pub lock_time: LockTime,
+}
+
+impl Htlc {
+    /// Returns the witness script of the contract.
+    pub fn to_script(&self) -> Script {
+        Builder::new()
+            .push_opcode(OP_IF)
+            .push_opcode(OP_SIZE)
+            .push_int(PREIMAGE_SIZE as i64)
+            .push_opcode(OP_EQUALVERIFY)
+            .push_opcode(OP_SHA256)
+            .push_slice(&self.payment_hash[..])
+            .push_opcode(OP_EQUALVERIFY)
+            .push_key(&self.recipient)
+            .push_opcode(OP_ELSE)
+            .push_int(i64::from(self.lock_time.to_consensus_u32()))
+            .push_opcode(OP_CLTV)
+            .push_opcode(OP_DROP)
+            .push_key(&self.refund)
+            .push_opcode(OP_ENDIF)
+            .push_opcode(OP_CHECKSIG)
+            .into_script()
+    }
+
+    /// Returns the `P2WSH` script pubkey of the contract.
+    pub fn script_pubkey(&self) -> Script {
+        self.to_script().to_v0_p2wsh()
+    }
+
+    /// Returns the `P2WSH` address of the contract for the given network.
+    pub fn address(&self, network: Network) -> Address {
+        Address::p2wsh(&self.to_script(), network)
+    }
+
+    /// Computes the [`BIP-143`][bip-143] compliant signature for the given input, which
+    /// spends the contract output. The secret key must belong either to the recipient
+    /// or to the refund key.
+    ///
+    /// To spend the output via the refund path, the transaction lock time must be not less
+    /// than the contract lock time, and the input sequence must not be final, see
+    /// [`set_lock_time`](../fn.set_lock_time.html).
+    ///
+    /// [bip-143]: https://github.com/bitcoin/bips/blob/master/bip-0143.mediawiki
+    pub fn sign_input<'a, 'b, V: Into<UnspentTxOutValue<'b>>>(
+        &self,
+        txin: TxInRef<'a>,
+        value: V,
+        secret_key: &SecretKey,
+    ) -> Result<InputSignature, Error> {
+        super::sign_input(
+            &self.to_script(),
+            &[&self.recipient, &self.refund],
+            txin,
+            value,
+            secret_key,
+        )
+    }
+
+    /// Creates the witness stack for the recipient spending path.
+    ///
+    /// Returns an error if the preimage does not match the payment hash, since the
+    /// witness would be rejected by the network.
+    pub fn redeem_witness(
+        &self,
+        signature: InputSignature,
+        preimage: &[u8],
+    ) -> Result<Vec<Vec<u8>>, Error> {
+        ensure!(
+            preimage.len() == PREIMAGE_SIZE && sha256::Hash::hash(preimage) == self.payment_hash,
+            Error::PreimageMismatch
+        );
+        Ok(vec![
+            signature.into(),
+            preimage.to_vec(),
+            vec![1],
+            self.to_script().to_bytes(),
+        ])
+    }
+
+    /// Creates the witness stack for the refund spending path.
+    pub fn refund_witness(&self, signature: InputSignature) -> Vec<Vec<u8>> {
+        vec![signature.into(), Vec::new(), self.to_script().to_bytes()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{
+        blockdata::transaction::{OutPoint, Transaction, TxIn, TxOut},
+        network::constants::Network,
+        Script,
+    };
+    use bitcoin_hashes::{sha256, Hash};
+
+    use super::Htlc;
+    use crate::{
+        script::script_to_asm,
+        sign,
+        test_data::{fake_funding_tx, keypair_from_wif},
+        Error, LockTime, TxInRef,
+    };
+
+    fn spending_tx(prev_tx: &Transaction, lock_time: u32) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(prev_tx.txid(), 0),
+                script_sig: Script::default(),
+                sequence: 0xFFFF_FFFE,
+                witness: Vec::default(),
+            }],
+            output: vec![TxOut {
+                value: 9_000,
+                script_pubkey: Script::default(),
+            }],
+        }
+    }
+
+    #[test]
+    fn test_htlc_spend_paths() {
+        let (recipient, recipient_sk) =
+            keypair_from_wif("cPHmynxvqfr7sXsJcohiGzoPGBShggxL6VWUdW14skohFZ1LQoeV");
+        let (refund, refund_sk) =
+            keypair_from_wif("cTtSTL1stvg2tmK349WTmQDfHLMLqkkxwuo8ZJeQov9zEhtYtb4u");
+        let (_, other_sk) =
+            keypair_from_wif("cQZZ7WvJUb6hXxCq9SF6516vb9bavQRPn2t3g9LFUtAoZuY7vNFk");
+        let preimage = [7_u8; 32];
+        let htlc = Htlc {
+            payment_hash: sha256::Hash::hash(&preimage),
+            recipient,
+            refund,
+            lock_time: LockTime::Height(1_000),
+        };
+        let prev_tx = fake_funding_tx(&htlc.script_pubkey(), 10_000);
+
+        // Recipient path.
+        let transaction = spending_tx(&prev_tx, 0);
+        let txin = TxInRef::new(&transaction, 0);
+        let signature = htlc.sign_input(txin, &prev_tx, &recipient_sk.key).unwrap();
+        sign::verify_input_signature(
+            sign::verification_context(),
+            txin,
+            &htlc.to_script(),
+            &prev_tx,
+            &recipient,
+            &signature,
+        )
+        .unwrap();
+        let witness = htlc.redeem_witness(signature.clone(), &preimage).unwrap();
+        assert_eq!(witness.len(), 4);
+        assert_eq!(witness[1], preimage.to_vec());
+        assert_eq!(witness[2], vec![1]);
+        assert_eq!(witness[3], htlc.to_script().to_bytes());
+        assert_eq!(
+            htlc.redeem_witness(signature, &[8; 32]),
+            Err(Error::PreimageMismatch)
+        );
+
+        // Refund path.
+        let transaction = spending_tx(&prev_tx, 1_000);
+        let txin = TxInRef::new(&transaction, 0);
+        let signature = htlc.sign_input(txin, &prev_tx, &refund_sk.key).unwrap();
+        let witness = htlc.refund_witness(signature.clone());
+        assert_eq!(
+            witness,
+            vec![signature.into(), Vec::new(), htlc.to_script().to_bytes()]
+        );
+
+        assert_eq!(
+            htlc.sign_input(txin, &prev_tx, &other_sk.key),
+            Err(Error::KeyNotInScript)
+        );
+        let other_tx = fake_funding_tx(&Script::default(), 10_000);
+        assert_eq!(
+            htlc.sign_input(txin, &other_tx.output[0], &refund_sk.key),
+            Err(Error::ScriptMismatch)
+        );
+    }
+
+    #[test]
+    fn test_htlc_script() {
+        let (recipient, _) =
+            keypair_from_wif("cPHmynxvqfr7sXsJcohiGzoPGBShggxL6VWUdW14skohFZ1LQoeV");
+        let (refund, refund_sk) =
+            keypair_from_wif("cTtSTL1stvg2tmK349WTmQDfHLMLqkkxwuo8ZJeQov9zEhtYtb4u");
+        let payment_hash = sha256::Hash::hash(&[7_u8; 32]);
+        let htlc = Htlc {
+            payment_hash,
+            recipient,
+            refund,
+            lock_time: LockTime::Height(1_000),
+        };
+        assert_eq!(
+            script_to_asm(&htlc.to_script()),
+            format!(
+                "OP_IF OP_SIZE 20 OP_EQUALVERIFY OP_SHA256 {} OP_EQUALVERIFY {} \
+                 OP_ELSE e803 OP_CLTV OP_DROP {} OP_ENDIF OP_CHECKSIG",
+                hex::encode(&payment_hash[..]),
+                recipient,
+                refund
+            )
+        );
+        assert_eq!(
+            htlc.address(Network::Testnet).script_pubkey(),
+            htlc.script_pubkey()
+        );
+        assert!(htlc.script_pubkey().is_v0_p2wsh());
+
+        // The timestamp is pushed as the little-endian number.
+        let timestamp_htlc = Htlc {
+            lock_time: LockTime::from_time(1_600_000_000).unwrap(),
+            ..htlc.clone()
+        };
+        assert!(script_to_asm(&timestamp_htlc.to_script()).contains(" 00105e5f OP_CLTV "));
+        assert_ne!(timestamp_htlc.script_pubkey(), htlc.script_pubkey());
+
+        // The signature of the refund key does not match the recipient key.
+        let prev_tx = fake_funding_tx(&htlc.script_pubkey(), 10_000);
+        let transaction = spending_tx(&prev_tx, 1_000);
+        let txin = TxInRef::new(&transaction, 0);
+        let signature = htlc.sign_input(txin, &prev_tx, &refund_sk.key).unwrap();
+        assert_eq!(
+            sign::verify_input_signature(
+                sign::verification_context(),
+                txin,
+                &htlc.to_script(),
+                &prev_tx,
+                &recipient,
+                &signature,
+            ),
+            Err(secp256k1::Error::IncorrectSignature.into())
+        );
+        // The preimage must be exactly 32 bytes long.
+        assert_eq!(
+            htlc.redeem_witness(signature, &[7; 33]),
+            Err(Error::PreimageMismatch)
+        );
+    }
+}
@@ -0,0 +1,486 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The outputs of the Lightning commitment transactions with the anchor outputs,
+//! as defined by [`BOLT #3`][bolt-3].
+//!
+//! The `to_remote` output is spent by the remote key after one block:
+//!
+//! ```text
+//! <remote_key> OP_CHECKSIGVERIFY 1 OP_CHECKSEQUENCEVERIFY
+//! ```
+//!
+//! The anchor output is spent either by the funding key at any time, or by anyone after
+//! 16 blocks:
+//!
+//! ```text
+//! <funding_key> OP_CHECKSIG OP_IFDUP
+//! OP_NOTIF
+//!     16 OP_CHECKSEQUENCEVERIFY
+//! OP_ENDIF
+//! ```
+//!
+//! The [`sweep_transaction`][sweep] function moves such outputs to the multisig address.
+//!
+//! [bolt-3]: https://github.com/lightning/bolts/blob/master/03-transactions.md
+//! [sweep]: fn.sweep_transaction.html
+
+use bitcoin::{
+    blockdata::{
+        opcodes::all::{OP_CHECKSIG, OP_CHECKSIGVERIFY, OP_CSV, OP_ENDIF, OP_IFDUP, OP_NOTIF},
+        script::{Builder, Script},
+        transaction::{OutPoint, Transaction, TxIn, TxOut},
+    },
+    network::constants::Network,
+    util::address::Address,
+    PublicKey,
+};
+use secp256k1::SecretKey;
+
+use super::set_relative_lock_time;
+use crate::{
+    builder::{signed_vsize, DummyInput},
+    multisig::RedeemScript,
+    p2wsh, policy, Error, FeeRate, InputSignature, Sequence, TxInRef, UnspentTxOutValue,
+};
+
+/// The value of the anchor output in satoshis.
+pub const ANCHOR_VALUE: u64 = 330;
+/// The number of blocks after which anyone can spend the anchor output.
+pub const ANCHOR_DELAY: u16 = 16;
+/// The number of blocks after which the remote key can spend the `to_remote` output.
+pub const TO_REMOTE_DELAY: u16 = 1;
+
+/// The `to_remote` output of the commitment transaction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToRemote {
+    /// The public key, which can spend the output.
+    pub public_key: PublicKey,
+}
+
+impl ToRemote {
+    /// Returns the witness script of the output.
+    pub fn to_script(&self) -> Script {
+        Builder::new()
+            .push_key(&self.public_key)
+            .push_opcode(OP_CHECKSIGVERIFY)
+            .push_int(i64::from(TO_REMOTE_DELAY))
+            .push_opcode(OP_CSV)
+            .into_script()
+    }
+
+    /// Returns the `P2WSH` script pubkey of the output.
+    pub fn script_pubkey(&self) -> Script {
+        self.to_script().to_v0_p2wsh()
+    }
+
+    /// Returns the `P2WSH` address of the output for the given network.
+    pub fn address(&self, network: Network) -> Address {
+        Address::p2wsh(&self.to_script(), network)
+    }
+
+    /// Signs the input with the given index and puts the witness into it. Thus, the input
+    /// becomes spent.
+    ///
+    /// Before signing, the input sequence is set to the delay of one block. Changing
+    /// the sequence invalidates the signatures of other inputs, so this input should be
+    /// signed first.
+    pub fn spend_input<'a, V: Into<UnspentTxOutValue<'a>>>(
+        &self,
+        transaction: &mut Transaction,
+        index: usize,
+        value: V,
+        secret_key: &SecretKey,
+    ) -> Result<(), Error> {
+        set_relative_lock_time(transaction, index, TO_REMOTE_DELAY)?;
+
+        let script = self.to_script();
+        let txin = TxInRef::new(transaction, index);
+        let signature = super::sign_input(&script, &[&self.public_key], txin, value, secret_key)?;
+        transaction.input[index].witness = vec![signature.into(), script.to_bytes()];
+        Ok(())
+    }
+}
+
+/// The anchor output of the commitment transaction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Anchor {
+    /// The funding public key of the channel party, which can spend the output at any time.
+    pub funding_key: PublicKey,
+}
+
+impl Anchor {
+    /// Returns the witness script of the output.
+    pub fn to_script(&self) -> Script {
+        Builder::new()
+            .push_key(&self.funding_key)
+            .push_opcode(OP_CHECKSIG)
+            .push_opcode(OP_IFDUP)
+            .push_opcode(OP_NOTIF)
+            .push_int(i64::from(ANCHOR_DELAY))
+            .push_opcode(OP_CSV)
+            .push_opcode(OP_ENDIF)
+            .into_script()
+    }
+
+    /// Returns the `P2WSH` script pubkey of the output.
+    pub fn script_pubkey(&self) -> Script {
+        self.to_script().to_v0_p2wsh()
+    }
+
+    /// Returns the `P2WSH` address of the output for the given network.
+    pub fn address(&self, network: Network) -> Address {
+        Address::p2wsh(&self.to_script(), network)
+    }
+
+    /// Signs the input with the given index by the funding key and puts the witness into it.
+    /// Thus, the input becomes spent.
+    pub fn spend_input<'a, V: Into<UnspentTxOutValue<'a>>>(
+        &self,
+        transaction: &mut Transaction,
+        index: usize,
+        value: V,
+        secret_key: &SecretKey,
+    ) -> Result<(), Error> {
+        let script = self.to_script();
+        let txin = TxInRef::checked_new(transaction, index).ok_or(Error::NoSuchInput(index))?;
+        let signature = super::sign_input(&script, &[&self.funding_key], txin, value, secret_key)?;
+        transaction.input[index].witness = vec![signature.into(), script.to_bytes()];
+        Ok(())
+    }
+
+    /// Puts the witness without the signature into the input with the given index, so anyone
+    /// can spend the output after the delay. The input sequence is set to the delay.
+    pub fn spend_input_after_delay(
+        &self,
+        transaction: &mut Transaction,
+        index: usize,
+    ) -> Result<(), Error> {
+        set_relative_lock_time(transaction, index, ANCHOR_DELAY)?;
+        transaction.input[index].witness = vec![Vec::new(), self.to_script().to_bytes()];
+        Ok(())
+    }
+}
+
+/// The Lightning output to be swept along with its spending path.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LightningOutput {
+    /// The `to_remote` output spent by the remote key.
+    ToRemote(ToRemote),
+    /// The anchor output spent by the funding key.
+    Anchor(Anchor),
+    /// The anchor output spent by anyone after the delay.
+    ExpiredAnchor(Anchor),
+}
+
+impl LightningOutput {
+    /// Returns the `P2WSH` script pubkey of the output.
+    pub fn script_pubkey(&self) -> Script {
+        match self {
+            LightningOutput::ToRemote(to_remote) => to_remote.script_pubkey(),
+            LightningOutput::Anchor(anchor) | LightningOutput::ExpiredAnchor(anchor) => {
+                anchor.script_pubkey()
+            }
+        }
+    }
+
+    /// Returns the relative lock time in blocks required by the spending path, if any.
+    pub fn delay(&self) -> Option<u16> {
+        match self {
+            LightningOutput::ToRemote(_) => Some(TO_REMOTE_DELAY),
+            LightningOutput::Anchor(_) => None,
+            LightningOutput::ExpiredAnchor(_) => Some(ANCHOR_DELAY),
+        }
+    }
+
+    /// Returns the spending conditions of the output for the fee estimation.
+    pub fn dummy_input(&self) -> DummyInput {
+        let (signature_size, script) = match self {
+            LightningOutput::ToRemote(to_remote) => {
+                (InputSignature::MAX_LEN, to_remote.to_script())
+            }
+            LightningOutput::Anchor(anchor) => (InputSignature::MAX_LEN, anchor.to_script()),
+            LightningOutput::ExpiredAnchor(anchor) => (0, anchor.to_script()),
+        };
+        DummyInput::Witness(vec![signature_size, script.len()])
+    }
+}
+
+/// Creates the unsigned transaction, which sweeps the given Lightning outputs to the address
+/// of the given redeem script. The input sequences are set as required by the spending paths,
+/// and the fee for the given fee rate is deducted from the swept balance.
+///
+/// The inputs should be signed by the [`ToRemote`][to-remote] and [`Anchor`][anchor]
+/// helpers in the given order; the `to_remote` ones are better signed first.
+///
+/// [to-remote]: struct.ToRemote.html
+/// [anchor]: struct.Anchor.html
+pub fn sweep_transaction<I>(
+    outputs: I,
+    redeem_script: &RedeemScript,
+    fee_rate: FeeRate,
+) -> Result<Transaction, Error>
+where
+    I: IntoIterator<Item = (OutPoint, TxOut, LightningOutput)>,
+{
+    let mut transaction = Transaction {
+        version: 2,
+        lock_time: 0,
+        input: Vec::new(),
+        output: vec![TxOut {
+            value: 0,
+            script_pubkey: p2wsh::script_pubkey(redeem_script),
+        }],
+    };
+    let mut dummy_inputs = Vec::new();
+    let mut available = 0_u64;
+    for (outpoint, output, spent) in outputs {
+        ensure!(
+            output.script_pubkey == spent.script_pubkey(),
+            Error::ScriptMismatch
+        );
+        available = available
+            .checked_add(output.value)
+            .ok_or(Error::ValueOverflow)?;
+        transaction.input.push(TxIn {
+            previous_output: outpoint,
+            script_sig: Script::default(),
+            sequence: Sequence::MAX.0,
+            witness: Vec::default(),
+        });
+        if let Some(delay) = spent.delay() {
+            set_relative_lock_time(&mut transaction, dummy_inputs.len(), delay)?;
+        }
+        dummy_inputs.push(spent.dummy_input());
+    }
+    ensure!(!transaction.input.is_empty(), Error::NoInputs);
+
+    let vsize = signed_vsize(&transaction, &dummy_inputs)?;
+    let fee = fee_rate.fee_vb(vsize).ok_or(Error::ValueOverflow)?;
+    let dust = policy::dust_threshold(
+        &transaction.output[0].script_pubkey,
+        policy::DUST_RELAY_FEE_RATE,
+    );
+    let required = fee.checked_add(dust).ok_or(Error::ValueOverflow)?;
+    ensure!(
+        available >= required,
+        Error::InsufficientFunds {
+            available,
+            required,
+        }
+    );
+    transaction.output[0].value = available - fee;
+    Ok(transaction)
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{
+        blockdata::transaction::{OutPoint, TxOut},
+        network::constants::Network,
+    };
+
+    use super::{sweep_transaction, Anchor, LightningOutput, ToRemote, ANCHOR_VALUE};
+    use crate::{
+        builder::DummyInput,
+        locktime::check_sequence,
+        multisig::RedeemScriptBuilder,
+        script::script_to_asm,
+        sign,
+        test_data::{deterministic_keypair, fake_funding_tx},
+        Error, FeeRate, InputSignatureRef, Sequence, TxInRef,
+    };
+
+    #[test]
+    fn test_sweep_lightning_outputs() {
+        let (remote_key, remote_sk) = deterministic_keypair(Network::Testnet);
+        let (funding_key, funding_sk) = deterministic_keypair(Network::Testnet);
+        let to_remote = ToRemote {
+            public_key: remote_key,
+        };
+        let anchor = Anchor { funding_key };
+        assert_eq!(
+            script_to_asm(&to_remote.to_script()),
+            format!("{} OP_CHECKSIGVERIFY OP_PUSHNUM_1 OP_CSV", remote_key)
+        );
+        assert_eq!(
+            script_to_asm(&anchor.to_script()),
+            format!(
+                "{} OP_CHECKSIG OP_IFDUP OP_NOTIF OP_PUSHNUM_16 OP_CSV OP_ENDIF",
+                funding_key
+            )
+        );
+
+        let redeem_script = RedeemScriptBuilder::with_public_keys(
+            (0..3).map(|_| deterministic_keypair(Network::Testnet).0),
+        )
+        .quorum(2)
+        .to_script()
+        .unwrap();
+        let funding_txs = [
+            fake_funding_tx(&to_remote.script_pubkey(), 50_000),
+            fake_funding_tx(&anchor.script_pubkey(), ANCHOR_VALUE),
+            fake_funding_tx(&anchor.script_pubkey(), ANCHOR_VALUE + 1),
+        ];
+        let spent = [
+            LightningOutput::ToRemote(to_remote.clone()),
+            LightningOutput::Anchor(anchor.clone()),
+            LightningOutput::ExpiredAnchor(anchor.clone()),
+        ];
+        let outputs = funding_txs
+            .iter()
+            .zip(spent.iter())
+            .map(|(tx, spent)| {
+                (
+                    OutPoint::new(tx.txid(), 0),
+                    tx.output[0].clone(),
+                    spent.clone(),
+                )
+            })
+            .collect::<Vec<_>>();
+        let fee_rate = FeeRate::from_sat_per_vb(1).unwrap();
+        let mut transaction = sweep_transaction(outputs.clone(), &redeem_script, fee_rate).unwrap();
+        assert_eq!(transaction.input[0].sequence, Sequence::from_height(1).0);
+        assert_eq!(transaction.input[1].sequence, Sequence::MAX.0);
+        assert_eq!(transaction.input[2].sequence, Sequence::from_height(16).0);
+
+        to_remote
+            .spend_input(&mut transaction, 0, &funding_txs[0], &remote_sk.key)
+            .unwrap();
+        anchor
+            .spend_input(&mut transaction, 1, &funding_txs[1], &funding_sk.key)
+            .unwrap();
+        anchor.spend_input_after_delay(&mut transaction, 2).unwrap();
+        let signers = [
+            (remote_key, to_remote.to_script()),
+            (funding_key, anchor.to_script()),
+        ];
+        for (index, (public_key, script)) in signers.iter().enumerate() {
+            let witness = &transaction.input[index].witness;
+            assert_eq!(witness[1], script.to_bytes());
+            let signature = InputSignatureRef::from_bytes(&witness[0]).unwrap();
+            sign::verify_input_signature(
+                sign::verification_context(),
+                TxInRef::new(&transaction, index),
+                script,
+                &funding_txs[index],
+                public_key,
+                signature,
+            )
+            .unwrap();
+        }
+        assert!(transaction.input[2].witness[0].is_empty());
+        // The fee is not less than required.
+        let fee = 50_000 + 2 * ANCHOR_VALUE + 1 - transaction.output[0].value;
+        assert!(fee >= fee_rate.fee_wu(transaction.get_weight() as u64).unwrap());
+
+        // The anchor output cannot be spent by the other key.
+        assert_eq!(
+            anchor.spend_input(&mut transaction, 1, &funding_txs[1], &remote_sk.key),
+            Err(Error::KeyNotInScript)
+        );
+        let mut wrong = outputs;
+        wrong[0].1 = TxOut {
+            value: 50_000,
+            script_pubkey: anchor.script_pubkey(),
+        };
+        assert_eq!(
+            sweep_transaction(wrong, &redeem_script, fee_rate),
+            Err(Error::ScriptMismatch)
+        );
+    }
+
+    #[test]
+    fn test_sweep_lightning_outputs_errors() {
+        let to_remote = ToRemote {
+            public_key: deterministic_keypair(Network::Testnet).0,
+        };
+        let anchor = Anchor {
+            funding_key: deterministic_keypair(Network::Testnet).0,
+        };
+        let redeem_script =
+            RedeemScriptBuilder::with_public_keys(vec![deterministic_keypair(Network::Testnet).0])
+                .to_script()
+                .unwrap();
+        let fee_rate = FeeRate::from_sat_per_vb(1).unwrap();
+        let output = |value, spent: &LightningOutput| {
+            (
+                OutPoint::default(),
+                TxOut {
+                    value,
+                    script_pubkey: spent.script_pubkey(),
+                },
+                spent.clone(),
+            )
+        };
+        let expired = LightningOutput::ExpiredAnchor(anchor.clone());
+        let to_remote_output = LightningOutput::ToRemote(to_remote.clone());
+
+        assert_eq!(
+            sweep_transaction(Vec::new(), &redeem_script, fee_rate),
+            Err(Error::NoInputs)
+        );
+        // The single anchor does not cover the fee and the dust threshold.
+        assert!(matches!(
+            sweep_transaction(
+                vec![output(ANCHOR_VALUE, &expired)],
+                &redeem_script,
+                fee_rate
+            ),
+            Err(Error::InsufficientFunds {
+                available: ANCHOR_VALUE,
+                ..
+            })
+        ));
+        assert_eq!(
+            sweep_transaction(
+                vec![
+                    output(u64::MAX, &to_remote_output),
+                    output(ANCHOR_VALUE, &expired)
+                ],
+                &redeem_script,
+                fee_rate
+            ),
+            Err(Error::ValueOverflow)
+        );
+
+        // The input sequences satisfy the delays of the spent scripts.
+        let transaction = sweep_transaction(
+            vec![
+                output(50_000, &to_remote_output),
+                output(ANCHOR_VALUE, &expired),
+            ],
+            &redeem_script,
+            fee_rate,
+        )
+        .unwrap();
+        check_sequence(&transaction, 0, &to_remote.to_script()).unwrap();
+        check_sequence(&transaction, 1, &anchor.to_script()).unwrap();
+        assert_eq!(
+            expired.dummy_input(),
+            DummyInput::Witness(vec![0, anchor.to_script().len()])
+        );
+
+        let mut transaction = transaction;
+        let (_, funding_sk) = deterministic_keypair(Network::Testnet);
+        assert_eq!(
+            anchor.spend_input(&mut transaction, 2, ANCHOR_VALUE, &funding_sk.key),
+            Err(Error::NoSuchInput(2))
+        );
+        assert_eq!(
+            anchor.spend_input_after_delay(&mut transaction, 2),
+            Err(Error::NoSuchInput(2))
+        );
+    }
+}
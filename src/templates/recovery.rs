@@ -0,0 +1,295 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A multisig output with the single key recovery path after a relative delay:
+//!
+//! ```text
+//! OP_IF
+//!     <k> <pk1> ... <pkn> <n> OP_CHECKMULTISIG
+//! OP_ELSE
+//!     <delay> OP_CHECKSEQUENCEVERIFY OP_DROP <recovery_key> OP_CHECKSIG
+//! OP_ENDIF
+//! ```
+
+use bitcoin::{
+    blockdata::{
+        opcodes::all::{OP_CHECKSIG, OP_CSV, OP_DROP, OP_ELSE, OP_ENDIF, OP_IF},
+        script::{Builder, Script},
+    },
+    network::constants::Network,
+    util::address::Address,
+    PublicKey,
+};
+use secp256k1::SecretKey;
+
+use crate::{multisig::RedeemScript, Error, InputSignature, TxInRef, UnspentTxOutValue};
+
+/// The multisig output with the recovery key.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MultisigWithRecovery {
+    /// The multisig script of the main spending path.
+    pub multisig: RedeemScript,
+    /// The public key, which can spend the output after the delay.
+    pub recovery_key: PublicKey,
+    /// The number of blocks after the output confirmation, after which the recovery key
+    /// can spend it.
+    pub delay: u16,
+}
+
+impl MultisigWithRecovery {
+    /// Returns the witness script of the output.
+    pub fn to_script(&self) -> Script {
+        let recovery = Builder::new()
+            .push_opcode(OP_ELSE)
+            .push_int(i64::from(self.delay))
+            .push_opcode(OP_CSV)
+            .push_opcode(OP_DROP)
+            .push_key(&self.recovery_key)
+            .push_opcode(OP_CHECKSIG)
+            .push_opcode(OP_ENDIF)
+            .into_script();
+
+        let mut bytes = vec![OP_IF.into_u8()];
+        bytes.extend_from_slice(self.multisig.script.as_bytes());
+        bytes.extend_from_slice(recovery.as_bytes());
+        Script::from(bytes)
+    }
+
+    /// Returns the `P2WSH` script pubkey of the output.
+    pub fn script_pubkey(&self) -> Script {
+        self.to_script().to_v0_p2wsh()
+    }
+
+    /// Returns the `P2WSH` address of the output for the given network.
+    pub fn address(&self, network: Network) -> Address {
+        Address::p2wsh(&self.to_script(), network)
+    }
+
+    /// Computes the [`BIP-143`][bip-143] compliant signature for the given input, which
+    /// spends the output. The secret key must belong either to one of the multisig
+    /// participants or to the recovery key.
+    ///
+    /// To spend the output via the recovery path, the input sequence must be set to
+    /// the delay, see [`set_relative_lock_time`](../fn.set_relative_lock_time.html).
+    ///
+    /// [bip-143]: https://github.com/bitcoin/bips/blob/master/bip-0143.mediawiki
+    pub fn sign_input<'a, 'b, V: Into<UnspentTxOutValue<'b>>>(
+        &self,
+        txin: TxInRef<'a>,
+        value: V,
+        secret_key: &SecretKey,
+    ) -> Result<InputSignature, Error> {
+        let content = self.multisig.content();
+        let public_keys = content
+            .public_keys
+            .iter()
+            .chain(Some(&self.recovery_key))
+            .collect::<Vec<_>>();
+        super::sign_input(&self.to_script(), &public_keys, txin, value, secret_key)
+    }
+
+    /// Creates the witness stack for the multisig spending path. The signatures must be
+    /// ordered in the same way as the corresponding public keys in the multisig script.
+    pub fn multisig_witness(&self, signatures: Vec<InputSignature>) -> Result<Vec<Vec<u8>>, Error> {
+        let quorum = self.multisig.quorum();
+        ensure!(
+            signatures.len() == quorum,
+            Error::SignaturesCount {
+                quorum,
+                actual: signatures.len(),
+            }
+        );
+
+        // The dummy item is consumed by `OP_CHECKMULTISIG`.
+        let mut witness_stack = Vec::with_capacity(signatures.len() + 3);
+        witness_stack.push(Vec::default());
+        witness_stack.extend(signatures.into_iter().map(Vec::from));
+        witness_stack.push(vec![1]);
+        witness_stack.push(self.to_script().to_bytes());
+        Ok(witness_stack)
+    }
+
+    /// Creates the witness stack for the recovery spending path.
+    pub fn recovery_witness(&self, signature: InputSignature) -> Vec<Vec<u8>> {
+        vec![signature.into(), Vec::new(), self.to_script().to_bytes()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{
+        blockdata::transaction::{OutPoint, Transaction, TxIn, TxOut},
+        network::constants::Network,
+        Script,
+    };
+
+    use super::MultisigWithRecovery;
+    use crate::{
+        locktime::check_sequence,
+        multisig::RedeemScriptBuilder,
+        script::script_to_asm,
+        sign,
+        templates::set_relative_lock_time,
+        test_data::{deterministic_keypair, fake_funding_tx, keypair_from_wif},
+        Error, Sequence, TxInRef,
+    };
+
+    #[test]
+    fn test_multisig_with_recovery() {
+        let keypairs = [
+            "cPHmynxvqfr7sXsJcohiGzoPGBShggxL6VWUdW14skohFZ1LQoeV",
+            "cTtSTL1stvg2tmK349WTmQDfHLMLqkkxwuo8ZJeQov9zEhtYtb4u",
+            "cQZZ7WvJUb6hXxCq9SF6516vb9bavQRPn2t3g9LFUtAoZuY7vNFk",
+        ]
+        .iter()
+        .map(|wif| keypair_from_wif(wif))
+        .collect::<Vec<_>>();
+        let (recovery_key, recovery_sk) =
+            keypair_from_wif("cQoWXdtbsRP9nu6i8qX8h3qQmM25XDF7XJMBjgnqqr5MqDxmmkjN");
+
+        let multisig = RedeemScriptBuilder::with_public_keys(keypairs.iter().map(|x| x.0))
+            .quorum(2)
+            .to_script()
+            .unwrap();
+        let template = MultisigWithRecovery {
+            multisig: multisig.clone(),
+            recovery_key,
+            delay: 144,
+        };
+        let script = template.to_script();
+        assert_eq!(script[0], 0x63);
+        assert_eq!(
+            &script[1..multisig.script.len() + 1],
+            multisig.script.as_bytes()
+        );
+
+        let prev_tx = fake_funding_tx(&template.script_pubkey(), 10_000);
+        let mut transaction = Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(prev_tx.txid(), 0),
+                script_sig: Script::default(),
+                sequence: 0xFFFF_FFFF,
+                witness: Vec::default(),
+            }],
+            output: vec![TxOut {
+                value: 9_000,
+                script_pubkey: Script::default(),
+            }],
+        };
+
+        // Multisig path.
+        let signatures = keypairs[..2]
+            .iter()
+            .map(|(_, sk)| {
+                let txin = TxInRef::new(&transaction, 0);
+                template.sign_input(txin, &prev_tx, &sk.key).unwrap()
+            })
+            .collect::<Vec<_>>();
+        let witness = template.multisig_witness(signatures.clone()).unwrap();
+        assert_eq!(witness.len(), 5);
+        assert!(witness[0].is_empty());
+        assert_eq!(witness[3], vec![1]);
+        assert_eq!(
+            template.multisig_witness(signatures[..1].to_vec()),
+            Err(Error::SignaturesCount {
+                quorum: 2,
+                actual: 1
+            })
+        );
+
+        // Recovery path.
+        set_relative_lock_time(&mut transaction, 0, template.delay).unwrap();
+        assert_eq!(transaction.version, 2);
+        assert_eq!(transaction.input[0].sequence, 144);
+        let txin = TxInRef::new(&transaction, 0);
+        let signature = template
+            .sign_input(txin, &prev_tx, &recovery_sk.key)
+            .unwrap();
+        sign::verify_input_signature(
+            sign::verification_context(),
+            txin,
+            &script,
+            &prev_tx,
+            &recovery_key,
+            &signature,
+        )
+        .unwrap();
+        let witness = template.recovery_witness(signature);
+        assert_eq!(witness.len(), 3);
+        assert!(witness[1].is_empty());
+    }
+
+    #[test]
+    fn test_multisig_with_recovery_script() {
+        let multisig = RedeemScriptBuilder::with_public_keys(
+            (0..2).map(|_| deterministic_keypair(Network::Testnet).0),
+        )
+        .quorum(1)
+        .to_script()
+        .unwrap();
+        let (recovery_key, _) = deterministic_keypair(Network::Testnet);
+        let template = MultisigWithRecovery {
+            multisig: multisig.clone(),
+            recovery_key,
+            delay: 1_000,
+        };
+        let script = template.to_script();
+        assert_eq!(
+            script_to_asm(&script),
+            format!(
+                "OP_IF {} OP_ELSE e803 OP_CSV OP_DROP {} OP_CHECKSIG OP_ENDIF",
+                script_to_asm(&multisig.script),
+                recovery_key
+            )
+        );
+        assert_eq!(
+            template.address(Network::Testnet).script_pubkey(),
+            template.script_pubkey()
+        );
+
+        // The recovery path requires the input sequence with the delay.
+        let prev_tx = fake_funding_tx(&template.script_pubkey(), 10_000);
+        let mut transaction = Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(prev_tx.txid(), 0),
+                script_sig: Script::default(),
+                sequence: 0xFFFF_FFFF,
+                witness: Vec::default(),
+            }],
+            output: Vec::new(),
+        };
+        assert_eq!(
+            check_sequence(&transaction, 0, &script),
+            Err(Error::RelativeLockTime(Sequence::from_height(1_000)))
+        );
+        set_relative_lock_time(&mut transaction, 0, 999).unwrap();
+        assert_eq!(
+            check_sequence(&transaction, 0, &script),
+            Err(Error::RelativeLockTime(Sequence::from_height(1_000)))
+        );
+        set_relative_lock_time(&mut transaction, 0, template.delay).unwrap();
+        check_sequence(&transaction, 0, &script).unwrap();
+
+        // Only the participants and the recovery key can sign.
+        let (_, other_sk) = deterministic_keypair(Network::Testnet);
+        assert_eq!(
+            template.sign_input(TxInRef::new(&transaction, 0), &prev_tx, &other_sk.key),
+            Err(Error::KeyNotInScript)
+        );
+    }
+}
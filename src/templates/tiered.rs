@@ -0,0 +1,325 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A multi-tier multisig output, which requires the quorum of each key group,
+//! for example "2-of-3 operators AND 1-of-2 auditors":
+//!
+//! ```text
+//! <k1> <pk1_1> ... <pk1_n> <n1> OP_CHECKMULTISIGVERIFY
+//! ...
+//! <km> <pkm_1> ... <pkm_n> <nm> OP_CHECKMULTISIG
+//! ```
+
+use bitcoin::{
+    blockdata::{
+        opcodes::all::{OP_CHECKMULTISIG, OP_CHECKMULTISIGVERIFY},
+        script::Script,
+    },
+    network::constants::Network,
+    util::address::Address,
+};
+use secp256k1::SecretKey;
+
+use crate::{
+    multisig::{RedeemScript, RedeemScriptError, MAX_SCRIPT_SIZE},
+    Error, InputSignature, TxInRef, UnspentTxOutValue,
+};
+
+/// The multi-tier multisig output.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TieredMultisig {
+    groups: Vec<RedeemScript>,
+    script: Script,
+}
+
+impl TieredMultisig {
+    /// Creates the output from the multisig scripts of the key groups. All groups must
+    /// provide their quorums of signatures to spend the output.
+    pub fn new(groups: Vec<RedeemScript>) -> Result<TieredMultisig, Error> {
+        ensure!(!groups.is_empty(), RedeemScriptError::NotEnoughPublicKeys);
+
+        let mut bytes = Vec::new();
+        for (index, group) in groups.iter().enumerate() {
+            let (_, body) = group.script.as_bytes().split_last().unwrap();
+            bytes.extend_from_slice(body);
+            // All groups except the last one must abort the script execution on failure.
+            let opcode = if index + 1 == groups.len() {
+                OP_CHECKMULTISIG
+            } else {
+                OP_CHECKMULTISIGVERIFY
+            };
+            bytes.push(opcode.into_u8());
+        }
+        ensure!(
+            bytes.len() <= MAX_SCRIPT_SIZE,
+            RedeemScriptError::ScriptTooLarge
+        );
+        Ok(TieredMultisig {
+            groups,
+            script: Script::from(bytes),
+        })
+    }
+
+    /// Returns the multisig scripts of the key groups.
+    pub fn groups(&self) -> &[RedeemScript] {
+        &self.groups
+    }
+
+    /// Returns the witness script of the output.
+    pub fn script(&self) -> &Script {
+        &self.script
+    }
+
+    /// Returns the `P2WSH` script pubkey of the output.
+    pub fn script_pubkey(&self) -> Script {
+        self.script.to_v0_p2wsh()
+    }
+
+    /// Returns the `P2WSH` address of the output for the given network.
+    pub fn address(&self, network: Network) -> Address {
+        Address::p2wsh(&self.script, network)
+    }
+
+    /// Computes the [`BIP-143`][bip-143] compliant signature for the given input, which
+    /// spends the output. The secret key must belong to a participant of one of the groups.
+    ///
+    /// [bip-143]: https://github.com/bitcoin/bips/blob/master/bip-0143.mediawiki
+    pub fn sign_input<'a, 'b, V: Into<UnspentTxOutValue<'b>>>(
+        &self,
+        txin: TxInRef<'a>,
+        value: V,
+        secret_key: &SecretKey,
+    ) -> Result<InputSignature, Error> {
+        let public_keys = self
+            .groups
+            .iter()
+            .flat_map(|group| group.content().public_keys)
+            .collect::<Vec<_>>();
+        let public_keys = public_keys.iter().collect::<Vec<_>>();
+        super::sign_input(&self.script, &public_keys, txin, value, secret_key)
+    }
+
+    /// Creates the witness stack from the signatures of each group in the group order.
+    /// The number of signatures of each group must be equal to its quorum, and they must
+    /// be ordered in the same way as the corresponding public keys in the group script.
+    pub fn witness_data(
+        &self,
+        signatures: Vec<Vec<InputSignature>>,
+    ) -> Result<Vec<Vec<u8>>, Error> {
+        ensure!(
+            signatures.len() == self.groups.len(),
+            Error::SignaturesCount {
+                quorum: self.groups.len(),
+                actual: signatures.len(),
+            }
+        );
+
+        let mut stack = Vec::new();
+        // The first group consumes the topmost stack elements.
+        for (group, signatures) in self.groups.iter().zip(signatures).rev() {
+            let quorum = group.quorum();
+            ensure!(
+                signatures.len() == quorum,
+                Error::SignaturesCount {
+                    quorum,
+                    actual: signatures.len(),
+                }
+            );
+            // The dummy item is consumed by `OP_CHECKMULTISIG`.
+            stack.push(Vec::default());
+            stack.extend(signatures.into_iter().map(Vec::from));
+        }
+        stack.push(self.script.to_bytes());
+        Ok(stack)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{
+        blockdata::transaction::{OutPoint, Transaction, TxIn, TxOut},
+        network::constants::Network,
+        Script,
+    };
+
+    use super::TieredMultisig;
+    use crate::{
+        multisig::{RedeemScript, RedeemScriptBuilder, RedeemScriptError},
+        sign,
+        test_data::{deterministic_keypair, fake_funding_tx, keypair_from_wif},
+        Error, TxInRef,
+    };
+
+    fn group(size: usize, quorum: usize) -> RedeemScript {
+        RedeemScriptBuilder::with_public_keys(
+            (0..size).map(|_| deterministic_keypair(Network::Testnet).0),
+        )
+        .quorum(quorum)
+        .to_script()
+        .unwrap()
+    }
+
+    #[test]
+    fn test_tiered_multisig() {
+        let keypairs = [
+            "cPHmynxvqfr7sXsJcohiGzoPGBShggxL6VWUdW14skohFZ1LQoeV",
+            "cTtSTL1stvg2tmK349WTmQDfHLMLqkkxwuo8ZJeQov9zEhtYtb4u",
+            "cQZZ7WvJUb6hXxCq9SF6516vb9bavQRPn2t3g9LFUtAoZuY7vNFk",
+            "cQoWXdtbsRP9nu6i8qX8h3qQmM25XDF7XJMBjgnqqr5MqDxmmkjN",
+            "cMs8EwSJwfQ5DrVqYcDgjKV52k3DrGZhK1MDNrabY16WxPjvACgG",
+        ]
+        .iter()
+        .map(|wif| keypair_from_wif(wif))
+        .collect::<Vec<_>>();
+        let operators = RedeemScriptBuilder::with_public_keys(keypairs[..3].iter().map(|x| x.0))
+            .quorum(2)
+            .to_script()
+            .unwrap();
+        let auditors = RedeemScriptBuilder::with_public_keys(keypairs[3..].iter().map(|x| x.0))
+            .quorum(1)
+            .to_script()
+            .unwrap();
+        let template = TieredMultisig::new(vec![operators.clone(), auditors.clone()]).unwrap();
+
+        let script = template.script().as_bytes();
+        let operators_len = operators.script.len();
+        assert_eq!(
+            &script[..operators_len - 1],
+            &operators.script.as_bytes()[..operators_len - 1]
+        );
+        assert_eq!(script[operators_len - 1], 0xaf);
+        assert_eq!(&script[operators_len..], auditors.script.as_bytes());
+
+        let prev_tx = fake_funding_tx(&template.script_pubkey(), 10_000);
+        let transaction = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(prev_tx.txid(), 0),
+                script_sig: Script::default(),
+                sequence: 0xFFFF_FFFF,
+                witness: Vec::default(),
+            }],
+            output: vec![TxOut {
+                value: 9_000,
+                script_pubkey: Script::default(),
+            }],
+        };
+        let txin = TxInRef::new(&transaction, 0);
+        let signatures = keypairs
+            .iter()
+            .map(|(public_key, sk)| {
+                let signature = template.sign_input(txin, &prev_tx, &sk.key).unwrap();
+                sign::verify_input_signature(
+                    sign::verification_context(),
+                    txin,
+                    template.script(),
+                    &prev_tx,
+                    public_key,
+                    &signature,
+                )
+                .unwrap();
+                signature
+            })
+            .collect::<Vec<_>>();
+
+        let witness = template
+            .witness_data(vec![
+                vec![signatures[0].clone(), signatures[2].clone()],
+                vec![signatures[4].clone()],
+            ])
+            .unwrap();
+        let expected = vec![
+            Vec::new(),
+            signatures[4].clone().into(),
+            Vec::new(),
+            signatures[0].clone().into(),
+            signatures[2].clone().into(),
+            template.script().to_bytes(),
+        ];
+        assert_eq!(witness, expected);
+
+        assert_eq!(
+            template.witness_data(vec![
+                vec![signatures[0].clone()],
+                vec![signatures[4].clone()]
+            ]),
+            Err(Error::SignaturesCount {
+                quorum: 2,
+                actual: 1
+            })
+        );
+        assert_eq!(
+            template.witness_data(vec![vec![signatures[0].clone(), signatures[1].clone()]]),
+            Err(Error::SignaturesCount {
+                quorum: 2,
+                actual: 1
+            })
+        );
+        assert_eq!(
+            TieredMultisig::new(Vec::new()),
+            Err(Error::from(RedeemScriptError::NotEnoughPublicKeys))
+        );
+    }
+
+    #[test]
+    fn test_tiered_multisig_groups() {
+        // The single group is the regular multisig.
+        let operators = group(3, 2);
+        let template = TieredMultisig::new(vec![operators.clone()]).unwrap();
+        assert_eq!(template.script(), &operators.script);
+        assert_eq!(template.groups(), &[operators]);
+        assert_eq!(
+            template.address(Network::Testnet).script_pubkey(),
+            template.script_pubkey()
+        );
+
+        let prev_tx = fake_funding_tx(&template.script_pubkey(), 10_000);
+        let transaction = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(prev_tx.txid(), 0),
+                ..TxIn::default()
+            }],
+            output: Vec::new(),
+        };
+        let (_, stranger_sk) = deterministic_keypair(Network::Testnet);
+        assert_eq!(
+            template.sign_input(TxInRef::new(&transaction, 0), &prev_tx, &stranger_sk.key),
+            Err(Error::KeyNotInScript)
+        );
+        assert_eq!(
+            template.witness_data(Vec::new()),
+            Err(Error::SignaturesCount {
+                quorum: 1,
+                actual: 0
+            })
+        );
+
+        // The groups fit into the standard script size.
+        let groups = (0..8).map(|_| group(15, 8)).collect::<Vec<_>>();
+        assert_eq!(
+            TieredMultisig::new(groups[..7].to_vec())
+                .unwrap()
+                .groups()
+                .len(),
+            7
+        );
+        assert_eq!(
+            TieredMultisig::new(groups),
+            Err(Error::from(RedeemScriptError::ScriptTooLarge))
+        );
+    }
+}
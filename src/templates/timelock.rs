@@ -0,0 +1,209 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! An output locked by a single key until the given absolute lock time:
+//!
+//! ```text
+//! <lock_time> OP_CHECKLOCKTIMEVERIFY OP_DROP <public_key> OP_CHECKSIG
+//! ```
+//!
+//! Such outputs are used for the time-locked recovery of funds.
+
+use bitcoin::{
+    blockdata::{
+        opcodes::all::{OP_CHECKSIG, OP_CLTV, OP_DROP},
+        script::{Builder, Script},
+        transaction::Transaction,
+    },
+    network::constants::Network,
+    util::address::Address,
+    PublicKey,
+};
+use secp256k1::SecretKey;
+
+use super::set_lock_time;
+use crate::{Error, LockTime, TxInRef, UnspentTxOutValue};
+
+/// The output locked by a single key until the absolute lock time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Timelock {
+    /// The public key, which can spend the output.
+    pub public_key: PublicKey,
+    /// The absolute lock time as a block height or a unix timestamp.
+    pub lock_time: LockTime,
+}
+
+impl Timelock {
+    /// Returns the witness script of the output.
+    pub fn to_script(&self) -> Script {
+        Builder::new()
+            .push_int(i64::from(self.lock_time.to_consensus_u32()))
+            .push_opcode(OP_CLTV)
+            .push_opcode(OP_DROP)
+            .push_key(&self.public_key)
+            .push_opcode(OP_CHECKSIG)
+            .into_script()
+    }
+
+    /// Returns the `P2WSH` script pubkey of the output.
+    pub fn script_pubkey(&self) -> Script {
+        self.to_script().to_v0_p2wsh()
+    }
+
+    /// Returns the `P2WSH` address of the output for the given network.
+    pub fn address(&self, network: Network) -> Address {
+        Address::p2wsh(&self.to_script(), network)
+    }
+
+    /// Signs the input with the given index and puts the witness into it. Thus, the input
+    /// becomes spent.
+    ///
+    /// Before signing, the transaction lock time and the input sequence are adjusted
+    /// as required by `OP_CHECKLOCKTIMEVERIFY`. Changing the lock time invalidates
+    /// the signatures of other inputs, so this input should be signed first.
+    pub fn spend_input<'a, V: Into<UnspentTxOutValue<'a>>>(
+        &self,
+        transaction: &mut Transaction,
+        index: usize,
+        value: V,
+        secret_key: &SecretKey,
+    ) -> Result<(), Error> {
+        set_lock_time(transaction, index, self.lock_time)?;
+
+        let script = self.to_script();
+        let txin = TxInRef::new(transaction, index);
+        let signature = super::sign_input(&script, &[&self.public_key], txin, value, secret_key)?;
+        transaction.input[index].witness = vec![signature.into(), script.to_bytes()];
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{
+        blockdata::transaction::{OutPoint, Transaction, TxIn, TxOut},
+        network::constants::Network,
+        Script,
+    };
+
+    use super::Timelock;
+    use crate::{
+        script::script_to_asm,
+        sign,
+        test_data::{fake_funding_tx, keypair_from_wif},
+        Error, InputSignatureRef, LockTime, TxInRef,
+    };
+
+    #[test]
+    fn test_timelock_spend_input() {
+        let (public_key, secret_key) =
+            keypair_from_wif("cPHmynxvqfr7sXsJcohiGzoPGBShggxL6VWUdW14skohFZ1LQoeV");
+        let timelock = Timelock {
+            public_key,
+            lock_time: LockTime::Height(650_000),
+        };
+        let prev_tx = fake_funding_tx(&timelock.script_pubkey(), 10_000);
+
+        let mut transaction = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(prev_tx.txid(), 0),
+                script_sig: Script::default(),
+                sequence: 0xFFFF_FFFF,
+                witness: Vec::default(),
+            }],
+            output: vec![TxOut {
+                value: 9_000,
+                script_pubkey: Script::default(),
+            }],
+        };
+        timelock
+            .spend_input(&mut transaction, 0, &prev_tx, &secret_key.key)
+            .unwrap();
+        assert_eq!(transaction.lock_time, 650_000);
+        assert_eq!(transaction.input[0].sequence, 0xFFFF_FFFE);
+
+        let witness = &transaction.input[0].witness;
+        assert_eq!(witness[1], timelock.to_script().to_bytes());
+        let txin = TxInRef::new(&transaction, 0);
+        let signature = InputSignatureRef::from_bytes(&witness[0]).unwrap();
+        sign::verify_input_signature(
+            sign::verification_context(),
+            txin,
+            &timelock.to_script(),
+            &prev_tx,
+            &public_key,
+            signature,
+        )
+        .unwrap();
+
+        // The timestamp lock time conflicts with the block height one.
+        transaction.lock_time = 1_600_000_000;
+        assert_eq!(
+            timelock.spend_input(&mut transaction, 0, &prev_tx, &secret_key.key),
+            Err(Error::LockTimeConflict)
+        );
+        assert_eq!(
+            timelock.spend_input(&mut transaction, 1, &prev_tx, &secret_key.key),
+            Err(Error::NoSuchInput(1))
+        );
+    }
+
+    #[test]
+    fn test_timelock_script() {
+        let (public_key, _) =
+            keypair_from_wif("cPHmynxvqfr7sXsJcohiGzoPGBShggxL6VWUdW14skohFZ1LQoeV");
+        let timelock = Timelock {
+            public_key,
+            lock_time: LockTime::Height(650_000),
+        };
+        assert_eq!(
+            script_to_asm(&timelock.to_script()),
+            format!("10eb09 OP_CLTV OP_DROP {} OP_CHECKSIG", public_key)
+        );
+        assert_eq!(
+            timelock.address(Network::Testnet).script_pubkey(),
+            timelock.script_pubkey()
+        );
+
+        // The output of another script can not be spent.
+        let (_, other_sk) =
+            keypair_from_wif("cTtSTL1stvg2tmK349WTmQDfHLMLqkkxwuo8ZJeQov9zEhtYtb4u");
+        let prev_tx = fake_funding_tx(&timelock.script_pubkey(), 10_000);
+        let other_tx = fake_funding_tx(&Script::default(), 10_000);
+        let mut transaction = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(prev_tx.txid(), 0),
+                script_sig: Script::default(),
+                sequence: 0xFFFF_FFFF,
+                witness: Vec::default(),
+            }],
+            output: Vec::new(),
+        };
+        assert_eq!(
+            timelock.spend_input(&mut transaction, 0, &other_tx.output[0], &other_sk.key),
+            Err(Error::KeyNotInScript)
+        );
+        let (_, secret_key) =
+            keypair_from_wif("cPHmynxvqfr7sXsJcohiGzoPGBShggxL6VWUdW14skohFZ1LQoeV");
+        assert_eq!(
+            timelock.spend_input(&mut transaction, 0, &other_tx.output[0], &secret_key.key),
+            Err(Error::ScriptMismatch)
+        );
+        assert!(transaction.input[0].witness.is_empty());
+    }
+}
@@ -0,0 +1,459 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A weighted multisig output, which can be spent if the total weight of the signers
+//! reaches the threshold:
+//!
+//! ```text
+//! <pk1> OP_CHECKSIG OP_IF <w1> OP_ELSE 0 OP_ENDIF
+//! OP_SWAP <pk2> OP_CHECKSIG OP_IF <w2> OP_ADD OP_ENDIF
+//! ...
+//! OP_SWAP <pkn> OP_CHECKSIG OP_IF <wn> OP_ADD OP_ENDIF
+//! <threshold> OP_GREATERTHANOREQUAL
+//! ```
+//!
+//! The participants, which do not sign, provide the empty witness items instead
+//! of the signatures.
+
+use bitcoin::{
+    blockdata::{
+        opcodes::all::{
+            OP_ADD, OP_CHECKSIG, OP_ELSE, OP_ENDIF, OP_GREATERTHANOREQUAL, OP_IF, OP_SWAP,
+        },
+        script::{Builder, Script},
+    },
+    network::constants::Network,
+    util::address::Address,
+    PublicKey,
+};
+use secp256k1::SecretKey;
+
+use crate::{
+    multisig::{RedeemScriptError, MAX_SCRIPT_SIZE},
+    Error, InputSignature, TxInRef, UnspentTxOutValue,
+};
+
+/// The weighted multisig output.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WeightedMultisig {
+    script: Script,
+    threshold: u16,
+    participants: Vec<(PublicKey, u16)>,
+}
+
+impl WeightedMultisig {
+    /// Returns the witness script of the output.
+    pub fn script(&self) -> &Script {
+        &self.script
+    }
+
+    /// Returns the total weight of the signers required to spend the output.
+    pub fn threshold(&self) -> u16 {
+        self.threshold
+    }
+
+    /// Returns the public keys of the participants with their weights in the script order.
+    pub fn participants(&self) -> &[(PublicKey, u16)] {
+        &self.participants
+    }
+
+    /// Returns the `P2WSH` script pubkey of the output.
+    pub fn script_pubkey(&self) -> Script {
+        self.script.to_v0_p2wsh()
+    }
+
+    /// Returns the `P2WSH` address of the output for the given network.
+    pub fn address(&self, network: Network) -> Address {
+        Address::p2wsh(&self.script, network)
+    }
+
+    /// Computes the [`BIP-143`][bip-143] compliant signature for the given input, which
+    /// spends the output. The secret key must belong to one of the participants.
+    ///
+    /// [bip-143]: https://github.com/bitcoin/bips/blob/master/bip-0143.mediawiki
+    pub fn sign_input<'a, 'b, V: Into<UnspentTxOutValue<'b>>>(
+        &self,
+        txin: TxInRef<'a>,
+        value: V,
+        secret_key: &SecretKey,
+    ) -> Result<InputSignature, Error> {
+        let public_keys = self
+            .participants
+            .iter()
+            .map(|(key, _)| key)
+            .collect::<Vec<_>>();
+        super::sign_input(&self.script, &public_keys, txin, value, secret_key)
+    }
+
+    /// Creates the witness stack from the signatures of the given participants in any order.
+    /// The total weight of the signers must reach the threshold.
+    pub fn witness_data(
+        &self,
+        signatures: Vec<(PublicKey, InputSignature)>,
+    ) -> Result<Vec<Vec<u8>>, Error> {
+        let count = self.participants.len();
+        let mut stack = vec![Vec::new(); count];
+        let mut weight = 0;
+        for (index, (public_key, signature)) in signatures.into_iter().enumerate() {
+            let position = self
+                .participants
+                .iter()
+                .position(|(key, _)| *key == public_key)
+                .ok_or(Error::UnknownPublicKey)?;
+            // The first key in the script consumes the topmost stack element.
+            let item = &mut stack[count - 1 - position];
+            ensure!(item.is_empty(), Error::DuplicatePublicKey(index));
+            *item = signature.into();
+            weight += u32::from(self.participants[position].1);
+        }
+        ensure!(
+            weight >= u32::from(self.threshold),
+            Error::InsufficientWeight {
+                threshold: self.threshold,
+                actual: weight,
+            }
+        );
+        stack.push(self.script.to_bytes());
+        Ok(stack)
+    }
+}
+
+/// The weighted multisig builder.
+#[derive(Debug, Default)]
+pub struct WeightedMultisigBuilder {
+    threshold: u16,
+    participants: Vec<(PublicKey, u16)>,
+}
+
+impl WeightedMultisigBuilder {
+    /// Creates builder.
+    pub fn new() -> WeightedMultisigBuilder {
+        WeightedMultisigBuilder::default()
+    }
+
+    /// Creates builder for the given threshold value.
+    pub fn with_threshold(threshold: u16) -> WeightedMultisigBuilder {
+        WeightedMultisigBuilder {
+            threshold,
+            participants: Vec::default(),
+        }
+    }
+
+    /// Adds a new participant with the given weight.
+    pub fn participant(
+        &mut self,
+        public_key: PublicKey,
+        weight: u16,
+    ) -> &mut WeightedMultisigBuilder {
+        self.participants.push((public_key, weight));
+        self
+    }
+
+    /// Sets the total weight of the signers required to spend the output.
+    pub fn threshold(&mut self, threshold: u16) -> &mut WeightedMultisigBuilder {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Finalizes the script building.
+    pub fn to_script(&self) -> Result<WeightedMultisig, Error> {
+        let total_weight = self
+            .participants
+            .iter()
+            .map(|(_, weight)| u32::from(*weight))
+            .sum::<u32>();
+        ensure!(self.threshold > 0, RedeemScriptError::NoQuorum);
+        ensure!(
+            !self.participants.is_empty(),
+            RedeemScriptError::NotEnoughPublicKeys
+        );
+        ensure!(
+            self.participants.iter().all(|(_, weight)| *weight > 0),
+            RedeemScriptError::ZeroWeight
+        );
+        ensure!(
+            total_weight >= u32::from(self.threshold),
+            RedeemScriptError::IncorrectQuorum
+        );
+        ensure!(
+            self.participants.iter().all(|(key, _)| key.compressed),
+            RedeemScriptError::UncompressedPublicKey
+        );
+        for (index, (key, _)) in self.participants.iter().enumerate() {
+            ensure!(
+                self.participants[..index]
+                    .iter()
+                    .all(|(other, _)| other != key),
+                Error::DuplicatePublicKey(index)
+            );
+        }
+
+        let mut builder = Builder::new();
+        for (index, (key, weight)) in self.participants.iter().enumerate() {
+            if index > 0 {
+                builder = builder.push_opcode(OP_SWAP);
+            }
+            builder = builder
+                .push_key(key)
+                .push_opcode(OP_CHECKSIG)
+                .push_opcode(OP_IF)
+                .push_int(i64::from(*weight));
+            builder = if index == 0 {
+                builder.push_opcode(OP_ELSE).push_int(0)
+            } else {
+                builder.push_opcode(OP_ADD)
+            };
+            builder = builder.push_opcode(OP_ENDIF);
+        }
+        let script = builder
+            .push_int(i64::from(self.threshold))
+            .push_opcode(OP_GREATERTHANOREQUAL)
+            .into_script();
+        ensure!(
+            script.len() <= MAX_SCRIPT_SIZE,
+            RedeemScriptError::ScriptTooLarge
+        );
+        Ok(WeightedMultisig {
+            script,
+            threshold: self.threshold,
+            participants: self.participants.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{
+        blockdata::transaction::{OutPoint, Transaction, TxIn, TxOut},
+        network::constants::Network,
+        PublicKey, Script,
+    };
+
+    use super::WeightedMultisigBuilder;
+    use crate::{
+        multisig::RedeemScriptError,
+        script::script_to_asm,
+        sign,
+        test_data::{deterministic_keypair, fake_funding_tx, keypair_from_wif},
+        Error, TxInRef,
+    };
+
+    #[test]
+    fn test_weighted_multisig() {
+        let keypairs = [
+            "cPHmynxvqfr7sXsJcohiGzoPGBShggxL6VWUdW14skohFZ1LQoeV",
+            "cTtSTL1stvg2tmK349WTmQDfHLMLqkkxwuo8ZJeQov9zEhtYtb4u",
+            "cQZZ7WvJUb6hXxCq9SF6516vb9bavQRPn2t3g9LFUtAoZuY7vNFk",
+        ]
+        .iter()
+        .map(|wif| keypair_from_wif(wif))
+        .collect::<Vec<_>>();
+        let mut builder = WeightedMultisigBuilder::with_threshold(4);
+        for ((public_key, _), weight) in keypairs.iter().zip(&[3, 2, 1]) {
+            builder.participant(*public_key, *weight);
+        }
+        let multisig = builder.to_script().unwrap();
+
+        let mut expected = Vec::new();
+        for (index, ((public_key, _), weight)) in
+            keypairs.iter().zip(&[0x53, 0x52, 0x51]).enumerate()
+        {
+            if index > 0 {
+                expected.push(0x7c);
+            }
+            expected.push(33);
+            expected.extend_from_slice(&public_key.key.serialize());
+            expected.extend_from_slice(&[0xac, 0x63, *weight]);
+            if index == 0 {
+                expected.extend_from_slice(&[0x67, 0x00]);
+            } else {
+                expected.push(0x93);
+            }
+            expected.push(0x68);
+        }
+        expected.extend_from_slice(&[0x54, 0xa2]);
+        assert_eq!(multisig.script().as_bytes(), &expected[..]);
+
+        let prev_tx = fake_funding_tx(&multisig.script_pubkey(), 10_000);
+        let transaction = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(prev_tx.txid(), 0),
+                script_sig: Script::default(),
+                sequence: 0xFFFF_FFFF,
+                witness: Vec::default(),
+            }],
+            output: vec![TxOut {
+                value: 9_000,
+                script_pubkey: Script::default(),
+            }],
+        };
+        let txin = TxInRef::new(&transaction, 0);
+        let signatures = keypairs
+            .iter()
+            .map(|(public_key, sk)| {
+                let signature = multisig.sign_input(txin, &prev_tx, &sk.key).unwrap();
+                sign::verify_input_signature(
+                    sign::verification_context(),
+                    txin,
+                    multisig.script(),
+                    &prev_tx,
+                    public_key,
+                    &signature,
+                )
+                .unwrap();
+                (*public_key, signature)
+            })
+            .collect::<Vec<_>>();
+
+        // The first and the third participants reach the threshold.
+        let chosen = vec![signatures[2].clone(), signatures[0].clone()];
+        let witness = multisig.witness_data(chosen).unwrap();
+        assert_eq!(witness.len(), 4);
+        assert_eq!(witness[0], Vec::from(signatures[2].1.clone()));
+        assert!(witness[1].is_empty());
+        assert_eq!(witness[2], Vec::from(signatures[0].1.clone()));
+        assert_eq!(witness[3], multisig.script().to_bytes());
+
+        assert_eq!(
+            multisig.witness_data(signatures[1..].to_vec()),
+            Err(Error::InsufficientWeight {
+                threshold: 4,
+                actual: 3
+            })
+        );
+        let duplicate = vec![signatures[0].clone(), signatures[0].clone()];
+        assert_eq!(
+            multisig.witness_data(duplicate),
+            Err(Error::DuplicatePublicKey(1))
+        );
+    }
+
+    #[test]
+    fn test_weighted_multisig_builder_errors() {
+        let (public_key, _) =
+            keypair_from_wif("cPHmynxvqfr7sXsJcohiGzoPGBShggxL6VWUdW14skohFZ1LQoeV");
+        assert_eq!(
+            WeightedMultisigBuilder::with_threshold(3)
+                .participant(public_key, 2)
+                .to_script(),
+            Err(Error::from(RedeemScriptError::IncorrectQuorum))
+        );
+        assert_eq!(
+            WeightedMultisigBuilder::with_threshold(1)
+                .participant(public_key, 0)
+                .to_script(),
+            Err(Error::from(RedeemScriptError::ZeroWeight))
+        );
+        assert_eq!(
+            WeightedMultisigBuilder::new()
+                .participant(public_key, 1)
+                .to_script(),
+            Err(Error::from(RedeemScriptError::NoQuorum))
+        );
+        assert_eq!(
+            WeightedMultisigBuilder::with_threshold(2)
+                .participant(public_key, 1)
+                .participant(public_key, 1)
+                .to_script(),
+            Err(Error::DuplicatePublicKey(1))
+        );
+        assert_eq!(
+            WeightedMultisigBuilder::with_threshold(1).to_script(),
+            Err(Error::from(RedeemScriptError::NotEnoughPublicKeys))
+        );
+        let uncompressed = PublicKey {
+            compressed: false,
+            ..public_key
+        };
+        assert_eq!(
+            WeightedMultisigBuilder::with_threshold(1)
+                .participant(uncompressed, 1)
+                .to_script(),
+            Err(Error::from(RedeemScriptError::UncompressedPublicKey))
+        );
+        let mut builder = WeightedMultisigBuilder::with_threshold(1);
+        for _ in 0..100 {
+            builder.participant(deterministic_keypair(Network::Testnet).0, 1);
+        }
+        assert_eq!(
+            builder.to_script(),
+            Err(Error::from(RedeemScriptError::ScriptTooLarge))
+        );
+    }
+
+    #[test]
+    fn test_weighted_multisig_large_weights() {
+        let participants = (0..2)
+            .map(|_| deterministic_keypair(Network::Testnet))
+            .collect::<Vec<_>>();
+        let multisig = WeightedMultisigBuilder::with_threshold(1_000)
+            .participant(participants[0].0, 700)
+            .participant(participants[1].0, 300)
+            .to_script()
+            .unwrap();
+        assert_eq!(multisig.threshold(), 1_000);
+        assert_eq!(
+            multisig.participants(),
+            &[(participants[0].0, 700), (participants[1].0, 300)]
+        );
+        assert_eq!(
+            script_to_asm(multisig.script()),
+            format!(
+                "{} OP_CHECKSIG OP_IF bc02 OP_ELSE OP_0 OP_ENDIF \
+                 OP_SWAP {} OP_CHECKSIG OP_IF 2c01 OP_ADD OP_ENDIF \
+                 e803 OP_GREATERTHANOREQUAL",
+                participants[0].0, participants[1].0
+            )
+        );
+        assert_eq!(
+            multisig.address(Network::Testnet).script_pubkey(),
+            multisig.script_pubkey()
+        );
+
+        let prev_tx = fake_funding_tx(&multisig.script_pubkey(), 10_000);
+        let transaction = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(prev_tx.txid(), 0),
+                ..TxIn::default()
+            }],
+            output: Vec::new(),
+        };
+        let txin = TxInRef::new(&transaction, 0);
+        let signature = multisig
+            .sign_input(txin, &prev_tx, &participants[0].1.key)
+            .unwrap();
+        assert_eq!(
+            multisig.witness_data(vec![(participants[0].0, signature.clone())]),
+            Err(Error::InsufficientWeight {
+                threshold: 1_000,
+                actual: 700
+            })
+        );
+
+        // The stranger can neither sign nor provide the signature.
+        let (stranger, stranger_sk) = deterministic_keypair(Network::Testnet);
+        assert_eq!(
+            multisig.sign_input(txin, &prev_tx, &stranger_sk.key),
+            Err(Error::KeyNotInScript)
+        );
+        assert_eq!(
+            multisig.witness_data(vec![(stranger, signature)]),
+            Err(Error::UnknownPublicKey)
+        );
+    }
+}
@@ -14,15 +14,38 @@
 
 //! A set of helpers for testing.
 
+#[cfg(feature = "proptest")]
+pub mod arbitrary;
+#[cfg(any(test, feature = "test-vectors"))]
+pub mod bip143;
+#[cfg(feature = "regtest")]
+pub mod regtest;
+
 use bitcoin::{
-    blockdata::transaction::Transaction,
-    consensus, Network, {PrivateKey, PublicKey},
+    blockdata::{
+        script::Script,
+        transaction::{OutPoint, Transaction, TxIn, TxOut},
+    },
+    hash_types::Txid,
+    Network, {PrivateKey, PublicKey},
 };
+#[cfg(test)]
+use bitcoin_hashes::sha256;
+use bitcoin_hashes::{sha256d, Hash};
+
+use crate::{decode_tx, Error};
+#[cfg(feature = "random")]
 use rand::Rng;
-use secp256k1::{Secp256k1, SecretKey};
+use secp256k1::Secp256k1;
+#[cfg(any(test, feature = "random"))]
+use secp256k1::SecretKey;
+
+#[cfg(test)]
+use std::cell::Cell;
 
 /// Computes a bitcoin private key and a corresponding public key using a
 /// given pseudo-random number generator.
+#[cfg(feature = "random")]
 pub fn secp_gen_keypair_with_rng<R: Rng + ?Sized>(
     rng: &mut R,
     network: Network,
@@ -39,22 +62,82 @@ pub fn secp_gen_keypair_with_rng<R: Rng + ?Sized>(
 
 /// Generates a bitcoin private key and a corresponding public key using a cryptographically
 /// secure pseudo-random number generator.
+#[cfg(feature = "random")]
 pub fn secp_gen_keypair(network: Network) -> (PublicKey, PrivateKey) {
     let mut rng = rand::thread_rng();
     secp_gen_keypair_with_rng(&mut rng, network)
 }
 
+/// Creates a key pair for the unit tests, which do not depend on the `random` feature.
+/// The keys are derived from the number of the previous calls in the current thread,
+/// so each call returns a new key, and every test gets the same keys on each run.
+#[cfg(test)]
+pub(crate) fn deterministic_keypair(network: Network) -> (PublicKey, PrivateKey) {
+    thread_local! {
+        static COUNTER: Cell<u64> = const { Cell::new(0) };
+    }
+
+    let index = COUNTER.with(|counter| counter.replace(counter.get() + 1));
+    let sk = PrivateKey {
+        network,
+        compressed: true,
+        key: SecretKey::from_slice(&sha256::Hash::hash(&index.to_le_bytes())[..])
+            .expect("The hash is a valid secret key"),
+    };
+    let pk = PublicKey::from_private_key(&Secp256k1::signing_only(), &sk);
+    (pk, sk)
+}
+
 /// Decodes a Bitcoin transaction from the given hex string.
 ///
 /// # Panics
 ///
 /// - If the given hex string can't be decoded as a Bitcoin transaction.
 pub fn btc_tx_from_hex(s: &str) -> Transaction {
-    let bytes = hex::decode(s).unwrap();
-    consensus::deserialize(&bytes).unwrap()
+    try_btc_tx_from_hex(s).unwrap()
+}
+
+/// Decodes a Bitcoin transaction from the given hex string, returns an error instead
+/// of panicking if the string is incorrect. The same as [`decode_tx`].
+///
+/// [`decode_tx`]: ../fn.decode_tx.html
+pub fn try_btc_tx_from_hex(s: &str) -> Result<Transaction, Error> {
+    decode_tx(s)
+}
+
+/// Fabricates a transaction whose first output pays the given value to the given script
+/// pubkey, so it can be used as a previous transaction in tests.
+///
+/// The transaction spends a fake outpoint, which is derived from the given script pubkey
+/// and value, so the funding transactions for different outputs have different identifiers.
+pub fn fake_funding_tx(script_pubkey: &Script, value: u64) -> Transaction {
+    let mut preimage = script_pubkey.to_bytes();
+    preimage.extend_from_slice(&value.to_le_bytes());
+    let fake_txid = Txid::from_hash(sha256d::Hash::hash(&preimage));
+
+    Transaction {
+        version: 2,
+        lock_time: 0,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(fake_txid, 0),
+            script_sig: Script::default(),
+            sequence: 0xFFFF_FFFF,
+            witness: Vec::default(),
+        }],
+        output: vec![TxOut {
+            value,
+            script_pubkey: script_pubkey.clone(),
+        }],
+    }
 }
 
 /// Parses WIF encoded private key and creates a public key from this private key.
+///
+/// The test networks share the same WIF prefix, so the keys of the regtest and signet
+/// networks are decoded as testnet ones. Use [`keypair_from_wif_for_network`] to get
+/// the key with the exact network.
+///
+/// [`keypair_from_wif_for_network`]: fn.keypair_from_wif_for_network.html
 pub fn keypair_from_wif(wif: &str) -> (PublicKey, PrivateKey) {
     let ctx = Secp256k1::signing_only();
 
@@ -62,3 +145,55 @@ pub fn keypair_from_wif(wif: &str) -> (PublicKey, PrivateKey) {
     let pk = sk.public_key(&ctx);
     (pk, sk)
 }
+
+/// Parses WIF encoded private key of the given network and creates a public key from this
+/// private key.
+///
+/// # Panics
+///
+/// - If the given string is not a correct WIF of the given network.
+pub fn keypair_from_wif_for_network(wif: &str, network: Network) -> (PublicKey, PrivateKey) {
+    let (pk, mut sk) = keypair_from_wif(wif);
+    let is_test_network = |network| network != Network::Bitcoin;
+    assert!(
+        sk.network == network || is_test_network(sk.network) && is_test_network(network),
+        "The private key does not belong to the {} network",
+        network
+    );
+    sk.network = network;
+    (pk, sk)
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{blockdata::transaction::OutPoint, network::constants::Network};
+
+    use crate::{
+        p2wpk,
+        test_data::{fake_funding_tx, keypair_from_wif},
+        TxOutProvider,
+    };
+
+    #[test]
+    fn test_fake_funding_tx() {
+        let (public_key, _) =
+            keypair_from_wif("cPHmynxvqfr7sXsJcohiGzoPGBShggxL6VWUdW14skohFZ1LQoeV");
+        let script_pubkey = p2wpk::script_pubkey(&public_key);
+
+        let funding_tx = fake_funding_tx(&script_pubkey, 1_000);
+        assert_eq!(funding_tx, fake_funding_tx(&script_pubkey, 1_000));
+        assert_ne!(
+            funding_tx.txid(),
+            fake_funding_tx(&script_pubkey, 2_000).txid()
+        );
+        let output = funding_tx
+            .tx_out(&OutPoint::new(funding_tx.txid(), 0))
+            .unwrap();
+        assert_eq!(output.value, 1_000);
+        assert_eq!(output.script_pubkey, script_pubkey);
+        assert_eq!(
+            p2wpk::address(&public_key, Network::Testnet).script_pubkey(),
+            script_pubkey
+        );
+    }
+}
@@ -0,0 +1,178 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! [`proptest`] strategies for the crate types, which are available with the `proptest`
+//! feature.
+//!
+//! [`proptest`]: https://docs.rs/proptest
+
+use bitcoin::{
+    blockdata::{
+        script::Script,
+        transaction::{OutPoint, Transaction, TxIn, TxOut},
+    },
+    hash_types::Txid,
+    network::constants::Network,
+    PrivateKey, PublicKey,
+};
+use bitcoin_hashes::Hash;
+use proptest::{
+    arbitrary::{any, Arbitrary},
+    collection::vec,
+    strategy::{BoxedStrategy, Just, Strategy},
+};
+use secp256k1::{Secp256k1, SecretKey};
+
+use std::ops::Range;
+
+use crate::multisig::{RedeemScript, RedeemScriptBuilder, MAX_PUBLIC_KEYS};
+
+/// Returns a strategy which generates keypairs of the given network.
+pub fn keypair(network: Network) -> impl Strategy<Value = (PublicKey, PrivateKey)> {
+    any::<[u8; 32]>().prop_filter_map("Incorrect secret key", move |bytes| {
+        let key = SecretKey::from_slice(&bytes).ok()?;
+        let private_key = PrivateKey {
+            network,
+            compressed: true,
+            key,
+        };
+        let public_key = PublicKey::from_private_key(&Secp256k1::signing_only(), &private_key);
+        Some((public_key, private_key))
+    })
+}
+
+/// Returns a strategy which generates sets of keypairs with the distinct public keys,
+/// the number of keypairs is taken from the given range.
+pub fn keypairs(
+    network: Network,
+    count: Range<usize>,
+) -> impl Strategy<Value = Vec<(PublicKey, PrivateKey)>> {
+    vec(keypair(network), count).prop_filter("Duplicate public keys", |keypairs| {
+        keypairs
+            .iter()
+            .enumerate()
+            .all(|(i, (key, _))| keypairs[..i].iter().all(|(other, _)| other != key))
+    })
+}
+
+/// Returns a strategy which generates redeem scripts for the given keypairs with
+/// an arbitrary quorum.
+pub fn redeem_script_for(
+    keypairs: Vec<(PublicKey, PrivateKey)>,
+) -> impl Strategy<Value = (RedeemScript, Vec<(PublicKey, PrivateKey)>)> {
+    (1..=keypairs.len(), Just(keypairs)).prop_map(|(quorum, keypairs)| {
+        let redeem_script = RedeemScriptBuilder::with_public_keys(keypairs.iter().map(|x| x.0))
+            .quorum(quorum)
+            .to_script()
+            .expect("Correct redeem script");
+        (redeem_script, keypairs)
+    })
+}
+
+/// Returns a strategy which generates unsigned transactions with the number of inputs
+/// and outputs taken from the given ranges.
+pub fn unsigned_transaction(
+    inputs: Range<usize>,
+    outputs: Range<usize>,
+) -> impl Strategy<Value = Transaction> {
+    let input =
+        (any::<[u8; 32]>(), any::<u32>(), any::<u32>()).prop_map(|(txid, vout, seq)| TxIn {
+            previous_output: OutPoint::new(Txid::from_inner(txid), vout),
+            script_sig: Script::default(),
+            sequence: seq,
+            witness: Vec::default(),
+        });
+    let output =
+        (0..21_000_000_u64 * 100_000_000, vec(any::<u8>(), 0..64)).prop_map(|(value, script)| {
+            TxOut {
+                value,
+                script_pubkey: script.into(),
+            }
+        });
+    (
+        1..=2_i32,
+        any::<u32>(),
+        vec(input, inputs),
+        vec(output, outputs),
+    )
+        .prop_map(|(version, lock_time, input, output)| Transaction {
+            version,
+            lock_time,
+            input,
+            output,
+        })
+}
+
+impl Arbitrary for RedeemScript {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<RedeemScript>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        keypairs(Network::Testnet, 1..MAX_PUBLIC_KEYS + 1)
+            .prop_flat_map(redeem_script_for)
+            .prop_map(|(redeem_script, _)| redeem_script)
+            .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::network::constants::Network;
+    use proptest::{prelude::*, sample::Index};
+
+    use super::{keypairs, redeem_script_for, unsigned_transaction};
+    use crate::{
+        multisig::{RedeemScript, RedeemScriptContent},
+        p2wsh, TxInRef,
+    };
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(16))]
+
+        #[test]
+        fn test_redeem_script_roundtrip(redeem_script in any::<RedeemScript>()) {
+            let content = RedeemScriptContent::parse(redeem_script.as_ref()).unwrap();
+            prop_assert_eq!(content, redeem_script.content());
+            prop_assert_eq!(redeem_script.to_string().parse::<RedeemScript>().unwrap(), redeem_script);
+        }
+
+        #[test]
+        fn test_p2wsh_sign_verify_roundtrip(
+            (redeem_script, keypairs) in keypairs(Network::Testnet, 1..5).prop_flat_map(redeem_script_for),
+            transaction in unsigned_transaction(1..4, 1..3),
+            input in any::<Index>(),
+            value in 1..1_000_000_000_u64,
+        ) {
+            let mut transaction = transaction;
+            let index = input.index(transaction.input.len());
+            let quorum = redeem_script.quorum();
+            let mut signer = p2wsh::InputSigner::new(redeem_script.clone());
+            let signatures = keypairs[..quorum]
+                .iter()
+                .map(|(_, private_key)| {
+                    let txin = TxInRef::new(&transaction, index);
+                    signer.sign_input(txin, value, &private_key.key).unwrap()
+                })
+                .collect::<Vec<_>>();
+            signer.spend_input(&mut transaction, index, value, signatures.clone()).unwrap();
+
+            let witness = p2wsh::parse_witness(&transaction.input[index].witness).unwrap();
+            prop_assert_eq!(&witness.signatures, &signatures);
+            let txin = TxInRef::new(&transaction, index);
+            for ((public_key, _), signature) in keypairs.iter().zip(&signatures) {
+                prop_assert!(signer.verify_input(txin, value, public_key, signature).is_ok());
+            }
+        }
+    }
+}
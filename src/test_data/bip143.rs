@@ -0,0 +1,147 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The reference [`BIP-143`][bip-143] test vectors with the `SIGHASH_ALL` signatures,
+//! which are available with the `test-vectors` feature.
+//!
+//! # Examples
+//!
+//! ```
+//! use btc_transaction_utils::{multisig::RedeemScript, p2wsh, test_data::bip143, TxInRef};
+//!
+//! for vector in bip143::vectors() {
+//!     // Only the multisig script codes can be checked with the `P2WSH` signer.
+//!     if let Ok(redeem_script) = RedeemScript::from_script(vector.script_code()) {
+//!         let transaction = vector.transaction();
+//!         let txin = TxInRef::new(&transaction, vector.input_index);
+//!         let sighash = p2wsh::InputSigner::new(redeem_script).signature_hash(txin, vector.amount);
+//!         assert_eq!(&sighash[..], vector.sighash().as_slice(), "{}", vector.name);
+//!     }
+//! }
+//! ```
+//!
+//! [bip-143]: https://github.com/bitcoin/bips/blob/master/bip-0143.mediawiki
+
+use bitcoin::blockdata::{script::Script, transaction::Transaction};
+
+use crate::test_data::btc_tx_from_hex;
+
+/// The reference test vector of the sighash computation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bip143Vector {
+    /// The name of the example in the specification.
+    pub name: &'static str,
+    /// The hex encoded unsigned transaction.
+    pub unsigned_tx: &'static str,
+    /// The index of the signed input.
+    pub input_index: usize,
+    /// The hex encoded script code of the signed input, without the length prefix.
+    pub script_code: &'static str,
+    /// The value of the spent output in satoshis.
+    pub amount: u64,
+    /// The hex encoded expected sighash.
+    pub sighash: &'static str,
+}
+
+impl Bip143Vector {
+    /// Returns the decoded unsigned transaction.
+    pub fn transaction(&self) -> Transaction {
+        btc_tx_from_hex(self.unsigned_tx)
+    }
+
+    /// Returns the decoded script code.
+    pub fn script_code(&self) -> Script {
+        hex::decode(self.script_code).unwrap().into()
+    }
+
+    /// Returns the decoded expected sighash.
+    pub fn sighash(&self) -> Vec<u8> {
+        hex::decode(self.sighash).unwrap()
+    }
+}
+
+const VECTORS: &[Bip143Vector] = &[
+    Bip143Vector {
+        name: "Native P2WPKH",
+        unsigned_tx: "0100000002fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f\
+                      0000000000eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57\
+                      b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85\
+                      c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2\
+                      f0167faa815988ac11000000",
+        input_index: 1,
+        script_code: "76a9141d0f172a0ecb48aee1be1f2687d2963ae33f71a188ac",
+        amount: 600_000_000,
+        sighash: "c37af31116d1b27caf68aae9e3ac82f1477929014d5b917657d0eb49478cb670",
+    },
+    Bip143Vector {
+        name: "P2SH-P2WPKH",
+        unsigned_tx: "0100000001db6b1b20aa0fd7b23880be2ecbd4a98130974cf4748fb66092ac4d3ceb1a5477\
+                      0100000000feffffff02b8b4eb0b000000001976a914a457b684d7f0d539a46a45bbc043f3\
+                      5b59d0d96388ac0008af2f000000001976a914fd270b1ee6abcaea97fea7ad0402e8bd8ad6\
+                      d77c88ac92040000",
+        input_index: 0,
+        script_code: "76a91479091972186c449eb1ded22b78e40d009bdf008988ac",
+        amount: 1_000_000_000,
+        sighash: "64f3b0f4dd2bb3aa1ce8566d220cc74dda9df97d8490cc81d89d735c92e59fb6",
+    },
+    Bip143Vector {
+        name: "P2SH-P2WSH 6-of-6 multisig",
+        unsigned_tx: "010000000136641869ca081e70f394c6948e8af409e18b619df2ed74aa106c1ca29787b96e\
+                      0100000000ffffffff0200e9a435000000001976a914389ffce9cd9ae88dcc0631e88a821f\
+                      fdbe9bfe2688acc0832f05000000001976a9147480a33f950689af511e6e84c138dbbd3c3e\
+                      e41588ac00000000",
+        input_index: 0,
+        script_code: "56210307b8ae49ac90a048e9b53357a2354b3334e9c8bee813ecb98e99a7e07e8c3ba32103\
+                      b28f0c28bfab54554ae8c658ac5c3e0ce6e79ad336331f78c428dd43eea8449b21034b8113\
+                      d703413d57761b8b9781957b8c0ac1dfe69f492580ca4195f50376ba4a21033400f6afecb8\
+                      33092a9a21cfdf1ed1376e58c5d1f47de74683123987e967a8f42103a6d48b1131e94ba04d\
+                      9737d61acdaa1322008af9602b3b14862c07a1789aac162102d8b661b0b3302ee2f162b09e\
+                      07a55ad5dfbe673a9f01d9f0c19617681024306b56ae",
+        amount: 987_654_321,
+        sighash: "185c0be5263dce5b4bb50a047973c1b6272bfbd0103a89444597dc40b248ee7c",
+    },
+];
+
+/// Returns an iterator over the reference test vectors.
+pub fn vectors() -> impl Iterator<Item = &'static Bip143Vector> {
+    VECTORS.iter()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::vectors;
+    use crate::{multisig::RedeemScript, p2wsh, sign::signature_hash, TxInRef};
+
+    #[test]
+    fn test_bip143_vectors() {
+        for vector in vectors() {
+            let transaction = vector.transaction();
+            let txin = TxInRef::new(&transaction, vector.input_index);
+            let sighash = signature_hash(txin, &vector.script_code(), vector.amount);
+            assert_eq!(&sighash[..], vector.sighash().as_slice(), "{}", vector.name);
+        }
+    }
+
+    #[test]
+    fn test_bip143_multisig_signer() {
+        let vector = vectors().nth(2).unwrap();
+        let redeem_script = RedeemScript::from_script(vector.script_code()).unwrap();
+        let mut signer = p2wsh::InputSigner::new(redeem_script);
+
+        let transaction = vector.transaction();
+        let txin = TxInRef::new(&transaction, vector.input_index);
+        let sighash = signer.signature_hash(txin, vector.amount);
+        assert_eq!(&sighash[..], vector.sighash().as_slice());
+    }
+}
@@ -0,0 +1,443 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A harness for the tests against the `bitcoind` node in the regtest mode, which is
+//! available with the `regtest` feature.
+//!
+//! The harness does not use the node wallet: the blocks are mined to the harness own key,
+//! and the mature coinbase outputs are spent to fund the given script pubkeys.
+//!
+//! The helpers panic on errors, since they are intended to be used in tests only.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use btc_transaction_utils::{p2wpk, test_data::{regtest::Regtest, secp_gen_keypair}};
+//! use bitcoin::network::constants::Network;
+//!
+//! let mut node = Regtest::spawn();
+//! let (public_key, _) = secp_gen_keypair(Network::Regtest);
+//! let funding_tx = node.fund(&p2wpk::script_pubkey(&public_key), 100_000);
+//! assert_eq!(funding_tx.output[0].value, 100_000);
+//! ```
+
+use bitcoin::{
+    blockdata::{
+        block::Block,
+        opcodes::all::OP_RETURN,
+        script::{Builder, Script},
+        transaction::{OutPoint, Transaction, TxIn, TxOut},
+    },
+    consensus,
+    hash_types::{BlockHash, Txid},
+    network::constants::Network,
+    PrivateKey, PublicKey,
+};
+use bitcoin_hashes::hex::FromHex;
+use serde_json::{json, Value};
+
+use std::{
+    env, fs,
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    path::PathBuf,
+    process::{Child, Command, Stdio},
+    thread,
+    time::Duration,
+};
+
+use crate::{p2wpk, test_data::secp_gen_keypair, TxInRef};
+
+/// The number of blocks after which the coinbase outputs can be spent.
+const COINBASE_MATURITY: usize = 100;
+/// The fee paid by the funding transactions.
+const FUNDING_FEE: u64 = 10_000;
+/// The number of attempts to connect to the spawned node.
+const CONNECT_ATTEMPTS: usize = 100;
+/// The delay between attempts to connect to the spawned node.
+const CONNECT_DELAY: Duration = Duration::from_millis(100);
+
+/// The connection to the `bitcoind` node in the regtest mode.
+#[derive(Debug)]
+pub struct Regtest {
+    rpc: RpcClient,
+    node: Option<SpawnedNode>,
+    miner: (PublicKey, PrivateKey),
+    coinbases: Vec<Transaction>,
+    next_coinbase: usize,
+}
+
+#[derive(Debug)]
+struct SpawnedNode {
+    process: Child,
+    data_dir: PathBuf,
+}
+
+#[derive(Debug)]
+struct RpcClient {
+    addr: String,
+    auth: String,
+}
+
+impl Regtest {
+    /// Spawns the `bitcoind` binary in the regtest mode with a temporary data directory.
+    /// The path to the binary can be set via the `BITCOIND_EXE` environment variable.
+    ///
+    /// The node is stopped and its data directory is removed on drop.
+    pub fn spawn() -> Regtest {
+        let rpc_port = free_port();
+        let data_dir = env::temp_dir().join(format!(
+            "btc-transaction-utils-regtest-{}-{}",
+            std::process::id(),
+            rpc_port
+        ));
+        fs::create_dir_all(&data_dir).expect("Unable to create the node data directory");
+
+        let bitcoind = env::var("BITCOIND_EXE").unwrap_or_else(|_| "bitcoind".to_owned());
+        let process = Command::new(bitcoind)
+            .arg("-regtest")
+            .arg("-server")
+            .arg("-listen=0")
+            .arg("-disablewallet")
+            .arg(format!("-datadir={}", data_dir.display()))
+            .arg(format!("-rpcport={}", rpc_port))
+            .stdout(Stdio::null())
+            .spawn()
+            .expect("Unable to spawn bitcoind");
+        let node = SpawnedNode { process, data_dir };
+
+        let cookie_file = node.data_dir.join("regtest").join(".cookie");
+        let mut attempts = 0;
+        let cookie = loop {
+            if let Ok(cookie) = fs::read_to_string(&cookie_file) {
+                break cookie;
+            }
+            attempts += 1;
+            assert!(attempts < CONNECT_ATTEMPTS, "bitcoind has not started");
+            thread::sleep(CONNECT_DELAY);
+        };
+        let rpc = RpcClient::new(format!("127.0.0.1:{}", rpc_port), cookie.trim());
+        Regtest::with_rpc(rpc, Some(node))
+    }
+
+    /// Connects to the already running node with the given RPC address and credentials.
+    pub fn connect(addr: &str, user: &str, password: &str) -> Regtest {
+        let rpc = RpcClient::new(addr.to_owned(), &format!("{}:{}", user, password));
+        Regtest::with_rpc(rpc, None)
+    }
+
+    fn with_rpc(rpc: RpcClient, node: Option<SpawnedNode>) -> Regtest {
+        let mut attempts = 0;
+        while rpc.try_call("getblockchaininfo", json!([])).is_err() {
+            attempts += 1;
+            assert!(attempts < CONNECT_ATTEMPTS, "bitcoind RPC is not available");
+            thread::sleep(CONNECT_DELAY);
+        }
+
+        let mut regtest = Regtest {
+            rpc,
+            node,
+            miner: secp_gen_keypair(Network::Regtest),
+            coinbases: Vec::new(),
+            next_coinbase: 0,
+        };
+        regtest.mine_blocks(COINBASE_MATURITY + 1);
+        regtest
+    }
+
+    /// Mines the given number of blocks and returns their hashes.
+    pub fn mine_blocks(&mut self, count: usize) -> Vec<BlockHash> {
+        let address = p2wpk::address(&self.miner.0, Network::Regtest);
+        let hashes: Vec<String> = serde_json::from_value(
+            self.rpc
+                .call("generatetoaddress", json!([count, address.to_string()])),
+        )
+        .expect("Incorrect generatetoaddress response");
+
+        hashes
+            .iter()
+            .map(|hash| {
+                let block = self.block(hash);
+                self.coinbases.push(block.txdata[0].clone());
+                block.block_hash()
+            })
+            .collect()
+    }
+
+    /// Creates, broadcasts and confirms a transaction whose first output pays the given
+    /// value to the given script pubkey.
+    pub fn fund(&mut self, script_pubkey: &Script, value: u64) -> Transaction {
+        if self.coinbases.len() - self.next_coinbase <= COINBASE_MATURITY {
+            self.mine_blocks(1);
+        }
+        let coinbase = self.coinbases[self.next_coinbase].clone();
+        self.next_coinbase += 1;
+
+        let available = coinbase.output[0].value;
+        assert!(
+            value + FUNDING_FEE <= available,
+            "The value is too large to be funded from a single coinbase"
+        );
+        let mut outputs = vec![TxOut {
+            value,
+            script_pubkey: script_pubkey.clone(),
+        }];
+        let change = available - value - FUNDING_FEE;
+        if change > 0 {
+            outputs.push(TxOut {
+                value: change,
+                script_pubkey: p2wpk::script_pubkey(&self.miner.0),
+            });
+        }
+        let mut transaction = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(coinbase.txid(), 0),
+                script_sig: Script::default(),
+                sequence: 0xFFFF_FFFF,
+                witness: Vec::default(),
+            }],
+            output: outputs,
+        };
+
+        let mut signer = p2wpk::InputSigner::new(self.miner.0, Network::Regtest);
+        let signature = signer
+            .sign_input(TxInRef::new(&transaction, 0), &coinbase, &self.miner.1.key)
+            .expect("Unable to sign the funding transaction");
+        signer.spend_input(&mut transaction.input[0], signature);
+
+        self.send_transaction(&transaction);
+        self.mine_blocks(1);
+        transaction
+    }
+
+    /// Checks whether the node would accept the given transaction into the mempool
+    /// and returns the rejection reason otherwise.
+    pub fn test_mempool_accept(&self, transaction: &Transaction) -> Result<(), String> {
+        let hex = hex::encode(consensus::serialize(transaction));
+        let result = self.rpc.call("testmempoolaccept", json!([[hex]]));
+        let result = &result[0];
+        if result["allowed"].as_bool() == Some(true) {
+            Ok(())
+        } else {
+            Err(result["reject-reason"]
+                .as_str()
+                .unwrap_or("unknown reason")
+                .to_owned())
+        }
+    }
+
+    /// Asserts that the node would accept the given transaction into the mempool.
+    pub fn assert_accepted(&self, transaction: &Transaction) {
+        if let Err(reason) = self.test_mempool_accept(transaction) {
+            panic!("Transaction {} is rejected: {}", transaction.txid(), reason);
+        }
+    }
+
+    /// Broadcasts the given transaction and returns its identifier.
+    pub fn send_transaction(&self, transaction: &Transaction) -> Txid {
+        let hex = hex::encode(consensus::serialize(transaction));
+        let txid = self.rpc.call("sendrawtransaction", json!([hex]));
+        txid.as_str()
+            .and_then(|txid| Txid::from_hex(txid).ok())
+            .expect("Incorrect sendrawtransaction response")
+    }
+
+    /// Returns the number of confirmations of the given transaction, or `None` if
+    /// the transaction is not confirmed yet.
+    pub fn confirmations(&self, transaction: &Transaction) -> Option<u64> {
+        // The `getrawtransaction` method requires the transaction index for the confirmed
+        // transactions, so the transaction output is looked up in the UTXO set instead.
+        let txout = self.rpc.call(
+            "gettxout",
+            json!([transaction.txid().to_string(), 0, false]),
+        );
+        txout["confirmations"].as_u64().filter(|&count| count > 0)
+    }
+
+    /// Returns a script pubkey which can be used for outputs that are never spent.
+    pub fn burn_script() -> Script {
+        Builder::new().push_opcode(OP_RETURN).into_script()
+    }
+
+    fn block(&self, hash: &str) -> Block {
+        let hex = self.rpc.call("getblock", json!([hash, 0]));
+        let bytes = hex
+            .as_str()
+            .and_then(|hex| hex::decode(hex).ok())
+            .expect("Incorrect getblock response");
+        consensus::deserialize(&bytes).expect("Incorrect block encoding")
+    }
+}
+
+impl Drop for Regtest {
+    fn drop(&mut self) {
+        if let Some(mut node) = self.node.take() {
+            let _ = self.rpc.try_call("stop", json!([]));
+            let _ = node.process.wait();
+            let _ = fs::remove_dir_all(&node.data_dir);
+        }
+    }
+}
+
+impl RpcClient {
+    fn new(addr: String, credentials: &str) -> RpcClient {
+        RpcClient {
+            addr,
+            auth: base64_encode(credentials.as_bytes()),
+        }
+    }
+
+    fn call(&self, method: &str, params: Value) -> Value {
+        self.try_call(method, params)
+            .unwrap_or_else(|error| panic!("RPC call `{}` failed: {}", method, error))
+    }
+
+    fn try_call(&self, method: &str, params: Value) -> Result<Value, String> {
+        let body = json!({
+            "jsonrpc": "1.0",
+            "id": 0,
+            "method": method,
+            "params": params,
+        })
+        .to_string();
+        let request = format!(
+            "POST / HTTP/1.1\r\nHost: {}\r\nAuthorization: Basic {}\r\n\
+             Content-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.addr,
+            self.auth,
+            body.len(),
+            body
+        );
+
+        let mut stream = TcpStream::connect(&self.addr).map_err(|e| e.to_string())?;
+        stream
+            .write_all(request.as_bytes())
+            .map_err(|e| e.to_string())?;
+        let mut response = String::new();
+        stream
+            .read_to_string(&mut response)
+            .map_err(|e| e.to_string())?;
+
+        // The node returns JSON-RPC errors with the non-successful HTTP status,
+        // so only the body is inspected.
+        let body = response
+            .split("\r\n\r\n")
+            .nth(1)
+            .ok_or_else(|| format!("Incorrect HTTP response: {}", response))?;
+        let mut response: Value = serde_json::from_str(body).map_err(|e| e.to_string())?;
+        if !response["error"].is_null() {
+            return Err(response["error"].to_string());
+        }
+        Ok(response["result"].take())
+    }
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .map(|addr| addr.port())
+        .expect("Unable to find a free port")
+}
+
+fn base64_encode(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity((data.len() + 2) / 3 * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let triple = u32::from(bytes[0]) << 16 | u32::from(bytes[1]) << 8 | u32::from(bytes[2]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                let index = (triple >> (18 - 6 * i)) & 0x3F;
+                encoded.push(char::from(ALPHABET[index as usize]));
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{
+        blockdata::{
+            script::Script,
+            transaction::{OutPoint, Transaction, TxIn, TxOut},
+        },
+        network::constants::Network,
+    };
+
+    use super::{base64_encode, Regtest};
+    use crate::{multisig::RedeemScriptBuilder, p2wsh, test_data::secp_gen_keypair, TxInRef};
+
+    #[test]
+    fn test_base64_encode() {
+        assert_eq!(base64_encode(b""), "");
+        assert_eq!(base64_encode(b"f"), "Zg==");
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+        assert_eq!(base64_encode(b"foo"), "Zm9v");
+        assert_eq!(base64_encode(b"user:password"), "dXNlcjpwYXNzd29yZA==");
+    }
+
+    #[test]
+    #[ignore = "requires bitcoind"]
+    fn test_regtest_multisig_spend() {
+        let mut node = Regtest::spawn();
+        let keypairs = (0..3)
+            .map(|_| secp_gen_keypair(Network::Regtest))
+            .collect::<Vec<_>>();
+        let redeem_script = RedeemScriptBuilder::with_public_keys(keypairs.iter().map(|x| x.0))
+            .quorum(2)
+            .to_script()
+            .unwrap();
+        let funding_tx = node.fund(&p2wsh::script_pubkey(&redeem_script), 100_000);
+        assert_eq!(node.confirmations(&funding_tx), Some(1));
+
+        let mut transaction = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(funding_tx.txid(), 0),
+                script_sig: Script::default(),
+                sequence: 0xFFFF_FFFF,
+                witness: Vec::default(),
+            }],
+            output: vec![TxOut {
+                value: 90_000,
+                script_pubkey: Regtest::burn_script(),
+            }],
+        };
+        let mut signer = p2wsh::InputSigner::new(redeem_script);
+        let signatures = keypairs[..2]
+            .iter()
+            .map(|(_, private_key)| {
+                let txin = TxInRef::new(&transaction, 0);
+                signer
+                    .sign_input(txin, &funding_tx, &private_key.key)
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        signer
+            .spend_input(&mut transaction, 0, &funding_tx, signatures)
+            .unwrap();
+        node.assert_accepted(&transaction);
+    }
+}
@@ -0,0 +1,352 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A tracker of the confirmations of the broadcast transactions.
+//!
+//! The tracker is fed with the blocks and the block headers of the best chain.
+//! When the block at the known height is replaced, the transactions confirmed in the
+//! disconnected blocks become unconfirmed again, so the caller can rebroadcast them.
+
+use bitcoin::{
+    blockdata::{
+        block::{Block, BlockHeader},
+        transaction::Transaction,
+    },
+    BlockHash, Txid, Wtxid,
+};
+
+use std::collections::{BTreeMap, HashMap};
+
+/// The confirmation status of the tracked transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxStatus {
+    /// The transaction is not included in the best chain.
+    Unconfirmed,
+    /// The transaction is included in the block of the best chain.
+    Confirmed {
+        /// The height of the block.
+        height: u32,
+        /// The hash of the block.
+        block_hash: BlockHash,
+    },
+}
+
+/// The change of the confirmation status of the tracked transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrackerEvent {
+    /// The transaction is included in the block with the given height.
+    Confirmed {
+        /// The identifier of the transaction.
+        txid: Txid,
+        /// The height of the block.
+        height: u32,
+    },
+    /// The block, which includes the transaction, is disconnected from the best chain.
+    Unconfirmed {
+        /// The identifier of the transaction.
+        txid: Txid,
+    },
+}
+
+#[derive(Debug, Clone)]
+struct TrackedTx {
+    wtxid: Wtxid,
+    status: TxStatus,
+}
+
+/// The tracker of the confirmations of the broadcast transactions.
+#[derive(Debug, Clone, Default)]
+pub struct ConfirmationTracker {
+    transactions: BTreeMap<Txid, TrackedTx>,
+    wtxids: HashMap<Wtxid, Txid>,
+    chain: BTreeMap<u32, BlockHash>,
+}
+
+impl ConfirmationTracker {
+    /// Creates a tracker without transactions.
+    pub fn new() -> ConfirmationTracker {
+        ConfirmationTracker::default()
+    }
+
+    /// Starts tracking the given broadcast transaction.
+    pub fn track(&mut self, transaction: &Transaction) {
+        let txid = transaction.txid();
+        let wtxid = transaction.wtxid();
+        self.wtxids.insert(wtxid, txid);
+        self.transactions.entry(txid).or_insert(TrackedTx {
+            wtxid,
+            status: TxStatus::Unconfirmed,
+        });
+    }
+
+    /// Stops tracking the transaction with the given identifier. Returns `false` if
+    /// the transaction is not tracked.
+    pub fn untrack(&mut self, txid: &Txid) -> bool {
+        if let Some(tracked) = self.transactions.remove(txid) {
+            self.wtxids.remove(&tracked.wtxid);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Returns the identifier of the tracked transaction with the given witness identifier.
+    pub fn txid_by_wtxid(&self, wtxid: &Wtxid) -> Option<Txid> {
+        self.wtxids.get(wtxid).copied()
+    }
+
+    /// Returns the height of the best chain tip.
+    pub fn tip_height(&self) -> Option<u32> {
+        self.chain.keys().next_back().copied()
+    }
+
+    /// Returns the status of the tracked transaction.
+    pub fn status(&self, txid: &Txid) -> Option<TxStatus> {
+        self.transactions.get(txid).map(|tracked| tracked.status)
+    }
+
+    /// Returns the number of confirmations of the tracked transaction, which is zero
+    /// for the unconfirmed transaction.
+    pub fn confirmations(&self, txid: &Txid) -> Option<u32> {
+        let confirmations = match self.status(txid)? {
+            TxStatus::Unconfirmed => 0,
+            TxStatus::Confirmed { height, .. } => self
+                .tip_height()
+                .map_or(0, |tip| tip.saturating_sub(height) + 1),
+        };
+        Some(confirmations)
+    }
+
+    /// Connects the block header with the given height to the best chain.
+    ///
+    /// If the header replaces the known one, the blocks starting from this height are
+    /// disconnected. If the header does not follow the known previous header, the
+    /// previous block is disconnected as well, so the headers of the new chain should be
+    /// connected starting from the fork point.
+    pub fn connect_header(&mut self, header: &BlockHeader, height: u32) -> Vec<TrackerEvent> {
+        let block_hash = header.block_hash();
+        if self.chain.get(&height) == Some(&block_hash) {
+            return Vec::new();
+        }
+
+        let prev_hash = height
+            .checked_sub(1)
+            .and_then(|prev_height| self.chain.get(&prev_height));
+        let is_orphan = matches!(prev_hash, Some(prev_hash) if *prev_hash != header.prev_blockhash);
+        let fork_height = if is_orphan { height - 1 } else { height };
+        let events = self.disconnect_from(fork_height);
+        self.chain.insert(height, block_hash);
+        events
+    }
+
+    /// Connects the block with the given height to the best chain in the same way as
+    /// the [`connect_header`][connect-header] method does, and marks the tracked
+    /// transactions of the block as confirmed.
+    ///
+    /// [connect-header]: #method.connect_header
+    pub fn connect_block(&mut self, block: &Block, height: u32) -> Vec<TrackerEvent> {
+        let mut events = self.connect_header(&block.header, height);
+        let block_hash = block.block_hash();
+        for transaction in &block.txdata {
+            let txid = transaction.txid();
+            if let Some(tracked) = self.transactions.get_mut(&txid) {
+                let status = TxStatus::Confirmed { height, block_hash };
+                if tracked.status != status {
+                    tracked.status = status;
+                    events.push(TrackerEvent::Confirmed { txid, height });
+                }
+            }
+        }
+        events
+    }
+
+    fn disconnect_from(&mut self, height: u32) -> Vec<TrackerEvent> {
+        self.chain.split_off(&height);
+        let mut events = Vec::new();
+        for (txid, tracked) in &mut self.transactions {
+            match tracked.status {
+                TxStatus::Confirmed {
+                    height: tx_height, ..
+                } if tx_height >= height => {
+                    tracked.status = TxStatus::Unconfirmed;
+                    events.push(TrackerEvent::Unconfirmed { txid: *txid });
+                }
+                _ => {}
+            }
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{
+        blockdata::{
+            block::{Block, BlockHeader},
+            script::Script,
+            transaction::{OutPoint, Transaction, TxIn, TxOut},
+        },
+        BlockHash,
+    };
+
+    use super::{ConfirmationTracker, TrackerEvent, TxStatus};
+
+    fn block(prev_blockhash: BlockHash, nonce: u32, txdata: Vec<Transaction>) -> Block {
+        Block {
+            header: BlockHeader {
+                version: 1,
+                prev_blockhash,
+                merkle_root: Default::default(),
+                time: 0,
+                bits: 0,
+                nonce,
+            },
+            txdata,
+        }
+    }
+
+    /// Creates the segwit transaction, which spends the output with the given index.
+    fn transaction(vout: u32) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Default::default(), vout),
+                script_sig: Script::default(),
+                sequence: 0xFFFF_FFFF,
+                witness: vec![vec![1; 72]],
+            }],
+            output: vec![TxOut::default()],
+        }
+    }
+
+    #[test]
+    fn test_confirmation_tracker_reorg() {
+        let transaction = transaction(0);
+        let txid = transaction.txid();
+        let mut tracker = ConfirmationTracker::new();
+        tracker.track(&transaction);
+        assert_eq!(tracker.txid_by_wtxid(&transaction.wtxid()), Some(txid));
+        assert_eq!(tracker.confirmations(&txid), Some(0));
+
+        let first = block(Default::default(), 0, vec![]);
+        assert!(tracker.connect_block(&first, 100).is_empty());
+        let second = block(first.block_hash(), 0, vec![transaction.clone()]);
+        assert_eq!(
+            tracker.connect_block(&second, 101),
+            vec![TrackerEvent::Confirmed { txid, height: 101 }]
+        );
+        // The repeated block does not produce events.
+        assert!(tracker.connect_block(&second, 101).is_empty());
+        let third = block(second.block_hash(), 0, vec![]);
+        assert!(tracker.connect_header(&third.header, 102).is_empty());
+        assert_eq!(tracker.confirmations(&txid), Some(2));
+        assert_eq!(
+            tracker.status(&txid),
+            Some(TxStatus::Confirmed {
+                height: 101,
+                block_hash: second.block_hash(),
+            })
+        );
+
+        // The competing chain replaces the blocks starting from the height 101.
+        let forked_second = block(first.block_hash(), 1, vec![]);
+        let forked_third = block(forked_second.block_hash(), 1, vec![]);
+        assert_eq!(
+            tracker.connect_header(&forked_third.header, 102),
+            vec![TrackerEvent::Unconfirmed { txid }]
+        );
+        assert_eq!(tracker.confirmations(&txid), Some(0));
+        assert_eq!(tracker.tip_height(), Some(102));
+        assert!(tracker.connect_block(&forked_second, 101).is_empty());
+        assert_eq!(tracker.tip_height(), Some(101));
+
+        assert!(tracker.untrack(&txid));
+        assert_eq!(tracker.status(&txid), None);
+        assert_eq!(tracker.txid_by_wtxid(&transaction.wtxid()), None);
+        assert!(!tracker.untrack(&txid));
+    }
+
+    #[test]
+    fn test_confirmation_tracker_untracked() {
+        let (tracked, untracked) = (transaction(0), transaction(1));
+        let mut tracker = ConfirmationTracker::new();
+        assert_eq!(tracker.tip_height(), None);
+        tracker.track(&tracked);
+        assert_eq!(tracker.status(&untracked.txid()), None);
+        assert_eq!(tracker.confirmations(&untracked.txid()), None);
+        assert_eq!(tracker.txid_by_wtxid(&untracked.wtxid()), None);
+
+        // Only the tracked transactions produce events.
+        let first = block(
+            Default::default(),
+            0,
+            vec![untracked.clone(), tracked.clone()],
+        );
+        assert_eq!(
+            tracker.connect_block(&first, 10),
+            vec![TrackerEvent::Confirmed {
+                txid: tracked.txid(),
+                height: 10,
+            }]
+        );
+        assert_eq!(tracker.status(&untracked.txid()), None);
+        assert_eq!(tracker.confirmations(&tracked.txid()), Some(1));
+
+        // Tracking the confirmed transaction again does not reset its status.
+        tracker.track(&tracked);
+        assert_eq!(tracker.confirmations(&tracked.txid()), Some(1));
+    }
+
+    #[test]
+    fn test_confirmation_tracker_reconfirmation() {
+        let (early, late) = (transaction(0), transaction(1));
+        let mut tracker = ConfirmationTracker::new();
+        tracker.track(&early);
+        tracker.track(&late);
+
+        let first = block(Default::default(), 0, vec![early.clone()]);
+        let second = block(first.block_hash(), 0, vec![late.clone()]);
+        tracker.connect_block(&first, 100);
+        tracker.connect_block(&second, 101);
+        assert_eq!(tracker.confirmations(&early.txid()), Some(2));
+
+        // The fork below the tip disconnects only the transactions confirmed after it.
+        let forked_second = block(first.block_hash(), 1, vec![]);
+        assert_eq!(
+            tracker.connect_block(&forked_second, 101),
+            vec![TrackerEvent::Unconfirmed { txid: late.txid() }]
+        );
+        assert_eq!(tracker.confirmations(&early.txid()), Some(2));
+        assert_eq!(tracker.confirmations(&late.txid()), Some(0));
+
+        // The disconnected transaction is confirmed again in the new chain.
+        let forked_third = block(forked_second.block_hash(), 1, vec![late.clone()]);
+        assert_eq!(
+            tracker.connect_block(&forked_third, 102),
+            vec![TrackerEvent::Confirmed {
+                txid: late.txid(),
+                height: 102,
+            }]
+        );
+        assert_eq!(
+            tracker.status(&late.txid()),
+            Some(TxStatus::Confirmed {
+                height: 102,
+                block_hash: forked_third.block_hash(),
+            })
+        );
+        assert_eq!(tracker.confirmations(&early.txid()), Some(3));
+    }
+}
@@ -0,0 +1,176 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A hex-serializable wrapper around the bitcoin transaction.
+
+use bitcoin::{
+    blockdata::transaction::Transaction,
+    consensus::{self, encode},
+    hash_types::{Txid, Wtxid},
+};
+
+use std::{fmt, str::FromStr};
+
+use crate::Error;
+
+/// A bitcoin transaction which is represented as the hex string of its consensus encoding
+/// in the `Display`, `FromStr` and serde traits.
+///
+/// # Examples
+///
+/// ```
+/// use btc_transaction_utils::BtcTransaction;
+///
+/// let transaction: BtcTransaction =
+///     "02000000000101beccab33bc72bfc81b63fdec8a4a9a4719e4418bdb7b20e47b0\
+///      2074dc42f2d800000000017160014f3b1b3819c1290cd5d675c1319dc7d9d98d5\
+///      71bcfeffffff02dceffa0200000000160014368c6b7c38f0ff0839bf78d77544d\
+///      a96cb685bf28096980000000000160014284175e336fa10865fb4d1351c9e18e7\
+///      30f5d6f90247304402207c893c85d75e2230dde04f5a1e2c83c4f0b7d93213372\
+///      746eb2227b068260d840220705484b6ec70a8fc0d1f80c3a98079602595351b7a\
+///      9bca7caddb9a6adb0a3440012103150514f05f3e3f40c7b404b16f8a09c2c71ba\
+///      d3ba8da5dd1e411a7069cc080a004b91300"
+///         .parse()
+///         .unwrap();
+/// assert_eq!(transaction.as_ref().output.len(), 2);
+/// assert_eq!(transaction.to_string().parse::<BtcTransaction>().unwrap(), transaction);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BtcTransaction(Transaction);
+
+impl BtcTransaction {
+    /// Returns the transaction identifier.
+    pub fn txid(&self) -> Txid {
+        self.0.txid()
+    }
+
+    /// Returns the witness transaction identifier.
+    pub fn wtxid(&self) -> Wtxid {
+        self.0.wtxid()
+    }
+
+    /// Returns the consensus encoding of the transaction.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        consensus::serialize(&self.0)
+    }
+
+    /// Tries to decode the transaction from its consensus encoding.
+    pub fn from_bytes(bytes: &[u8]) -> Result<BtcTransaction, Error> {
+        consensus::deserialize(bytes)
+            .map(BtcTransaction)
+            .map_err(|error: encode::Error| Error::ConsensusEncoding(error.to_string()))
+    }
+}
+
+/// Decodes the transaction from the hex string of its consensus encoding.
+///
+/// Unlike the helpers in the `test_data` module, this function never panics, so it is
+/// suitable for the transactions received from untrusted sources.
+pub fn decode_tx(hex: &str) -> Result<Transaction, Error> {
+    hex.parse::<BtcTransaction>().map(Transaction::from)
+}
+
+impl fmt::Display for BtcTransaction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&hex::encode(self.to_bytes()))
+    }
+}
+
+impl FromStr for BtcTransaction {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = hex::decode(s)?;
+        BtcTransaction::from_bytes(&bytes)
+    }
+}
+
+impl From<Transaction> for BtcTransaction {
+    fn from(transaction: Transaction) -> BtcTransaction {
+        BtcTransaction(transaction)
+    }
+}
+
+impl From<BtcTransaction> for Transaction {
+    fn from(transaction: BtcTransaction) -> Transaction {
+        transaction.0
+    }
+}
+
+impl AsRef<Transaction> for BtcTransaction {
+    fn as_ref(&self) -> &Transaction {
+        &self.0
+    }
+}
+
+impl AsMut<Transaction> for BtcTransaction {
+    fn as_mut(&mut self) -> &mut Transaction {
+        &mut self.0
+    }
+}
+
+impl ::serde::Serialize for BtcTransaction {
+    fn serialize<S>(&self, ser: S) -> ::std::result::Result<S::Ok, S::Error>
+    where
+        S: ::serde::Serializer,
+    {
+        ::serde_str::serialize(self, ser)
+    }
+}
+
+impl<'de> ::serde::Deserialize<'de> for BtcTransaction {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: ::serde::Deserializer<'de>,
+    {
+        ::serde_str::deserialize(deserializer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{decode_tx, test_data::btc_tx_from_hex, BtcTransaction, Error};
+
+    #[test]
+    fn test_btc_transaction_hex() {
+        let hex = "02000000000101beccab33bc72bfc81b63fdec8a4a9a4719e4418bdb7b20e47b02074dc42f2d80000\
+                   0000017160014f3b1b3819c1290cd5d675c1319dc7d9d98d571bcfeffffff02dceffa020000000016\
+                   0014368c6b7c38f0ff0839bf78d77544da96cb685bf28096980000000000160014284175e336fa10\
+                   865fb4d1351c9e18e730f5d6f90247304402207c893c85d75e2230dde04f5a1e2c83c4f0b7d93213\
+                   372746eb2227b068260d840220705484b6ec70a8fc0d1f80c3a98079602595351b7a9bca7caddb9a\
+                   6adb0a3440012103150514f05f3e3f40c7b404b16f8a09c2c71bad3ba8da5dd1e411a7069cc080a0\
+                   04b91300";
+        let transaction = hex.parse::<BtcTransaction>().unwrap();
+        assert_eq!(transaction.to_string(), hex);
+        assert_eq!(transaction.as_ref(), &btc_tx_from_hex(hex));
+        assert_eq!(transaction.txid(), btc_tx_from_hex(hex).txid());
+        assert_ne!(transaction.txid().as_hash(), transaction.wtxid().as_hash());
+
+        let json = serde_json::to_string(&transaction).unwrap();
+        assert_eq!(json, format!("\"{}\"", hex));
+        assert_eq!(
+            serde_json::from_str::<BtcTransaction>(&json).unwrap(),
+            transaction
+        );
+
+        assert!(matches!(
+            "0200".parse::<BtcTransaction>(),
+            Err(Error::ConsensusEncoding(_))
+        ));
+        assert!(matches!("zz".parse::<BtcTransaction>(), Err(Error::Hex(_))));
+
+        assert_eq!(decode_tx(hex).unwrap(), btc_tx_from_hex(hex));
+        assert!(decode_tx(&hex[..hex.len() - 2]).is_err());
+    }
+}
@@ -0,0 +1,721 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The backend for the Trezor hardware wallet, which uses the `signTransaction`
+//! method of the Trezor Connect.
+//!
+//! The device does not sign the arbitrary signature hashes, so the whole transaction
+//! is sent to the device in the [`SignTransaction`][sign-transaction] request. The `P2WSH`
+//! inputs and change outputs carry the multisig description, so the device checks the
+//! redeem script itself and displays the `m-of-n` policy to the user.
+//!
+//! The request and the response are serialized into the JSON format expected by the
+//! Trezor Connect, so the caller can pass them to the Connect by any means. The
+//! [`TrezorSigner`][trezor-signer] implements the [`ExternalSigner`][external-signer]
+//! on top of the [`TrezorConnect`][trezor-connect] trait, which performs the call.
+//!
+//! [sign-transaction]: struct.SignTransaction.html
+//! [trezor-signer]: struct.TrezorSigner.html
+//! [trezor-connect]: trait.TrezorConnect.html
+//! [external-signer]: ../trait.ExternalSigner.html
+
+use bitcoin::{
+    blockdata::{
+        script::{Instruction, Script},
+        transaction::Transaction,
+    },
+    network::constants::Network,
+    util::{address::Address, bip32::DerivationPath},
+    PublicKey,
+};
+use serde::{Deserialize, Serialize};
+
+use std::cell::RefCell;
+
+use crate::{
+    multisig::RedeemScript, p2wpk, p2wsh, sign, Amount, Error, ExternalSigner, InputSignature,
+    TxInRef,
+};
+
+/// The spending conditions of the input or the change output.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TrezorScript {
+    /// The `P2WPK` output of the given public key.
+    P2wpk(PublicKey),
+    /// The `P2WSH` output of the given multisig redeem script.
+    P2wsh(RedeemScript),
+}
+
+impl TrezorScript {
+    fn script_pubkey(&self) -> Script {
+        match self {
+            TrezorScript::P2wpk(public_key) => p2wpk::script_pubkey(public_key),
+            TrezorScript::P2wsh(redeem_script) => p2wsh::script_pubkey(redeem_script),
+        }
+    }
+
+    fn script_code(&self) -> Script {
+        match self {
+            // The network does not affect the script.
+            TrezorScript::P2wpk(public_key) => {
+                Address::p2pkh(public_key, Network::Bitcoin).script_pubkey()
+            }
+            TrezorScript::P2wsh(redeem_script) => redeem_script.script.clone(),
+        }
+    }
+
+    fn multisig(&self) -> Option<Multisig> {
+        match self {
+            TrezorScript::P2wpk(_) => None,
+            TrezorScript::P2wsh(redeem_script) => Some(Multisig::new(redeem_script)),
+        }
+    }
+}
+
+/// The transaction input to be signed by the device.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrezorInput {
+    /// The value of the spent output.
+    pub value: Amount,
+    /// The derivation path of the signing key.
+    pub path: DerivationPath,
+    /// The spending conditions of the spent output.
+    pub script: TrezorScript,
+}
+
+/// The hierarchical deterministic node of the multisig participant.
+///
+/// The redeem script contains the plain public keys, so they are described as the nodes
+/// without the chain code and with the empty derivation path.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct HdNode {
+    depth: u8,
+    fingerprint: u32,
+    child_num: u32,
+    chain_code: String,
+    #[serde(with = "serde_str")]
+    public_key: PublicKey,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct MultisigPublicKey {
+    node: HdNode,
+    address_n: Vec<u32>,
+}
+
+/// The multisig description of the redeem script.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct Multisig {
+    pubkeys: Vec<MultisigPublicKey>,
+    signatures: Vec<String>,
+    m: usize,
+}
+
+impl Multisig {
+    fn new(redeem_script: &RedeemScript) -> Multisig {
+        let content = redeem_script.content();
+        Multisig {
+            signatures: vec![String::new(); content.public_keys.len()],
+            pubkeys: content
+                .public_keys
+                .into_iter()
+                .map(|public_key| MultisigPublicKey {
+                    node: HdNode {
+                        depth: 0,
+                        fingerprint: 0,
+                        child_num: 0,
+                        chain_code: hex::encode([0; 32]),
+                        public_key,
+                    },
+                    address_n: Vec::new(),
+                })
+                .collect(),
+            m: content.quorum,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct InputParams {
+    address_n: Vec<u32>,
+    prev_hash: String,
+    prev_index: u32,
+    amount: String,
+    sequence: u32,
+    script_type: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    multisig: Option<Multisig>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+enum OutputParams {
+    External {
+        address: String,
+        amount: String,
+        script_type: &'static str,
+    },
+    Change {
+        address_n: Vec<u32>,
+        amount: String,
+        script_type: &'static str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        multisig: Option<Multisig>,
+    },
+    OpReturn {
+        op_return_data: String,
+        amount: String,
+        script_type: &'static str,
+    },
+}
+
+/// The parameters of the `signTransaction` method of the Trezor Connect.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SignTransactionParams {
+    coin: &'static str,
+    inputs: Vec<InputParams>,
+    outputs: Vec<OutputParams>,
+    version: i32,
+    locktime: u32,
+    push: bool,
+}
+
+/// The payload of the successful response of the `signTransaction` method.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SignedTransaction {
+    /// The hex encoded `DER` signatures of the inputs without the sighash type.
+    pub signatures: Vec<String>,
+    /// The hex encoded signed transaction.
+    #[serde(rename = "serializedTx")]
+    pub serialized_tx: String,
+}
+
+/// The request to sign the transaction by the Trezor device.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SignTransaction {
+    transaction: Transaction,
+    inputs: Vec<TrezorInput>,
+    network: Network,
+    change: Vec<Option<(DerivationPath, TrezorScript)>>,
+}
+
+impl SignTransaction {
+    /// Creates a request to sign all inputs of the given transaction, the inputs are given
+    /// in the transaction input order.
+    pub fn new(
+        transaction: Transaction,
+        inputs: Vec<TrezorInput>,
+        network: Network,
+    ) -> Result<SignTransaction, Error> {
+        ensure!(
+            transaction.input.len() == inputs.len(),
+            Error::InputsCount {
+                transaction: transaction.input.len(),
+                inputs: inputs.len(),
+            }
+        );
+        ensure!(!inputs.is_empty(), Error::NoInputs);
+        let change = vec![None; transaction.output.len()];
+        Ok(SignTransaction {
+            transaction,
+            inputs,
+            network,
+            change,
+        })
+    }
+
+    /// Marks the output with the given index as the change, which is sent to the script
+    /// controlled by the device. The device does not ask the user to confirm such output.
+    pub fn change_output(
+        &mut self,
+        index: usize,
+        path: DerivationPath,
+        script: TrezorScript,
+    ) -> Result<&mut Self, Error> {
+        let output = self
+            .transaction
+            .output
+            .get(index)
+            .ok_or(Error::NoSuchOutput(index))?;
+        ensure!(
+            output.script_pubkey == script.script_pubkey(),
+            Error::ScriptMismatch
+        );
+        self.change[index] = Some((path, script));
+        Ok(self)
+    }
+
+    /// Returns the parameters of the `signTransaction` method.
+    pub fn to_params(&self) -> Result<SignTransactionParams, Error> {
+        let inputs = self
+            .transaction
+            .input
+            .iter()
+            .zip(&self.inputs)
+            .map(|(txin, input)| InputParams {
+                address_n: path_to_vec(&input.path),
+                prev_hash: txin.previous_output.txid.to_string(),
+                prev_index: txin.previous_output.vout,
+                amount: input.value.as_sat().to_string(),
+                sequence: txin.sequence,
+                script_type: "SPENDWITNESS",
+                multisig: input.script.multisig(),
+            })
+            .collect();
+
+        let outputs = self
+            .transaction
+            .output
+            .iter()
+            .zip(&self.change)
+            .enumerate()
+            .map(|(index, (output, change))| {
+                let amount = output.value.to_string();
+                if let Some((path, script)) = change {
+                    return Ok(OutputParams::Change {
+                        address_n: path_to_vec(path),
+                        amount,
+                        script_type: "PAYTOWITNESS",
+                        multisig: script.multisig(),
+                    });
+                }
+                if output.script_pubkey.is_op_return() {
+                    return Ok(OutputParams::OpReturn {
+                        op_return_data: hex::encode(op_return_data(&output.script_pubkey)),
+                        amount,
+                        script_type: "PAYTOOPRETURN",
+                    });
+                }
+                let address = Address::from_script(&output.script_pubkey, self.network)
+                    .ok_or(Error::NoOutputAddress(index))?;
+                Ok(OutputParams::External {
+                    address: address.to_string(),
+                    amount,
+                    script_type: "PAYTOADDRESS",
+                })
+            })
+            .collect::<Result<_, Error>>()?;
+
+        Ok(SignTransactionParams {
+            coin: coin_name(self.network),
+            inputs,
+            outputs,
+            version: self.transaction.version,
+            locktime: self.transaction.lock_time,
+            push: false,
+        })
+    }
+
+    /// Checks the response of the device and returns the input signatures.
+    ///
+    /// The signed transaction must be the requested one and each signature must be made
+    /// by the key of the corresponding input.
+    pub fn to_signatures(
+        &self,
+        response: &SignedTransaction,
+    ) -> Result<Vec<InputSignature>, Error> {
+        let signed = crate::decode_tx(&response.serialized_tx)?;
+        ensure!(
+            signed.txid() == self.transaction.txid(),
+            Error::ExternalSigner("The device signed another transaction.".to_owned())
+        );
+        ensure!(
+            response.signatures.len() == self.inputs.len(),
+            Error::ExternalSigner(format!(
+                "The device returned {} signatures for {} inputs.",
+                response.signatures.len(),
+                self.inputs.len()
+            ))
+        );
+
+        response
+            .signatures
+            .iter()
+            .zip(&self.inputs)
+            .enumerate()
+            .map(|(index, (signature, input))| {
+                self.to_signature(index, signature, input)
+                    .map_err(|error| Error::input(index, error))
+            })
+            .collect()
+    }
+
+    fn to_signature(
+        &self,
+        index: usize,
+        signature: &str,
+        input: &TrezorInput,
+    ) -> Result<InputSignature, Error> {
+        let mut signature = hex::decode(signature)?;
+        signature.push(bitcoin::SigHashType::All as u8);
+        let signature = InputSignature::from_bytes(signature)?;
+
+        let txin = TxInRef::new(&self.transaction, index);
+        let script_code = input.script.script_code();
+        let verify = |public_key: &PublicKey| {
+            sign::verify_input_signature(
+                sign::verification_context(),
+                txin,
+                &script_code,
+                input.value.as_sat(),
+                public_key,
+                &signature,
+            )
+        };
+        match &input.script {
+            TrezorScript::P2wpk(public_key) => verify(public_key)?,
+            TrezorScript::P2wsh(redeem_script) => {
+                let is_valid = redeem_script
+                    .public_keys()
+                    .any(|public_key| verify(&public_key).is_ok());
+                ensure!(is_valid, Error::KeyNotInScript);
+            }
+        }
+        Ok(signature)
+    }
+
+    /// Returns the index of the input with the given signature hash.
+    fn input_index(&self, sighash: &[u8; 32]) -> Result<usize, Error> {
+        self.inputs
+            .iter()
+            .enumerate()
+            .position(|(index, input)| {
+                let txin = TxInRef::new(&self.transaction, index);
+                let hash = sign::signature_hash(txin, &input.script.script_code(), input.value);
+                hash[..] == sighash[..]
+            })
+            .ok_or(Error::SighashMismatch)
+    }
+}
+
+/// The connection to the Trezor Connect.
+pub trait TrezorConnect {
+    /// Calls the `signTransaction` method with the given parameters and returns the payload
+    /// of the successful response.
+    fn sign_transaction(&self, params: &SignTransactionParams) -> Result<SignedTransaction, Error>;
+}
+
+/// The external signer, which signs the inputs of the given transaction with the key
+/// of the Trezor device.
+///
+/// The device signs the whole transaction at the first signature hash request, and the
+/// returned signatures are reused for the other inputs. Only the signature hashes of the
+/// transaction inputs are signed, other ones are rejected with the
+/// [`Error::SighashMismatch`][sighash-mismatch].
+///
+/// [sighash-mismatch]: ../enum.Error.html#variant.SighashMismatch
+#[derive(Debug)]
+pub struct TrezorSigner<C> {
+    connect: C,
+    request: SignTransaction,
+    public_key: PublicKey,
+    signatures: RefCell<Vec<InputSignature>>,
+}
+
+impl<C: TrezorConnect> TrezorSigner<C> {
+    /// Creates a signer for the given request, the device signs the inputs with the key
+    /// corresponding to the given public key.
+    pub fn new(connect: C, request: SignTransaction, public_key: PublicKey) -> TrezorSigner<C> {
+        TrezorSigner {
+            connect,
+            request,
+            public_key,
+            signatures: RefCell::default(),
+        }
+    }
+}
+
+impl<C: TrezorConnect> ExternalSigner for TrezorSigner<C> {
+    fn public_key(&self) -> PublicKey {
+        self.public_key
+    }
+
+    fn sign_sighash(&self, sighash: &[u8; 32]) -> Result<Vec<u8>, Error> {
+        let index = self.request.input_index(sighash)?;
+        let mut signatures = self.signatures.borrow_mut();
+        // The request has at least one input, so the empty list means that the device
+        // has not signed the transaction yet.
+        if signatures.is_empty() {
+            let response = self.connect.sign_transaction(&self.request.to_params()?)?;
+            *signatures = self.request.to_signatures(&response)?;
+        }
+        Ok(signatures[index].content().to_vec())
+    }
+}
+
+fn coin_name(network: Network) -> &'static str {
+    match network {
+        Network::Bitcoin => "btc",
+        Network::Regtest => "regtest",
+        Network::Testnet | Network::Signet => "test",
+    }
+}
+
+/// Returns the data pushed after the `OP_RETURN` opcode.
+fn op_return_data(script: &Script) -> Vec<u8> {
+    script
+        .instructions()
+        .skip(1)
+        .filter_map(|instruction| match instruction {
+            Ok(Instruction::PushBytes(bytes)) => Some(bytes),
+            _ => None,
+        })
+        .flatten()
+        .copied()
+        .collect()
+}
+
+fn path_to_vec(path: &DerivationPath) -> Vec<u32> {
+    path.as_ref().iter().copied().map(u32::from).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{
+        blockdata::{
+            opcodes::all::OP_RETURN,
+            script::{Builder, Script},
+            transaction::{OutPoint, Transaction, TxIn, TxOut},
+        },
+        network::constants::Network,
+        util::bip32::DerivationPath,
+    };
+    use serde_json::json;
+
+    use std::{cell::Cell, str::FromStr};
+
+    use super::{
+        SignTransaction, SignTransactionParams, SignedTransaction, TrezorConnect, TrezorInput,
+        TrezorScript, TrezorSigner,
+    };
+    use crate::{
+        multisig::RedeemScriptBuilder, p2wpk, p2wsh, test_data::keypair_from_wif, Amount,
+        BtcTransaction, Error, ExternalSigner, TxInRef,
+    };
+
+    /// The connection, which replies with the prepared response and counts the calls.
+    #[derive(Debug)]
+    struct TestConnect {
+        response: SignedTransaction,
+        calls: Cell<usize>,
+    }
+
+    impl TrezorConnect for &TestConnect {
+        fn sign_transaction(
+            &self,
+            params: &SignTransactionParams,
+        ) -> Result<SignedTransaction, Error> {
+            assert_eq!(params.inputs.len(), 2);
+            self.calls.set(self.calls.get() + 1);
+            Ok(self.response.clone())
+        }
+    }
+
+    #[test]
+    fn test_trezor_sign_transaction() {
+        let keypairs = vec![
+            "cPHmynxvqfr7sXsJcohiGzoPGBShggxL6VWUdW14skohFZ1LQoeV",
+            "cTtSTL1stvg2tmK349WTmQDfHLMLqkkxwuo8ZJeQov9zEhtYtb4u",
+        ]
+        .into_iter()
+        .map(keypair_from_wif)
+        .collect::<Vec<_>>();
+        let redeem_script = RedeemScriptBuilder::with_public_keys(keypairs.iter().map(|x| x.0))
+            .quorum(1)
+            .to_script()
+            .unwrap();
+        let transaction = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: (0..2)
+                .map(|vout| TxIn {
+                    previous_output: OutPoint::new(Default::default(), vout),
+                    script_sig: Script::default(),
+                    sequence: 0xFFFF_FFFD,
+                    witness: Vec::default(),
+                })
+                .collect(),
+            output: vec![
+                TxOut {
+                    value: 10_000,
+                    script_pubkey: p2wpk::script_pubkey(&keypairs[1].0),
+                },
+                TxOut {
+                    value: 30_000,
+                    script_pubkey: p2wsh::script_pubkey(&redeem_script),
+                },
+                TxOut {
+                    value: 0,
+                    script_pubkey: Builder::new()
+                        .push_opcode(OP_RETURN)
+                        .push_slice(b"exonum")
+                        .into_script(),
+                },
+            ],
+        };
+        let path = DerivationPath::from_str("m/84'/1'/0'/0/0").unwrap();
+        let inputs = vec![
+            TrezorInput {
+                value: Amount::from_sat(20_000),
+                path: path.clone(),
+                script: TrezorScript::P2wsh(redeem_script.clone()),
+            },
+            TrezorInput {
+                value: Amount::from_sat(25_000),
+                path: path.clone(),
+                script: TrezorScript::P2wpk(keypairs[0].0),
+            },
+        ];
+
+        assert_eq!(
+            SignTransaction::new(transaction.clone(), inputs[..1].to_vec(), Network::Testnet)
+                .unwrap_err(),
+            Error::InputsCount {
+                transaction: 2,
+                inputs: 1,
+            }
+        );
+        let mut request =
+            SignTransaction::new(transaction.clone(), inputs, Network::Testnet).unwrap();
+        assert_eq!(
+            request
+                .change_output(3, path.clone(), TrezorScript::P2wpk(keypairs[0].0))
+                .unwrap_err(),
+            Error::NoSuchOutput(3)
+        );
+        // The change must be sent to the given script.
+        assert_eq!(
+            request
+                .change_output(0, path.clone(), TrezorScript::P2wsh(redeem_script.clone()))
+                .unwrap_err(),
+            Error::ScriptMismatch
+        );
+        request
+            .change_output(1, path.clone(), TrezorScript::P2wsh(redeem_script.clone()))
+            .unwrap();
+
+        let params = serde_json::to_value(request.to_params().unwrap()).unwrap();
+        assert_eq!(params["coin"], "test");
+        assert_eq!(params["inputs"][0]["script_type"], "SPENDWITNESS");
+        assert_eq!(params["inputs"][0]["amount"], "20000");
+        assert_eq!(params["inputs"][0]["multisig"]["m"], 1);
+        assert_eq!(
+            params["inputs"][0]["multisig"]["pubkeys"][1]["node"]["public_key"],
+            keypairs[1].0.to_string()
+        );
+        assert!(params["inputs"][1].get("multisig").is_none());
+        assert_eq!(
+            params["outputs"][0],
+            json!({
+                "address": p2wpk::address(&keypairs[1].0, Network::Testnet).to_string(),
+                "amount": "10000",
+                "script_type": "PAYTOADDRESS",
+            })
+        );
+        assert_eq!(
+            params["outputs"][1]["address_n"],
+            json!([0x8000_0054_u32, 0x8000_0001_u32, 0x8000_0000_u32, 0, 0])
+        );
+        assert_eq!(
+            params["outputs"][1]["multisig"]["pubkeys"][0]["address_n"],
+            json!([])
+        );
+        assert_eq!(
+            params["outputs"][2]["op_return_data"],
+            hex::encode(b"exonum")
+        );
+
+        // Emulates the device response.
+        let mut signed = transaction.clone();
+        let signatures = vec![
+            p2wsh::InputSigner::new(redeem_script.clone())
+                .sign_input(TxInRef::new(&transaction, 0), 20_000, &keypairs[1].1.key)
+                .unwrap(),
+            p2wpk::InputSigner::new(keypairs[0].0, Network::Testnet)
+                .sign_input(TxInRef::new(&transaction, 1), 25_000, &keypairs[0].1.key)
+                .unwrap(),
+        ];
+        signed.input[0].witness = vec![vec![1; 72]];
+        let mut response = SignedTransaction {
+            signatures: signatures
+                .iter()
+                .map(|signature| hex::encode(signature.content()))
+                .collect(),
+            serialized_tx: BtcTransaction::from(signed).to_string(),
+        };
+        assert_eq!(request.to_signatures(&response).unwrap(), signatures);
+
+        // The external signer asks the device once and reuses the signatures.
+        let connect = TestConnect {
+            response: response.clone(),
+            calls: Cell::new(0),
+        };
+        let trezor_signer = TrezorSigner::new(&connect, request.clone(), keypairs[1].0);
+        assert_eq!(
+            trezor_signer.sign_sighash(&[0; 32]).unwrap_err(),
+            Error::SighashMismatch
+        );
+        assert_eq!(connect.calls.get(), 0);
+        let signature = p2wsh::InputSigner::new(redeem_script.clone())
+            .sign_input_external(TxInRef::new(&transaction, 0), 20_000, &trezor_signer)
+            .unwrap();
+        assert_eq!(signature, signatures[0]);
+        // The second input is signed by another key.
+        assert!(p2wpk::InputSigner::new(keypairs[1].0, Network::Testnet)
+            .sign_input_external(TxInRef::new(&transaction, 1), 25_000, &trezor_signer)
+            .is_err());
+        assert_eq!(connect.calls.get(), 1);
+
+        // The signature made by another key is rejected.
+        response.signatures.swap(0, 1);
+        assert!(request.to_signatures(&response).is_err());
+        // The response must contain the requested transaction.
+        response.serialized_tx = BtcTransaction::from(Transaction {
+            lock_time: 1,
+            ..transaction
+        })
+        .to_string();
+        assert_eq!(
+            request.to_signatures(&response).unwrap_err(),
+            Error::ExternalSigner("The device signed another transaction.".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_trezor_output_without_address() {
+        let (public_key, _) =
+            keypair_from_wif("cPHmynxvqfr7sXsJcohiGzoPGBShggxL6VWUdW14skohFZ1LQoeV");
+        let transaction = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::default(),
+                script_sig: Script::default(),
+                sequence: 0xFFFF_FFFD,
+                witness: Vec::default(),
+            }],
+            output: vec![TxOut {
+                value: 10_000,
+                script_pubkey: Builder::new().push_int(1).into_script(),
+            }],
+        };
+        let inputs = vec![TrezorInput {
+            value: Amount::from_sat(20_000),
+            path: DerivationPath::from_str("m/84'/1'/0'/0/0").unwrap(),
+            script: TrezorScript::P2wpk(public_key),
+        }];
+        let request = SignTransaction::new(transaction, inputs, Network::Testnet).unwrap();
+        assert_eq!(request.to_params().unwrap_err(), Error::NoOutputAddress(0));
+    }
+}
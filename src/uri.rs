@@ -0,0 +1,307 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Building and parsing of the [BIP-21] payment URIs.
+//!
+//! [BIP-21]: https://github.com/bitcoin/bips/blob/master/bip-0021.mediawiki
+
+use bitcoin::{
+    network::constants::Network,
+    util::address::{Address, Payload},
+};
+use thiserror::Error;
+
+use std::fmt::{self, Write};
+
+use crate::{Amount, Error};
+
+/// The scheme of the payment URIs.
+const SCHEME: &str = "bitcoin:";
+/// The number of satoshis in one bitcoin.
+const SATOSHIS_PER_BITCOIN: u64 = 100_000_000;
+/// The number of decimal places in the bitcoin amount.
+const AMOUNT_DECIMALS: usize = 8;
+
+/// Possible errors of the payment URI parsing.
+#[derive(Debug, Clone, Copy, Error, PartialEq)]
+pub enum UriError {
+    /// The URI does not start with the `bitcoin:` scheme.
+    #[error("The URI does not start with the `bitcoin:` scheme.")]
+    Scheme,
+    /// The URI contains an incorrect address.
+    #[error("The URI contains an incorrect address.")]
+    Address,
+    /// The address belongs to the other network.
+    #[error("The address belongs to the {actual} network instead of {expected}.")]
+    Network {
+        /// The expected network.
+        expected: Network,
+        /// The network of the address.
+        actual: Network,
+    },
+    /// The amount is not a correct decimal number of bitcoins.
+    #[error("The amount is not a correct decimal number of bitcoins.")]
+    Amount,
+    /// The URI contains a required parameter which is not supported.
+    #[error("The URI contains an unsupported required parameter.")]
+    RequiredParameter,
+    /// The URI contains an incorrect percent-encoded sequence.
+    #[error("The URI contains an incorrect percent-encoded sequence.")]
+    Encoding,
+}
+
+/// The payment request encoded in the `bitcoin:` URI.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PaymentUri {
+    /// The address to pay to.
+    pub address: Address,
+    /// The requested amount.
+    pub amount: Option<Amount>,
+    /// The label of the recipient.
+    pub label: Option<String>,
+    /// The message which describes the payment.
+    pub message: Option<String>,
+}
+
+impl PaymentUri {
+    /// Creates a payment URI for the given address without additional parameters.
+    pub fn new(address: Address) -> PaymentUri {
+        PaymentUri {
+            address,
+            amount: None,
+            label: None,
+            message: None,
+        }
+    }
+
+    /// Parses the payment URI and checks that the address belongs to the given network.
+    ///
+    /// The unknown optional parameters are ignored, and the unknown parameters with
+    /// the `req-` prefix are rejected as required by the specification.
+    pub fn parse(uri: &str, network: Network) -> Result<PaymentUri, Error> {
+        ensure!(
+            uri.len() >= SCHEME.len() && uri[..SCHEME.len()].eq_ignore_ascii_case(SCHEME),
+            UriError::Scheme
+        );
+        let uri = &uri[SCHEME.len()..];
+        let (address, query) = match uri.find('?') {
+            Some(pos) => (&uri[..pos], Some(&uri[pos + 1..])),
+            None => (uri, None),
+        };
+
+        let address = address.parse::<Address>().map_err(|_| UriError::Address)?;
+        ensure!(
+            is_same_network(&address, network),
+            UriError::Network {
+                expected: network,
+                actual: address.network,
+            }
+        );
+
+        let mut payment = PaymentUri::new(address);
+        for parameter in query.into_iter().flat_map(|query| query.split('&')) {
+            let (key, value) = match parameter.find('=') {
+                Some(pos) => (&parameter[..pos], &parameter[pos + 1..]),
+                None => (parameter, ""),
+            };
+            match key {
+                "amount" => payment.amount = Some(parse_amount(value)?),
+                "label" => payment.label = Some(percent_decode(value)?),
+                "message" => payment.message = Some(percent_decode(value)?),
+                _ if key.starts_with("req-") => return Err(UriError::RequiredParameter.into()),
+                _ => {}
+            }
+        }
+        Ok(payment)
+    }
+}
+
+impl fmt::Display for PaymentUri {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", SCHEME, self.address)?;
+        let mut separator = '?';
+        if let Some(amount) = self.amount {
+            write!(f, "{}amount={}", separator, format_amount(amount))?;
+            separator = '&';
+        }
+        if let Some(label) = &self.label {
+            write!(f, "{}label={}", separator, percent_encode(label))?;
+            separator = '&';
+        }
+        if let Some(message) = &self.message {
+            write!(f, "{}message={}", separator, percent_encode(message))?;
+        }
+        Ok(())
+    }
+}
+
+/// The base58 addresses of the test networks are indistinguishable, and the signet network
+/// shares the bech32 prefix with the testnet one, so such addresses are parsed as testnet ones.
+pub(crate) fn is_same_network(address: &Address, network: Network) -> bool {
+    match (&address.payload, address.network, network) {
+        (_, Network::Testnet, Network::Signet) => true,
+        (Payload::WitnessProgram { .. }, actual, expected) => actual == expected,
+        (_, Network::Testnet, Network::Regtest) => true,
+        (_, actual, expected) => actual == expected,
+    }
+}
+
+fn parse_amount(value: &str) -> Result<Amount, UriError> {
+    let (integer, fraction) = match value.find('.') {
+        Some(pos) => (&value[..pos], &value[pos + 1..]),
+        None => (value, ""),
+    };
+    let is_digits = |s: &str| s.bytes().all(|c| c.is_ascii_digit());
+    ensure!(
+        !integer.is_empty() && is_digits(integer) && is_digits(fraction),
+        UriError::Amount
+    );
+    ensure!(fraction.len() <= AMOUNT_DECIMALS, UriError::Amount);
+
+    let integer = integer.parse::<u64>().map_err(|_| UriError::Amount)?;
+    let fraction = format!("{:0<width$}", fraction, width = AMOUNT_DECIMALS)
+        .parse::<u64>()
+        .map_err(|_| UriError::Amount)?;
+    integer
+        .checked_mul(SATOSHIS_PER_BITCOIN)
+        .and_then(|value| value.checked_add(fraction))
+        .map(Amount::from_sat)
+        .ok_or(UriError::Amount)
+}
+
+fn format_amount(amount: Amount) -> String {
+    let amount = amount.as_sat();
+    let integer = amount / SATOSHIS_PER_BITCOIN;
+    let fraction = amount % SATOSHIS_PER_BITCOIN;
+    if fraction == 0 {
+        integer.to_string()
+    } else {
+        let fraction = format!("{:0width$}", fraction, width = AMOUNT_DECIMALS);
+        format!("{}.{}", integer, fraction.trim_end_matches('0'))
+    }
+}
+
+fn percent_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => write!(encoded, "%{:02X}", byte).unwrap(),
+        }
+    }
+    encoded
+}
+
+fn percent_decode(value: &str) -> Result<String, UriError> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut iter = value.bytes();
+    while let Some(byte) = iter.next() {
+        if byte == b'%' {
+            let hex = [
+                iter.next().ok_or(UriError::Encoding)?,
+                iter.next().ok_or(UriError::Encoding)?,
+            ];
+            let decoded = hex::decode(hex).map_err(|_| UriError::Encoding)?;
+            bytes.extend_from_slice(&decoded);
+        } else {
+            bytes.push(byte);
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| UriError::Encoding)
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{network::constants::Network, util::address::Address};
+
+    use crate::{
+        p2wpk,
+        test_data::deterministic_keypair,
+        uri::{PaymentUri, UriError},
+        Amount, Error,
+    };
+
+    #[test]
+    fn test_payment_uri_roundtrip() {
+        let (public_key, _) = deterministic_keypair(Network::Testnet);
+        let address = p2wpk::address(&public_key, Network::Testnet);
+
+        let mut payment = PaymentUri::new(address.clone());
+        assert_eq!(payment.to_string(), format!("bitcoin:{}", address));
+        payment.amount = Some(Amount::from_sat(2_050_000));
+        payment.label = Some("Exonum & Co".to_owned());
+        payment.message = Some("Donation for project xyz".to_owned());
+
+        let uri = payment.to_string();
+        assert_eq!(
+            uri,
+            format!(
+                "bitcoin:{}?amount=0.0205&label=Exonum%20%26%20Co\
+                 &message=Donation%20for%20project%20xyz",
+                address
+            )
+        );
+        assert_eq!(PaymentUri::parse(&uri, Network::Testnet).unwrap(), payment);
+        // Signet shares the bech32 prefix with testnet.
+        assert!(PaymentUri::parse(&uri, Network::Signet).is_ok());
+        assert!(PaymentUri::parse(&uri, Network::Regtest).is_err());
+    }
+
+    #[test]
+    fn test_payment_uri_parse() {
+        let address = "1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2";
+        let payment = PaymentUri::parse(
+            &format!("BITCOIN:{}?amount=20.3&label=Luke-Jr&other=1", address),
+            Network::Bitcoin,
+        )
+        .unwrap();
+        assert_eq!(payment.address, address.parse::<Address>().unwrap());
+        assert_eq!(payment.amount, Some(Amount::from_btc(20.3).unwrap()));
+        assert_eq!(payment.label.as_deref(), Some("Luke-Jr"));
+        assert_eq!(payment.message, None);
+
+        let parse = |uri: &str| PaymentUri::parse(uri, Network::Bitcoin);
+        assert_eq!(
+            parse(&format!("litecoin:{}", address)),
+            Err(Error::from(UriError::Scheme))
+        );
+        assert_eq!(parse("bitcoin:xyz"), Err(Error::from(UriError::Address)));
+        assert_eq!(
+            PaymentUri::parse(&format!("bitcoin:{}", address), Network::Testnet),
+            Err(Error::from(UriError::Network {
+                expected: Network::Testnet,
+                actual: Network::Bitcoin,
+            }))
+        );
+        for amount in &["1.123456789", "-1", "1e3", ".5", "184467440737.1"] {
+            assert_eq!(
+                parse(&format!("bitcoin:{}?amount={}", address, amount)),
+                Err(Error::from(UriError::Amount))
+            );
+        }
+        assert_eq!(
+            parse(&format!(
+                "bitcoin:{}?req-somethingyoudontunderstand=50",
+                address
+            )),
+            Err(Error::from(UriError::RequiredParameter))
+        );
+        assert_eq!(
+            parse(&format!("bitcoin:{}?label=%E", address)),
+            Err(Error::from(UriError::Encoding))
+        );
+    }
+}
@@ -0,0 +1,414 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! An in-memory tracker of the unspent outputs which pay to the watched scripts.
+//!
+//! The set is updated by applying transactions and blocks, so the tracked outputs
+//! can be passed directly to the [`TransactionBuilder`][builder] and to the input signers.
+//!
+//! [builder]: ../builder/struct.TransactionBuilder.html
+
+use bitcoin::{
+    blockdata::{
+        block::Block,
+        transaction::{OutPoint, Transaction, TxOut},
+    },
+    Txid,
+};
+
+use std::collections::{btree_map, BTreeMap};
+
+use crate::{
+    multisig::RedeemScript,
+    scan::{BlockChanges, OutputScanner},
+    Amount, TxOutProvider,
+};
+
+/// An unspent transaction output.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Utxo {
+    /// The transaction output itself.
+    pub output: TxOut,
+    /// The height of the block which contains the output transaction,
+    /// or `None` if the transaction is not confirmed yet.
+    pub height: Option<u32>,
+}
+
+/// The balance of the unspent outputs, split by their state.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Balance {
+    /// The value of the confirmed outputs, which are not locked.
+    pub confirmed: Amount,
+    /// The value of the unconfirmed outputs, which are not locked.
+    pub unconfirmed: Amount,
+    /// The value of the outputs spent by the unconfirmed transactions.
+    pub locked: Amount,
+}
+
+impl Balance {
+    /// Returns the value of all outputs, including the locked ones.
+    pub fn total(&self) -> Amount {
+        self.confirmed + self.unconfirmed + self.locked
+    }
+
+    /// Returns the value of the outputs, which are not locked.
+    pub fn available(&self) -> Amount {
+        self.confirmed + self.unconfirmed
+    }
+}
+
+/// A set of unspent outputs which pay to the scripts watched by the given scanner.
+#[derive(Debug, Clone)]
+pub struct UtxoSet {
+    scanner: OutputScanner,
+    utxos: BTreeMap<OutPoint, Utxo>,
+    pending_spends: BTreeMap<OutPoint, Txid>,
+    tip_height: Option<u32>,
+}
+
+impl UtxoSet {
+    /// Creates an empty set which tracks outputs found by the given scanner.
+    pub fn new(scanner: OutputScanner) -> UtxoSet {
+        UtxoSet {
+            scanner,
+            utxos: BTreeMap::default(),
+            pending_spends: BTreeMap::default(),
+            tip_height: None,
+        }
+    }
+
+    /// Returns a reference to the scanner used to find the relevant outputs.
+    pub fn scanner(&self) -> &OutputScanner {
+        &self.scanner
+    }
+
+    /// Returns the height of the latest applied block.
+    pub fn tip_height(&self) -> Option<u32> {
+        self.tip_height
+    }
+
+    /// Applies the given transaction. The outputs spent by the transaction are removed
+    /// from the set and the relevant outputs of the transaction are added to it.
+    ///
+    /// The height should be `None` for transactions which are not confirmed yet.
+    /// The outputs spent by such transactions are kept in the set, but locked until
+    /// the spending transaction is confirmed or [unlocked][unlock].
+    ///
+    /// [unlock]: #method.unlock
+    pub fn apply_transaction(&mut self, transaction: &Transaction, height: Option<u32>) {
+        let txid = transaction.txid();
+        for input in &transaction.input {
+            let outpoint = input.previous_output;
+            if height.is_some() {
+                self.remove(&outpoint);
+            } else if self.utxos.contains_key(&outpoint) {
+                self.pending_spends.insert(outpoint, txid);
+            }
+        }
+        for (outpoint, output) in self.scanner.scan_transaction(transaction) {
+            self.utxos.insert(outpoint, Utxo { output, height });
+        }
+    }
+
+    /// Applies all transactions of the given block with the given height.
+    /// Returns the relevant outputs received and spent by the block transactions.
+    pub fn apply_block(&mut self, block: &Block, height: u32) -> BlockChanges {
+        let changes = self.scanner.scan_block_changes(block, self);
+        for (outpoint, output) in &changes.received {
+            let utxo = Utxo {
+                output: output.clone(),
+                height: Some(height),
+            };
+            self.utxos.insert(*outpoint, utxo);
+        }
+        // The outputs received and spent in the same block are removed as well.
+        for spent in &changes.spent {
+            self.remove(&spent.outpoint);
+        }
+        self.tip_height = self.tip_height.max(Some(height));
+        changes
+    }
+
+    /// Unlocks the outputs spent by the unconfirmed transaction with the given identifier,
+    /// for example, if the transaction is dropped from the mempool. Returns the number
+    /// of the unlocked outputs.
+    pub fn unlock(&mut self, txid: &Txid) -> usize {
+        let len = self.pending_spends.len();
+        self.pending_spends
+            .retain(|_, spending_txid| spending_txid != txid);
+        len - self.pending_spends.len()
+    }
+
+    /// Returns the identifier of the unconfirmed transaction, which spends the output
+    /// with the given outpoint.
+    pub fn pending_spend(&self, outpoint: &OutPoint) -> Option<Txid> {
+        self.pending_spends.get(outpoint).copied()
+    }
+
+    /// Returns the balance of the unspent outputs.
+    pub fn balance(&self) -> Balance {
+        let mut balance = Balance::default();
+        for (outpoint, utxo) in &self.utxos {
+            let value = Amount::from_sat(utxo.output.value);
+            if self.pending_spends.contains_key(outpoint) {
+                balance.locked += value;
+            } else if self.confirmations(outpoint) > Some(0) {
+                balance.confirmed += value;
+            } else {
+                balance.unconfirmed += value;
+            }
+        }
+        balance
+    }
+
+    /// Returns the unspent output with the given outpoint.
+    pub fn get(&self, outpoint: &OutPoint) -> Option<&Utxo> {
+        self.utxos.get(outpoint)
+    }
+
+    /// Returns the number of confirmations of the output with the given outpoint,
+    /// or `None` if there is no such unspent output.
+    pub fn confirmations(&self, outpoint: &OutPoint) -> Option<u32> {
+        let utxo = self.get(outpoint)?;
+        let confirmations = match (utxo.height, self.tip_height) {
+            (Some(height), Some(tip_height)) if tip_height >= height => tip_height - height + 1,
+            _ => 0,
+        };
+        Some(confirmations)
+    }
+
+    /// Returns the number of unspent outputs.
+    pub fn len(&self) -> usize {
+        self.utxos.len()
+    }
+
+    /// Returns `true` if there are no unspent outputs.
+    pub fn is_empty(&self) -> bool {
+        self.utxos.is_empty()
+    }
+
+    /// Returns an iterator over the unspent outputs, ordered by the outpoints.
+    pub fn iter(&self) -> btree_map::Iter<'_, OutPoint, Utxo> {
+        self.utxos.iter()
+    }
+
+    /// Returns unspent outputs with at least the given number of confirmations in the form
+    /// suitable for the [`TransactionBuilder`][builder]. The locked outputs are skipped.
+    ///
+    /// [builder]: ../builder/struct.TransactionBuilder.html#method.inputs
+    pub fn spendable_outputs(&self, min_confirmations: u32) -> Vec<(OutPoint, TxOut)> {
+        self.utxos
+            .iter()
+            .filter(|(outpoint, _)| !self.pending_spends.contains_key(outpoint))
+            .filter(|(outpoint, _)| self.confirmations(outpoint) >= Some(min_confirmations))
+            .map(|(outpoint, utxo)| (*outpoint, utxo.output.clone()))
+            .collect()
+    }
+
+    fn remove(&mut self, outpoint: &OutPoint) {
+        self.utxos.remove(outpoint);
+        self.pending_spends.remove(outpoint);
+    }
+}
+
+impl<'a> From<&'a RedeemScript> for UtxoSet {
+    fn from(redeem_script: &'a RedeemScript) -> UtxoSet {
+        UtxoSet::new(OutputScanner::from(redeem_script))
+    }
+}
+
+impl TxOutProvider for UtxoSet {
+    fn tx_out(&self, outpoint: &OutPoint) -> Option<&TxOut> {
+        self.get(outpoint).map(|utxo| &utxo.output)
+    }
+}
+
+impl<'a> IntoIterator for &'a UtxoSet {
+    type Item = (&'a OutPoint, &'a Utxo);
+    type IntoIter = btree_map::Iter<'a, OutPoint, Utxo>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{
+        blockdata::{
+            block::{Block, BlockHeader},
+            script::Script,
+            transaction::{OutPoint, Transaction, TxIn, TxOut},
+        },
+        network::constants::Network,
+    };
+
+    use crate::{
+        multisig::RedeemScriptBuilder,
+        p2wsh,
+        test_data::deterministic_keypair,
+        utxo::{Balance, UtxoSet},
+        Amount,
+    };
+
+    fn transaction(inputs: Vec<OutPoint>, outputs: Vec<TxOut>) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: 0,
+            input: inputs
+                .into_iter()
+                .map(|previous_output| TxIn {
+                    previous_output,
+                    script_sig: Script::default(),
+                    sequence: 0xFFFF_FFFF,
+                    witness: Vec::default(),
+                })
+                .collect(),
+            output: outputs,
+        }
+    }
+
+    fn block(txdata: Vec<Transaction>) -> Block {
+        Block {
+            header: BlockHeader {
+                version: 1,
+                prev_blockhash: Default::default(),
+                merkle_root: Default::default(),
+                time: 0,
+                bits: 0,
+                nonce: 0,
+            },
+            txdata,
+        }
+    }
+
+    #[test]
+    fn test_utxo_set_apply() {
+        let public_keys = (0..3).map(|_| deterministic_keypair(Network::Testnet).0);
+        let redeem_script = RedeemScriptBuilder::with_public_keys(public_keys)
+            .quorum(2)
+            .to_script()
+            .unwrap();
+        let output = TxOut {
+            value: 10_000,
+            script_pubkey: p2wsh::script_pubkey(&redeem_script),
+        };
+
+        let mut utxos = UtxoSet::from(&redeem_script);
+        let funding_tx = transaction(vec![OutPoint::default()], vec![output.clone()]);
+        let funding_outpoint = OutPoint::new(funding_tx.txid(), 0);
+        // Unconfirmed output.
+        utxos.apply_transaction(&funding_tx, None);
+        assert_eq!(utxos.confirmations(&funding_outpoint), Some(0));
+        assert!(utxos.spendable_outputs(1).is_empty());
+        // The output becomes confirmed.
+        utxos.apply_block(&block(vec![funding_tx]), 100);
+        utxos.apply_block(&block(vec![]), 101);
+        assert_eq!(utxos.confirmations(&funding_outpoint), Some(2));
+        assert_eq!(
+            utxos.spendable_outputs(1),
+            vec![(funding_outpoint, output.clone())]
+        );
+        // The output is spent with the change back to the multisig.
+        let spending_tx = transaction(vec![funding_outpoint], vec![output.clone()]);
+        let changes = utxos.apply_block(&block(vec![spending_tx.clone()]), 102);
+        assert_eq!(changes.received.len(), 1);
+        assert_eq!(changes.spent[0].outpoint, funding_outpoint);
+        assert_eq!(utxos.len(), 1);
+        assert_eq!(utxos.get(&funding_outpoint), None);
+        assert_eq!(
+            utxos.confirmations(&OutPoint::new(spending_tx.txid(), 0)),
+            Some(1)
+        );
+        // The output is received and spent in the same block.
+        let change_outpoint = OutPoint::new(spending_tx.txid(), 0);
+        let first_tx = transaction(vec![change_outpoint], vec![output.clone()]);
+        let second_tx = transaction(vec![OutPoint::new(first_tx.txid(), 0)], vec![output]);
+        let changes = utxos.apply_block(&block(vec![first_tx, second_tx.clone()]), 103);
+        assert_eq!(changes.received.len(), 2);
+        assert_eq!(changes.spent.len(), 2);
+        assert_eq!(
+            utxos
+                .iter()
+                .map(|(outpoint, _)| *outpoint)
+                .collect::<Vec<_>>(),
+            vec![OutPoint::new(second_tx.txid(), 0)]
+        );
+    }
+
+    #[test]
+    fn test_utxo_set_balance() {
+        let public_keys = (0..3).map(|_| deterministic_keypair(Network::Testnet).0);
+        let redeem_script = RedeemScriptBuilder::with_public_keys(public_keys)
+            .quorum(2)
+            .to_script()
+            .unwrap();
+        let output = |value| TxOut {
+            value,
+            script_pubkey: p2wsh::script_pubkey(&redeem_script),
+        };
+
+        let mut utxos = UtxoSet::from(&redeem_script);
+        let confirmed_tx = transaction(vec![OutPoint::default()], vec![output(10_000)]);
+        let confirmed_outpoint = OutPoint::new(confirmed_tx.txid(), 0);
+        utxos.apply_block(&block(vec![confirmed_tx]), 100);
+        let unconfirmed_tx = transaction(
+            vec![OutPoint::new(Default::default(), 1)],
+            vec![output(2_000)],
+        );
+        utxos.apply_transaction(&unconfirmed_tx, None);
+        assert_eq!(
+            utxos.balance(),
+            Balance {
+                confirmed: Amount::from_sat(10_000),
+                unconfirmed: Amount::from_sat(2_000),
+                locked: Amount::ZERO,
+            }
+        );
+
+        // The confirmed output is spent by the pending transaction with the change.
+        let spending_tx = transaction(vec![confirmed_outpoint], vec![output(7_000)]);
+        utxos.apply_transaction(&spending_tx, None);
+        assert_eq!(
+            utxos.pending_spend(&confirmed_outpoint),
+            Some(spending_tx.txid())
+        );
+        let balance = utxos.balance();
+        assert_eq!(balance.confirmed, Amount::ZERO);
+        assert_eq!(balance.unconfirmed, Amount::from_sat(9_000));
+        assert_eq!(balance.locked, Amount::from_sat(10_000));
+        assert_eq!(balance.total(), Amount::from_sat(19_000));
+        assert_eq!(balance.available(), Amount::from_sat(9_000));
+        assert!(utxos.spendable_outputs(1).is_empty());
+
+        // The pending transaction is dropped.
+        assert_eq!(utxos.unlock(&spending_tx.txid()), 1);
+        assert_eq!(
+            utxos.spendable_outputs(1),
+            vec![(confirmed_outpoint, output(10_000))]
+        );
+        // The output is spent again, and the spending transaction is confirmed.
+        utxos.apply_transaction(&spending_tx, None);
+        utxos.apply_block(&block(vec![spending_tx]), 101);
+        assert_eq!(utxos.pending_spend(&confirmed_outpoint), None);
+        assert_eq!(
+            utxos.balance(),
+            Balance {
+                confirmed: Amount::from_sat(7_000),
+                unconfirmed: Amount::from_sat(2_000),
+                locked: Amount::ZERO,
+            }
+        );
+    }
+}
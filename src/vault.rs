@@ -0,0 +1,766 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The vault built from the pre-signed transactions.
+//!
+//! The deposit is locked by the multisig of the stakeholders. The only way to withdraw it
+//! is the pre-signed unvault transaction, which moves the deposit to the unvault output:
+//!
+//! ```text
+//! OP_IF
+//!     <delay> OP_CHECKSEQUENCEVERIFY OP_DROP
+//!     <k> <spender_pk1> ... <spender_pkn> <n> OP_CHECKMULTISIG
+//! OP_ELSE
+//!     <k> <stakeholder_pk1> ... <stakeholder_pkn> <n> OP_CHECKMULTISIG
+//! OP_ENDIF
+//! ```
+//!
+//! The spenders can use the unvault output only after the delay. Until then, anyone who
+//! watches the chain can broadcast the pre-signed clawback transaction, which moves the funds
+//! to the cold storage multisig.
+//!
+//! The clawback transaction must be fully signed before the stakeholders sign the unvault one,
+//! otherwise the unvault output could not be clawed back. The [`PresignedVault`][presigned]
+//! enforces this order and keeps the collected signatures in the serializable form,
+//! so it can be stored and exchanged between the stakeholders.
+//!
+//! [presigned]: struct.PresignedVault.html
+
+use bitcoin::{
+    blockdata::{
+        opcodes::all::{OP_CSV, OP_DROP, OP_ELSE, OP_ENDIF, OP_IF},
+        script::{Builder, Script},
+        transaction::{OutPoint, Transaction, TxIn, TxOut},
+    },
+    network::constants::Network,
+    util::address::Address,
+    PublicKey,
+};
+use secp256k1::SecretKey;
+use serde::{Deserialize, Serialize};
+
+use std::{collections::BTreeMap, convert::TryFrom};
+
+use crate::{
+    builder::{signed_vsize, DummyInput},
+    multisig::RedeemScript,
+    p2wsh, policy, sign, templates, Amount, BtcTransaction, Error, FeeRate, InputSignature,
+    Sequence, TxInRef, UnspentTxOutValue,
+};
+
+/// The participants and the parameters of the vault.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Vault {
+    /// The multisig script of the stakeholders, which locks the deposit and signs
+    /// the pre-signed transactions.
+    pub stakeholders: RedeemScript,
+    /// The multisig script of the spenders, which can spend the unvault output after
+    /// the delay.
+    pub spenders: RedeemScript,
+    /// The multisig script of the cold storage, which receives the clawed back funds.
+    pub cold_storage: RedeemScript,
+    /// The number of blocks after the unvault transaction confirmation, after which
+    /// the spenders can spend its output.
+    pub delay: u16,
+}
+
+impl Vault {
+    /// Returns the `P2WSH` script pubkey of the deposit output.
+    pub fn deposit_script_pubkey(&self) -> Script {
+        p2wsh::script_pubkey(&self.stakeholders)
+    }
+
+    /// Returns the `P2WSH` address of the deposit output for the given network.
+    pub fn deposit_address(&self, network: Network) -> Address {
+        p2wsh::address(&self.stakeholders, network)
+    }
+
+    /// Returns the witness script of the unvault output.
+    pub fn unvault_script(&self) -> Script {
+        let delay = Builder::new()
+            .push_opcode(OP_IF)
+            .push_int(i64::from(self.delay))
+            .push_opcode(OP_CSV)
+            .push_opcode(OP_DROP)
+            .into_script();
+
+        let mut bytes = delay.to_bytes();
+        bytes.extend_from_slice(self.spenders.script.as_bytes());
+        bytes.push(OP_ELSE.into_u8());
+        bytes.extend_from_slice(self.stakeholders.script.as_bytes());
+        bytes.push(OP_ENDIF.into_u8());
+        Script::from(bytes)
+    }
+
+    /// Returns the `P2WSH` script pubkey of the unvault output.
+    pub fn unvault_script_pubkey(&self) -> Script {
+        self.unvault_script().to_v0_p2wsh()
+    }
+
+    /// Creates the unsigned unvault transaction, which moves the deposit with the given
+    /// outpoint and value to the unvault output. The fee for the given fee rate is deducted
+    /// from the deposit.
+    pub fn unvault_transaction(
+        &self,
+        deposit: OutPoint,
+        value: Amount,
+        fee_rate: FeeRate,
+    ) -> Result<Transaction, Error> {
+        let transaction = single_output_transaction(deposit, self.unvault_script_pubkey());
+        let vsize = signed_vsize(
+            &transaction,
+            &[DummyInput::P2wsh(self.stakeholders.clone())],
+        )?;
+        with_output_value(transaction, value.as_sat(), fee_rate, vsize)
+    }
+
+    /// Creates the unsigned clawback transaction, which moves the output of the given unvault
+    /// transaction to the cold storage. The fee for the given fee rate is deducted from
+    /// the unvault output.
+    pub fn clawback_transaction(
+        &self,
+        unvault: &Transaction,
+        fee_rate: FeeRate,
+    ) -> Result<Transaction, Error> {
+        let unvault_output = unvault.output.first().ok_or(Error::NoSuchOutput(0))?;
+        ensure!(
+            unvault_output.script_pubkey == self.unvault_script_pubkey(),
+            Error::ScriptMismatch
+        );
+
+        let transaction = single_output_transaction(
+            OutPoint::new(unvault.txid(), 0),
+            p2wsh::script_pubkey(&self.cold_storage),
+        );
+        let mut witness = vec![0];
+        witness.extend((0..self.stakeholders.quorum()).map(|_| InputSignature::MAX_LEN));
+        witness.extend(&[0, self.unvault_script().len()]);
+        let vsize = signed_vsize(&transaction, &[DummyInput::Witness(witness)])?;
+        with_output_value(transaction, unvault_output.value, fee_rate, vsize)
+    }
+
+    /// Computes the [`BIP-143`][bip-143] compliant signature for the given input, which
+    /// spends the unvault output. The secret key must belong either to one of the spenders
+    /// or to one of the stakeholders.
+    ///
+    /// To spend the output by the spenders, the input sequence must be set to the delay,
+    /// see [`set_relative_lock_time`](../templates/fn.set_relative_lock_time.html).
+    ///
+    /// [bip-143]: https://github.com/bitcoin/bips/blob/master/bip-0143.mediawiki
+    pub fn sign_input<'a, 'b, V: Into<UnspentTxOutValue<'b>>>(
+        &self,
+        txin: TxInRef<'a>,
+        value: V,
+        secret_key: &SecretKey,
+    ) -> Result<InputSignature, Error> {
+        let spenders = self.spenders.content().public_keys;
+        let stakeholders = self.stakeholders.content().public_keys;
+        let public_keys = spenders.iter().chain(&stakeholders).collect::<Vec<_>>();
+        templates::sign_input(
+            &self.unvault_script(),
+            &public_keys,
+            txin,
+            value,
+            secret_key,
+        )
+    }
+
+    /// Creates the witness stack for the spenders path of the unvault output. The signatures
+    /// must be ordered in the same way as the corresponding public keys in the spenders
+    /// multisig script.
+    pub fn spend_witness(&self, signatures: Vec<InputSignature>) -> Result<Vec<Vec<u8>>, Error> {
+        self.unvault_witness(&self.spenders, signatures, vec![1])
+    }
+
+    fn clawback_witness(&self, signatures: Vec<InputSignature>) -> Result<Vec<Vec<u8>>, Error> {
+        self.unvault_witness(&self.stakeholders, signatures, Vec::new())
+    }
+
+    fn unvault_witness(
+        &self,
+        multisig: &RedeemScript,
+        signatures: Vec<InputSignature>,
+        branch: Vec<u8>,
+    ) -> Result<Vec<Vec<u8>>, Error> {
+        let quorum = multisig.quorum();
+        ensure!(
+            signatures.len() == quorum,
+            Error::SignaturesCount {
+                quorum,
+                actual: signatures.len(),
+            }
+        );
+
+        // The dummy item is consumed by `OP_CHECKMULTISIG`.
+        let mut witness_stack = Vec::with_capacity(signatures.len() + 3);
+        witness_stack.push(Vec::default());
+        witness_stack.extend(signatures.into_iter().map(Vec::from));
+        witness_stack.push(branch);
+        witness_stack.push(self.unvault_script().to_bytes());
+        Ok(witness_stack)
+    }
+}
+
+/// The serializable state of the vault pre-signing, which collects the signatures
+/// of the stakeholders for the clawback and the unvault transactions.
+///
+/// The deserialized state is checked in the same way as the added signatures, so
+/// the signing order is enforced for the states received from the other stakeholders too.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "UncheckedPresignedVault")]
+pub struct PresignedVault {
+    /// The parameters of the vault.
+    vault: Vault,
+    /// The value of the deposit output.
+    deposit_value: u64,
+    /// The unsigned unvault transaction.
+    unvault: BtcTransaction,
+    /// The unsigned clawback transaction.
+    clawback: BtcTransaction,
+    /// The collected signatures for the unvault transaction, keyed by the index of the public
+    /// key in the stakeholders script.
+    unvault_signatures: BTreeMap<usize, InputSignature>,
+    /// The collected signatures for the clawback transaction, keyed by the index of the public
+    /// key in the stakeholders script.
+    clawback_signatures: BTreeMap<usize, InputSignature>,
+}
+
+/// The deserialized state of the vault pre-signing, which is not checked yet.
+#[derive(Deserialize)]
+struct UncheckedPresignedVault {
+    vault: Vault,
+    deposit_value: u64,
+    unvault: BtcTransaction,
+    clawback: BtcTransaction,
+    unvault_signatures: BTreeMap<usize, InputSignature>,
+    clawback_signatures: BTreeMap<usize, InputSignature>,
+}
+
+impl TryFrom<UncheckedPresignedVault> for PresignedVault {
+    type Error = Error;
+
+    fn try_from(state: UncheckedPresignedVault) -> Result<PresignedVault, Error> {
+        let unvault: &Transaction = state.unvault.as_ref();
+        let clawback: &Transaction = state.clawback.as_ref();
+        ensure!(!unvault.input.is_empty(), Error::NoSuchInput(0));
+        ensure!(
+            unvault.output.first().map(|output| &output.script_pubkey)
+                == Some(&state.vault.unvault_script_pubkey()),
+            Error::ScriptMismatch
+        );
+        let unvault_outpoint = OutPoint::new(unvault.txid(), 0);
+        ensure!(
+            clawback.input.first().map(|input| input.previous_output) == Some(unvault_outpoint),
+            Error::UnknownOutput(unvault_outpoint)
+        );
+
+        let public_keys = state.vault.stakeholders.content().public_keys;
+        let public_key = |index: usize| public_keys.get(index).ok_or(Error::UnknownPublicKey);
+        let mut presigned = PresignedVault {
+            vault: state.vault,
+            deposit_value: state.deposit_value,
+            unvault: state.unvault,
+            clawback: state.clawback,
+            unvault_signatures: BTreeMap::default(),
+            clawback_signatures: BTreeMap::default(),
+        };
+        for (index, signature) in state.clawback_signatures {
+            presigned.add_clawback_signature(public_key(index)?, signature)?;
+        }
+        for (index, signature) in state.unvault_signatures {
+            presigned.add_unvault_signature(public_key(index)?, signature)?;
+        }
+        Ok(presigned)
+    }
+}
+
+impl PresignedVault {
+    /// Creates the unsigned unvault and clawback transactions for the deposit with the given
+    /// outpoint and value. Both transactions pay the fee for the given fee rate.
+    pub fn new(
+        vault: Vault,
+        deposit: OutPoint,
+        value: Amount,
+        fee_rate: FeeRate,
+    ) -> Result<PresignedVault, Error> {
+        let unvault = vault.unvault_transaction(deposit, value, fee_rate)?;
+        let clawback = vault.clawback_transaction(&unvault, fee_rate)?;
+        Ok(PresignedVault {
+            vault,
+            deposit_value: value.as_sat(),
+            unvault: unvault.into(),
+            clawback: clawback.into(),
+            unvault_signatures: BTreeMap::default(),
+            clawback_signatures: BTreeMap::default(),
+        })
+    }
+
+    /// Returns a reference to the vault parameters.
+    pub fn vault(&self) -> &Vault {
+        &self.vault
+    }
+
+    /// Returns a reference to the unsigned unvault transaction.
+    pub fn unvault_transaction(&self) -> &Transaction {
+        self.unvault.as_ref()
+    }
+
+    /// Returns a reference to the unsigned clawback transaction.
+    pub fn clawback_transaction(&self) -> &Transaction {
+        self.clawback.as_ref()
+    }
+
+    /// Verifies the clawback signature of the given stakeholder and adds it to the state.
+    pub fn add_clawback_signature(
+        &mut self,
+        public_key: &PublicKey,
+        signature: InputSignature,
+    ) -> Result<(), Error> {
+        let key_index = self
+            .vault
+            .stakeholders
+            .key_index(public_key)
+            .ok_or(Error::UnknownPublicKey)?;
+        sign::verify_input_signature(
+            sign::verification_context(),
+            TxInRef::new(self.clawback_transaction(), 0),
+            &self.vault.unvault_script(),
+            &self.unvault_transaction().output[0],
+            public_key,
+            &signature,
+        )?;
+        self.clawback_signatures.insert(key_index, signature);
+        Ok(())
+    }
+
+    /// Verifies the unvault signature of the given stakeholder and adds it to the state.
+    /// The clawback transaction must be fully signed before.
+    pub fn add_unvault_signature(
+        &mut self,
+        public_key: &PublicKey,
+        signature: InputSignature,
+    ) -> Result<(), Error> {
+        ensure!(self.is_clawback_signed(), Error::ClawbackNotSigned);
+        let key_index = self
+            .vault
+            .stakeholders
+            .key_index(public_key)
+            .ok_or(Error::UnknownPublicKey)?;
+        p2wsh::InputSigner::new(self.vault.stakeholders.clone()).verify_input(
+            TxInRef::new(self.unvault_transaction(), 0),
+            self.deposit_value,
+            public_key,
+            &signature,
+        )?;
+        self.unvault_signatures.insert(key_index, signature);
+        Ok(())
+    }
+
+    /// Signs the clawback transaction with the given secret key of the stakeholder
+    /// and adds the signature to the state. Returns the created signature, so it can be sent
+    /// to the other stakeholders.
+    pub fn sign_clawback(&mut self, secret_key: &SecretKey) -> Result<InputSignature, Error> {
+        let stakeholders = self.vault.stakeholders.content().public_keys;
+        let signature = templates::sign_input(
+            &self.vault.unvault_script(),
+            &stakeholders.iter().collect::<Vec<_>>(),
+            TxInRef::new(self.clawback_transaction(), 0),
+            &self.unvault_transaction().output[0],
+            secret_key,
+        )?;
+        self.add_clawback_signature(&public_key(secret_key), signature.clone())?;
+        Ok(signature)
+    }
+
+    /// Signs the unvault transaction with the given secret key of the stakeholder
+    /// and adds the signature to the state. The clawback transaction must be fully
+    /// signed before.
+    pub fn sign_unvault(&mut self, secret_key: &SecretKey) -> Result<InputSignature, Error> {
+        ensure!(self.is_clawback_signed(), Error::ClawbackNotSigned);
+        let signature = p2wsh::InputSigner::new(self.vault.stakeholders.clone()).sign_input(
+            TxInRef::new(self.unvault_transaction(), 0),
+            self.deposit_value,
+            secret_key,
+        )?;
+        self.add_unvault_signature(&public_key(secret_key), signature.clone())?;
+        Ok(signature)
+    }
+
+    /// Returns `true` if the quorum of the stakeholders has signed the clawback transaction.
+    pub fn is_clawback_signed(&self) -> bool {
+        self.clawback_signatures.len() >= self.vault.stakeholders.quorum()
+    }
+
+    /// Returns `true` if the quorum of the stakeholders has signed both transactions.
+    pub fn is_complete(&self) -> bool {
+        self.is_clawback_signed()
+            && self.unvault_signatures.len() >= self.vault.stakeholders.quorum()
+    }
+
+    /// Returns the fully signed clawback transaction.
+    pub fn signed_clawback(&self) -> Result<Transaction, Error> {
+        ensure!(self.is_clawback_signed(), Error::ClawbackNotSigned);
+        // Signatures must be ordered in the same way as public keys in the redeem script.
+        let signatures = self
+            .clawback_signatures
+            .values()
+            .take(self.vault.stakeholders.quorum())
+            .cloned()
+            .collect();
+        let mut transaction = self.clawback_transaction().clone();
+        transaction.input[0].witness = self.vault.clawback_witness(signatures)?;
+        Ok(transaction)
+    }
+
+    /// Returns the fully signed unvault transaction.
+    pub fn signed_unvault(&self) -> Result<Transaction, Error> {
+        ensure!(self.is_clawback_signed(), Error::ClawbackNotSigned);
+        let quorum = self.vault.stakeholders.quorum();
+        ensure!(
+            self.unvault_signatures.len() >= quorum,
+            Error::SignaturesCount {
+                quorum,
+                actual: self.unvault_signatures.len(),
+            }
+        );
+        let signatures = self.unvault_signatures.values().take(quorum).cloned();
+        let mut transaction = self.unvault_transaction().clone();
+        p2wsh::InputSigner::new(self.vault.stakeholders.clone()).spend_input(
+            &mut transaction,
+            0,
+            self.deposit_value,
+            signatures,
+        )?;
+        Ok(transaction)
+    }
+}
+
+fn public_key(secret_key: &SecretKey) -> PublicKey {
+    PublicKey {
+        compressed: true,
+        key: secp256k1::PublicKey::from_secret_key(&sign::shared_context(), secret_key),
+    }
+}
+
+fn single_output_transaction(previous_output: OutPoint, script_pubkey: Script) -> Transaction {
+    Transaction {
+        version: 2,
+        lock_time: 0,
+        input: vec![TxIn {
+            previous_output,
+            script_sig: Script::default(),
+            sequence: Sequence::MAX.0,
+            witness: Vec::default(),
+        }],
+        output: vec![TxOut {
+            value: 0,
+            script_pubkey,
+        }],
+    }
+}
+
+/// Sets the value of the single output to the given balance without the fee. The output
+/// value must not be dust.
+fn with_output_value(
+    mut transaction: Transaction,
+    available: u64,
+    fee_rate: FeeRate,
+    vsize: u64,
+) -> Result<Transaction, Error> {
+    let fee = fee_rate.fee_vb(vsize).ok_or(Error::ValueOverflow)?;
+    let dust = policy::dust_threshold(
+        &transaction.output[0].script_pubkey,
+        policy::DUST_RELAY_FEE_RATE,
+    );
+    let required = fee.checked_add(dust).ok_or(Error::ValueOverflow)?;
+    ensure!(
+        available >= required,
+        Error::InsufficientFunds {
+            available,
+            required,
+        }
+    );
+    transaction.output[0].value = available - fee;
+    Ok(transaction)
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{
+        blockdata::transaction::{OutPoint, Transaction, TxIn, TxOut},
+        network::constants::Network,
+        PrivateKey, PublicKey, Script,
+    };
+
+    use super::{PresignedVault, Vault};
+    use crate::{
+        multisig::RedeemScriptBuilder,
+        p2wsh,
+        templates::set_relative_lock_time,
+        test_data::{deterministic_keypair, fake_funding_tx},
+        verify::verify_transaction,
+        Amount, Error, FeeRate, Sequence, TxInRef,
+    };
+
+    struct Fixture {
+        stakeholders: Vec<(PublicKey, PrivateKey)>,
+        spenders: Vec<(PublicKey, PrivateKey)>,
+        vault: Vault,
+        deposit: Transaction,
+        fee_rate: FeeRate,
+        presigned: PresignedVault,
+    }
+
+    fn fixture() -> Fixture {
+        let stakeholders = (0..3)
+            .map(|_| deterministic_keypair(Network::Testnet))
+            .collect::<Vec<_>>();
+        let spenders = (0..2)
+            .map(|_| deterministic_keypair(Network::Testnet))
+            .collect::<Vec<_>>();
+        let multisig = |keys: Vec<_>, quorum| {
+            RedeemScriptBuilder::with_public_keys(keys)
+                .quorum(quorum)
+                .to_script()
+                .unwrap()
+        };
+        let vault = Vault {
+            stakeholders: multisig(stakeholders.iter().map(|x| x.0).collect(), 3),
+            spenders: multisig(spenders.iter().map(|x| x.0).collect(), 1),
+            cold_storage: multisig(vec![deterministic_keypair(Network::Testnet).0], 1),
+            delay: 144,
+        };
+        let deposit = fake_funding_tx(&vault.deposit_script_pubkey(), 100_000);
+        let fee_rate = FeeRate::from_sat_per_vb(2).unwrap();
+        let presigned = PresignedVault::new(
+            vault.clone(),
+            OutPoint::new(deposit.txid(), 0),
+            Amount::from_sat(100_000),
+            fee_rate,
+        )
+        .unwrap();
+        Fixture {
+            stakeholders,
+            spenders,
+            vault,
+            deposit,
+            fee_rate,
+            presigned,
+        }
+    }
+
+    /// Deserializes the state from the JSON value and returns the error message.
+    fn deserialization_error(state: serde_json::Value) -> String {
+        serde_json::from_value::<PresignedVault>(state)
+            .unwrap_err()
+            .to_string()
+    }
+
+    #[test]
+    fn test_presigned_vault() {
+        let Fixture {
+            stakeholders,
+            spenders,
+            vault,
+            deposit,
+            fee_rate,
+            mut presigned,
+        } = fixture();
+
+        // The unvault transaction cannot be signed before the clawback one.
+        assert_eq!(
+            presigned.sign_unvault(&stakeholders[0].1.key),
+            Err(Error::ClawbackNotSigned)
+        );
+        for (_, secret_key) in &stakeholders {
+            presigned.sign_clawback(&secret_key.key).unwrap();
+        }
+        assert!(presigned.is_clawback_signed());
+        assert_eq!(
+            presigned.signed_unvault(),
+            Err(Error::SignaturesCount {
+                quorum: 3,
+                actual: 0
+            })
+        );
+        assert_eq!(
+            presigned.sign_clawback(&spenders[0].1.key),
+            Err(Error::KeyNotInScript)
+        );
+
+        // The state survives the storage round trip.
+        let json = serde_json::to_string(&presigned).unwrap();
+        let mut presigned: PresignedVault = serde_json::from_str(&json).unwrap();
+        for (_, secret_key) in &stakeholders {
+            presigned.sign_unvault(&secret_key.key).unwrap();
+        }
+        assert!(presigned.is_complete());
+        let json = serde_json::to_string(&presigned).unwrap();
+        assert_eq!(
+            serde_json::from_str::<PresignedVault>(&json).unwrap(),
+            presigned
+        );
+
+        let unvault = presigned.signed_unvault().unwrap();
+        verify_transaction(&unvault, &deposit.output).unwrap();
+        let clawback = presigned.signed_clawback().unwrap();
+        assert_eq!(clawback.input[0].previous_output.txid, unvault.txid());
+        assert_eq!(
+            clawback.output[0].script_pubkey,
+            p2wsh::script_pubkey(&vault.cold_storage)
+        );
+        // The fees are not less than required.
+        let unvault_fee = 100_000 - unvault.output[0].value;
+        assert!(unvault_fee >= fee_rate.fee_wu(unvault.get_weight() as u64).unwrap());
+        let clawback_fee = unvault.output[0].value - clawback.output[0].value;
+        assert!(clawback_fee >= fee_rate.fee_wu(clawback.get_weight() as u64).unwrap());
+
+        // The spenders path after the delay.
+        let mut transaction = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(unvault.txid(), 0),
+                script_sig: Script::default(),
+                sequence: Sequence::MAX.0,
+                witness: Vec::default(),
+            }],
+            output: vec![TxOut {
+                value: 90_000,
+                script_pubkey: Script::default(),
+            }],
+        };
+        set_relative_lock_time(&mut transaction, 0, vault.delay).unwrap();
+        let signature = vault
+            .sign_input(
+                TxInRef::new(&transaction, 0),
+                &unvault.output[0],
+                &spenders[1].1.key,
+            )
+            .unwrap();
+        let witness = vault.spend_witness(vec![signature]).unwrap();
+        assert_eq!(witness[2], vec![1]);
+        assert_eq!(witness[3], vault.unvault_script().to_bytes());
+
+        // The deposit does not cover the fees.
+        let result =
+            PresignedVault::new(vault, OutPoint::default(), Amount::from_sat(500), fee_rate);
+        assert!(matches!(
+            result,
+            Err(Error::InsufficientFunds { available: 500, .. })
+        ));
+    }
+
+    #[test]
+    fn test_presigned_vault_signatures() {
+        let Fixture {
+            stakeholders,
+            spenders,
+            vault,
+            mut presigned,
+            ..
+        } = fixture();
+
+        let signature = presigned.sign_clawback(&stakeholders[0].1.key).unwrap();
+        // The signature of one stakeholder does not match the key of another one.
+        assert_eq!(
+            presigned.add_clawback_signature(&stakeholders[1].0, signature.clone()),
+            Err(secp256k1::Error::IncorrectSignature.into())
+        );
+        assert_eq!(
+            presigned.add_clawback_signature(&spenders[0].0, signature),
+            Err(Error::UnknownPublicKey)
+        );
+        assert!(!presigned.is_clawback_signed());
+        assert_eq!(presigned.signed_clawback(), Err(Error::ClawbackNotSigned));
+        assert_eq!(presigned.signed_unvault(), Err(Error::ClawbackNotSigned));
+
+        // The spenders path requires the quorum of the spenders signatures.
+        assert_eq!(
+            vault.spend_witness(Vec::new()),
+            Err(Error::SignaturesCount {
+                quorum: 1,
+                actual: 0
+            })
+        );
+        // The clawback transaction spends only the unvault output.
+        let clawback = presigned.clawback_transaction().clone();
+        assert_eq!(
+            vault.clawback_transaction(&clawback, FeeRate::from_sat_per_vb(1).unwrap()),
+            Err(Error::ScriptMismatch)
+        );
+    }
+
+    #[test]
+    fn test_presigned_vault_tampered_state() {
+        let Fixture {
+            stakeholders,
+            deposit,
+            mut presigned,
+            ..
+        } = fixture();
+        for (_, secret_key) in &stakeholders[..2] {
+            presigned.sign_clawback(&secret_key.key).unwrap();
+        }
+        let state = serde_json::to_value(&presigned).unwrap();
+        assert_eq!(
+            serde_json::from_value::<PresignedVault>(state.clone()).unwrap(),
+            presigned
+        );
+
+        // The signature is stored under the index of another stakeholder.
+        let mut tampered = state.clone();
+        tampered["clawback_signatures"]["0"] = state["clawback_signatures"]["1"].clone();
+        let signature = presigned
+            .clone()
+            .sign_clawback(&stakeholders[1].1.key)
+            .unwrap();
+        let expected = presigned
+            .clone()
+            .add_clawback_signature(&stakeholders[0].0, signature)
+            .unwrap_err();
+        assert_eq!(deserialization_error(tampered), expected.to_string());
+
+        // The signature is stored under the unknown index.
+        let mut tampered = state.clone();
+        tampered["clawback_signatures"]["3"] = state["clawback_signatures"]["1"].clone();
+        assert_eq!(
+            deserialization_error(tampered),
+            Error::UnknownPublicKey.to_string()
+        );
+
+        // The unvault signature is added before the clawback transaction is fully signed.
+        let signature = p2wsh::InputSigner::new(presigned.vault().stakeholders.clone())
+            .sign_input(
+                TxInRef::new(presigned.unvault_transaction(), 0),
+                &deposit.output[0],
+                &stakeholders[0].1.key,
+            )
+            .unwrap();
+        let mut tampered = state.clone();
+        tampered["unvault_signatures"]["0"] = serde_json::to_value(&signature).unwrap();
+        assert_eq!(
+            deserialization_error(tampered),
+            Error::ClawbackNotSigned.to_string()
+        );
+
+        // The unvault transaction does not pay to the unvault output.
+        let mut tampered = state.clone();
+        tampered["unvault"] = state["clawback"].clone();
+        assert_eq!(
+            deserialization_error(tampered),
+            Error::ScriptMismatch.to_string()
+        );
+    }
+}
@@ -0,0 +1,342 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Verification of the fully signed transactions.
+//!
+//! This is useful for watchtowers which validate the signed anchoring transaction
+//! before broadcasting it.
+
+use crate::{
+    p2wpk, p2wsh,
+    script::{classify, ScriptType},
+    Error, TxInRef, WitnessError,
+};
+use bitcoin::{
+    blockdata::{
+        script::Script,
+        transaction::{Transaction, TxOut},
+    },
+    network::constants::Network,
+};
+
+/// Checks witnesses of all transaction inputs against the given spent outputs. The spent
+/// outputs must be given in the same order as the corresponding inputs.
+///
+/// Each input must spend either a `P2WPK` or a `P2WSH` multisig output; the witness must
+/// match the spent script pubkey and contain the required number of valid `SIGHASH_ALL`
+/// signatures.
+/// The pay-to-anchor outputs are spent with the empty witness. The script sig of each
+/// input must be empty, since all these outputs are native witness programs.
+/// Errors related to the specific input are wrapped into the [`Error::Input`][input] variant.
+///
+/// [input]: ../enum.Error.html#variant.Input
+pub fn verify_transaction(transaction: &Transaction, prev_outputs: &[TxOut]) -> Result<(), Error> {
+    ensure!(
+        transaction.input.len() == prev_outputs.len(),
+        Error::OutputsCount {
+            inputs: transaction.input.len(),
+            outputs: prev_outputs.len(),
+        }
+    );
+
+    for (index, prev_output) in prev_outputs.iter().enumerate() {
+        let txin = TxInRef::new(transaction, index);
+        verify_input(txin, prev_output).map_err(|error| Error::input(index, error))?;
+    }
+    Ok(())
+}
+
+/// Checks that the transaction pays the given payments and returns the change only to
+/// the approved scripts, for example, to the script pubkey of the current multisig.
+///
+/// Each payment must match a separate transaction output, and all other outputs are
+/// treated as change. This protects against the compromised transaction builder, which
+/// redirects the change to a foreign script.
+pub fn verify_change(
+    transaction: &Transaction,
+    payments: &[TxOut],
+    change_scripts: &[Script],
+) -> Result<(), Error> {
+    let mut is_payment = vec![false; transaction.output.len()];
+    for (index, payment) in payments.iter().enumerate() {
+        let position = transaction
+            .output
+            .iter()
+            .zip(&is_payment)
+            .position(|(output, is_payment)| !is_payment && output == payment);
+        match position {
+            Some(position) => is_payment[position] = true,
+            None => return Err(Error::MissingPayment(index)),
+        }
+    }
+
+    for (index, output) in transaction.output.iter().enumerate() {
+        ensure!(
+            is_payment[index] || change_scripts.contains(&output.script_pubkey),
+            Error::UnapprovedChange(index)
+        );
+    }
+    Ok(())
+}
+
+fn verify_input(txin: TxInRef, prev_output: &TxOut) -> Result<(), Error> {
+    let witness = &txin.input().witness;
+    let script_pubkey = &prev_output.script_pubkey;
+    let script_type = classify(script_pubkey);
+
+    // All supported outputs are native witness programs, so the consensus rules require
+    // the script sig of the input to be empty.
+    if let ScriptType::P2wpkh | ScriptType::P2wsh | ScriptType::P2a = script_type {
+        ensure!(txin.input().script_sig.is_empty(), Error::NonEmptyScriptSig);
+    }
+
+    match script_type {
+        ScriptType::P2wpkh => {
+            let witness = p2wpk::parse_witness(witness)?;
+            ensure!(
+                p2wpk::script_pubkey(&witness.public_key) == *script_pubkey,
+                Error::ScriptMismatch
+            );
+            // The network is irrelevant for the signature verification.
+            let signer = p2wpk::InputSigner::new(witness.public_key, Network::Bitcoin);
+            signer.verify_input(txin, prev_output, &witness.public_key, &witness.signature)
+        }
+        ScriptType::P2wsh => {
+            let witness = p2wsh::parse_witness(witness)?;
+            ensure!(
+                witness.redeem_script.matches_output(prev_output),
+                Error::ScriptMismatch
+            );
+            let signer = p2wsh::InputSigner::new(witness.redeem_script);
+            signer.check_signatures(txin, prev_output, &witness.signatures)
+        }
+        // The anchor output is spent by anyone with the empty witness.
+        ScriptType::P2a => {
+            ensure!(witness.is_empty(), WitnessError::ItemsCount(witness.len()));
+            Ok(())
+        }
+        _ => Err(Error::UnsupportedScript),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{
+        blockdata::{
+            opcodes::all::OP_RETURN,
+            script::{Builder, Script},
+            transaction::{OutPoint, SigHashType, Transaction, TxIn, TxOut},
+        },
+        network::constants::Network,
+    };
+
+    use std::slice;
+
+    use crate::{
+        multisig::RedeemScriptBuilder,
+        p2wpk, p2wsh,
+        script::p2a_script_pubkey,
+        test_data::deterministic_keypair,
+        verify::{verify_change, verify_transaction},
+        Error, SignatureError, TxInRef, WitnessError,
+    };
+
+    #[test]
+    fn test_verify_transaction() {
+        let keypairs = (0..3)
+            .map(|_| deterministic_keypair(Network::Testnet))
+            .collect::<Vec<_>>();
+        let redeem_script = RedeemScriptBuilder::with_public_keys(keypairs.iter().map(|x| x.0))
+            .quorum(2)
+            .to_script()
+            .unwrap();
+        let prev_outputs = vec![
+            TxOut {
+                value: 10_000,
+                script_pubkey: p2wsh::script_pubkey(&redeem_script),
+            },
+            TxOut {
+                value: 20_000,
+                script_pubkey: p2wpk::script_pubkey(&keypairs[0].0),
+            },
+        ];
+        let mut transaction = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: (0..2)
+                .map(|vout| TxIn {
+                    previous_output: OutPoint::new(Default::default(), vout),
+                    script_sig: Script::default(),
+                    sequence: 0xFFFF_FFFF,
+                    witness: Vec::default(),
+                })
+                .collect(),
+            output: vec![TxOut {
+                value: 0,
+                script_pubkey: Builder::new().push_opcode(OP_RETURN).into_script(),
+            }],
+        };
+
+        // Signs the multisig input.
+        let mut signer = p2wsh::InputSigner::new(redeem_script);
+        let signatures = keypairs[1..3]
+            .iter()
+            .map(|keypair| {
+                let txin = TxInRef::new(&transaction, 0);
+                signer
+                    .sign_input(txin, &prev_outputs[0], &keypair.1.key)
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        signer
+            .spend_input(&mut transaction, 0, &prev_outputs[0], signatures)
+            .unwrap();
+        assert_eq!(
+            verify_transaction(&transaction, &prev_outputs),
+            Err(Error::input(1, WitnessError::ItemsCount(0)))
+        );
+        // Signs the P2WPK input.
+        let mut signer = p2wpk::InputSigner::new(keypairs[0].0, Network::Testnet);
+        let signature = signer
+            .sign_input(
+                TxInRef::new(&transaction, 1),
+                &prev_outputs[1],
+                &keypairs[0].1.key,
+            )
+            .unwrap();
+        signer.spend_input(&mut transaction.input[1], signature);
+        verify_transaction(&transaction, &prev_outputs).unwrap();
+
+        // The signatures re-tagged with the other sighash type do not match their sighashes.
+        let retag = |signature: &mut Vec<u8>| {
+            *signature.last_mut().unwrap() = SigHashType::AllPlusAnyoneCanPay as u8;
+        };
+        let mut retagged = transaction.clone();
+        retag(&mut retagged.input[0].witness[1]);
+        assert_eq!(
+            verify_transaction(&retagged, &prev_outputs),
+            Err(Error::input(0, Error::InvalidSignature(0)))
+        );
+        let mut retagged = transaction.clone();
+        retag(&mut retagged.input[1].witness[0]);
+        assert_eq!(
+            verify_transaction(&retagged, &prev_outputs),
+            Err(Error::input(
+                1,
+                SignatureError::UnsupportedSighashType(SigHashType::AllPlusAnyoneCanPay)
+            ))
+        );
+        // The witness program commits to the compressed public key.
+        let mut uncompressed = transaction.clone();
+        uncompressed.input[1].witness[1] = keypairs[0].0.key.serialize_uncompressed().to_vec();
+        assert_eq!(
+            verify_transaction(&uncompressed, &prev_outputs),
+            Err(Error::input(1, WitnessError::InvalidPublicKey))
+        );
+
+        // The native segwit inputs must have the empty script sig.
+        for index in 0..2 {
+            let mut malleated = transaction.clone();
+            malleated.input[index].script_sig = Builder::new().push_slice(&[0; 4]).into_script();
+            assert_eq!(
+                verify_transaction(&malleated, &prev_outputs),
+                Err(Error::input(index, Error::NonEmptyScriptSig))
+            );
+        }
+
+        // The spent outputs are swapped.
+        let swapped_outputs = vec![prev_outputs[1].clone(), prev_outputs[0].clone()];
+        assert!(verify_transaction(&transaction, &swapped_outputs).is_err());
+        // The spent value is different.
+        let mut wrong_outputs = prev_outputs.clone();
+        wrong_outputs[0].value += 1;
+        assert_eq!(
+            verify_transaction(&transaction, &wrong_outputs),
+            Err(Error::input(0, Error::InvalidSignature(0)))
+        );
+        wrong_outputs[0].value -= 1;
+        wrong_outputs[1].value += 1;
+        assert_eq!(
+            verify_transaction(&transaction, &wrong_outputs),
+            Err(Error::input(1, secp256k1::Error::IncorrectSignature))
+        );
+
+        // The anchor output is spent with the empty witness.
+        let anchor = TxOut {
+            value: 0,
+            script_pubkey: p2a_script_pubkey(),
+        };
+        transaction.input.truncate(1);
+        assert_eq!(
+            verify_transaction(&transaction, slice::from_ref(&anchor)),
+            Err(Error::input(0, WitnessError::ItemsCount(4)))
+        );
+        transaction.input[0].witness.clear();
+        verify_transaction(&transaction, slice::from_ref(&anchor)).unwrap();
+        // The script sig of the anchor input is not empty.
+        transaction.input[0].script_sig = Builder::new().push_int(1).into_script();
+        assert_eq!(
+            verify_transaction(&transaction, &[anchor]),
+            Err(Error::input(0, Error::NonEmptyScriptSig))
+        );
+    }
+
+    #[test]
+    fn test_verify_change() {
+        let public_keys = (0..3)
+            .map(|_| deterministic_keypair(Network::Testnet).0)
+            .collect::<Vec<_>>();
+        let redeem_script = RedeemScriptBuilder::with_public_keys(public_keys.iter().copied())
+            .quorum(2)
+            .to_script()
+            .unwrap();
+        let change_script = p2wsh::script_pubkey(&redeem_script);
+        let payment = TxOut {
+            value: 5_000,
+            script_pubkey: p2wpk::script_pubkey(&public_keys[0]),
+        };
+        let change = TxOut {
+            value: 10_000,
+            script_pubkey: change_script.clone(),
+        };
+        let mut transaction = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: Vec::default(),
+            output: vec![payment.clone(), change],
+        };
+        let payments = vec![payment.clone()];
+        let change_scripts = vec![change_script.clone()];
+        verify_change(&transaction, &payments, &change_scripts).unwrap();
+        // The same payment is expected twice.
+        assert_eq!(
+            verify_change(&transaction, &[payment.clone(), payment], &change_scripts),
+            Err(Error::MissingPayment(1))
+        );
+
+        // The change is redirected to the foreign script.
+        transaction.output[1].script_pubkey = p2wpk::script_pubkey(&public_keys[1]);
+        assert_eq!(
+            verify_change(&transaction, &payments, &change_scripts),
+            Err(Error::UnapprovedChange(1))
+        );
+        // The payment value is changed.
+        transaction.output[1].script_pubkey = change_script;
+        transaction.output[0].value -= 1;
+        assert_eq!(
+            verify_change(&transaction, &payments, &change_scripts),
+            Err(Error::MissingPayment(0))
+        );
+    }
+}
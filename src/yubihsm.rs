@@ -0,0 +1,158 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The [`ExternalSigner`][external-signer] backed by the `YubiHSM 2` device.
+//! The signature hashes are signed inside the device, so the secret key never leaves it.
+//!
+//! The signing keys are the asymmetric objects with the `EcK256` algorithm and the
+//! `SIGN_ECDSA` capability. The signer is created either by the object id of the key
+//! or by its public key, for example, the one found in the redeem script.
+//!
+//! [external-signer]: ../trait.ExternalSigner.html
+
+use ::yubihsm::{
+    asymmetric::Algorithm,
+    client::{Error as ClientError, ErrorKind},
+    object, Client,
+};
+use bitcoin::PublicKey;
+
+use crate::{Error, ExternalSigner};
+
+/// The signer, which uses the key stored in the `YubiHSM 2` device.
+#[derive(Clone)]
+pub struct YubiHsmSigner {
+    client: Client,
+    key_id: object::Id,
+    public_key: PublicKey,
+}
+
+impl std::fmt::Debug for YubiHsmSigner {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("YubiHsmSigner")
+            .field("key_id", &self.key_id)
+            .field("public_key", &self.public_key)
+            .finish()
+    }
+}
+
+impl YubiHsmSigner {
+    /// Creates a signer for the key with the given object id.
+    pub fn new(client: Client, key_id: object::Id) -> Result<YubiHsmSigner, Error> {
+        let public_key = public_key(&client, key_id)?;
+        Ok(YubiHsmSigner {
+            client,
+            key_id,
+            public_key,
+        })
+    }
+
+    /// Creates a signer for the stored key with the given public key.
+    pub fn with_public_key(client: Client, public_key: &PublicKey) -> Result<YubiHsmSigner, Error> {
+        let key_id = public_keys(&client)?
+            .into_iter()
+            .find(|(_, key)| key.key == public_key.key)
+            .map(|(key_id, _)| key_id)
+            .ok_or_else(|| {
+                Error::ExternalSigner(format!("No key in the HSM for `{}`.", public_key))
+            })?;
+        YubiHsmSigner::new(client, key_id)
+    }
+
+    /// Returns the object id of the signing key.
+    pub fn key_id(&self) -> object::Id {
+        self.key_id
+    }
+}
+
+impl ExternalSigner for YubiHsmSigner {
+    fn public_key(&self) -> PublicKey {
+        self.public_key
+    }
+
+    fn sign_sighash(&self, sighash: &[u8; 32]) -> Result<Vec<u8>, Error> {
+        self.client
+            .sign_ecdsa_prehash_raw(self.key_id, sighash.as_ref())
+            .map_err(hsm_error)
+    }
+}
+
+/// Returns the object ids and the public keys of all `secp256k1` keys stored in the device.
+pub fn public_keys(client: &Client) -> Result<Vec<(object::Id, PublicKey)>, Error> {
+    let filters = [
+        object::Filter::Type(object::Type::AsymmetricKey),
+        object::Filter::Algorithm(Algorithm::EcK256.into()),
+    ];
+    client
+        .list_objects(&filters)
+        .map_err(hsm_error)?
+        .into_iter()
+        .map(|entry| Ok((entry.object_id, public_key(client, entry.object_id)?)))
+        .collect()
+}
+
+fn public_key(client: &Client, key_id: object::Id) -> Result<PublicKey, Error> {
+    let public_key = client.get_public_key(key_id).map_err(hsm_error)?;
+    ensure!(
+        public_key.algorithm == Algorithm::EcK256,
+        Error::ExternalSigner(format!(
+            "Key {} is not a secp256k1 key, its algorithm is {:?}.",
+            key_id, public_key.algorithm
+        ))
+    );
+    Ok(PublicKey {
+        compressed: true,
+        key: parse_public_key(public_key.as_ref())?,
+    })
+}
+
+/// Parses the public key returned by the device, which is the uncompressed point
+/// without the `0x04` prefix.
+fn parse_public_key(point: &[u8]) -> Result<secp256k1::PublicKey, Error> {
+    let mut bytes = Vec::with_capacity(point.len() + 1);
+    bytes.push(0x04);
+    bytes.extend_from_slice(point);
+    secp256k1::PublicKey::from_slice(&bytes).map_err(|e| Error::ExternalSigner(e.to_string()))
+}
+
+fn hsm_error(error: ClientError) -> Error {
+    // The device errors are wrapped twice, so the innermost one is the most informative.
+    let mut source: &dyn std::error::Error = &error;
+    while let Some(inner) = source.source() {
+        source = inner;
+    }
+    let message = match error.kind() {
+        ErrorKind::AuthenticationError => format!("The HSM authentication failed: {}", source),
+        ErrorKind::DeviceError => format!("The HSM rejected the command: {}", source),
+        _ => format!("The HSM client failed: {}", error),
+    };
+    Error::ExternalSigner(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_public_key;
+    use crate::test_data::deterministic_keypair;
+
+    use bitcoin::network::constants::Network;
+
+    #[test]
+    fn test_parse_public_key() {
+        let public_key = deterministic_keypair(Network::Testnet).0.key;
+        let point = public_key.serialize_uncompressed();
+        assert_eq!(parse_public_key(&point[1..]).unwrap(), public_key);
+        assert!(parse_public_key(&point).is_err());
+        assert!(parse_public_key(&point[1..33]).is_err());
+    }
+}